@@ -12,16 +12,47 @@
 //! - `nonce`: A 256-bit random value used in the proof-of-work mechanism.
 //! - `hash`: The resulting hash of the block, which must satisfy the network's
 //! difficulty requirements.
+//! - `timestamp`: Time of the block in seconds since the UNIX epoch.
+//! - `complexity`: The complexity the block was mined with.
+//! - `version`: Format version of the block.
+//!
+//! Blocks of version 1 are legacy ones: their `timestamp` and `complexity` are
+//! zero and not included in their message, so the existing chains remain 
+//! valid. Blocks of version 2 (timed blocks) include the version with the
+//! fields in the message. Blocks of version 3 use the tagged hashing for the
+//! message (see `hash_tagged`), so it cannot be reinterpreted as a hash of 
+//! other contexts (e.g. a transaction message).
+//! The message of the legacy block is the hash of all the transaction hashes,
+//! whereas timed blocks commit to the merkle root of the transaction hashes
+//! with the `offset` and the `size` (the size determines the required work
+//! and the shape of the merkle branches, so it cannot be forged). So the 
+//! header of a timed block (`BlockHeader`) can be validated without
+//! the transactions (the body), that allows light nodes to sync and verify 
+//! the headers only.
+//! Timestamps cannot decrease, so once a chain has a timed block, all the next
+//! blocks are timed. The complexity of timed blocks is determined by the 
+//! retargeting (see the `difficulty` module).
+//!
+//! The canonical encoding starts with the version and ends with opaque 
+//! extension bytes, so the next versions can add their fields there. Nodes 
+//! that do not support the version still can read the common fields of the 
+//! block with `BlockEnvelope`, whereas `Block` decoding accepts the supported
+//! versions only.
 //!
 //! The module also defines:
 //! - `BlockInfo`: A concise summary of a block's essential information.
 //! - `BlockData`: An extended structure that includes all transactions
 //! associated with a block.
+//! - `BlockHeader`: The block with the merkle root instead of transactions.
+//! - `BlockEnvelope`: The block of any version with its extension bytes.
 //!
 //! Constants:
-//! - `GENESIS_HASH`: The predefined hash value for the genesis (first) block.
-//! - `COMPLEXITY`: The network's difficulty level, determining the required
-//! number of trailing zeros in a valid block hash.
+//! - `GENESIS_HASH`: The predefined hash value for the genesis (first) block
+//!   of the mainnet (other networks define it in their `ChainSpec`).
+//! - `COMPLEXITY`: The initial network's difficulty level, determining the 
+//!   required number of trailing zeros in a valid block hash.
+//! - `BLOCK_MAX_SIZE` and `BLOCK_MAX_BYTES`: The consensus limits of the 
+//!   number of transactions in a block and their encoded size.
 //!
 //! The `Block` struct provides methods for:
 //! - Creating new blocks.
@@ -33,90 +64,212 @@
 //! This module ensures that each block adheres to the Uqoin protocol's rules,
 //! maintaining the blockchain's security and consistency.
 
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use rand::Rng;
-use sha3::{Sha3_256, Digest};
 use serde::{Serialize, Deserialize};
 
 use crate::validate;
 use crate::utils::*;
-use crate::transaction::{Type, Transaction, group_transactions};
+use crate::error::{ValidationReport, ErrorCollector, FailFast};
+use crate::transaction::{Type, Transaction, try_group_transactions};
 use crate::state::State;
+use crate::codec::{Codec, Writer, Reader};
+use crate::spec::{ChainSpec, ProtocolParams};
 
 
 /// Hash of the zero block.
 pub const GENESIS_HASH: &str = 
     "E12BA98A17FD8F70608668AA32AEB3BE1F202B4BD69880A6C0CFE855B1A0706B";
 
-/// Complexity after calibration.
+/// Complexity after calibration (see `difficulty::calibrate_complexity`).
 pub const COMPLEXITY: usize = 24;
 
+/// Legacy version of blocks (without timestamp and complexity).
+pub const BLOCK_VERSION_1: u8 = 1;
+
+/// Version of timed blocks with the merkle root in the message.
+pub const BLOCK_VERSION_2: u8 = 2;
+
+/// Version of timed blocks with the tagged message.
+pub const BLOCK_VERSION_3: u8 = 3;
+
+/// Latest supported version of blocks.
+pub const BLOCK_VERSION: u8 = BLOCK_VERSION_3;
+
+/// Tag of the block message (since version 3).
+pub const TAG_BLOCK_MSG: &str = "uqoin/block/msg";
+
+/// Maximum number of transactions in a block.
+pub const BLOCK_MAX_SIZE: usize = 1024;
+
+/// Maximum total size of the encoded transactions in a block in bytes.
+pub const BLOCK_MAX_BYTES: usize = 196608;
+
 
 /// Basic structure for block.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub offset: u64,
     pub size: u64,
+    #[serde(with = "u256_hex")]
     pub hash_prev: U256,
+    #[serde(with = "u256_hex")]
     pub validator: U256,
+    #[serde(with = "u256_hex")]
     pub nonce: U256,
+    #[serde(with = "u256_hex")]
     pub hash: U256,
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default)]
+    pub complexity: u64,
+    #[serde(default = "default_version")]
+    pub version: u8,
 }
 
 
 impl Block {
-    /// New block.
+    /// New legacy block (without timestamp and complexity) of version 1.
     pub fn new(offset: u64, size: u64, hash_prev: U256, validator: U256, 
                nonce: U256, hash: U256) -> Self {
-        Self { offset, size, hash_prev, validator, nonce, hash }
+        Self { 
+            offset, size, hash_prev, validator, nonce, hash, 
+            timestamp: 0, 
+            complexity: 0,
+            version: BLOCK_VERSION_1,
+        }
+    }
+
+    /// Set timestamp and complexity. The hash must be calculated with them.
+    /// The version is raised to the one of `get_version` if it is lower.
+    pub fn with_time(mut self, timestamp: u64, complexity: u64) -> Self {
+        self.timestamp = timestamp;
+        self.complexity = complexity;
+        self.version = self.version.max(Self::get_version(timestamp));
+        self
+    }
+
+    /// Version of the block with the timestamp: zero timestamp means a legacy
+    /// block, others are built of the latest version.
+    pub fn get_version(timestamp: u64) -> u8 {
+        if timestamp > 0 { BLOCK_VERSION } else { BLOCK_VERSION_1 }
     }
 
     /// Full validation of the block that includes transactions, info of the 
     /// previous block, complexity, state between this block and the previous
-    /// one.
+    /// one. `complexity` is expected to be calculated by 
+    /// `Retarget::complexity_for_height`. It stops at the first error (the 
+    /// same one `validate_report` starts with).
     pub fn validate(&self, transactions: &[Transaction], 
                     block_info_prev: &BlockInfo, complexity: usize, 
                     state: &State, senders: &[U256]) -> UqoinResult<()> {
+        self.validate_with(transactions, block_info_prev, complexity, state, 
+                           senders, &mut FailFast)
+    }
+
+    /// Validate the block as `validate` does, but collect all the errors 
+    /// instead of stopping at the first one (e.g. for debugging tools and 
+    /// peer-ban decisions). It does all the checks whatever the errors, so 
+    /// `validate` is preferred for untrusted blocks. The block is valid if 
+    /// the report is ok.
+    pub fn validate_report(&self, transactions: &[Transaction], 
+                           block_info_prev: &BlockInfo, complexity: usize, 
+                           state: &State, 
+                           senders: &[U256]) -> ValidationReport {
+        ValidationReport::run(|report| self.validate_with(
+            transactions, block_info_prev, complexity, state, senders, report
+        ))
+    }
+
+    /// Checks of `validate` and `validate_report` passing the errors to the
+    /// collector.
+    fn validate_with<C: ErrorCollector>(&self, transactions: &[Transaction], 
+                                        block_info_prev: &BlockInfo, 
+                                        complexity: usize, state: &State, 
+                                        senders: &[U256], 
+                                        errors: &mut C) -> UqoinResult<()> {
+        // Check version
+        errors.collect(self.validate_version())?;
+
+        // Check block limits
+        errors.collect(Self::validate_size_for(self.version, transactions, 
+                                               state.get_params()))?;
+
         // Check block hash
-        validate!(block_info_prev.hash == self.hash_prev, 
-                  BlockPreviousHashMismatch)?;
+        errors.collect(validate!(block_info_prev.hash == self.hash_prev, 
+                                 BlockPreviousHashMismatch))?;
 
         // Check block offset
-        validate!(block_info_prev.offset == self.offset, 
-                  BlockOffsetMismatch)?;
-
-        // Validate transactions
-        Self::validate_transactions(transactions, &self.validator, state, 
-                                    senders)?;
+        errors.collect(validate!(block_info_prev.offset == self.offset, 
+                                 BlockOffsetMismatch))?;
+
+        // Check block size
+        errors.collect(validate!(self.size == transactions.len() as u64, 
+                                 BlockBroken)
+            .map_err(|err| err.with_values(self.size, transactions.len())))?;
+
+        // Check timestamp does not decrease
+        errors.collect(validate!(self.timestamp >= block_info_prev.timestamp, 
+                                 BlockInvalidTimestamp)
+            .map_err(|err| err.with_values(block_info_prev.timestamp, 
+                                           self.timestamp)))?;
+
+        // Check declared complexity (zero for legacy blocks)
+        let expected = Self::get_declared_complexity(self.timestamp, 
+                                                     complexity);
+        errors.collect(validate!(self.complexity == expected, 
+                                 BlockInvalidHashComplexity)
+            .map_err(|err| err.with_values(expected, self.complexity)))?;
+
+        // Validate transactions (the hash is checked after a broken group)
+        let result = Self::validate_transactions_with(
+            transactions, &self.validator, state, senders, errors
+        );
+        errors.collect(result)?;
 
         // Calculate the message
-        let msg = Self::calc_msg(&self.hash_prev, &self.validator, 
-                                 transactions);
+        let msg = Self::calc_msg(self.version, self.offset, &self.hash_prev, 
+                                 &self.validator, self.timestamp, 
+                                 self.complexity, transactions);
 
         // Calculate the hash
         let hash = Self::calc_hash(&msg, &self.nonce);
 
         // Check hash
-        validate!(hash == self.hash, BlockInvalidHash)?;
+        errors.collect(validate!(hash == self.hash, BlockInvalidHash))?;
 
         // Validate hash
-        Self::validate_hash_complexity(&self.hash, transactions.len(), 
-                                       complexity)?;
-
-        // Return
-        Ok(())
+        errors.collect(Self::validate_hash_complexity(
+            &self.hash, transactions.len(), complexity
+        ))
     }
 
     /// Build a new block for the transactions. It validates the final hash.
-    pub fn build(block_info_prev: &BlockInfo, validator: U256, 
+    /// Zero `timestamp` builds a legacy block. `complexity` is expected to be
+    /// calculated by `Retarget::complexity_for_height`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build(block_info_prev: &BlockInfo, validator: U256, timestamp: u64,
                  transactions: &[Transaction], nonce: U256,
                  complexity: usize, state: &State, 
                  senders: &[U256]) -> UqoinResult<Self> {
+        // Check block limits
+        Self::validate_size_for(Self::get_version(timestamp), transactions, 
+                                state.get_params())?;
+
         // Validate transactions
         Self::validate_transactions(transactions, &validator, state, senders)?;
 
+        // Declared complexity (zero for legacy blocks)
+        let block_complexity = Self::get_declared_complexity(timestamp, 
+                                                             complexity);
+
         // Calculate the message
-        let msg = Self::calc_msg(&block_info_prev.hash, &validator, 
-                                 transactions);
+        let msg = Self::calc_msg(Self::get_version(timestamp), 
+                                 block_info_prev.offset, 
+                                 &block_info_prev.hash, &validator, timestamp,
+                                 block_complexity, transactions);
 
         // Calculate the hash
         let hash = Self::calc_hash(&msg, &nonce);
@@ -128,7 +281,63 @@ impl Block {
         Ok(Self::new(block_info_prev.offset, 
                      transactions.len() as u64, 
                      block_info_prev.hash.clone(),
-                     validator, nonce, hash))
+                     validator, nonce, hash)
+           .with_time(timestamp, block_complexity))
+    }
+
+    /// Validate the version. The checks:
+    /// 1. The version is supported.
+    /// 2. Only legacy blocks (version 1) have zero timestamp, the timed 
+    ///    blocks are of any later version.
+    pub fn validate_version(&self) -> UqoinResult<()> {
+        validate!((BLOCK_VERSION_1..=BLOCK_VERSION).contains(&self.version), 
+                  BlockUnsupportedVersion)
+            .map_err(|err| err.with_values(BLOCK_VERSION, self.version))?;
+        validate!((self.version == BLOCK_VERSION_1) == (self.timestamp == 0), 
+                  BlockInvalidTimestamp)
+            .map_err(|err| err.with_values(Self::get_version(self.timestamp), 
+                                           self.version))
+    }
+
+    /// Validate the block limits: the number of transactions must not exceed
+    /// `BLOCK_MAX_SIZE` and their encoded size must not exceed 
+    /// `BLOCK_MAX_BYTES`.
+    pub fn validate_size(transactions: &[Transaction]) -> UqoinResult<()> {
+        Self::validate_size_with(transactions, &ProtocolParams::default())
+    }
+
+    /// Validate the block limits of the parameters (see `validate_size`).
+    pub fn validate_size_with(transactions: &[Transaction], 
+                              params: &ProtocolParams) -> UqoinResult<()> {
+        validate!(transactions.len() <= params.block_max_size, BlockTooLarge)
+            .map_err(|err| err.with_values(params.block_max_size, 
+                                           transactions.len()))?;
+        let bytes = Self::get_encoded_size(transactions);
+        validate!(bytes <= params.block_max_bytes, BlockTooLarge)
+            .map_err(|err| err.with_values(params.block_max_bytes, bytes))
+    }
+
+    /// Validate the block limits of the parameters for the block version. 
+    /// The legacy blocks (version 1) were built before the limits, so they
+    /// are not checked.
+    pub fn validate_size_for(version: u8, transactions: &[Transaction], 
+                             params: &ProtocolParams) -> UqoinResult<()> {
+        if version > BLOCK_VERSION_1 {
+            Self::validate_size_with(transactions, params)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check if `size` transactions of `bytes` encoded size fit the block 
+    /// limits of the mainnet (see `ProtocolParams::is_size_allowed`).
+    pub fn is_size_allowed(size: usize, bytes: usize) -> bool {
+        ProtocolParams::default().is_size_allowed(size, bytes)
+    }
+
+    /// Total size of the encoded transactions in bytes.
+    pub fn get_encoded_size(transactions: &[Transaction]) -> usize {
+        transactions.iter().map(|tr| tr.get_encoded_size()).sum()
     }
 
     /// Validate coins. The checks:
@@ -151,51 +360,107 @@ impl Block {
 
     /// Validate transactions. The checks:
     /// 1. All coins are valid (see `validate_coins`).
-    /// 2. All transactions can be groupped into groups and extensions.
-    /// 3. Sender of each extension is the validator.
-    /// 4. Values of groups and extensions correspond each other.
-    /// Each group or extension has valid structure after the groupping because
-    /// they cannot be created invalid due to inner validation.
+    /// 2. All transactions are inside their validity windows for the next 
+    ///    block number of the state.
+    /// 3. All transactions can be groupped into groups and extensions.
+    /// 4. The validator does not pay fees to itself (see 
+    ///    `Transaction::forbids_self_transfer`).
+    /// 5. Sender of each extension is the validator.
+    /// 6. Values of groups and extensions correspond each other.
+    ///    Each group or extension has valid structure after the groupping
+    ///    because they cannot be created invalid due to inner validation.
     pub fn validate_transactions(transactions: &[Transaction], validator: &U256, 
                                  state: &State, senders: &[U256]) -> 
                                  UqoinResult<()> {
+        Self::validate_transactions_with(transactions, validator, state, 
+                                         senders, &mut FailFast)
+    }
+
+    /// Validate transactions as `validate_transactions` does, but collect all
+    /// the errors. The groupping stops at the first broken group, because the
+    /// boundaries of the next groups are unknown after it.
+    pub fn validate_transactions_report(transactions: &[Transaction], 
+                                        validator: &U256, state: &State, 
+                                        senders: &[U256]) -> 
+                                        ValidationReport {
+        ValidationReport::run(|report| Self::validate_transactions_with(
+            transactions, validator, state, senders, report
+        ))
+    }
+
+    /// Checks of `validate_transactions` and `validate_transactions_report`
+    /// passing the errors to the collector.
+    fn validate_transactions_with<C: ErrorCollector>(
+                transactions: &[Transaction], validator: &U256, 
+                state: &State, senders: &[U256], 
+                errors: &mut C) -> UqoinResult<()> {
         // // Check coins
         // Self::validate_coins(transactions, state, senders)?;
 
-        // Repeated coins are not valid
-        validate!(check_unique(transactions.iter().map(|tr| &tr.coin)), 
-                  CoinNotUnique)?;
+        // Repeated coins are not valid, they are searched if any
+        let coins = transactions.iter().map(|tr| &tr.coin)
+                                .collect::<Vec<&U256>>();
+        if !par_check_unique(&coins) {
+            let mut coins_seen = HashSet::new();
+            for (ix, coin) in coins.into_iter().enumerate() {
+                errors.collect(validate!(coins_seen.insert(coin), 
+                                         CoinNotUnique)
+                    .map_err(|err| err.with_index(ix).with_coin(coin)))?;
+            }
+        }
+
+        // Check validity windows for the block number
+        let bix = state.get_last_block_info().bix + 1;
+        for (ix, tr) in transactions.iter().enumerate() {
+            if let Some(validity) = tr.validity.as_ref() {
+                errors.collect(validate!(tr.is_valid_at(bix), 
+                                         TransactionExpired)
+                    .map_err(|err| err.with_index(ix).with_coin(&tr.coin)
+                                      .with_values(format!("{}..={}", 
+                                                           validity.from,
+                                                           validity.until), 
+                                                   bix)))?;
+            }
+        }
 
-        // Set a countdown for groupped transactions
-        let mut countdown = transactions.len();
+        // Loop for groups and extensions, the grouping stops at the first 
+        // error
+        for item in try_group_transactions(transactions, state, senders) {
+            let (offset, group, ext) = item?;
 
-        // Loop for groups and extensions
-        for (offset, group, ext) in group_transactions(transactions.to_vec(), 
-                                                       state, senders) {
             // Get senders
             let group_senders = &senders[offset .. offset + group.len()];
             let ext_senders = &senders[
                 offset + group.len() .. offset + group.len() + ext.len()
             ];
 
+            // Check the validator does not pay the fee to itself
+            if group.get_fee().is_some_and(|fee| fee.forbids_self_transfer()) {
+                errors.collect(validate!(&group.get_sender(group_senders) != 
+                                         validator, TransactionSelfTransfer)
+                    .map_err(|err| err.with_index(offset + group.len() - 1)))?;
+            }
+
             // Check validator
             if let Some(ext_sender) = ext.get_sender(ext_senders) {
-                validate!(&ext_sender == validator, BlockValidatorMismatch)?;
+                errors.collect(validate!(&ext_sender == validator, 
+                                         BlockValidatorMismatch)
+                    .map_err(|err| err.with_index(offset + group.len())
+                                      .with_values(validator.to_hex(), 
+                                                   ext_sender.to_hex())))?;
             }
 
             // Check value
             if ext.get_type() != Type::Transfer {
-                validate!(group.get_order(state, group_senders) 
-                    == ext.get_order(state, ext_senders), BlockOrderMismatch)?;
+                let group_order = group.get_order(state, group_senders);
+                let ext_order = ext.get_order(state, ext_senders);
+                errors.collect(validate!(group_order == ext_order, 
+                                         BlockOrderMismatch)
+                    .map_err(|err| err.with_index(offset + group.len())
+                                      .with_values(group_order, ext_order)))?;
             }
-
-            // Decrement the countdown
-            countdown -= group.len() + ext.len();
         }
 
-        // Validate that all transactions have been groupped
-        validate!(countdown == 0, BlockBroken)?;
-
         Ok(())
     }
 
@@ -207,12 +472,69 @@ impl Block {
                   BlockInvalidHashComplexity)
     }
 
-    /// calculate block message as hash of the important content.
-    pub fn calc_msg(block_hash_prev: &U256, validator: &U256, 
+    /// calculate block message as hash of the important content. Legacy
+    /// blocks (version 1) hash all the transactions, timed blocks use the 
+    /// merkle root (see `calc_msg_of_root`). `offset` is the offset of the
+    /// block (not used for legacy blocks).
+    pub fn calc_msg(version: u8, offset: u64, block_hash_prev: &U256, 
+                    validator: &U256, timestamp: u64, complexity: u64, 
                     transactions: &[Transaction]) -> U256 {
-        let mut elems = vec![block_hash_prev.clone(), validator.clone()];
-        elems.extend(transactions.iter().map(|tr| tr.get_hash()));
-        hash_of_u256(elems.iter())
+        if version == BLOCK_VERSION_1 {
+            let mut elems = vec![block_hash_prev.clone(), validator.clone()];
+            elems.extend(transactions.iter().map(|tr| tr.get_hash()));
+            hash_of_u256(elems.iter())
+        } else {
+            Self::calc_msg_of_root(version, offset, transactions.len() as u64,
+                                   block_hash_prev, validator, timestamp, 
+                                   complexity, 
+                                   &Self::calc_merkle_root(transactions))
+        }
+    }
+
+    /// Calculate message of the timed block from its merkle root. The version,
+    /// the offset and the size are hashed too, since version 3 the hash is 
+    /// tagged.
+    #[allow(clippy::too_many_arguments)]
+    pub fn calc_msg_of_root(version: u8, offset: u64, size: u64, 
+                            block_hash_prev: &U256, validator: &U256, 
+                            timestamp: u64, complexity: u64, 
+                            merkle_root: &U256) -> U256 {
+        let elems = [
+            &U256::from(version as u64), &U256::from(offset), 
+            &U256::from(size), block_hash_prev, validator, 
+            &U256::from(timestamp), &U256::from(complexity), merkle_root
+        ];
+        if version >= BLOCK_VERSION_3 {
+            hash_tagged(TAG_BLOCK_MSG, elems.into_iter())
+        } else {
+            hash_of_u256(elems.into_iter())
+        }
+    }
+
+    /// Calculate merkle root of the transaction hashes.
+    pub fn calc_merkle_root(transactions: &[Transaction]) -> U256 {
+        merkle_root(&par_hashes(transactions, Transaction::get_hash))
+    }
+
+    /// Get header of the block with the transactions.
+    pub fn get_header(&self, transactions: &[Transaction]) -> BlockHeader {
+        BlockHeader {
+            offset: self.offset,
+            size: self.size,
+            hash_prev: self.hash_prev.clone(),
+            validator: self.validator.clone(),
+            timestamp: self.timestamp,
+            complexity: self.complexity,
+            merkle_root: Self::calc_merkle_root(transactions),
+            nonce: self.nonce.clone(),
+            hash: self.hash.clone(),
+            version: self.version,
+        }
+    }
+
+    /// Complexity declared in the block: zero for legacy blocks.
+    pub fn get_declared_complexity(timestamp: u64, complexity: usize) -> u64 {
+        if timestamp > 0 { complexity as u64 } else { 0 }
     }
 
     /// Calculate block hash from message and nonce.
@@ -225,25 +547,98 @@ impl Block {
         hash_bytes <= limit_hash_bytes
     }
 
-    /// Find correct nonce bytes to mine the block.
-    pub fn mine<R: Rng>(rng: &mut R, block_hash_prev: &U256, validator: &U256, 
-                        transactions: &[Transaction], 
-                        complexity: usize, 
+    /// Find correct nonce bytes to mine the block after `block_info_prev`. 
+    /// Zero `timestamp` mines a legacy block.
+    pub fn mine<R: Rng>(rng: &mut R, block_info_prev: &BlockInfo, 
+                        validator: &U256, timestamp: u64, 
+                        transactions: &[Transaction], complexity: usize, 
                         iterations: Option<usize>) -> Option<[u8; 32]> {
         // Calculate the message bytes
-        let msg = Self::calc_msg(block_hash_prev, validator, transactions);
-
-        // Number of transactions
-        let size = transactions.len();
+        let msg = Self::calc_msg(
+            Self::get_version(timestamp), block_info_prev.offset, 
+            &block_info_prev.hash, validator, timestamp, 
+            Self::get_declared_complexity(timestamp, complexity), transactions
+        );
+
+        // Mine the message
+        Self::mine_msg(rng, &msg, transactions.len(), complexity, iterations)
+    }
 
+    /// Find correct nonce bytes for the precalculated block message (e.g. 
+    /// from `BlockTemplate`) and the number of transactions `size`.
+    pub fn mine_msg<R: Rng>(rng: &mut R, msg: &U256, size: usize, 
+                            complexity: usize, 
+                            iterations: Option<usize>) -> Option<[u8; 32]> {
         // Calculate limit hash
         let limit_hash = Self::calc_limit_hash(size, complexity);
 
-        // Initialize SHA3 hasher with the block message
-        let mut hasher = Sha3_256::new();
-        hasher.update(msg.to_bytes());
+        // Initialize the hasher with the block message
+        let mut hasher = Hasher256::new();
+        hasher.update_u256(msg);
 
         // Mining loop
+        Self::mine_loop(rng, &hasher, &limit_hash, iterations, || false)
+    }
+
+    /// Find correct nonce bytes to mine the block in `threads` worker threads
+    /// (all available cores if `threads` is 0). The hasher with the block 
+    /// message is precalculated once and shared between the workers. The 
+    /// search stops when any worker finds a nonce, when `stop` is set from 
+    /// outside or when each worker has made `iterations` attempts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn mine_parallel(block_info_prev: &BlockInfo, validator: &U256, 
+                         timestamp: u64, transactions: &[Transaction], 
+                         complexity: usize, threads: usize, 
+                         iterations: Option<usize>, 
+                         stop: &AtomicBool) -> Option<[u8; 32]> {
+        // Calculate the message bytes
+        let msg = Self::calc_msg(
+            Self::get_version(timestamp), block_info_prev.offset, 
+            &block_info_prev.hash, validator, timestamp, 
+            Self::get_declared_complexity(timestamp, complexity), transactions
+        );
+
+        // Calculate limit hash
+        let limit_hash = Self::calc_limit_hash(transactions.len(), complexity);
+
+        // Initialize the hasher with the block message
+        let mut hasher = Hasher256::new();
+        hasher.update_u256(&msg);
+
+        // Number of workers
+        let threads = if threads > 0 {
+            threads
+        } else {
+            std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+        };
+
+        // Flag to stop the rest workers when the nonce is found
+        let found = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let handles = (0..threads).map(|_| scope.spawn(|| {
+                let mut rng = rand::rng();
+                let nonce = Self::mine_loop(
+                    &mut rng, &hasher, &limit_hash, iterations, 
+                    || stop.load(Ordering::Relaxed) || 
+                       found.load(Ordering::Relaxed)
+                );
+                if nonce.is_some() {
+                    found.store(true, Ordering::Relaxed);
+                }
+                nonce
+            })).collect::<Vec<_>>();
+
+            handles.into_iter().filter_map(|handle| handle.join().unwrap())
+                   .next()
+        })
+    }
+
+    /// Mining loop over random nonces for the hasher with the block message.
+    fn mine_loop<R: Rng, F: Fn() -> bool>(rng: &mut R, hasher: &Hasher256, 
+                                          limit_hash: &[u8], 
+                                          iterations: Option<usize>,
+                                          is_stopped: F) -> Option<[u8; 32]> {
         for iteration in 0.. {
             // Stop by iterations
             if let Some(iterations) = iterations {
@@ -252,20 +647,19 @@ impl Block {
                 }
             }
 
-            // Clone the hasher state before adding nonce
-            let mut hasher_clone = hasher.clone();
+            // Stop by the flag
+            if is_stopped() {
+                break;
+            }
 
             // Generate a random 256-bit nonce
             let nonce_bytes: [u8; 32] = rng.random();
 
-            // Update the hasher with the generated nonce
-            hasher_clone.update(nonce_bytes);
-
-            // Get the bytes of the final hash
-            let hash_bytes = hasher_clone.finalize();
+            // Get the bytes of the final hash with the nonce
+            let hash_bytes = hasher.finalize_with(&nonce_bytes);
 
             // If the hash is valid return the generated nonce and U256
-            if Self::is_hash_valid(&hash_bytes, &limit_hash) {
+            if Self::is_hash_valid(&hash_bytes, limit_hash) {
                 return Some(nonce_bytes);
             }
         }
@@ -289,8 +683,235 @@ impl Block {
 }
 
 
-/// Short information about the block.
+/// Canonical encoding of the block: the version (1 byte), `offset` and `size`
+/// (8 bytes each), `hash_prev`, `validator`, `nonce` and `hash` (32 bytes 
+/// each), since version 2 `timestamp` and `complexity` (8 bytes each), and 
+/// the extension bytes prefixed with their length as 4 bytes (empty for the 
+/// supported versions). So the block takes 149 bytes for version 1 and 165 
+/// bytes for version 2.
+impl Codec for Block {
+    fn encode(&self, writer: &mut Writer) {
+        BlockEnvelope::encode_block(self, &[], writer);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        BlockEnvelope::decode(reader)?.into_block()
+    }
+}
+
+
+/// Block of any version with the extension bytes as they are in the canonical
+/// encoding. Blocks of unknown versions keep the common fields readable, but
+/// they cannot be validated.
+#[derive(Debug, Clone)]
+pub struct BlockEnvelope {
+    /// Block with the common fields.
+    pub block: Block,
+
+    /// Opaque extension bytes of the next versions.
+    pub extension: Vec<u8>,
+}
+
+
+impl BlockEnvelope {
+    /// Check if the version of the block is supported.
+    pub fn is_supported(&self) -> bool {
+        (BLOCK_VERSION_1..=BLOCK_VERSION).contains(&self.block.version)
+    }
+
+    /// Get the block if its version is supported and there is no extension.
+    pub fn into_block(self) -> UqoinResult<Block> {
+        validate!(self.is_supported(), BlockUnsupportedVersion)
+            .map_err(|err| err.with_values(BLOCK_VERSION, 
+                                           self.block.version))?;
+        validate!(self.extension.is_empty(), EncodingInvalid)?;
+        Ok(self.block)
+    }
+
+    /// Encode the block with the extension.
+    fn encode_block(block: &Block, extension: &[u8], writer: &mut Writer) {
+        writer.write_u8(block.version);
+        writer.write_u64(block.offset);
+        writer.write_u64(block.size);
+        writer.write_u256(&block.hash_prev);
+        writer.write_u256(&block.validator);
+        writer.write_u256(&block.nonce);
+        writer.write_u256(&block.hash);
+        if block.version >= BLOCK_VERSION_2 {
+            writer.write_u64(block.timestamp);
+            writer.write_u64(block.complexity);
+        }
+        writer.write_u32(extension.len() as u32);
+        writer.write_bytes(extension);
+    }
+}
+
+
+impl Codec for BlockEnvelope {
+    fn encode(&self, writer: &mut Writer) {
+        Self::encode_block(&self.block, &self.extension, writer);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        let version = reader.read_u8()?;
+        validate!(version >= BLOCK_VERSION_1, BlockUnsupportedVersion)?;
+
+        let mut block = Block::new(reader.read_u64()?, reader.read_u64()?, 
+                                   reader.read_u256()?, reader.read_u256()?, 
+                                   reader.read_u256()?, reader.read_u256()?);
+        block.version = version;
+        if version >= BLOCK_VERSION_2 {
+            block.timestamp = reader.read_u64()?;
+            block.complexity = reader.read_u64()?;
+        }
+
+        let size = reader.read_u32()? as usize;
+        let extension = reader.read_bytes(size)?.to_vec();
+        Ok(Self { block, extension })
+    }
+}
+
+
+fn default_version() -> u8 {
+    BLOCK_VERSION_1
+}
+
+
+/// Header of the block: the block with the merkle root of its transactions.
+/// Only headers of timed blocks can be validated without the transactions.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub offset: u64,
+    pub size: u64,
+    #[serde(with = "u256_hex")]
+    pub hash_prev: U256,
+    #[serde(with = "u256_hex")]
+    pub validator: U256,
+    pub timestamp: u64,
+    pub complexity: u64,
+    #[serde(with = "u256_hex")]
+    pub merkle_root: U256,
+    #[serde(with = "u256_hex")]
+    pub nonce: U256,
+    #[serde(with = "u256_hex")]
+    pub hash: U256,
+    pub version: u8,
+}
+
+
+impl BlockHeader {
+    /// Check if the header belongs to a legacy block.
+    pub fn is_legacy(&self) -> bool {
+        self.version == BLOCK_VERSION_1
+    }
+
+    /// Validate the header without transactions. The checks:
+    /// 1. The block is timed (legacy blocks require transactions) and its
+    ///    version is valid (see `Block::validate_version`).
+    /// 2. Previous hash, offset and timestamp correspond the previous block.
+    /// 3. Declared complexity is the expected one.
+    /// 4. Hash corresponds the message and the nonce.
+    /// 5. Hash satisfies the complexity.
+    pub fn validate(&self, block_info_prev: &BlockInfo, 
+                    complexity: usize) -> UqoinResult<()> {
+        // Check the block is timed
+        validate!(!self.is_legacy(), BlockLegacyHeader)?;
+        self.get_block().validate_version()?;
+
+        // Check the links to the previous block
+        validate!(block_info_prev.hash == self.hash_prev, 
+                  BlockPreviousHashMismatch)?;
+        validate!(block_info_prev.offset == self.offset, 
+                  BlockOffsetMismatch)?;
+        validate!(self.timestamp >= block_info_prev.timestamp, 
+                  BlockInvalidTimestamp)
+            .map_err(|err| err.with_values(block_info_prev.timestamp, 
+                                           self.timestamp))?;
+
+        // Check declared complexity
+        validate!(self.complexity == complexity as u64, 
+                  BlockInvalidHashComplexity)
+            .map_err(|err| err.with_values(complexity, self.complexity))?;
+
+        // Check hash
+        let msg = Block::calc_msg_of_root(self.version, self.offset, 
+                                          self.size, &self.hash_prev, 
+                                          &self.validator, self.timestamp, 
+                                          self.complexity, &self.merkle_root);
+        validate!(Block::calc_hash(&msg, &self.nonce) == self.hash, 
+                  BlockInvalidHash)?;
+
+        // Validate hash
+        Block::validate_hash_complexity(&self.hash, self.size as usize, 
+                                        complexity)
+    }
+
+    /// Validate that the transactions are the body of the header.
+    pub fn validate_body(&self, transactions: &[Transaction]) -> 
+                         UqoinResult<()> {
+        validate!(transactions.len() as u64 == self.size, BlockBroken)
+            .map_err(|err| err.with_values(self.size, transactions.len()))?;
+        validate!(Block::calc_merkle_root(transactions) == self.merkle_root, 
+                  BlockBroken)
+    }
+
+    /// Get the block without the merkle root.
+    pub fn get_block(&self) -> Block {
+        let mut block = Block::new(self.offset, self.size, 
+                                   self.hash_prev.clone(), 
+                                   self.validator.clone(), self.nonce.clone(),
+                                   self.hash.clone());
+        block.timestamp = self.timestamp;
+        block.complexity = self.complexity;
+        block.version = self.version;
+        block
+    }
+
+    /// Get short information of the block with number `bix`.
+    pub fn get_block_info(&self, bix: u64) -> BlockInfo {
+        BlockInfo::from_block(bix, &self.get_block())
+    }
+}
+
+
+/// Canonical encoding of the block header (193 bytes): the version (1 byte),
+/// `offset` and `size` (8 bytes each), `hash_prev` and `validator` (32 bytes
+/// each), `timestamp` and `complexity` (8 bytes each), `merkle_root`, `nonce`
+/// and `hash` (32 bytes each).
+impl Codec for BlockHeader {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_u8(self.version);
+        writer.write_u64(self.offset);
+        writer.write_u64(self.size);
+        writer.write_u256(&self.hash_prev);
+        writer.write_u256(&self.validator);
+        writer.write_u64(self.timestamp);
+        writer.write_u64(self.complexity);
+        writer.write_u256(&self.merkle_root);
+        writer.write_u256(&self.nonce);
+        writer.write_u256(&self.hash);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        let version = reader.read_u8()?;
+        Ok(Self {
+            offset: reader.read_u64()?,
+            size: reader.read_u64()?,
+            hash_prev: reader.read_u256()?,
+            validator: reader.read_u256()?,
+            timestamp: reader.read_u64()?,
+            complexity: reader.read_u64()?,
+            merkle_root: reader.read_u256()?,
+            nonce: reader.read_u256()?,
+            hash: reader.read_u256()?,
+            version,
+        })
+    }
+}
+
+
+/// Short information about the block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BlockInfo {
     /// Block number.
     pub bix: u64,
@@ -300,19 +921,70 @@ pub struct BlockInfo {
     pub offset: u64,
 
     /// Last block hash.
+    #[serde(with = "u256_hex")]
     pub hash: U256,
+
+    /// Last block timestamp (zero for legacy blocks).
+    #[serde(default)]
+    pub timestamp: u64,
+
+    /// Last block complexity (zero for legacy blocks).
+    #[serde(default)]
+    pub complexity: u64,
 }
 
 
 impl BlockInfo {
-    /// Get information of the genesis block (`bix=0`).
+    /// Get information of the genesis block (`bix=0`) of the mainnet.
     pub fn genesis() -> Self {
+        Self::genesis_of(&ChainSpec::mainnet())
+    }
+
+    /// Get information of the genesis block (`bix=0`) of the chain.
+    pub fn genesis_of(spec: &ChainSpec) -> Self {
         Self {
             bix: 0,
             offset: 0,
-            hash: U256::from_hex(GENESIS_HASH),
+            hash: spec.genesis_hash.clone(),
+            timestamp: 0,
+            complexity: 0,
         }
     }
+
+    /// Get information of the block with number `bix`.
+    pub fn from_block(bix: u64, block: &Block) -> Self {
+        Self {
+            bix,
+            offset: block.offset + block.size,
+            hash: block.hash.clone(),
+            timestamp: block.timestamp,
+            complexity: block.complexity,
+        }
+    }
+}
+
+
+/// Canonical encoding of the block info (64 bytes): `bix` and `offset` 
+/// (8 bytes each), `hash` (32 bytes), `timestamp` and `complexity` (8 bytes 
+/// each).
+impl Codec for BlockInfo {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_u64(self.bix);
+        writer.write_u64(self.offset);
+        writer.write_u256(&self.hash);
+        writer.write_u64(self.timestamp);
+        writer.write_u64(self.complexity);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        Ok(Self {
+            bix: reader.read_u64()?,
+            offset: reader.read_u64()?,
+            hash: reader.read_u256()?,
+            timestamp: reader.read_u64()?,
+            complexity: reader.read_u64()?,
+        })
+    }
 }
 
 
@@ -331,8 +1003,13 @@ pub struct BlockData {
 
 
 impl BlockData {
-    /// Get data of the genesis block (`bix=0`).
+    /// Get data of the genesis block (`bix=0`) of the mainnet.
     pub fn genesis() -> Self {
+        Self::genesis_of(&ChainSpec::mainnet())
+    }
+
+    /// Get data of the genesis block (`bix=0`) of the chain.
+    pub fn genesis_of(spec: &ChainSpec) -> Self {
         Self {
             bix: 0,
             block: Block {
@@ -341,7 +1018,10 @@ impl BlockData {
                 hash_prev: U256::from(0),
                 validator: U256::from(0),
                 nonce: U256::from(0),
-                hash: U256::from_hex(GENESIS_HASH),
+                hash: spec.genesis_hash.clone(),
+                timestamp: 0,
+                complexity: 0,
+                version: BLOCK_VERSION_1,
             },
             transactions: Vec::new(),
         }
@@ -349,11 +1029,28 @@ impl BlockData {
 
     /// Get short information.
     pub fn get_block_info(&self) -> BlockInfo {
-        BlockInfo {
-            bix: self.bix,
-            offset: self.block.offset + self.block.size,
-            hash: self.block.hash.clone(),
-        }
+        BlockInfo::from_block(self.bix, &self.block)
+    }
+}
+
+
+/// Canonical encoding of the block data: `bix` (8 bytes), the encoded block
+/// (see `Block`) and the sequence of the encoded transactions (prefixed with 
+/// their number as 4 bytes). The number of transactions must correspond the
+/// size of the block.
+impl Codec for BlockData {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_u64(self.bix);
+        self.block.encode(writer);
+        writer.write_seq(&self.transactions);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        let bix = reader.read_u64()?;
+        let block = Block::decode(reader)?;
+        let transactions: Vec<Transaction> = reader.read_seq()?;
+        validate!(transactions.len() as u64 == block.size, EncodingInvalid)?;
+        Ok(Self { bix, block, transactions })
     }
 }
 
@@ -363,6 +1060,362 @@ mod tests {
     use super::*;
     use test::Bencher;
     use crate::schema::Schema;
+    use crate::coin::coin_random;
+    use crate::transaction::{Validity, Memo};
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_validate_transactions_validity() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public);
+        let senders = vec![public.clone()];
+
+        let addr: U256 = rng.random();
+        let mut build = |validity| {
+            let mut tr = Transaction::new(coin.clone(), addr.clone(), 
+                                          U256::from(0), U256::from(0))
+                .with_validity(validity);
+            tr.sign(&mut rng, &key, 0, &schema);
+            tr
+        };
+
+        // The next block number is 1
+        let tr = build(Validity::new(1, 3));
+        assert!(Block::validate_transactions(
+            &[tr], &validator, &state, &senders
+        ).is_ok());
+
+        let tr = build(Validity::new(2, 3));
+        let err = Block::validate_transactions(
+            &[tr], &validator, &state, &senders
+        ).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TransactionExpired);
+
+        let context = err.context().unwrap();
+        assert_eq!(context.index, Some(0));
+        assert_eq!(context.coin, Some(coin));
+        assert_eq!(context.expected.as_deref(), Some("2..=3"));
+        assert_eq!(context.actual.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_validate_transactions_self_fee() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+
+        let (key, validator) = schema.gen_pair(&mut rng);
+        let mut build = |addr: U256| {
+            let coin = coin_random(&mut rng, &validator);
+            let mut tr = Transaction::new(coin, addr, U256::from(0), 
+                                          U256::from(0)).with_tagged_hash();
+            tr.sign(&mut rng, &key, 0, &schema);
+            tr
+        };
+
+        let transactions = vec![build(U256::from(100)), build(U256::from(0))];
+        let senders = vec![validator.clone(); 2];
+        let err = Block::validate_transactions(
+            &transactions, &validator, &state, &senders
+        ).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TransactionSelfTransfer);
+        assert_eq!(err.context().unwrap().index, Some(1));
+
+        // Without the fee it is fine
+        assert!(Block::validate_transactions(
+            &transactions[..1], &validator, &state, &senders[..1]
+        ).is_ok());
+
+        // The fees of the earlier versions are not restricted
+        let coin = coin_random(&mut rng, &validator);
+        let transactions = vec![
+            transactions[0].clone(),
+            Transaction::build(&mut rng, coin, U256::from(0), &key, 0, &schema)
+        ];
+        assert!(Block::validate_transactions(
+            &transactions, &validator, &state, &senders
+        ).is_ok());
+    }
+
+    #[test]
+    fn test_validate_report() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+        let complexity = 4;
+        let timestamp = 1700000000;
+
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public);
+        let tr = Transaction::build(&mut rng, coin, U256::from(100), &key, 0, 
+                                    &schema);
+        let transactions = vec![tr.clone(), tr];
+        let senders = vec![public; 2];
+
+        // Valid block
+        let block_info_prev = BlockInfo::genesis();
+        let nonce = Block::mine(&mut rng, &block_info_prev, &validator, 
+                                timestamp, &transactions[..1], complexity, 
+                                None).unwrap();
+        let block = Block::build(&block_info_prev, validator, timestamp, 
+                                 &transactions[..1], U256::from_bytes(&nonce), 
+                                 complexity, &state, &senders[..1]).unwrap();
+        assert!(block.validate_report(&transactions[..1], &block_info_prev, 
+                                      complexity, &state, &senders[..1])
+                     .is_ok());
+
+        // Repeated coin, broken offset and hash are all reported
+        let mut block2 = block.clone();
+        block2.offset = 1;
+        block2.size = 2;
+        let errors = block2.validate_report(&transactions, &block_info_prev, 
+                                            complexity, &state, &senders);
+        let kinds = errors.iter().map(|err| err.kind()).collect::<Vec<_>>();
+        assert_eq!(kinds[..3], [ErrorKind::BlockOffsetMismatch, 
+                                ErrorKind::CoinNotUnique, 
+                                ErrorKind::BlockInvalidHash]);
+        assert_eq!(errors[1].context().unwrap().index, Some(1));
+
+        // The first error is returned by validate
+        assert_eq!(block2.validate(&transactions, &block_info_prev, 
+                                   complexity, &state, &senders)
+                       .unwrap_err().kind(), ErrorKind::BlockOffsetMismatch);
+    }
+
+    #[test]
+    fn test_validate_size() {
+        let mut rng = rand::rng();
+        let tr = Transaction::new(rng.random(), rng.random(), 
+                                  rng.random(), rng.random());
+
+        assert!(Block::validate_size(&vec![tr.clone(); BLOCK_MAX_SIZE])
+                    .is_ok());
+        let err = Block::validate_size(&vec![tr.clone(); BLOCK_MAX_SIZE + 1])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BlockTooLarge);
+        assert_eq!(err.context().unwrap().actual.as_deref(), Some("1025"));
+
+        // The size in bytes is limited too (for transactions with memo)
+        let tr = tr.with_memo(Memo::new(b"abc").unwrap());
+        let count = BLOCK_MAX_BYTES / tr.get_encoded_size();
+        assert!(count < BLOCK_MAX_SIZE);
+        assert!(Block::validate_size(&vec![tr.clone(); count]).is_ok());
+        assert!(Block::validate_size(&vec![tr.clone(); count + 1]).is_err());
+
+        // The legacy blocks are not limited
+        let params = ProtocolParams { block_max_size: 1, 
+                                      ..ProtocolParams::default() };
+        let transactions = vec![tr; 2];
+        assert!(Block::validate_size_for(BLOCK_VERSION_1, &transactions, 
+                                         &params).is_ok());
+        assert_eq!(Block::validate_size_for(BLOCK_VERSION_2, &transactions, 
+                                            &params).unwrap_err().kind(),
+                   ErrorKind::BlockTooLarge);
+    }
+
+    #[test]
+    fn test_codec() {
+        let mut rng = rand::rng();
+
+        let transactions = (0..3).map(|_| Transaction::new(
+            rng.random(), rng.random(), rng.random(), rng.random()
+        )).collect::<Vec<Transaction>>();
+        let block = Block::new(10, 3, rng.random(), rng.random(), 
+                               rng.random(), rng.random())
+            .with_time(1700000000, 24);
+        let block_data = BlockData { bix: 5, block, transactions };
+
+        let bytes = block_data.block.to_bytes();
+        assert_eq!(bytes.len(), 165);
+        assert_eq!(Block::from_bytes(&bytes).unwrap().hash, 
+                   block_data.block.hash);
+
+        let block_info = block_data.get_block_info();
+        let bytes = block_info.to_bytes();
+        assert_eq!(bytes.len(), 64);
+        let block_info2 = BlockInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(block_info2.offset, 13);
+        assert_eq!(block_info2.timestamp, 1700000000);
+        assert_eq!(block_info2.complexity, 24);
+        assert_eq!(block_info2.hash, block_info.hash);
+
+        let bytes = block_data.to_bytes();
+        assert_eq!(bytes.len(), 8 + 165 + 4 + 3 * 129);
+        let block_data2 = BlockData::from_bytes(&bytes).unwrap();
+        assert_eq!(block_data2.to_bytes(), bytes);
+
+        // The number of transactions must correspond the block size
+        let mut block_data3 = block_data2.clone();
+        block_data3.transactions.pop();
+        assert!(BlockData::from_bytes(&block_data3.to_bytes()).is_err());
+
+        // Legacy block
+        let block = Block::new(10, 3, rng.random(), rng.random(), 
+                               rng.random(), rng.random());
+        let bytes = block.to_bytes();
+        assert_eq!(bytes.len(), 149);
+        assert_eq!(Block::from_bytes(&bytes).unwrap().version, 
+                   BLOCK_VERSION_1);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut rng = rand::rng();
+
+        let block = Block::new(10, 3, rng.random(), rng.random(), 
+                               rng.random(), rng.random())
+            .with_time(1700000000, 24);
+        assert_eq!(block.version, BLOCK_VERSION);
+
+        // Block of a future version with extension bytes
+        let mut future = block.clone();
+        future.version = BLOCK_VERSION + 1;
+        let envelope = BlockEnvelope { block: future, extension: vec![1, 2] };
+        let bytes = envelope.to_bytes();
+
+        // The common fields are readable
+        let envelope2 = BlockEnvelope::from_bytes(&bytes).unwrap();
+        assert!(!envelope2.is_supported());
+        assert_eq!(envelope2.block.hash, block.hash);
+        assert_eq!(envelope2.block.timestamp, 1700000000);
+        assert_eq!(envelope2.extension, vec![1, 2]);
+        assert_eq!(envelope2.to_bytes(), bytes);
+
+        // But the block is not
+        assert_eq!(Block::from_bytes(&bytes).unwrap_err().kind(), 
+                   ErrorKind::BlockUnsupportedVersion);
+        assert_eq!(envelope2.into_block().unwrap_err().kind(), 
+                   ErrorKind::BlockUnsupportedVersion);
+
+        // Supported blocks are read by both
+        let bytes = block.to_bytes();
+        assert!(BlockEnvelope::from_bytes(&bytes).unwrap().is_supported());
+        assert_eq!(Block::from_bytes(&bytes).unwrap().version, 
+                   BLOCK_VERSION);
+
+        // Missing version in JSON means a legacy block
+        let mut json = serde_json::to_value(&block).unwrap();
+        json.as_object_mut().unwrap().remove("version");
+        let block2: Block = serde_json::from_value(json).unwrap();
+        assert_eq!(block2.version, BLOCK_VERSION_1);
+        assert_eq!(block2.validate_version().unwrap_err().kind(), 
+                   ErrorKind::BlockInvalidTimestamp);
+    }
+
+    #[test]
+    fn test_version_2() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+        let complexity = 4;
+        let timestamp = 1700000000;
+
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public);
+        let transactions = vec![Transaction::build(
+            &mut rng, coin, U256::from(100), &key, 0, &schema
+        )];
+        let senders = vec![public];
+
+        // Timed block of the previous version
+        let block_info_prev = BlockInfo::genesis();
+        let msg = Block::calc_msg(BLOCK_VERSION_2, block_info_prev.offset, 
+                                  &block_info_prev.hash, &validator, 
+                                  timestamp, complexity as u64, 
+                                  &transactions);
+        let (nonce, hash) = std::iter::repeat_with(|| {
+            let nonce: U256 = rand::rng().random();
+            let hash = Block::calc_hash(&msg, &nonce);
+            (nonce, hash)
+        }).find(|(_, hash)| Block::validate_hash_complexity(
+            hash, transactions.len(), complexity
+        ).is_ok()).unwrap();
+        let mut block = Block::new(block_info_prev.offset, 1, 
+                                   block_info_prev.hash.clone(), validator, 
+                                   nonce, hash)
+            .with_time(timestamp, complexity as u64);
+        block.version = BLOCK_VERSION_2;
+        assert!(block.validate(&transactions, &block_info_prev, complexity, 
+                               &state, &senders).is_ok());
+
+        // It must be timed
+        block.timestamp = 0;
+        assert_eq!(block.validate_version().unwrap_err().kind(), 
+                   ErrorKind::BlockInvalidTimestamp);
+    }
+
+    #[test]
+    fn test_header() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+        let complexity = 4;
+        let timestamp = 1700000000;
+
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public);
+        let transactions = vec![Transaction::build(
+            &mut rng, coin, U256::from(100), &key, 0, &schema
+        )];
+        let senders = vec![public];
+
+        // Mine and build a timed block
+        let block_info_prev = BlockInfo::genesis();
+        let nonce = Block::mine(&mut rng, &block_info_prev, &validator, 
+                                timestamp, &transactions, complexity, 
+                                None).unwrap();
+        let block = Block::build(&block_info_prev, validator, timestamp, 
+                                 &transactions, U256::from_bytes(&nonce), 
+                                 complexity, &state, &senders).unwrap();
+
+        // Validate the header without transactions
+        let header = block.get_header(&transactions);
+        assert!(header.validate(&block_info_prev, complexity).is_ok());
+        assert!(header.validate_body(&transactions).is_ok());
+        assert!(header.validate_body(&[]).is_err());
+        assert_eq!(header.get_block_info(1).hash, block.hash);
+        assert_eq!(BlockHeader::from_bytes(&header.to_bytes()).unwrap().hash, 
+                   block.hash);
+        assert_eq!(header.to_bytes().len(), 193);
+
+        // Broken merkle root
+        let mut header2 = header.clone();
+        header2.merkle_root = U256::from(1);
+        assert_eq!(header2.validate(&block_info_prev, complexity).unwrap_err()
+                       .kind(), ErrorKind::BlockInvalidHash);
+
+        // Forged size and offset (the size sets the required work)
+        let mut header2 = header.clone();
+        header2.size = 0;
+        assert_eq!(header2.validate(&block_info_prev, complexity).unwrap_err()
+                       .kind(), ErrorKind::BlockInvalidHash);
+        let info_prev = BlockInfo { offset: 5, ..block_info_prev.clone() };
+        let mut header2 = header.clone();
+        header2.offset = 5;
+        assert_eq!(header2.validate(&info_prev, complexity).unwrap_err()
+                       .kind(), ErrorKind::BlockInvalidHash);
+
+        // Block with the size different from its transactions
+        let mut block2 = block.clone();
+        block2.size = 2;
+        assert_eq!(block2.validate(&transactions, &block_info_prev, complexity,
+                                   &state, &senders).unwrap_err().kind(),
+                   ErrorKind::BlockBroken);
+
+        // Legacy headers cannot be validated
+        let mut header3 = header.clone();
+        header3.version = BLOCK_VERSION_1;
+        assert_eq!(header3.validate(&block_info_prev, complexity).unwrap_err()
+                       .kind(), ErrorKind::BlockLegacyHeader);
+    }
 
     #[test]
     fn test_mine() {
@@ -374,18 +1427,20 @@ mod tests {
         let mut rng = rand::rng();
         let schema = Schema::new();
 
-        let block_hash_prev: U256 = rng.random();
+        let block_info_prev = BlockInfo { hash: rng.random(), 
+                                          ..BlockInfo::genesis() };
         let validator: U256 = schema.gen_pair(&mut rng).1;
 
         let transactions: Vec<Transaction> = vec![];
 
         // Mining the nonce
-        let nonce_bytes = Block::mine(&mut rng, &block_hash_prev, &validator, 
-                                      &transactions, complexity, 
+        let nonce_bytes = Block::mine(&mut rng, &block_info_prev, &validator, 
+                                      0, &transactions, complexity, 
                                       Some(10000)).unwrap();
 
         // Calculate hash
-        let msg = Block::calc_msg(&block_hash_prev, &validator, &transactions);
+        let msg = Block::calc_msg(BLOCK_VERSION_1, 0, &block_info_prev.hash, 
+                                  &validator, 0, 0, &transactions);
         let nonce = U256::from_bytes(&nonce_bytes);
         let hash = hash_of_u256([&msg, &nonce].into_iter());
 
@@ -397,6 +1452,41 @@ mod tests {
         assert!(Block::is_hash_valid(&hash.to_bytes(), &limit_hash));
     }
 
+    #[test]
+    fn test_mine_parallel() {
+        let complexity = 8;
+
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let block_info_prev = BlockInfo { hash: rng.random(), 
+                                          ..BlockInfo::genesis() };
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let transactions: Vec<Transaction> = vec![];
+
+        // Mining the nonce
+        let stop = AtomicBool::new(false);
+        let timestamp = 1700000000;
+        let nonce_bytes = Block::mine_parallel(&block_info_prev, &validator, 
+                                               timestamp, &transactions, 
+                                               complexity, 2, Some(10000), 
+                                               &stop).unwrap();
+
+        // Check hash
+        let msg = Block::calc_msg(BLOCK_VERSION, 0, &block_info_prev.hash, 
+                                  &validator, timestamp, complexity as u64, 
+                                  &transactions);
+        let hash = Block::calc_hash(&msg, &U256::from_bytes(&nonce_bytes));
+        assert!(Block::validate_hash_complexity(&hash, transactions.len(), 
+                                                complexity).is_ok());
+
+        // Nothing is mined if stopped
+        stop.store(true, Ordering::Relaxed);
+        assert!(Block::mine_parallel(&block_info_prev, &validator, timestamp,
+                                     &transactions, complexity, 0, None, 
+                                     &stop).is_none());
+    }
+
     #[bench]
     fn bench_mine_10(bencher: &mut Bencher) {
         let size = 10;
@@ -404,7 +1494,8 @@ mod tests {
         let mut rng = rand::rng();
         let schema = Schema::new();
 
-        let block_hash_prev: U256 = rng.random();
+        let block_info_prev = BlockInfo { hash: rng.random(), 
+                                          ..BlockInfo::genesis() };
         let validator: U256 = schema.gen_pair(&mut rng).1;
         let coin: U256 = rng.random();
         let addr: U256 = rng.random();
@@ -418,36 +1509,8 @@ mod tests {
         ];
 
         bencher.iter(|| {
-            let _nonce = Block::mine(&mut rng, &block_hash_prev, &validator, 
-                                     &transactions, 1, None);
+            let _nonce = Block::mine(&mut rng, &block_info_prev, &validator, 
+                                     0, &transactions, 1, None);
         });
     }
-    
-    // Uncomment it to start calibration: 
-    //     `cargo bench block::tests::bench_mine_calibration`
-    // #[bench]
-    // fn bench_mine_calibration(bencher: &mut Bencher) {
-    //     // The result is ~4 s/iter
-    //     let complexity = 24;
-
-    //     let mut rng = rand::rng();
-    //     let schema = Schema::new();
-
-    //     let block_hash_prev: U256 = rng.random();
-    //     let validator: U256 = schema.gen_pair(&mut rng).1;
-    //     let coin: U256 = rng.random();
-    //     let addr: U256 = rng.random();
-    //     let key: U256 = schema.gen_key(&mut rng);
-
-    //     let transactions: Vec<Transaction> = vec![
-    //         Transaction::build(
-    //             &mut rng, coin.clone(), addr.clone(), &key, 0, &schema
-    //         ),
-    //     ];
-
-    //     bencher.iter(|| {
-    //         let _nonce = Block::mine(&mut rng, &block_hash_prev, &validator, 
-    //                                  &transactions, complexity, None);
-    //     });
-    // }
 }
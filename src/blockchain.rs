@@ -9,14 +9,530 @@
 //! supporting structured access to individual transactions, blocks, and raw 
 //! bytes. It enables adding new blocks, reading block and transaction history,
 //! and low-level updates of serialized blockchain data.
+//!
+//! Blocks and transactions are stored as fixed-size records of their 
+//! canonical encoding (see `RecordCol`), so the files do not depend on the
+//! memory layout of the structures. The format of the columns is kept in 
+//! `format.json` (see `STORAGE_VERSION`). The columns of the format 1 (the 
+//! raw structures of the first versions) are migrated on opening: they are
+//! converted into temporary columns, `migration.json` marks the conversion
+//! finished, then the columns are renamed and the format is updated, so an
+//! interrupted migration is resumed or restarted on the next start.
+//!
+//! An optional address index (see `Blockchain::with_address_index`) maps the
+//! addresses to their transactions, so explorers do not scan the whole 
+//! transaction column. It is an extra column of entries linked per address,
+//! the last entry of each address is kept in memory.
+//!
+//! Readers do not block each other: they take the shared side of a gate and
+//! one of the read handles (the columns opened separately, see 
+//! `BLOCKCHAIN_READERS`), while the writers take the gate exclusively and 
+//! write through their own handles.
+//!
+//! Writes are journaled: before a block is pushed or the chain is truncated,
+//! the sizes of the columns to return to are synced to `journal.json` (it is
+//! written to a temporary file and renamed, so it is never partial), and the
+//! journal is removed after the columns are synced. If the node crashes in 
+//! between, the partially written block is rolled back on the next start 
+//! (see `Blockchain::recover`).
+//!
+//! Each block has a checksum record in `checksums.col`: the checksums of the
+//! block and of its transactions (see `Checksum`). They are checked when the
+//! blocks are read, so the data corrupted on disk surfaces as 
+//! `StorageChecksumMismatch` (the `InvalidData` IO error wrapping it) instead
+//! of garbage structures. The checksums of the existing chains are calculated
+//! on opening.
+//!
+//! Long-running nodes that need the recent history only can prune the old
+//! transactions (see `Blockchain::prune`). The blocks stay, so the offsets 
+//! remain consistent, and the transaction column starts from the first 
+//! retained transaction (its offset is stored in `prune.json`).
+//!
+//! The dead space of the columns (partial records left by a crash, records
+//! after the last block, index entries of the pruned transactions and 
+//! leftovers of the interrupted pruning) is reclaimed by 
+//! `Blockchain::compact`, long-lived nodes can run it periodically.
+//!
+//! Light clients that need chain verification and inclusion proofs only can
+//! use `LightBlockchain`: it stores the block headers (with the merkle roots)
+//! without the transactions.
+//!
+//! A range of blocks can be exported to a portable archive and imported into
+//! another node (see `Blockchain::export` and `Blockchain::import`). The 
+//! archive starts with `ARCHIVE_MAGIC`, the format version (1 byte), the 
+//! number of the first block and the number of blocks (8 bytes each), then
+//! the canonically encoded `BlockData` records follow (each prefixed with its
+//! length as 4 bytes), and the SHA3-256 checksum of all the previous bytes 
+//! ends the archive.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
+use serde::{Serialize, Deserialize};
 use tokio::io::{Result as TokioResult, ErrorKind};
-use tokio::sync::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{Mutex, MutexGuard, RwLock};
 use lbasedb::col::Col;
 use lbasedb::path_concat;
 
-use crate::transaction::Transaction;
-use crate::block::{Block, BlockInfo, BlockData};
+use crate::validate;
+use crate::utils::*;
+use crate::error::Error;
+use crate::codec::{Codec, Writer};
+use crate::schema::Schema;
+use crate::transaction::{Type, Transaction, Memo};
+use crate::block::{Block, BlockInfo, BlockData, BlockHeader};
+use crate::chain::ChainValidator;
+use crate::state::State;
+use crate::spec::ChainSpec;
+
+
+/// Default number of the read handles.
+pub const BLOCKCHAIN_READERS: usize = 4;
+
+/// Number of blocks read at once on verification and export.
+const READ_BATCH: u64 = 1000;
+
+/// Number of transactions copied at once on pruning.
+const PRUNE_BATCH: u64 = 65536;
+
+/// Number of records converted at once on the migration.
+const MIGRATE_BATCH: usize = 65536;
+
+/// Files of the blockchain in its directory.
+const BLOCKCHAIN_FILES: &[&str] = &[
+    "transactions.col", "blocks.col", "checksums.col", "addresses.col",
+    "journal.json", "prune.json", "transactions.col.tmp", "prune.json.tmp",
+    "addresses.col.tmp", "journal.json.tmp", "format.json", 
+    "format.json.tmp", "migration.json", "migration.json.tmp",
+    "transactions.col.v2", "blocks.col.v2",
+];
+
+/// Column files synced before the journal is removed.
+const BLOCKCHAIN_COLUMNS: &[&str] = &[
+    "transactions.col", "blocks.col", "checksums.col", "addresses.col",
+];
+
+/// Columns converted on the migration from the format 1.
+const MIGRATED_COLUMNS: &[&str] = &["transactions.col", "blocks.col"];
+
+/// Storage format 1: the raw structures of the first versions.
+pub const STORAGE_VERSION_1: u8 = 1;
+
+/// Storage format 2: the records of the canonical encoding.
+pub const STORAGE_VERSION_2: u8 = 2;
+
+/// Current storage format.
+pub const STORAGE_VERSION: u8 = STORAGE_VERSION_2;
+
+/// Size of the transaction record in bytes.
+const TRANSACTION_RECORD_SIZE: usize = 256;
+
+/// Size of the block record in bytes.
+const BLOCK_RECORD_SIZE: usize = 256;
+
+/// First bytes of the chain archive.
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"UQCH";
+
+/// Format version of the chain archive.
+pub const ARCHIVE_VERSION: u8 = 1;
+
+
+/// Entry of the address index: the address, the transaction number 
+/// (1-based) and the position of the previous entry of the address (1-based,
+/// zero if there is none).
+#[derive(Debug, Clone)]
+struct AddressEntry {
+    addr: U256,
+    tix: u64,
+    prev: u64,
+}
+
+
+/// Index of the transactions by address.
+struct AddressIndex {
+    entry_col: Col<AddressEntry>,
+    heads: HashMap<U256, u64>,
+    path: String,
+}
+
+
+impl AddressIndex {
+    async fn new(path: &str) -> TokioResult<Self> {
+        let entry_col = Col::<AddressEntry>::new(
+            path_concat!(path, "addresses.col")
+        ).await?;
+        let mut instance = Self { 
+            entry_col, 
+            heads: HashMap::new(), 
+            path: path.to_string(),
+        };
+        instance.load_heads().await?;
+        Ok(instance)
+    }
+
+    /// Remove the entries of the transactions up to `transaction_base` (the
+    /// pruned ones), they are the first entries, so the others are shifted.
+    async fn drop_pruned(&mut self, transaction_base: u64) -> 
+                         TokioResult<()> {
+        // Count the entries to drop
+        let size = self.entry_col.size().await?;
+        let mut dropped = 0;
+        while (dropped < size) && 
+              (self.entry_col.get(dropped).await?.tix <= transaction_base) {
+            dropped += 1;
+        }
+        if dropped == 0 {
+            return Ok(());
+        }
+
+        // Copy the rest with the shifted links
+        let col_path = path_concat!(self.path.as_str(), "addresses.col");
+        let col_path_tmp = format!("{}.tmp", col_path);
+        let mut col_tmp = Col::<AddressEntry>::new(&col_path_tmp).await?;
+        col_tmp.resize(0).await?;
+        let mut position = dropped;
+        while position < size {
+            let count = (PRUNE_BATCH as usize).min(size - position);
+            let entries: Vec<AddressEntry> = self.entry_col
+                .get_many(position, count).await?.into_iter()
+                .map(|entry| AddressEntry {
+                    prev: entry.prev.saturating_sub(dropped as u64),
+                    ..entry
+                }).collect();
+            col_tmp.push_many(&entries).await?;
+            position += count;
+        }
+        tokio::fs::rename(&col_path_tmp, &col_path).await?;
+
+        self.entry_col = Col::<AddressEntry>::new(&col_path).await?;
+        self.load_heads().await
+    }
+
+    /// Collect the last entries of the addresses.
+    async fn load_heads(&mut self) -> TokioResult<()> {
+        self.heads.clear();
+        for (ix, entry) in self.entry_col.get_all().await?.into_iter()
+                                                           .enumerate() {
+            self.heads.insert(entry.addr, ix as u64 + 1);
+        }
+        Ok(())
+    }
+
+    /// Transaction number of the last entry (0 if empty).
+    async fn get_last_tix(&mut self) -> TokioResult<u64> {
+        let size = self.entry_col.size().await?;
+        if size > 0 {
+            Ok(self.entry_col.get(size - 1).await?.tix)
+        } else {
+            Ok(0)
+        }
+    }
+
+    async fn push(&mut self, addr: &U256, tix: u64) -> TokioResult<()> {
+        let prev = self.heads.get(addr).copied().unwrap_or(0);
+        let entry = AddressEntry { addr: addr.clone(), tix, prev };
+        let ix = self.entry_col.push(&entry).await?;
+        self.heads.insert(addr.clone(), ix as u64 + 1);
+        Ok(())
+    }
+
+    /// Index the transactions starting from `tix` (1-based). The recipients
+    /// of transfers are indexed, the senders are indexed if they are given.
+    async fn push_transactions(&mut self, tix: u64, 
+                               transactions: &[Transaction], 
+                               senders: Option<&[U256]>) -> TokioResult<()> {
+        for (ix, tr) in transactions.iter().enumerate() {
+            if let Some(senders) = senders {
+                self.push(&senders[ix], tix + ix as u64).await?;
+            }
+            if tr.get_type() == Type::Transfer {
+                self.push(&tr.addr, tix + ix as u64).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the entries of the transactions above `transaction_count`.
+    async fn truncate(&mut self, transaction_count: u64) -> TokioResult<()> {
+        let mut size = self.entry_col.size().await?;
+        while (size > 0) && 
+              (self.entry_col.get(size - 1).await?.tix > transaction_count) {
+            size -= 1;
+        }
+        self.entry_col.resize(size).await?;
+        self.load_heads().await
+    }
+
+    /// Get transaction numbers of the address from the most recent.
+    async fn get_tixs(&mut self, addr: &U256, offset: usize, 
+                      count: usize) -> TokioResult<Vec<u64>> {
+        let mut tixs = Vec::new();
+        let mut position = self.heads.get(addr).copied().unwrap_or(0);
+        let mut skipped = 0;
+        while (position > 0) && (tixs.len() < count) {
+            let entry = self.entry_col.get(position as usize - 1).await?;
+            if skipped < offset {
+                skipped += 1;
+            } else {
+                tixs.push(entry.tix);
+            }
+            position = entry.prev;
+        }
+        Ok(tixs)
+    }
+}
+
+
+/// Sizes of the columns the interrupted write is rolled back to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Journal {
+    block_count: u64,
+    transaction_count: u64,
+}
+
+
+/// Number of the blocks whose transactions are pruned and the number of the
+/// pruned transactions (the offset of the first stored one).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PruneMeta {
+    block_count: u64,
+    transaction_count: u64,
+}
+
+
+/// Checksums of a stored block and of its transactions: the first 8 bytes
+/// of SHA3-256 of their canonical encoding.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Checksum {
+    block: u64,
+    transactions: u64,
+}
+
+
+impl Checksum {
+    /// Checksums of the block and its transactions (zero if they are not 
+    /// stored).
+    fn new(block: &Block, transactions: Option<&[Transaction]>) -> Self {
+        Self {
+            block: Self::calc(std::slice::from_ref(block)),
+            transactions: transactions.map(Self::calc).unwrap_or(0),
+        }
+    }
+
+    fn calc<T: Codec>(items: &[T]) -> u64 {
+        let mut hasher = Hasher256::new();
+        for item in items.iter() {
+            hasher.update(item.to_bytes());
+        }
+        u64::from_le_bytes(hasher.finalize_bytes()[..8].try_into().unwrap())
+    }
+
+    /// Check the block and its transactions (if given) against the 
+    /// checksums.
+    fn check(&self, block: &Block, 
+             transactions: Option<&[Transaction]>) -> UqoinResult<()> {
+        let mut pairs = vec![(self.block, 
+                              Self::calc(std::slice::from_ref(block)))];
+        if let Some(transactions) = transactions {
+            pairs.push((self.transactions, Self::calc(transactions)));
+        }
+        for (expected, actual) in pairs.into_iter() {
+            validate!(expected == actual, StorageChecksumMismatch)
+                .map_err(|err| err.with_values(format!("{:016x}", expected), 
+                                               format!("{:016x}", actual)))?;
+        }
+        Ok(())
+    }
+
+    /// Convert the mismatch of the block into IO error.
+    fn to_io_error(bix: u64, err: Error) -> std::io::Error {
+        std::io::Error::new(ErrorKind::InvalidData, err.with_bix(bix))
+    }
+}
+
+
+/// Column of the structures stored as fixed-size records of `N` bytes: the
+/// length of the canonical encoding (2 bytes) and the encoding padded with
+/// zeros. The records that cannot be decoded are returned as `InvalidData`.
+struct RecordCol<T, const N: usize> {
+    col: Col<[u8; N]>,
+    phantom: PhantomData<T>,
+}
+
+
+impl<T: Codec, const N: usize> RecordCol<T, N> {
+    async fn new(path: impl AsRef<Path>) -> TokioResult<Self> {
+        Ok(Self { col: Col::new(path).await?, phantom: PhantomData })
+    }
+
+    fn block_size() -> usize {
+        N
+    }
+
+    async fn size(&self) -> TokioResult<usize> {
+        self.col.size().await
+    }
+
+    async fn resize(&self, size: usize) -> TokioResult<()> {
+        self.col.resize(size).await
+    }
+
+    async fn push(&mut self, item: &T) -> TokioResult<usize> {
+        self.col.push(&Self::encode(item)).await
+    }
+
+    async fn push_many(&mut self, items: &[T]) -> TokioResult<usize> {
+        let records: Vec<[u8; N]> = items.iter().map(Self::encode).collect();
+        self.col.push_many(&records).await
+    }
+
+    async fn get(&mut self, ix: usize) -> TokioResult<T> {
+        Self::decode(&self.col.get(ix).await?)
+    }
+
+    async fn get_many(&mut self, ix: usize, 
+                      count: usize) -> TokioResult<Vec<T>> {
+        self.col.get_many(ix, count).await?.iter().map(Self::decode)
+            .collect()
+    }
+
+    async fn update_many(&mut self, ix: usize, 
+                         items: &[T]) -> TokioResult<()> {
+        let records: Vec<[u8; N]> = items.iter().map(Self::encode).collect();
+        self.col.update_many(ix, &records).await
+    }
+
+    async fn get_raw(&mut self, ix: usize, 
+                     count: usize) -> TokioResult<Vec<u8>> {
+        self.col.get_raw(ix, count).await
+    }
+
+    async fn update_raw(&mut self, ix: usize, 
+                        bytes: &[u8]) -> TokioResult<()> {
+        self.col.update_raw(ix, bytes).await
+    }
+
+    /// Record of the structure. The encoding must fit the record.
+    fn encode(item: &T) -> [u8; N] {
+        let bytes = item.to_bytes();
+        assert!(bytes.len() + 2 <= N);
+        let mut record = [0; N];
+        record[..2].copy_from_slice(&(bytes.len() as u16).to_le_bytes());
+        record[2..bytes.len() + 2].copy_from_slice(&bytes);
+        record
+    }
+
+    /// Decode the record. The length must fit the record and the padding
+    /// must be zero.
+    fn decode(record: &[u8; N]) -> TokioResult<T> {
+        let size = u16::from_le_bytes([record[0], record[1]]) as usize;
+        validate!((size + 2 <= N) && 
+                  record[size + 2..].iter().all(|byte| *byte == 0), 
+                  EncodingInvalid)
+            .and_then(|_| T::from_bytes(&record[2..size + 2]))
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+    }
+}
+
+
+/// Column of the transaction records.
+type TransactionCol = RecordCol<Transaction, TRANSACTION_RECORD_SIZE>;
+
+/// Column of the block records.
+type BlockCol = RecordCol<Block, BLOCK_RECORD_SIZE>;
+
+
+/// Storage format of the columns.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FormatMeta {
+    version: u8,
+}
+
+
+/// Transaction of the storage format 1 (the raw structure of version 1).
+#[derive(Debug, Clone)]
+#[repr(C)]
+struct LegacyTransaction {
+    coin: U256,
+    addr: U256,
+    sign_r: U256,
+    sign_s: U256,
+}
+
+
+impl From<LegacyTransaction> for Transaction {
+    fn from(legacy: LegacyTransaction) -> Self {
+        Self::new(legacy.coin, legacy.addr, legacy.sign_r, legacy.sign_s)
+    }
+}
+
+
+/// Block of the storage format 1 (the raw structure of version 1).
+#[derive(Debug, Clone)]
+#[repr(C)]
+struct LegacyBlock {
+    offset: u64,
+    size: u64,
+    hash_prev: U256,
+    validator: U256,
+    nonce: U256,
+    hash: U256,
+}
+
+
+impl From<LegacyBlock> for Block {
+    fn from(legacy: LegacyBlock) -> Self {
+        Self::new(legacy.offset, legacy.size, legacy.hash_prev, 
+                  legacy.validator, legacy.nonce, legacy.hash)
+    }
+}
+
+
+/// Result of the compaction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactionReport {
+    /// Size of the files before in bytes.
+    pub size_before: u64,
+
+    /// Size of the files after in bytes.
+    pub size_after: u64,
+}
+
+
+impl CompactionReport {
+    /// Number of the reclaimed bytes.
+    pub fn get_reclaimed(&self) -> u64 {
+        self.size_before.saturating_sub(self.size_after)
+    }
+}
+
+
+/// Columns opened for reading.
+struct ReadHandle {
+    transaction_col: TransactionCol,
+    block_col: BlockCol,
+    checksum_col: Col<Checksum>,
+}
+
+
+impl ReadHandle {
+    async fn new(path: &str) -> TokioResult<Self> {
+        Ok(Self {
+            transaction_col: TransactionCol::new(
+                path_concat!(path, "transactions.col")
+            ).await?,
+            block_col: BlockCol::new(
+                path_concat!(path, "blocks.col")
+            ).await?,
+            checksum_col: Col::<Checksum>::new(
+                path_concat!(path, "checksums.col")
+            ).await?,
+        })
+    }
+}
 
 
 /// A driver for storing and retrieving blocks and transactions on disk.
@@ -25,8 +541,17 @@ use crate::block::{Block, BlockInfo, BlockData};
 /// asynchronous access. It supports structured access to blocks and 
 /// transactions, as well as raw byte-level operations for advanced use cases.
 pub struct Blockchain {
-    transaction_col: Mutex<Col<Transaction>>,
-    block_col: Mutex<Col<Block>>,
+    transaction_col: Mutex<TransactionCol>,
+    block_col: Mutex<BlockCol>,
+    checksum_col: Mutex<Col<Checksum>>,
+    readers: Vec<Mutex<ReadHandle>>,
+    reader_next: AtomicUsize,
+    gate: RwLock<()>,
+    pruned_block_count: AtomicU64,
+    transaction_base: AtomicU64,
+    path: String,
+    address_index: Option<Mutex<AddressIndex>>,
+    chain_spec: ChainSpec,
 }
 
 
@@ -34,13 +559,505 @@ impl Blockchain {
     /// Creates a new blockchain instance by opening transaction and block 
     /// storage at the given path.
     pub async fn new(path: &str) -> TokioResult<Self> {
-        let transaction_col = Mutex::new(Col::<Transaction>::new(
+        Self::with_readers(path, BLOCKCHAIN_READERS).await
+    }
+
+    /// Open the blockchain with the given number of read handles (the 
+    /// maximum number of concurrent reads). The columns of the older storage
+    /// formats are migrated.
+    pub async fn with_readers(path: &str, readers: usize) -> 
+                              TokioResult<Self> {
+        assert!(readers > 0);
+        let prune_meta = Self::load_prune_meta(path).await?;
+        Self::migrate(path).await?;
+        let transaction_col = Mutex::new(TransactionCol::new(
             path_concat!(path, "transactions.col")
         ).await?);
-        let block_col = Mutex::new(Col::<Block>::new(
+        let block_col = Mutex::new(BlockCol::new(
             path_concat!(path, "blocks.col")
         ).await?);
-        Ok(Self { transaction_col, block_col })
+        let checksum_col = Mutex::new(Col::<Checksum>::new(
+            path_concat!(path, "checksums.col")
+        ).await?);
+        let mut read_handles = Vec::new();
+        for _ in 0..readers {
+            read_handles.push(Mutex::new(ReadHandle::new(path).await?));
+        }
+        let instance = Self { 
+            transaction_col, block_col, checksum_col,
+            readers: read_handles,
+            reader_next: AtomicUsize::new(0),
+            gate: RwLock::new(()),
+            pruned_block_count: AtomicU64::new(prune_meta.block_count),
+            transaction_base: AtomicU64::new(prune_meta.transaction_count),
+            path: path.to_string(), 
+            address_index: None,
+            chain_spec: ChainSpec::mainnet(),
+        };
+        instance.recover().await?;
+        instance.fill_checksums().await?;
+        Ok(instance)
+    }
+
+    /// Calculate the missing checksums (e.g. of the chain stored before the
+    /// checksums were introduced).
+    async fn fill_checksums(&self) -> TokioResult<()> {
+        let _gate = self.gate.write().await;
+        let block_count = self.block_col.lock().await.size().await?;
+        let checksum_col = self.checksum_col.lock().await;
+        let checksum_count = checksum_col.size().await?;
+        drop(checksum_col);
+        if checksum_count > block_count {
+            self.checksum_col.lock().await.resize(block_count).await
+        } else {
+            self.refresh_checksums(checksum_count, block_count).await
+        }
+    }
+
+    /// Recalculate the checksums of the blocks from the position `from` to 
+    /// `to` (0-based, exclusive). The write gate must be taken.
+    async fn refresh_checksums(&self, from: usize, 
+                               to: usize) -> TokioResult<()> {
+        let mut block_col = self.block_col.lock().await;
+        let mut transaction_col = self.transaction_col.lock().await;
+        let mut checksum_col = self.checksum_col.lock().await;
+        let from = from.min(checksum_col.size().await?);
+        let transaction_end = self.get_transaction_base() + 
+                              transaction_col.size().await? as u64;
+
+        let mut position = from;
+        while position < to {
+            let count = (READ_BATCH as usize).min(to - position);
+            let blocks = block_col.get_many(position, count).await?;
+            let mut checksums = Vec::with_capacity(count);
+            for block in blocks.iter() {
+                // The transactions are skipped if they are pruned or not 
+                // written yet
+                let transactions = if (block.offset >= 
+                                       self.get_transaction_base()) && 
+                        (block.offset + block.size <= transaction_end) {
+                    Some(transaction_col.get_many(
+                        self.get_transaction_position(block.offset)?, 
+                        block.size as usize
+                    ).await?)
+                } else {
+                    None
+                };
+                checksums.push(Checksum::new(block, transactions.as_deref()));
+            }
+            checksum_col.update_many(position, &checksums).await?;
+            position += count;
+        }
+        Ok(())
+    }
+
+    /// Position of the first block that contains transactions at the offset 
+    /// or after it. The write gate must be taken.
+    async fn find_block_position(&self, offset: u64) -> TokioResult<usize> {
+        let mut block_col = self.block_col.lock().await;
+        let (mut left, mut right) = (0, block_col.size().await?);
+        while left < right {
+            let middle = (left + right) / 2;
+            let block = block_col.get(middle).await?;
+            if block.offset + block.size <= offset {
+                left = middle + 1;
+            } else {
+                right = middle;
+            }
+        }
+        Ok(left)
+    }
+
+    /// Roll back the write interrupted by a crash if the journal is left.
+    /// Returns `true` if anything was rolled back. It is called on `new`.
+    pub async fn recover(&self) -> TokioResult<bool> {
+        let _gate = self.gate.write().await;
+        let path = self.get_journal_path();
+
+        // The journal was not renamed, so the write has not started
+        let path_tmp = format!("{}.tmp", path);
+        if tokio::fs::try_exists(&path_tmp).await? {
+            tokio::fs::remove_file(&path_tmp).await?;
+        }
+
+        if tokio::fs::try_exists(&path).await? {
+            let journal: Journal = serde_json::from_slice(
+                &tokio::fs::read(&path).await?
+            )?;
+            self.block_col.lock().await
+                .resize(journal.block_count as usize).await?;
+            self.checksum_col.lock().await
+                .resize(journal.block_count as usize).await?;
+            let position = self.get_transaction_position(
+                journal.transaction_count
+            )?;
+            self.transaction_col.lock().await.resize(position).await?;
+            if let Some(index) = self.address_index.as_ref() {
+                index.lock().await.truncate(journal.transaction_count).await?;
+            }
+            self.end_write().await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Sync the journal before the write: it is written to a temporary file
+    /// and renamed, so a crash never leaves a partial journal.
+    async fn begin_write(&self, block_count: u64, 
+                         transaction_count: u64) -> TokioResult<()> {
+        let journal = Journal { block_count, transaction_count };
+        let path = self.get_journal_path();
+        let path_tmp = format!("{}.tmp", path);
+        let mut file = tokio::fs::File::create(&path_tmp).await?;
+        file.write_all(&serde_json::to_vec(&journal)?).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&path_tmp, &path).await?;
+        sync_dir(&self.path).await
+    }
+
+    /// Sync the columns and remove the journal after the write.
+    async fn end_write(&self) -> TokioResult<()> {
+        for name in BLOCKCHAIN_COLUMNS.iter() {
+            let path = path_concat!(self.path.as_str(), name);
+            if tokio::fs::try_exists(&path).await? {
+                sync_file(&path).await?;
+            }
+        }
+        tokio::fs::remove_file(self.get_journal_path()).await?;
+        sync_dir(&self.path).await
+    }
+
+    /// Take a free read handle or wait for one.
+    async fn get_reader(&self) -> MutexGuard<'_, ReadHandle> {
+        for reader in self.readers.iter() {
+            if let Ok(guard) = reader.try_lock() {
+                return guard;
+            }
+        }
+        let ix = self.reader_next.fetch_add(1, Ordering::Relaxed);
+        self.readers[ix % self.readers.len()].lock().await
+    }
+
+    fn get_journal_path(&self) -> String {
+        path_concat!(self.path.as_str(), "journal.json")
+    }
+
+    /// Number of the blocks whose transactions are pruned.
+    pub fn get_pruned_block_count(&self) -> u64 {
+        self.pruned_block_count.load(Ordering::Acquire)
+    }
+
+    /// Offset of the first stored transaction (the number of the pruned 
+    /// ones).
+    pub fn get_transaction_base(&self) -> u64 {
+        self.transaction_base.load(Ordering::Acquire)
+    }
+
+    /// Position of the transaction in the column by its offset (0-based).
+    /// `NotFound` is returned for the pruned transactions.
+    fn get_transaction_position(&self, offset: u64) -> TokioResult<usize> {
+        offset.checked_sub(self.get_transaction_base())
+            .map(|position| position as usize)
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
+
+    /// Delete the transactions of all the blocks except the last 
+    /// `keep_blocks` ones. The blocks are kept. Returns the number of the
+    /// deleted transactions.
+    pub async fn prune(&self, keep_blocks: u64) -> TokioResult<u64> {
+        let _gate = self.gate.write().await;
+        let block_count = self.block_col.lock().await.size().await? as u64;
+        if block_count <= keep_blocks + self.get_pruned_block_count() {
+            return Ok(0);
+        }
+
+        // Offset of the first retained transaction
+        let pruned_block_count = block_count - keep_blocks;
+        let block = self.block_col.lock().await
+            .get(pruned_block_count as usize - 1).await?;
+        let base = self.get_transaction_base();
+        let base_new = block.offset + block.size;
+
+        // Copy the retained transactions into a new column
+        let col_path = path_concat!(self.path.as_str(), "transactions.col");
+        let col_path_tmp = format!("{}.tmp", col_path);
+        let mut col_tmp = TransactionCol::new(&col_path_tmp).await?;
+        col_tmp.resize(0).await?;
+        let mut transaction_col = self.transaction_col.lock().await;
+        let size = transaction_col.size().await? as u64;
+        let mut position = base_new - base;
+        while position < size {
+            let count = PRUNE_BATCH.min(size - position);
+            let transactions = transaction_col
+                .get_many(position as usize, count as usize).await?;
+            col_tmp.push_many(&transactions).await?;
+            position += count;
+        }
+
+        // Commit: the metadata is renamed after the column (see 
+        // `load_prune_meta` for recovery)
+        let meta = PruneMeta {
+            block_count: pruned_block_count, 
+            transaction_count: base_new,
+        };
+        let meta_path = path_concat!(self.path.as_str(), "prune.json");
+        let meta_path_tmp = format!("{}.tmp", meta_path);
+        let mut file = tokio::fs::File::create(&meta_path_tmp).await?;
+        file.write_all(&serde_json::to_vec(&meta)?).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&col_path_tmp, &col_path).await?;
+        tokio::fs::rename(&meta_path_tmp, &meta_path).await?;
+
+        // Reopen the column
+        *transaction_col = TransactionCol::new(&col_path).await?;
+        for reader in self.readers.iter() {
+            reader.lock().await.transaction_col = 
+                TransactionCol::new(&col_path).await?;
+        }
+        self.pruned_block_count.store(pruned_block_count, Ordering::Release);
+        self.transaction_base.store(base_new, Ordering::Release);
+
+        Ok(base_new - base)
+    }
+
+    /// Total size of the blockchain files in bytes.
+    pub async fn get_disk_size(&self) -> TokioResult<u64> {
+        let mut size = 0;
+        for name in BLOCKCHAIN_FILES.iter() {
+            let path = path_concat!(self.path.as_str(), name);
+            if tokio::fs::try_exists(&path).await? {
+                size += tokio::fs::metadata(&path).await?.len();
+            }
+        }
+        Ok(size)
+    }
+
+    /// Reclaim the dead space: trim the partial records and the records 
+    /// after the last block, drop the address index entries of the pruned 
+    /// transactions and remove the leftovers of the interrupted pruning. It
+    /// must not be run between the raw updates of transactions and their 
+    /// blocks (see `update_transaction_raw`), as the transactions after the
+    /// last block are dropped.
+    pub async fn compact(&self) -> TokioResult<CompactionReport> {
+        let _gate = self.gate.write().await;
+        let size_before = self.get_disk_size().await?;
+
+        // Leftovers of the interrupted pruning
+        for name in ["transactions.col.tmp", "prune.json.tmp"].iter() {
+            let path = path_concat!(self.path.as_str(), name);
+            if tokio::fs::try_exists(&path).await? {
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+
+        // Trim the columns to the last block
+        let mut block_col = self.block_col.lock().await;
+        let block_count = block_col.size().await?;
+        block_col.resize(block_count).await?;
+        self.checksum_col.lock().await.resize(block_count).await?;
+        let transaction_count = if block_count > 0 {
+            let block = block_col.get(block_count - 1).await?;
+            block.offset + block.size
+        } else {
+            self.get_transaction_base()
+        };
+        drop(block_col);
+        let position = self.get_transaction_position(transaction_count)?;
+        self.transaction_col.lock().await.resize(position).await?;
+
+        // Address index
+        if let Some(index) = self.address_index.as_ref() {
+            let mut index = index.lock().await;
+            index.truncate(transaction_count).await?;
+            index.drop_pruned(self.get_transaction_base()).await?;
+        }
+
+        Ok(CompactionReport { 
+            size_before, 
+            size_after: self.get_disk_size().await?,
+        })
+    }
+
+    /// Load the pruning metadata finishing or discarding the interrupted
+    /// pruning.
+    async fn load_prune_meta(path: &str) -> TokioResult<PruneMeta> {
+        let col_path_tmp = path_concat!(path, "transactions.col.tmp");
+        let meta_path = path_concat!(path, "prune.json");
+        let meta_path_tmp = format!("{}.tmp", meta_path);
+        let col_renamed = !tokio::fs::try_exists(&col_path_tmp).await?;
+        if tokio::fs::try_exists(&meta_path_tmp).await? {
+            if col_renamed {
+                tokio::fs::rename(&meta_path_tmp, &meta_path).await?;
+            } else {
+                tokio::fs::remove_file(&meta_path_tmp).await?;
+            }
+        }
+        if !col_renamed {
+            tokio::fs::remove_file(&col_path_tmp).await?;
+        }
+
+        if tokio::fs::try_exists(&meta_path).await? {
+            Ok(serde_json::from_slice(&tokio::fs::read(&meta_path).await?)?)
+        } else {
+            Ok(PruneMeta::default())
+        }
+    }
+
+    /// Migrate the columns of the older storage formats (see the module 
+    /// docs). `InvalidData` is returned if the format is unknown.
+    async fn migrate(path: &str) -> TokioResult<()> {
+        let format_path = path_concat!(path, "format.json");
+        let marker_path = path_concat!(path, "migration.json");
+
+        // Convert the columns of the format 1 (stored without the format)
+        if !tokio::fs::try_exists(&format_path).await? && 
+                !tokio::fs::try_exists(&marker_path).await? && 
+                Self::has_columns(path).await? {
+            Self::convert_legacy::<LegacyTransaction, Transaction, 
+                                   TRANSACTION_RECORD_SIZE>(
+                &path_concat!(path, "transactions.col")
+            ).await?;
+            Self::convert_legacy::<LegacyBlock, Block, BLOCK_RECORD_SIZE>(
+                &path_concat!(path, "blocks.col")
+            ).await?;
+            write_meta(path, "migration.json", 
+                       &FormatMeta { version: STORAGE_VERSION }).await?;
+        }
+
+        // Replace the columns with the converted ones
+        if tokio::fs::try_exists(&marker_path).await? {
+            for name in MIGRATED_COLUMNS.iter() {
+                let col_path = path_concat!(path, name);
+                let col_path_new = format!("{}.v2", col_path);
+                if tokio::fs::try_exists(&col_path_new).await? {
+                    tokio::fs::rename(&col_path_new, &col_path).await?;
+                }
+            }
+            sync_dir(path).await?;
+        }
+
+        // Update the format
+        if !tokio::fs::try_exists(&format_path).await? {
+            write_meta(path, "format.json", 
+                       &FormatMeta { version: STORAGE_VERSION }).await?;
+        }
+        if tokio::fs::try_exists(&marker_path).await? {
+            tokio::fs::remove_file(&marker_path).await?;
+            sync_dir(path).await?;
+        }
+
+        // Check the format
+        let meta: FormatMeta = serde_json::from_slice(
+            &tokio::fs::read(&format_path).await?
+        )?;
+        if meta.version != STORAGE_VERSION {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        Ok(())
+    }
+
+    /// Check if any column of blocks or transactions is not empty.
+    async fn has_columns(path: &str) -> TokioResult<bool> {
+        for name in MIGRATED_COLUMNS.iter() {
+            let col_path = path_concat!(path, name);
+            if tokio::fs::try_exists(&col_path).await? && 
+                    (tokio::fs::metadata(&col_path).await?.len() > 0) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Convert the column of the format 1 into the column of the current 
+    /// format next to it (with the `.v2` suffix).
+    async fn convert_legacy<L, T, const N: usize>(col_path: &str) -> 
+                                                  TokioResult<()>
+                                                  where L: Clone + Into<T>, 
+                                                        T: Codec {
+        let mut legacy_col = Col::<L>::new(col_path).await?;
+        let col_path_new = format!("{}.v2", col_path);
+        let mut col = RecordCol::<T, N>::new(&col_path_new).await?;
+        col.resize(0).await?;
+        let size = legacy_col.size().await?;
+        let mut position = 0;
+        while position < size {
+            let count = MIGRATE_BATCH.min(size - position);
+            let items: Vec<T> = legacy_col.get_many(position, count).await?
+                .into_iter().map(Into::into).collect();
+            col.push_many(&items).await?;
+            position += count;
+        }
+        sync_file(&col_path_new).await
+    }
+
+    /// Set the chain spec (the mainnet one is the default). `InvalidData` is 
+    /// returned if the stored chain does not start from the genesis of the 
+    /// spec.
+    pub async fn with_chain_spec(mut self, spec: ChainSpec) -> 
+                                 TokioResult<Self> {
+        if !self.is_empty().await? {
+            let block = self.get_block(1).await?;
+            if block.hash_prev != spec.genesis_hash {
+                return Err(ErrorKind::InvalidData.into());
+            }
+        }
+        self.chain_spec = spec;
+        Ok(self)
+    }
+
+    /// Get the chain spec.
+    pub fn get_chain_spec(&self) -> &ChainSpec {
+        &self.chain_spec
+    }
+
+    /// Enable the address index. The transactions stored before are indexed
+    /// by their recipients.
+    pub async fn with_address_index(mut self) -> TokioResult<Self> {
+        let mut index = AddressIndex::new(&self.path).await?;
+
+        // Drop the entries of the rolled back transactions
+        let count = self.get_transaction_count().await?;
+        index.truncate(count).await?;
+
+        // Index the transactions that are not indexed yet
+        let tix = (index.get_last_tix().await? + 1)
+            .max(self.get_transaction_base() + 1);
+        if tix <= count {
+            let transactions = self.get_transaction_many(
+                tix as usize - 1, (count - tix + 1) as usize
+            ).await?;
+            index.push_transactions(tix, &transactions, None).await?;
+        }
+
+        self.address_index = Some(Mutex::new(index));
+        Ok(self)
+    }
+
+    /// Check if the address index is enabled.
+    pub fn has_address_index(&self) -> bool {
+        self.address_index.is_some()
+    }
+
+    /// Retrieves the transactions of the address with their indices (1-based)
+    /// from the most recent one. `Unsupported` is returned if the address 
+    /// index is not enabled.
+    pub async fn get_transactions_by_address(&self, addr: &U256, 
+                                             offset: usize, count: usize) -> 
+                                             TokioResult<Vec<(u64, 
+                                                              Transaction)>> {
+        let index = self.address_index.as_ref()
+            .ok_or(ErrorKind::Unsupported)?;
+        let tixs = index.lock().await.get_tixs(addr, offset, count).await?;
+        let mut result = Vec::new();
+        for tix in tixs {
+            // Stop at the pruned transactions
+            if tix <= self.get_transaction_base() {
+                break;
+            }
+            result.push((tix, self.get_transaction(tix).await?));
+        }
+        Ok(result)
     }
 
     /// Checks whether the blockchain contains any blocks.
@@ -51,14 +1068,16 @@ impl Blockchain {
 
     /// Retrieves the total number of blocks stored in the blockchain.
     pub async fn get_block_count(&self) -> TokioResult<u64> {
-        let size = self.block_col.lock().await.size().await?;
+        let _gate = self.gate.read().await;
+        let size = self.get_reader().await.block_col.size().await?;
         Ok(size as u64)
     }
 
     /// Retrieves the total number of transactions stored in the blockchain.
     pub async fn get_transaction_count(&self) -> TokioResult<u64> {
-        let size = self.transaction_col.lock().await.size().await?;
-        Ok(size as u64)
+        let _gate = self.gate.read().await;
+        let size = self.get_reader().await.transaction_col.size().await?;
+        Ok(self.get_transaction_base() + size as u64)
     }
 
     /// Retrieves a block by its index (1-based).
@@ -66,7 +1085,13 @@ impl Blockchain {
         if bix == 0 {
             Err(ErrorKind::NotFound.into())
         } else {
-            self.block_col.lock().await.get(bix as usize - 1).await
+            let _gate = self.gate.read().await;
+            let mut reader = self.get_reader().await;
+            let block = reader.block_col.get(bix as usize - 1).await?;
+            reader.checksum_col.get(bix as usize - 1).await?
+                .check(&block, None)
+                .map_err(|err| Checksum::to_io_error(bix, err))?;
+            Ok(block)
         }
     }
 
@@ -76,34 +1101,45 @@ impl Blockchain {
         if tix == 0 {
             Err(ErrorKind::NotFound.into())
         } else {
-            self.transaction_col.lock().await.get(tix as usize - 1).await
+            let _gate = self.gate.read().await;
+            let position = self.get_transaction_position(tix - 1)?;
+            self.get_reader().await.transaction_col.get(position).await
         }
     }
 
+    /// Retrieves the memo of a transaction by its index (1-based).
+    pub async fn get_transaction_memo(&self, tix: u64) -> 
+                                      TokioResult<Option<Memo>> {
+        let transaction = self.get_transaction(tix).await?;
+        Ok(transaction.memo)
+    }
+
     /// Retrieves basic information (`BlockInfo`) about a block by its index
     /// (1-based). 
     pub async fn get_block_info(&self, bix: u64) -> TokioResult<BlockInfo> {
         if bix == 0 {
-            Ok(BlockInfo::genesis())
+            Ok(self.chain_spec.get_genesis_info())
         } else {
             let block = self.get_block(bix).await?;
-            Ok(BlockInfo {
-                bix,
-                offset: block.offset + block.size,
-                hash: block.hash,
-            })
+            Ok(BlockInfo::from_block(bix, &block))
         }
     }
 
+    /// Retrieves the header of the block by its index (1-based). The merkle
+    /// root is calculated from the transactions of the block.
+    pub async fn get_block_header(&self, bix: u64) -> TokioResult<BlockHeader> {
+        let block = self.get_block(bix).await?;
+        let transactions = self.get_transactions_of_block(&block).await?;
+        Ok(block.get_header(&transactions))
+    }
+
     /// Retrieves the full `BlockData`, including the block and its associated
     /// transactions  by its index (1-based).
     pub async fn get_block_data(&self, bix: u64) -> TokioResult<BlockData> {
         if bix == 0 {
-            Ok(BlockData::genesis())
+            Ok(BlockData::genesis_of(&self.chain_spec))
         } else {
-            let block = self.get_block(bix).await?;
-            let transactions = self.get_transactions_of_block(&block).await?;
-            Ok(BlockData { bix, block, transactions })
+            Ok(self.get_block_data_many(bix, 1).await?.pop().unwrap())
         }
     }
 
@@ -111,9 +1147,27 @@ impl Blockchain {
     /// block index (1-based).
     pub async fn get_block_data_many(&self, bix: u64, count: u64) -> 
                                      TokioResult<Vec<BlockData>> {
+        let (blocks, checksums) = self.get_block_data_many_unchecked(
+            bix, count
+        ).await?;
+        for (block_data, checksum) in blocks.iter().zip(checksums.iter()) {
+            checksum.check(&block_data.block, Some(&block_data.transactions))
+                .map_err(|err| Checksum::to_io_error(block_data.bix, err))?;
+        }
+        Ok(blocks)
+    }
+
+    async fn get_block_data_many_unchecked(&self, bix: u64, count: u64) -> 
+                                           TokioResult<(Vec<BlockData>, 
+                                                        Vec<Checksum>)> {
         if (bix > 0) && (count > 0) {
+            let _gate = self.gate.read().await;
+            let mut reader = self.get_reader().await;
+
             // Get all blocks
-            let blocks: Vec<Block> = self.block_col.lock().await
+            let blocks: Vec<Block> = reader.block_col
+                .get_many((bix - 1) as usize, count as usize).await?;
+            let checksums: Vec<Checksum> = reader.checksum_col
                 .get_many((bix - 1) as usize, count as usize).await?;
 
             // Calculate transaction offset and count
@@ -124,12 +1178,14 @@ impl Blockchain {
                 block_last.offset + block_last.size - transaction_offset;
 
             // Get all transactions
-            let transactions: Vec<Transaction> = self.transaction_col
-                .lock().await.get_many(transaction_offset as usize, 
-                                       transaction_count as usize).await?;
+            let position = self.get_transaction_position(
+                transaction_offset
+            )?;
+            let transactions: Vec<Transaction> = reader.transaction_col
+                .get_many(position, transaction_count as usize).await?;
 
             // Gather block data vector
-            Ok(blocks.into_iter().enumerate().map(|(ix, block)| {
+            Ok((blocks.into_iter().enumerate().map(|(ix, block)| {
                 let trs = &transactions[
                     (block.offset - transaction_offset) as usize
                     ..
@@ -140,7 +1196,7 @@ impl Blockchain {
                     block,
                     transactions: trs.to_vec(),
                 }
-            }).collect())
+            }).collect(), checksums))
         } else {
             Err(ErrorKind::NotFound.into())
         }
@@ -152,11 +1208,14 @@ impl Blockchain {
         self.get_block(bix).await
     }
 
-    /// Retrieves all transactions associated with a specific block.
+    /// Retrieves all transactions associated with a specific block. They are
+    /// not checked against the checksum (see `get_block_data`).
     pub async fn get_transactions_of_block(&self, block: &Block) -> 
                                            TokioResult<Vec<Transaction>> {
-        self.transaction_col.lock().await
-            .get_many(block.offset as usize, block.size as usize).await
+        let _gate = self.gate.read().await;
+        let position = self.get_transaction_position(block.offset)?;
+        self.get_reader().await.transaction_col
+            .get_many(position, block.size as usize).await
     }
 
     /// Pushes a new block along with its associated transactions into the
@@ -165,62 +1224,1189 @@ impl Blockchain {
     pub async fn push_new_block(&self, block: &Block,
                                 transactions: &[Transaction]) -> 
                                 TokioResult<u64> {
-        self.transaction_col.lock().await.update_many(block.offset as usize, 
+        self.push_block_indexed(block, transactions, None).await
+    }
+
+    /// The same as `push_new_block` but the senders of the transactions are
+    /// indexed too if the address index is enabled.
+    pub async fn push_new_block_with_senders(&self, block: &Block,
+                                             transactions: &[Transaction],
+                                             senders: &[U256]) -> 
+                                             TokioResult<u64> {
+        self.push_block_indexed(block, transactions, Some(senders)).await
+    }
+
+    /// Pushes several consecutive blocks with their transactions at once 
+    /// (e.g. on the initial sync): the columns are written in one go under a
+    /// single journal entry. The transactions of the blocks must follow each 
+    /// other (`InvalidInput` is returned otherwise). It returns the 1-based 
+    /// block number of the last inserted block.
+    pub async fn push_new_blocks(&self, blocks: &[(Block, Vec<Transaction>)]) 
+                                 -> TokioResult<u64> {
+        let Some((first, _)) = blocks.first() else {
+            return self.get_block_count().await;
+        };
+
+        // Check the offsets and concatenate the transactions
+        let mut offset = first.offset;
+        let mut transactions = Vec::new();
+        for (block, block_transactions) in blocks.iter() {
+            if (block.offset != offset) || 
+                    (block.size != block_transactions.len() as u64) {
+                return Err(ErrorKind::InvalidInput.into());
+            }
+            offset += block.size;
+            transactions.extend_from_slice(block_transactions);
+        }
+        let block_list: Vec<Block> = blocks.iter()
+            .map(|(block, _)| block.clone()).collect();
+        let checksums: Vec<Checksum> = blocks.iter()
+            .map(|(block, transactions)| Checksum::new(block, 
+                                                       Some(transactions)))
+            .collect();
+
+        // Write the columns
+        let _gate = self.gate.write().await;
+        let mut block_col = self.block_col.lock().await;
+        let block_count = block_col.size().await? as u64;
+        self.begin_write(block_count, first.offset).await?;
+
+        let position = self.get_transaction_position(first.offset)?;
+        self.transaction_col.lock().await.update_many(position, 
+                                                      &transactions).await?;
+        if let Some(index) = self.address_index.as_ref() {
+            index.lock().await.push_transactions(
+                first.offset + 1, &transactions, None
+            ).await?;
+        }
+        self.checksum_col.lock().await.update_many(block_count as usize, 
+                                                   &checksums).await?;
+        block_col.push_many(&block_list).await?;
+
+        self.end_write().await?;
+        Ok(block_count + blocks.len() as u64)
+    }
+
+    /// Initialize the empty blockchain with the blocks whose transactions
+    /// are not stored (e.g. on the sync from a state snapshot): they are
+    /// stored as pruned ones, so the next blocks are pushed as usual. The
+    /// blocks must start from the first one and follow each other 
+    /// (`InvalidInput` is returned otherwise). On error the directory must 
+    /// be discarded. It returns the number of the blocks.
+    pub async fn push_pruned_blocks(&self, blocks: &[Block]) -> 
+                                    TokioResult<u64> {
+        // Check the offsets
+        let mut offset = 0;
+        for block in blocks.iter() {
+            if block.offset != offset {
+                return Err(ErrorKind::InvalidInput.into());
+            }
+            offset += block.size;
+        }
+        let checksums: Vec<Checksum> = blocks.iter()
+            .map(|block| Checksum::new(block, None)).collect();
+
+        // Write the blocks
+        let _gate = self.gate.write().await;
+        let mut block_col = self.block_col.lock().await;
+        if (block_col.size().await? > 0) || 
+                (self.get_transaction_base() > 0) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        self.begin_write(0, 0).await?;
+        self.checksum_col.lock().await.update_many(0, &checksums).await?;
+        block_col.push_many(blocks).await?;
+
+        // Mark the blocks pruned
+        let meta = PruneMeta {
+            block_count: blocks.len() as u64,
+            transaction_count: offset,
+        };
+        let meta_path = path_concat!(self.path.as_str(), "prune.json");
+        let meta_path_tmp = format!("{}.tmp", meta_path);
+        let mut file = tokio::fs::File::create(&meta_path_tmp).await?;
+        file.write_all(&serde_json::to_vec(&meta)?).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&meta_path_tmp, &meta_path).await?;
+        self.pruned_block_count.store(meta.block_count, Ordering::Release);
+        self.transaction_base.store(offset, Ordering::Release);
+
+        self.end_write().await?;
+        Ok(blocks.len() as u64)
+    }
+
+    async fn push_block_indexed(&self, block: &Block, 
+                                transactions: &[Transaction],
+                                senders: Option<&[U256]>) -> 
+                                TokioResult<u64> {
+        let _gate = self.gate.write().await;
+        let block_count = self.block_col.lock().await.size().await? as u64;
+        self.begin_write(block_count, block.offset).await?;
+
+        let position = self.get_transaction_position(block.offset)?;
+        self.transaction_col.lock().await.update_many(position, 
                                                       transactions).await?;
-        let bix = self.block_col.lock().await.push(&block).await? as u64 + 1;
+        if let Some(index) = self.address_index.as_ref() {
+            index.lock().await.push_transactions(
+                block.offset + 1, transactions, senders
+            ).await?;
+        }
+        self.checksum_col.lock().await.update(
+            block_count as usize, &Checksum::new(block, Some(transactions))
+        ).await?;
+        let bix = self.block_col.lock().await.push(block).await? as u64 + 1;
+
+        self.end_write().await?;
         Ok(bix)
     }
 
     /// Truncates the blockchain to retain only a specified number of blocks.
+    /// It cannot truncate the blocks with pruned transactions 
+    /// (`InvalidInput` is returned).
     pub async fn truncate(&self, block_count: u64) -> TokioResult<()> {
-        if block_count > 0 {
-            let block = self.get_block(block_count).await?;
-            let transaction_count = block.offset + block.size;
-            self.block_col.lock().await.resize(block_count as usize).await?;
-            self.transaction_col.lock().await
-                .resize(transaction_count as usize).await?;
+        let _gate = self.gate.write().await;
+        if block_count < self.get_pruned_block_count() {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        let transaction_count = if block_count > 0 {
+            let block = self.block_col.lock().await
+                .get(block_count as usize - 1).await?;
+            block.offset + block.size
         } else {
-            self.block_col.lock().await.resize(0).await?;
-            self.transaction_col.lock().await.resize(0).await?;
+            0
+        };
+        self.begin_write(block_count, transaction_count).await?;
+        self.block_col.lock().await.resize(block_count as usize).await?;
+        self.checksum_col.lock().await.resize(block_count as usize).await?;
+        let position = self.get_transaction_position(transaction_count)?;
+        self.transaction_col.lock().await.resize(position).await?;
+        if let Some(index) = self.address_index.as_ref() {
+            index.lock().await.truncate(transaction_count).await?;
         }
-        Ok(())
+        self.end_write().await
+    }
+
+    /// Verify the integrity of the stored chain: the checksums, the previous 
+    /// hash links, the offsets, the block hashes and the size of the 
+    /// transaction column. If `validator` is given, the blocks are also fully
+    /// validated against the replayed state (it stops at the first invalid 
+    /// block). Returns the errors with the numbers of the corrupted blocks, 
+    /// the chain is intact if it is empty. The blocks with pruned 
+    /// transactions are skipped, the full validation of a pruned chain is 
+    /// `Unsupported`.
+    pub async fn verify(&self, schema: &Schema, 
+                        mut validator: Option<&mut ChainValidator>) -> 
+                        TokioResult<Vec<(u64, Error)>> {
+        let pruned_block_count = self.get_pruned_block_count();
+        if pruned_block_count > 0 && validator.is_some() {
+            return Err(ErrorKind::Unsupported.into());
+        }
+
+        let mut errors = Vec::new();
+        let mut state = State::new().with_chain_spec(&self.chain_spec);
+        let mut info_prev = if pruned_block_count > 0 {
+            self.get_block_info(pruned_block_count).await?
+        } else {
+            self.chain_spec.get_genesis_info()
+        };
+        let total = self.get_block_count().await?;
+
+        let mut bix = pruned_block_count + 1;
+        while bix <= total {
+            let count = READ_BATCH.min(total - bix + 1);
+            let (blocks, checksums) = self.get_block_data_many_unchecked(
+                bix, count
+            ).await?;
+
+            for (block_data, checksum) in blocks.iter()
+                                                .zip(checksums.iter()) {
+                let block = &block_data.block;
+                let bix = block_data.bix;
+
+                // Check the checksums
+                errors.extend(checksum.check(block, 
+                                             Some(&block_data.transactions))
+                    .err().map(|err| (bix, err)));
+
+                // Check the links
+                errors.extend(validate!(block.hash_prev == info_prev.hash, 
+                                        BlockPreviousHashMismatch)
+                    .map_err(|err| err.with_values(info_prev.hash.to_hex(), 
+                                                   block.hash_prev.to_hex()))
+                    .err().map(|err| (bix, err)));
+                errors.extend(validate!(block.offset == info_prev.offset, 
+                                        BlockOffsetMismatch)
+                    .map_err(|err| err.with_values(info_prev.offset, 
+                                                   block.offset))
+                    .err().map(|err| (bix, err)));
+
+                // Recalculate the hash
+                let msg = Block::calc_msg(block.version, block.offset, 
+                                          &block.hash_prev, &block.validator, 
+                                          block.timestamp, block.complexity, 
+                                          &block_data.transactions);
+                let hash = Block::calc_hash(&msg, &block.nonce);
+                errors.extend(validate!(hash == block.hash, BlockInvalidHash)
+                    .map_err(|err| err.with_values(hash.to_hex(), 
+                                                   block.hash.to_hex()))
+                    .err().map(|err| (bix, err)));
+
+                info_prev = block_data.get_block_info();
+            }
+
+            // Validate the blocks against the state
+            if let Some(validator) = validator.as_mut() 
+                    && let Err(err) = validator.validate_chain(
+                        &blocks, &mut state, schema
+                    ) {
+                errors.push((state.get_last_block_info().bix + 1, err));
+                break;
+            }
+
+            bix += count;
+        }
+
+        // Check the size of the transaction column
+        let transaction_count = self.get_transaction_count().await?;
+        errors.extend(validate!(transaction_count == info_prev.offset, 
+                                BlockOffsetMismatch)
+            .map_err(|err| err.with_values(info_prev.offset, 
+                                           transaction_count))
+            .err().map(|err| (total, err)));
+
+        Ok(errors)
+    }
+
+    /// Export `count` blocks starting from `bix` (1-based) with their 
+    /// transactions into the archive.
+    pub async fn export<W>(&self, writer: &mut W, bix: u64, 
+                           count: u64) -> TokioResult<()>
+                           where W: AsyncWrite + Unpin {
+        let total = self.get_block_count().await?;
+        if (bix == 0) || (bix + count > total + 1) {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        let mut hasher = Hasher256::new();
+
+        // Header
+        let mut header = Writer::new();
+        header.write_bytes(ARCHIVE_MAGIC);
+        header.write_u8(ARCHIVE_VERSION);
+        header.write_u64(bix);
+        header.write_u64(count);
+        let header = header.into_bytes();
+        hasher.update(&header);
+        writer.write_all(&header).await?;
+
+        // Records
+        let mut bix_next = bix;
+        while bix_next < bix + count {
+            let batch = READ_BATCH.min(bix + count - bix_next);
+            for block_data in self.get_block_data_many(bix_next, 
+                                                       batch).await? {
+                let bytes = block_data.to_bytes();
+                let len = (bytes.len() as u32).to_le_bytes();
+                hasher.update(len);
+                hasher.update(&bytes);
+                writer.write_all(&len).await?;
+                writer.write_all(&bytes).await?;
+            }
+            bix_next += batch;
+        }
+
+        // Checksum
+        writer.write_all(&hasher.finalize_bytes()).await?;
+        writer.flush().await
+    }
+
+    /// Import the blocks from the archive, they must follow the last stored 
+    /// block. The blocks are checked to be linked (not validated), and all the
+    /// imported blocks are rolled back if the archive is broken. Returns the
+    /// number of the imported blocks.
+    pub async fn import<R>(&self, reader: &mut R) -> TokioResult<u64>
+                           where R: AsyncRead + Unpin {
+        let block_count = self.get_block_count().await?;
+        let result = self.import_blocks(reader, block_count).await;
+        if result.is_err() {
+            self.truncate(block_count).await?;
+        }
+        result
+    }
+
+    async fn import_blocks<R>(&self, reader: &mut R, 
+                              block_count: u64) -> TokioResult<u64>
+                              where R: AsyncRead + Unpin {
+        let mut hasher = Hasher256::new();
+
+        // Header
+        let mut header = [0u8; 21];
+        reader.read_exact(&mut header).await?;
+        hasher.update(header);
+        validate!((&header[..4] == ARCHIVE_MAGIC) && 
+                  (header[4] == ARCHIVE_VERSION), EncodingInvalid)?;
+        let bix = u64::from_le_bytes(header[5..13].try_into().unwrap());
+        let count = u64::from_le_bytes(header[13..21].try_into().unwrap());
+        validate!(bix == block_count + 1, BlockBroken)
+            .map_err(|err| err.with_values(block_count + 1, bix))?;
+
+        // Records (pushed in batches)
+        let mut info_prev = self.get_block_info(block_count).await?;
+        let mut batch = Vec::new();
+        for _ in 0..count {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len).await?;
+            let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+            reader.read_exact(&mut bytes).await?;
+            hasher.update(len);
+            hasher.update(&bytes);
+
+            let block_data = BlockData::from_bytes(&bytes)?;
+            let block = &block_data.block;
+            validate!(block_data.bix == info_prev.bix + 1, BlockBroken)
+                .map_err(|err| err.with_values(info_prev.bix + 1, 
+                                               block_data.bix))?;
+            validate!(block.hash_prev == info_prev.hash, 
+                      BlockPreviousHashMismatch)?;
+            validate!(block.offset == info_prev.offset, BlockOffsetMismatch)
+                .map_err(|err| err.with_values(info_prev.offset, 
+                                               block.offset))?;
+
+            info_prev = block_data.get_block_info();
+            batch.push((block_data.block, block_data.transactions));
+            if batch.len() as u64 >= READ_BATCH {
+                self.push_new_blocks(&batch).await?;
+                batch.clear();
+            }
+        }
+        self.push_new_blocks(&batch).await?;
+
+        // Checksum
+        let mut checksum = [0u8; 32];
+        reader.read_exact(&mut checksum).await?;
+        let expected = hasher.finalize();
+        let actual = U256::from_bytes(&checksum);
+        validate!(actual == expected, EncodingInvalid)
+            .map_err(|err| err.with_values(expected.to_hex(), 
+                                           actual.to_hex()))?;
+
+        Ok(count)
     }
 
     /// Retrieves multiple consecutive blocks by offset and count.
     pub async fn get_block_many(&self, offset: usize, 
                                 count: usize) -> TokioResult<Vec<Block>> {
-        self.block_col.lock().await.get_many(offset, count).await
+        let _gate = self.gate.read().await;
+        let mut reader = self.get_reader().await;
+        let blocks = reader.block_col.get_many(offset, count).await?;
+        let checksums = reader.checksum_col.get_many(offset, count).await?;
+        for (ix, (block, checksum)) in blocks.iter().zip(checksums.iter())
+                                             .enumerate() {
+            checksum.check(block, None).map_err(
+                |err| Checksum::to_io_error((offset + ix + 1) as u64, err)
+            )?;
+        }
+        Ok(blocks)
     }
 
     /// Retrieves multiple consecutive transactions by offset and count.
     pub async fn get_transaction_many(&self, offset: usize, 
                                       count: usize) -> 
                                       TokioResult<Vec<Transaction>> {
-        self.transaction_col.lock().await.get_many(offset, count).await
+        let _gate = self.gate.read().await;
+        let position = self.get_transaction_position(offset as u64)?;
+        self.get_reader().await.transaction_col.get_many(position, count)
+            .await
     }
 
-    /// Retrieves the raw serialized bytes of a range of blocks.
+    /// Retrieves the raw records of a range of blocks (see `RecordCol`).
     pub async fn get_block_raw(&self, offset: usize, 
                                count: usize) -> TokioResult<Vec<u8>> {
-        self.block_col.lock().await.get_raw(offset, count).await
+        let _gate = self.gate.read().await;
+        self.get_reader().await.block_col.get_raw(offset, count).await
     }
 
-    /// Retrieves the raw serialized bytes of a range of transactions.
+    /// Retrieves the raw records of a range of transactions (see 
+    /// `RecordCol`).
     pub async fn get_transaction_raw(&self, offset: usize, 
                                      count: usize) -> TokioResult<Vec<u8>> {
-        self.transaction_col.lock().await.get_raw(offset, count).await
+        let _gate = self.gate.read().await;
+        let position = self.get_transaction_position(offset as u64)?;
+        self.get_reader().await.transaction_col.get_raw(position, count).await
     }
 
     /// Updates the raw serialized bytes of blocks starting at the given offset.
+    /// The checksums of the blocks are recalculated.
     pub async fn update_block_raw(&self, offset: usize, 
                                   bytes: &[u8]) -> TokioResult<()> {
-        self.block_col.lock().await.update_raw(offset, bytes).await
+        let _gate = self.gate.write().await;
+        self.block_col.lock().await.update_raw(offset, bytes).await?;
+        let count = bytes.len() / BlockCol::block_size();
+        self.refresh_checksums(offset, offset + count).await
     }
 
     /// Updates the raw serialized bytes of transactions starting at the given
-    /// offset.
+    /// offset. The checksums of the blocks containing them are recalculated.
     pub async fn update_transaction_raw(&self, offset: usize, 
                                         bytes: &[u8]) -> TokioResult<()> {
-        self.transaction_col.lock().await.update_raw(offset, bytes).await
+        let _gate = self.gate.write().await;
+        let position = self.get_transaction_position(offset as u64)?;
+        self.transaction_col.lock().await.update_raw(position, bytes).await?;
+
+        // Recalculate the checksums of the blocks containing the transactions
+        let count = bytes.len() / TransactionCol::block_size();
+        let from = self.find_block_position(offset as u64).await?;
+        let to = self.find_block_position((offset + count) as u64).await?;
+        let block_count = self.block_col.lock().await.size().await?;
+        self.refresh_checksums(from, (to + 1).min(block_count)).await
+    }
+}
+
+
+/// Header-only storage of the blockchain for light clients.
+pub struct LightBlockchain {
+    header_col: Mutex<Col<BlockHeader>>,
+    chain_spec: ChainSpec,
+}
+
+
+impl LightBlockchain {
+    /// Open the header storage at the given path.
+    pub async fn new(path: &str) -> TokioResult<Self> {
+        let header_col = Mutex::new(Col::<BlockHeader>::new(
+            path_concat!(path, "headers.col")
+        ).await?);
+        Ok(Self { header_col, chain_spec: ChainSpec::mainnet() })
+    }
+
+    /// Set the chain spec (the mainnet one is the default). `InvalidData` is 
+    /// returned if the stored headers do not start from the genesis of the 
+    /// spec.
+    pub async fn with_chain_spec(mut self, spec: ChainSpec) -> 
+                                 TokioResult<Self> {
+        if !self.is_empty().await? {
+            let header = self.get_block_header(1).await?;
+            if header.hash_prev != spec.genesis_hash {
+                return Err(ErrorKind::InvalidData.into());
+            }
+        }
+        self.chain_spec = spec;
+        Ok(self)
+    }
+
+    /// Get the chain spec.
+    pub fn get_chain_spec(&self) -> &ChainSpec {
+        &self.chain_spec
+    }
+
+    /// Checks whether the storage contains any headers.
+    pub async fn is_empty(&self) -> TokioResult<bool> {
+        Ok(self.get_block_count().await? == 0)
+    }
+
+    /// Retrieves the number of the stored headers.
+    pub async fn get_block_count(&self) -> TokioResult<u64> {
+        let size = self.header_col.lock().await.size().await?;
+        Ok(size as u64)
+    }
+
+    /// Retrieves the header of the block by its index (1-based).
+    pub async fn get_block_header(&self, bix: u64) -> TokioResult<BlockHeader> {
+        if bix == 0 {
+            Err(ErrorKind::NotFound.into())
+        } else {
+            self.header_col.lock().await.get(bix as usize - 1).await
+        }
+    }
+
+    /// Retrieves multiple consecutive headers starting from the block index 
+    /// (1-based).
+    pub async fn get_block_header_many(&self, bix: u64, count: u64) -> 
+                                       TokioResult<Vec<BlockHeader>> {
+        if bix == 0 {
+            Err(ErrorKind::NotFound.into())
+        } else {
+            self.header_col.lock().await
+                .get_many(bix as usize - 1, count as usize).await
+        }
+    }
+
+    /// Retrieves basic information about a block by its index (1-based).
+    pub async fn get_block_info(&self, bix: u64) -> TokioResult<BlockInfo> {
+        if bix == 0 {
+            Ok(self.chain_spec.get_genesis_info())
+        } else {
+            let header = self.get_block_header(bix).await?;
+            Ok(header.get_block_info(bix))
+        }
+    }
+
+    /// Retrieves information about the last block.
+    pub async fn get_last_block_info(&self) -> TokioResult<BlockInfo> {
+        let bix = self.get_block_count().await?;
+        self.get_block_info(bix).await
+    }
+
+    /// Pushes the header that follows the last one (the previous hash and 
+    /// the offset are checked, the header itself must be validated before).
+    /// It returns the 1-based block number of the header.
+    pub async fn push_new_header(&self, header: &BlockHeader) -> 
+                                 TokioResult<u64> {
+        let info = self.get_last_block_info().await?;
+        validate!(header.hash_prev == info.hash, BlockPreviousHashMismatch)?;
+        validate!(header.offset == info.offset, BlockOffsetMismatch)
+            .map_err(|err| err.with_values(info.offset, header.offset))?;
+        let ix = self.header_col.lock().await.push(header).await?;
+        Ok(ix as u64 + 1)
+    }
+
+    /// Truncates the storage to retain only a specified number of headers.
+    pub async fn truncate(&self, block_count: u64) -> TokioResult<()> {
+        self.header_col.lock().await.resize(block_count as usize).await
+    }
+}
+
+
+/// Sync the data of the file written through another handle (e.g. a 
+/// column).
+pub(crate) async fn sync_file(path: &str) -> TokioResult<()> {
+    tokio::fs::OpenOptions::new().write(true).open(path).await?
+        .sync_all().await
+}
+
+
+/// Write the metadata file into the directory atomically: it is written to 
+/// a temporary file that is synced and renamed.
+async fn write_meta<T: Serialize>(path: &str, name: &str, 
+                                  meta: &T) -> TokioResult<()> {
+    let meta_path = path_concat!(path, name);
+    let meta_path_tmp = format!("{}.tmp", meta_path);
+    let mut file = tokio::fs::File::create(&meta_path_tmp).await?;
+    file.write_all(&serde_json::to_vec(meta)?).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&meta_path_tmp, &meta_path).await?;
+    sync_dir(path).await
+}
+
+
+/// Sync the directory entries (the renamed and removed files). Directories
+/// cannot be opened for syncing on Windows, so it is skipped there.
+pub(crate) async fn sync_dir(path: &str) -> TokioResult<()> {
+    if cfg!(unix) {
+        tokio::fs::File::open(path).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::coin::coin_random;
+    use crate::difficulty::Retarget;
+    use crate::error::ErrorKind;
+    use crate::transaction::MEMO_MAX_SIZE;
+    use crate::testing::{TempDir, TestAccount};
+
+    #[tokio::test]
+    async fn test_address_index() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+        let receiver: U256 = rng.random();
+
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+
+        // A block is stored before the index is enabled
+        let account = TestAccount::new(&key, &sender, &schema);
+        let trs1 = vec![account.random_transfer(&mut rng, &receiver), 
+                        account.random_transfer(&mut rng, &U256::from(100))];
+        let trs2 = vec![account.random_transfer(&mut rng, &receiver)];
+        let block1 = Block::new(0, 2, U256::from(0), U256::from(0), 
+                                U256::from(0), U256::from(1));
+        let block2 = Block::new(2, 1, U256::from(1), U256::from(0), 
+                                U256::from(0), U256::from(2));
+
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        assert!(blockchain.get_transactions_by_address(&receiver, 0, 10).await
+                          .is_err());
+        blockchain.push_new_block(&block1, &trs1).await.unwrap();
+
+        let blockchain = blockchain.with_address_index().await.unwrap();
+        blockchain.push_new_block_with_senders(
+            &block2, &trs2, std::slice::from_ref(&sender)
+        ).await.unwrap();
+
+        let tixs = |items: Vec<(u64, Transaction)>| {
+            items.into_iter().map(|(tix, _)| tix).collect::<Vec<u64>>()
+        };
+        assert_eq!(tixs(blockchain.get_transactions_by_address(&receiver, 0, 
+                                                               10).await
+                                  .unwrap()), vec![3, 1]);
+        assert_eq!(tixs(blockchain.get_transactions_by_address(&receiver, 1, 
+                                                               10).await
+                                  .unwrap()), vec![1]);
+        assert_eq!(tixs(blockchain.get_transactions_by_address(&sender, 0, 
+                                                               10).await
+                                  .unwrap()), vec![3]);
+
+        // Truncation drops the entries, reopening restores the heads
+        blockchain.truncate(1).await.unwrap();
+        let blockchain = Blockchain::new(&path).await.unwrap()
+            .with_address_index().await.unwrap();
+        assert_eq!(tixs(blockchain.get_transactions_by_address(&receiver, 0, 
+                                                               10).await
+                                  .unwrap()), vec![1]);
+        assert!(blockchain.get_transactions_by_address(&sender, 0, 10).await
+                          .unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recover() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let addr = U256::from(100);
+        let trs1 = account.random_transfers(&mut rng, 1, &addr);
+        let trs2 = account.random_transfers(&mut rng, 2, &addr);
+        let block1 = Block::new(0, 1, U256::from(0), U256::from(0), 
+                                U256::from(0), U256::from(1));
+
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        blockchain.push_new_block(&block1, &trs1).await.unwrap();
+        assert!(!blockchain.recover().await.unwrap());
+
+        // Crash after the transactions of the second block are written
+        blockchain.begin_write(1, 1).await.unwrap();
+        blockchain.transaction_col.lock().await.update_many(1, &trs2).await
+                  .unwrap();
+        assert_eq!(blockchain.get_transaction_count().await.unwrap(), 3);
+
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        assert_eq!(blockchain.get_block_count().await.unwrap(), 1);
+        assert_eq!(blockchain.get_transaction_count().await.unwrap(), 1);
+        assert!(!blockchain.recover().await.unwrap());
+
+        // Crash while the journal is written
+        let journal_tmp = path_concat!(path.as_str(), "journal.json.tmp");
+        tokio::fs::write(&journal_tmp, b"{\"block_").await.unwrap();
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        assert_eq!(blockchain.get_block_count().await.unwrap(), 1);
+        assert!(!tokio::fs::try_exists(&journal_tmp).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let complexity = 4;
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+        let blockchain = Blockchain::new(&path).await.unwrap();
+
+        // Two valid blocks
+        let mut state = State::new();
+        for ix in 0..2 {
+            let coin = coin_random(&mut rng, &public);
+            let transactions = vec![Transaction::build(
+                &mut rng, coin, U256::from(100), &key, 0, &schema
+            )];
+            let timestamp = 1700000000 + ix;
+            let info = state.get_last_block_info().clone();
+            let senders = Transaction::calc_senders(&transactions, &state,
+                                                    &schema);
+            let nonce = Block::mine(&mut rng, &info, &validator,
+                                    timestamp, &transactions, complexity,
+                                    None).unwrap();
+            let block = Block::build(&info, validator.clone(), timestamp,
+                                     &transactions, U256::from_bytes(&nonce),
+                                     complexity, &state, &senders).unwrap();
+            state.roll_up(info.bix + 1, &block, &transactions, &schema);
+            blockchain.push_new_block(&block, &transactions).await.unwrap();
+        }
+        assert!(blockchain.verify(&schema, None).await.unwrap().is_empty());
+
+        // A broken block
+        let block = Block::new(2, 0, U256::from(1), validator.clone(), 
+                               U256::from(0), U256::from(2));
+        blockchain.push_new_block(&block, &[]).await.unwrap();
+        let kinds = |errors: Vec<(u64, Error)>| {
+            errors.into_iter().map(|(bix, err)| (bix, err.kind()))
+                  .collect::<Vec<(u64, ErrorKind)>>()
+        };
+        assert_eq!(kinds(blockchain.verify(&schema, None).await.unwrap()), 
+                   vec![(3, ErrorKind::BlockPreviousHashMismatch),
+                        (3, ErrorKind::BlockInvalidHash)]);
+
+        // Full validation with another complexity fails on the first block
+        let mut chain_validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity + 1);
+        let errors = blockchain.verify(&schema, Some(&mut chain_validator))
+                               .await.unwrap();
+        assert_eq!(kinds(errors)[2], 
+                   (1, ErrorKind::BlockInvalidHashComplexity));
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        let tmps: Vec<TempDir> = (0..3).map(
+            |_| TempDir::new("uqoin-blockchain")
+        ).collect();
+        let paths: Vec<String> = tmps.iter()
+            .map(|tmp| tmp.path().to_string()).collect();
+
+        // Linked blocks with one transaction each
+        let blockchain = Blockchain::new(&paths[0]).await.unwrap();
+        let mut hash_prev = BlockInfo::genesis().hash;
+        for ix in 0..3 {
+            let coin = coin_random(&mut rng, &public);
+            let transactions = vec![Transaction::build(
+                &mut rng, coin, U256::from(100), &key, 0, &schema
+            )];
+            let hash: U256 = rng.random();
+            let block = Block::new(ix, 1, hash_prev, U256::from(0), 
+                                   U256::from(0), hash.clone());
+            blockchain.push_new_block(&block, &transactions).await.unwrap();
+            hash_prev = hash;
+        }
+
+        let mut archive = Vec::new();
+        blockchain.export(&mut archive, 1, 3).await.unwrap();
+        assert!(blockchain.export(&mut Vec::new(), 2, 3).await.is_err());
+
+        let blockchain2 = Blockchain::new(&paths[1]).await.unwrap()
+            .with_chain_spec(ChainSpec::new(2, U256::from(5))).await.unwrap();
+        assert!(blockchain2.import(&mut archive.as_slice()).await.is_err());
+        let blockchain2 = blockchain2.with_chain_spec(ChainSpec::mainnet())
+                                     .await.unwrap();
+        assert_eq!(blockchain2.import(&mut archive.as_slice()).await.unwrap(), 
+                   3);
+        assert_eq!(blockchain2.get_transaction_count().await.unwrap(), 3);
+        assert_eq!(blockchain2.get_last_block().await.unwrap().hash, 
+                   blockchain.get_last_block().await.unwrap().hash);
+
+        // The broken archive is rolled back
+        *archive.last_mut().unwrap() ^= 1;
+        let blockchain3 = Blockchain::new(&paths[2]).await.unwrap();
+        assert!(blockchain3.import(&mut archive.as_slice()).await.is_err());
+        assert!(blockchain3.is_empty().await.unwrap());
+        assert_eq!(blockchain3.get_transaction_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_readers() {
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+
+        let blockchain = Blockchain::with_readers(&path, 2).await.unwrap();
+        for ix in 0..3 {
+            let block = Block::new(0, 0, U256::from(ix), U256::from(0), 
+                                   U256::from(0), U256::from(ix + 1));
+            blockchain.push_new_block(&block, &[]).await.unwrap();
+        }
+
+        // More reads than handles and a write in between
+        let block = Block::new(0, 0, U256::from(3), U256::from(0), 
+                               U256::from(0), U256::from(4));
+        let (b1, b2, b3, bix, count) = tokio::join!(
+            blockchain.get_block(1), blockchain.get_block(2), 
+            blockchain.get_block(3), blockchain.push_new_block(&block, &[]),
+            blockchain.get_block_count()
+        );
+        assert_eq!(b1.unwrap().hash, U256::from(1));
+        assert_eq!(b2.unwrap().hash, U256::from(2));
+        assert_eq!(b3.unwrap().hash, U256::from(3));
+        assert_eq!(bix.unwrap(), 4);
+        assert!(count.unwrap() >= 3);
+        assert_eq!(blockchain.get_last_block().await.unwrap().hash, 
+                   U256::from(4));
+    }
+
+    #[tokio::test]
+    async fn test_push_new_blocks() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let addr = U256::from(100);
+        let blocks = vec![
+            (Block::new(0, 2, U256::from(0), U256::from(0), U256::from(0), 
+                        U256::from(1)), 
+             account.random_transfers(&mut rng, 2, &addr)),
+            (Block::new(2, 0, U256::from(1), U256::from(0), U256::from(0), 
+                        U256::from(2)), vec![]),
+            (Block::new(2, 1, U256::from(2), U256::from(0), U256::from(0), 
+                        U256::from(3)), 
+             account.random_transfers(&mut rng, 1, &addr)),
+        ];
+
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        assert_eq!(blockchain.push_new_blocks(&[]).await.unwrap(), 0);
+        assert_eq!(blockchain.push_new_blocks(&blocks).await.unwrap(), 3);
+        assert_eq!(blockchain.get_block_count().await.unwrap(), 3);
+        assert_eq!(blockchain.get_transaction_count().await.unwrap(), 3);
+        assert_eq!(blockchain.get_block(3).await.unwrap().hash, U256::from(3));
+        assert_eq!(blockchain.get_transaction(3).await.unwrap().get_hash(),
+                   blocks[2].1[0].get_hash());
+
+        // The blocks with a gap in the offsets are rejected
+        let gap = vec![
+            (Block::new(3, 0, U256::from(3), U256::from(0), U256::from(0), 
+                        U256::from(4)), vec![]),
+            (Block::new(4, 0, U256::from(4), U256::from(0), U256::from(0), 
+                        U256::from(5)), vec![]),
+        ];
+        assert_eq!(blockchain.push_new_blocks(&gap).await.unwrap_err().kind(),
+                   std::io::ErrorKind::InvalidInput);
+        assert_eq!(blockchain.get_block_count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_checksums() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+
+        let coin = coin_random(&mut rng, &sender);
+        let trs = vec![Transaction::build(&mut rng, coin, U256::from(100), 
+                                          &key, 0, &schema)];
+        let block1 = Block::new(0, 1, U256::from(0), U256::from(0), 
+                                U256::from(0), U256::from(1));
+        let block2 = Block::new(1, 0, U256::from(1), U256::from(0), 
+                                U256::from(0), U256::from(2));
+
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        blockchain.push_new_block(&block1, &trs).await.unwrap();
+        blockchain.push_new_block(&block2, &[]).await.unwrap();
+        assert_eq!(blockchain.get_block_data(1).await.unwrap()
+                             .transactions.len(), 1);
+
+        // Raw updates recalculate the checksums
+        let raw = blockchain.get_transaction_raw(0, 1).await.unwrap();
+        blockchain.update_transaction_raw(0, &raw).await.unwrap();
+        let raw = blockchain.get_block_raw(0, 2).await.unwrap();
+        blockchain.update_block_raw(0, &raw).await.unwrap();
+        assert!(blockchain.get_block_data_many(1, 2).await.is_ok());
+
+        // Corrupt the transaction on disk
+        let mut corrupted = trs[0].clone();
+        corrupted.addr = U256::from(200);
+        blockchain.transaction_col.lock().await.update_many(0, &[corrupted])
+                  .await.unwrap();
+        let err = blockchain.get_block_data(1).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<Error>().unwrap()
+                      .kind(), ErrorKind::StorageChecksumMismatch);
+        assert!(blockchain.get_block(1).await.is_ok());
+        let errors = blockchain.verify(&schema, None).await.unwrap();
+        assert!(errors.iter().any(
+            |(bix, err)| (*bix == 1) && 
+                         (err.kind() == ErrorKind::StorageChecksumMismatch)
+        ));
+
+        // Missing checksums are calculated on opening
+        blockchain.transaction_col.lock().await.update_many(0, &trs[..1])
+                  .await.unwrap();
+        blockchain.checksum_col.lock().await.resize(0).await.unwrap();
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        assert!(blockchain.get_block_data_many(1, 2).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let addr = U256::from(100);
+        let trs_list = [1, 2, 1, 1].map(
+            |count| account.random_transfers(&mut rng, count, &addr)
+        );
+
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        let mut offset = 0;
+        for (ix, trs) in trs_list[..3].iter().enumerate() {
+            let block = Block::new(offset, trs.len() as u64, U256::from(ix), 
+                                   U256::from(0), U256::from(0), 
+                                   U256::from(ix + 1));
+            blockchain.push_new_block(&block, trs).await.unwrap();
+            offset += trs.len() as u64;
+        }
+
+        // Transactions of the first two blocks are deleted
+        assert_eq!(blockchain.prune(1).await.unwrap(), 3);
+        assert_eq!(blockchain.prune(1).await.unwrap(), 0);
+        assert_eq!(blockchain.get_pruned_block_count(), 2);
+        assert_eq!(blockchain.get_transaction_base(), 3);
+        assert_eq!(blockchain.get_block_count().await.unwrap(), 3);
+        assert_eq!(blockchain.get_transaction_count().await.unwrap(), 4);
+        assert_eq!(blockchain.get_transaction(2).await.unwrap_err().kind(),
+                   std::io::ErrorKind::NotFound);
+        assert_eq!(blockchain.get_transaction(4).await.unwrap().get_hash(),
+                   trs_list[2][0].get_hash());
+        assert_eq!(blockchain.truncate(1).await.unwrap_err().kind(),
+                   std::io::ErrorKind::InvalidInput);
+
+        // New blocks are appended after the retained ones
+        let block = Block::new(4, 1, U256::from(3), U256::from(0), 
+                               U256::from(0), U256::from(4));
+        blockchain.push_new_block(&block, &trs_list[3]).await.unwrap();
+        assert_eq!(blockchain.get_transactions_of_block(&block).await
+                             .unwrap()[0].get_hash(),
+                   trs_list[3][0].get_hash());
+
+        // An interrupted pruning is discarded on reopening
+        let col_path_tmp = path_concat!(path.as_str(), 
+                                        "transactions.col.tmp");
+        let meta_path_tmp = path_concat!(path.as_str(), "prune.json.tmp");
+        tokio::fs::write(&col_path_tmp, b"").await.unwrap();
+        tokio::fs::write(&meta_path_tmp, b"{}").await.unwrap();
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        assert!(!tokio::fs::try_exists(&col_path_tmp).await.unwrap());
+        assert!(!tokio::fs::try_exists(&meta_path_tmp).await.unwrap());
+        assert_eq!(blockchain.get_pruned_block_count(), 2);
+        assert_eq!(blockchain.get_transaction_count().await.unwrap(), 5);
+        assert_eq!(blockchain.get_block_data(3).await.unwrap()
+                             .transactions.len(), 1);
+        assert_eq!(blockchain.verify(&schema, Some(&mut ChainValidator::new(
+            Retarget::default()
+        ))).await.unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+
+        let blockchain = Blockchain::new(&path).await.unwrap()
+            .with_address_index().await.unwrap();
+        let receiver: U256 = rng.random();
+        for ix in 0..3 {
+            let coin = coin_random(&mut rng, &sender);
+            let trs = vec![Transaction::build(&mut rng, coin, 
+                                              receiver.clone(), &key, 0, 
+                                              &schema)];
+            let block = Block::new(ix, 1, U256::from(ix), U256::from(0), 
+                                   U256::from(0), U256::from(ix + 1));
+            blockchain.push_new_block(&block, &trs).await.unwrap();
+        }
+        assert_eq!(blockchain.compact().await.unwrap().get_reclaimed(), 0);
+
+        // Dead space: a partial record, pruned entries and a leftover
+        blockchain.prune(1).await.unwrap();
+        let tail = blockchain.get_transaction_raw(2, 1).await.unwrap();
+        blockchain.transaction_col.lock().await
+            .update_raw(1, &tail[..10]).await.unwrap();
+        tokio::fs::write(path_concat!(path.as_str(), "prune.json.tmp"), 
+                         b"{}").await.unwrap();
+        let size = blockchain.get_disk_size().await.unwrap();
+
+        let report = blockchain.compact().await.unwrap();
+        assert_eq!(report.size_before, size);
+        assert_eq!(report.get_reclaimed(), 
+                   10 + 2 + 2 * Col::<AddressEntry>::block_size() as u64);
+        assert_eq!(blockchain.get_transaction_count().await.unwrap(), 3);
+        let tixs: Vec<u64> = blockchain.get_transactions_by_address(
+            &receiver, 0, 10
+        ).await.unwrap().into_iter().map(|(tix, _)| tix).collect();
+        assert_eq!(tixs, vec![3]);
+    }
+
+    #[tokio::test]
+    async fn test_light_blockchain() {
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+
+        let light = LightBlockchain::new(&path).await.unwrap();
+        assert!(light.is_empty().await.unwrap());
+
+        let mut hash_prev = BlockInfo::genesis().hash;
+        for ix in 0..3 {
+            let block = Block::new(2 * ix, 2, hash_prev.clone(), 
+                                   U256::from(0), U256::from(0), 
+                                   U256::from(ix + 1));
+            let header = block.get_header(&[]);
+            assert_eq!(light.push_new_header(&header).await.unwrap(), ix + 1);
+            hash_prev = block.hash;
+        }
+
+        // Broken links are rejected
+        let block = Block::new(6, 1, U256::from(1), U256::from(0), 
+                               U256::from(0), U256::from(4));
+        assert!(light.push_new_header(&block.get_header(&[])).await.is_err());
+
+        assert_eq!(light.get_block_count().await.unwrap(), 3);
+        let info = light.get_last_block_info().await.unwrap();
+        assert_eq!((info.bix, info.offset), (3, 6));
+        assert_eq!(light.get_block_header_many(2, 2).await.unwrap()[1].hash,
+                   U256::from(3));
+
+        light.truncate(1).await.unwrap();
+        assert_eq!(light.get_last_block_info().await.unwrap().hash, 
+                   U256::from(1));
+
+        // The headers start from the mainnet genesis
+        let spec = ChainSpec::new(2, U256::from(5));
+        let light = light.with_chain_spec(ChainSpec::mainnet()).await
+                         .unwrap();
+        assert!(light.with_chain_spec(spec).await.is_err());
+    }
+
+    /// Write the columns of the storage format 1 (the raw structures).
+    async fn write_legacy_columns(path: &str, transactions: &[Transaction],
+                                  blocks: &[Block]) {
+        let transactions: Vec<LegacyTransaction> = transactions.iter()
+            .map(|tr| LegacyTransaction { 
+                coin: tr.coin.clone(), 
+                addr: tr.addr.clone(), 
+                sign_r: tr.sign_r.clone(), 
+                sign_s: tr.sign_s.clone(),
+            }).collect();
+        let blocks: Vec<LegacyBlock> = blocks.iter()
+            .map(|block| LegacyBlock { 
+                offset: block.offset, 
+                size: block.size, 
+                hash_prev: block.hash_prev.clone(), 
+                validator: block.validator.clone(), 
+                nonce: block.nonce.clone(), 
+                hash: block.hash.clone(),
+            }).collect();
+        Col::<LegacyTransaction>::new(path_concat!(path, "transactions.col"))
+            .await.unwrap().push_many(&transactions).await.unwrap();
+        Col::<LegacyBlock>::new(path_concat!(path, "blocks.col"))
+            .await.unwrap().push_many(&blocks).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate_legacy() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+        let account = TestAccount::new(&key, &sender, &schema);
+        let trs = account.random_transfers(&mut rng, 3, &U256::from(100));
+        let blocks = vec![
+            Block::new(0, 2, U256::from(0), U256::from(0), U256::from(0), 
+                       U256::from(1)),
+            Block::new(2, 1, U256::from(1), U256::from(0), U256::from(0), 
+                       U256::from(2)),
+        ];
+        let exists = |path: &str, name: &str| {
+            std::path::Path::new(&path_concat!(path, name)).exists()
+        };
+
+        // The layout of the first versions
+        assert_eq!(Col::<LegacyTransaction>::block_size(), 128);
+        assert_eq!(Col::<LegacyBlock>::block_size(), 144);
+
+        // The baseline columns are migrated on opening
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+        write_legacy_columns(&path, &trs, &blocks).await;
+        for _ in 0..2 {
+            let blockchain = Blockchain::new(&path).await.unwrap();
+            let block_data = blockchain.get_block_data_many(1, 2).await
+                                       .unwrap();
+            assert_eq!(block_data[0].block.to_bytes(), blocks[0].to_bytes());
+            assert_eq!(block_data[1].block.to_bytes(), blocks[1].to_bytes());
+            let transactions: Vec<&Transaction> = block_data.iter()
+                .flat_map(|bd| bd.transactions.iter()).collect();
+            assert_eq!(transactions.len(), trs.len());
+            for (tr, tr_legacy) in transactions.iter().zip(trs.iter()) {
+                assert_eq!(tr.to_bytes(), tr_legacy.to_bytes());
+            }
+        }
+        let meta: FormatMeta = serde_json::from_slice(
+            &tokio::fs::read(path_concat!(path.as_str(), "format.json"))
+                .await.unwrap()
+        ).unwrap();
+        assert_eq!(meta.version, STORAGE_VERSION);
+        for name in ["migration.json", "transactions.col.v2", "blocks.col.v2"] {
+            assert!(!exists(&path, name));
+        }
+
+        // The records keep the fields of the next versions
+        let memo = Memo::new(&[7; MEMO_MAX_SIZE]).unwrap();
+        let tr = Transaction::new(U256::from(1), U256::from(2), 
+                                  U256::from(3), U256::from(4))
+            .with_memo(memo.clone());
+        let block = Block::new(3, 1, U256::from(2), U256::from(0), 
+                               U256::from(0), U256::from(3))
+            .with_time(1700000000, 4);
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        blockchain.push_new_block(&block, std::slice::from_ref(&tr))
+                  .await.unwrap();
+        let block_data = blockchain.get_block_data(3).await.unwrap();
+        assert_eq!(block_data.block.to_bytes(), block.to_bytes());
+        assert_eq!(block_data.transactions[0].memo, Some(memo));
+        drop(blockchain);
+
+        // Interrupted after the conversion: the renames are finished
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+        write_legacy_columns(&path, &trs, &blocks).await;
+        Blockchain::convert_legacy::<LegacyTransaction, Transaction, 
+                                     TRANSACTION_RECORD_SIZE>(
+            &path_concat!(path.as_str(), "transactions.col")
+        ).await.unwrap();
+        Blockchain::convert_legacy::<LegacyBlock, Block, BLOCK_RECORD_SIZE>(
+            &path_concat!(path.as_str(), "blocks.col")
+        ).await.unwrap();
+        write_meta(&path, "migration.json", 
+                   &FormatMeta { version: STORAGE_VERSION }).await.unwrap();
+        tokio::fs::rename(path_concat!(path.as_str(), "transactions.col.v2"),
+                          path_concat!(path.as_str(), "transactions.col"))
+            .await.unwrap();
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        assert_eq!(blockchain.get_block_data(2).await.unwrap()
+                             .transactions[0].to_bytes(), trs[2].to_bytes());
+        assert!(!exists(&path, "migration.json"));
+        drop(blockchain);
+
+        // Interrupted conversion: it is restarted
+        let tmp = TempDir::new("uqoin-blockchain");
+        let path = tmp.path().to_string();
+        write_legacy_columns(&path, &trs, &blocks).await;
+        tokio::fs::write(path_concat!(path.as_str(), "blocks.col.v2"), 
+                         [1; 10]).await.unwrap();
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        assert_eq!(blockchain.get_block(2).await.unwrap().to_bytes(), 
+                   blocks[1].to_bytes());
+        drop(blockchain);
+
+        // Unknown format
+        write_meta(&path, "format.json", 
+                   &FormatMeta { version: STORAGE_VERSION + 1 }).await
+            .unwrap();
+        assert_eq!(Blockchain::new(&path).await.err().unwrap().kind(), 
+                   std::io::ErrorKind::InvalidData);
     }
 }
@@ -0,0 +1,400 @@
+//! Validation of block sequences (e.g. on a full re-sync).
+//!
+//! `ChainValidator` validates the blocks one by one rolling up the state with
+//! each valid block. The most expensive part of the validation is the sender
+//! recovery from the signatures, so it is pipelined: the senders of the next
+//! block are calculated in another thread while the current block is
+//! validated. The counters of the next block coins are known in advance: each
+//! coin of the current block gets its counter incremented by one (the coins
+//! of a valid block are unique).
+//!
+//! The complexity of each block is calculated by the retargeting (see the
+//! `difficulty` module) from the infos of the last blocks, they are kept by
+//! the validator. The timestamps are bounded by the median time past of 
+//! these blocks and the local clock (see `Retarget::validate_timestamp`).
+//!
+//! Checkpoints are known hashes of blocks at certain heights. The blocks at
+//! these heights must have the checkpoint hashes, so a new node cannot be fed
+//! with a long fake chain. The built-in checkpoints are `CHECKPOINTS`, others
+//! can be supplied from the config with `ChainValidator::with_checkpoints`.
+
+use std::collections::{HashSet, BTreeMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::validate;
+use crate::utils::*;
+use crate::schema::Schema;
+use crate::transaction::Transaction;
+use crate::block::{BlockInfo, BlockData};
+use crate::difficulty::Retarget;
+use crate::state::State;
+
+
+/// Built-in checkpoints as pairs of block number and block hash in hex.
+pub const CHECKPOINTS: &[(u64, &str)] = &[];
+
+
+/// Known hash of the block at the height.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Block number.
+    pub bix: u64,
+
+    /// Block hash.
+    pub hash: U256,
+}
+
+
+impl Checkpoint {
+    /// Create a checkpoint.
+    pub fn new(bix: u64, hash: U256) -> Self {
+        Self { bix, hash }
+    }
+
+    /// Get the built-in checkpoints (see `CHECKPOINTS`).
+    pub fn get_builtin() -> Vec<Self> {
+        CHECKPOINTS.iter()
+            .map(|(bix, hash)| Self::new(*bix, U256::from_hex(hash)))
+            .collect()
+    }
+}
+
+
+/// Validator of block sequences.
+#[derive(Debug, Clone)]
+pub struct ChainValidator {
+    retarget: Retarget,
+    complexity: Option<usize>,
+    clock: Option<u64>,
+    history: Vec<BlockInfo>,
+    checkpoints: BTreeMap<u64, U256>,
+}
+
+
+impl ChainValidator {
+    /// Create a validator with the retargeting parameters and the built-in
+    /// checkpoints.
+    pub fn new(retarget: Retarget) -> Self {
+        Self { 
+            retarget, 
+            complexity: None, 
+            clock: None,
+            history: Vec::new(),
+            checkpoints: BTreeMap::new(),
+        }.with_checkpoints(Checkpoint::get_builtin())
+    }
+
+    /// Add checkpoints (e.g. from the config). A checkpoint replaces the 
+    /// existing one of the same height.
+    pub fn with_checkpoints(mut self, checkpoints: Vec<Checkpoint>) -> Self {
+        self.checkpoints.extend(checkpoints.into_iter()
+                                           .map(|cp| (cp.bix, cp.hash)));
+        self
+    }
+
+    /// Get the checkpoint hash of the block.
+    pub fn get_checkpoint(&self, bix: u64) -> Option<&U256> {
+        self.checkpoints.get(&bix)
+    }
+
+    /// Get the checkpoint of the highest block.
+    pub fn get_last_checkpoint(&self) -> Option<Checkpoint> {
+        self.checkpoints.last_key_value()
+            .map(|(bix, hash)| Checkpoint::new(*bix, hash.clone()))
+    }
+
+    /// Use the fixed complexity instead of the retargeting (e.g. for tests
+    /// and private chains).
+    pub fn with_complexity(mut self, complexity: usize) -> Self {
+        self.complexity = Some(complexity);
+        self
+    }
+
+    /// Use the fixed time of the local clock (e.g. for tests), the system 
+    /// time is used by default.
+    pub fn with_clock(mut self, now: u64) -> Self {
+        self.clock = Some(now);
+        self
+    }
+
+    /// Set infos of the last blocks up to the last block of the state (see
+    /// `Retarget::get_history_size`). Without them only the last block info
+    /// of the state is used, so the blocks far from the genesis fail with
+    /// `BlockHistoryMissing`.
+    pub fn with_history(mut self, history: Vec<BlockInfo>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Replace the infos of the last blocks (see `with_history`).
+    pub fn set_history(&mut self, history: Vec<BlockInfo>) {
+        self.history = history;
+    }
+
+    /// Get the retargeting parameters.
+    pub fn get_retarget(&self) -> &Retarget {
+        &self.retarget
+    }
+
+    /// Infos of the last validated blocks.
+    pub fn get_history(&self) -> &[BlockInfo] {
+        &self.history
+    }
+
+    /// Get the complexity of the block that follows the given infos of the
+    /// last blocks (the fixed one if it is set).
+    pub fn get_complexity(&self, 
+                          history: &[BlockInfo]) -> UqoinResult<usize> {
+        match self.complexity {
+            Some(complexity) => Ok(complexity),
+            None => self.retarget.complexity_for_height(history),
+        }
+    }
+
+    /// Validate the timestamp of the block that follows the given infos of
+    /// the last blocks against their median time past and the local clock.
+    pub fn validate_timestamp(&self, timestamp: u64, 
+                              history: &[BlockInfo]) -> UqoinResult<()> {
+        let now = self.clock.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH)
+                             .map(|duration| duration.as_secs())
+                             .unwrap_or(0)
+        });
+        self.retarget.validate_timestamp(timestamp, history, now)
+    }
+
+    /// Check the hash of the block `bix` against the checkpoint of this 
+    /// height if any.
+    pub fn validate_checkpoint(&self, bix: u64, 
+                               hash: &U256) -> UqoinResult<()> {
+        if let Some(expected) = self.checkpoints.get(&bix) {
+            validate!(hash == expected, BlockCheckpointMismatch)
+                .map_err(|err| err.with_values(expected.to_hex(), 
+                                               hash.to_hex())
+                                  .with_bix(bix))?;
+        }
+        Ok(())
+    }
+
+    /// Validate the blocks that follow the last block of the state and roll
+    /// up the state with them. On error the state stays at the last valid
+    /// block. Returns the number of validated blocks.
+    pub fn validate_chain(&mut self, blocks: &[BlockData], state: &mut State,
+                          schema: &Schema) -> UqoinResult<usize> {
+        // Start the history from the last block of the state
+        if self.history.last().map(|info| info.bix) !=
+                Some(state.get_last_block_info().bix) {
+            self.history = vec![state.get_last_block_info().clone()];
+        }
+
+        // Senders of the first block
+        let mut senders = match blocks.first() {
+            Some(block_data) => Transaction::calc_senders(
+                &block_data.transactions, state, schema
+            ),
+            None => return Ok(0),
+        };
+
+        for (ix, block_data) in blocks.iter().enumerate() {
+            // Validate the block and calculate senders of the next block
+            let (result, senders_next) = std::thread::scope(|scope| {
+                let handle = blocks.get(ix + 1).map(|next| scope.spawn(|| {
+                    Self::calc_senders_next(&next.transactions,
+                                            &block_data.transactions,
+                                            state, schema)
+                }));
+                let result = self.validate_block(block_data, state, &senders);
+                (result, handle.map(|handle| handle.join().unwrap()))
+            });
+            result?;
+
+            // Roll up the state
+            state.roll_up_with_senders(block_data.bix, &block_data.block,
+                                       &block_data.transactions, &senders);
+
+            // Update the history
+            self.history.push(block_data.get_block_info());
+            if self.history.len() > self.retarget.get_history_size() {
+                self.history.remove(0);
+            }
+
+            senders = senders_next.unwrap_or_default();
+        }
+
+        Ok(blocks.len())
+    }
+
+    /// Validate the block that follows the last block of the state. The 
+    /// errors carry the number of the block.
+    fn validate_block(&self, block_data: &BlockData, state: &State,
+                      senders: &[U256]) -> UqoinResult<()> {
+        // Check block number
+        let bix = state.get_last_block_info().bix + 1;
+        validate!(block_data.bix == bix, BlockBroken)
+            .map_err(|err| err.with_values(bix, block_data.bix)
+                              .with_bix(block_data.bix))?;
+
+        // Check checkpoint
+        self.validate_checkpoint(bix, &block_data.block.hash)?;
+
+        // Validate the block
+        self.validate_timestamp(block_data.block.timestamp, &self.history)
+            .and_then(|_| self.get_complexity(&self.history))
+            .and_then(|complexity| block_data.block.validate(
+                &block_data.transactions, state.get_last_block_info(), 
+                complexity, state, senders
+            ))
+            .map_err(|err| err.with_bix(bix))
+    }
+
+    /// Calculate senders of the next block transactions before the state is
+    /// rolled up with the current block transactions.
+    fn calc_senders_next(transactions: &[Transaction],
+                         transactions_current: &[Transaction], state: &State,
+                         schema: &Schema) -> Vec<U256> {
+        let coins: HashSet<&U256> = transactions_current.iter()
+            .map(|tr| &tr.coin).collect();
+        transactions.iter().map(|tr| {
+            let counter = state.get_coin_counter(&tr.coin) +
+                          coins.contains(&tr.coin) as u64;
+            let signature = (tr.sign_r.clone(), tr.sign_s.clone());
+            schema.extract_public(
+                &tr.get_msg_of_chain(counter, state.get_chain_id()), &signature
+            )
+        }).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::block::Block;
+    use crate::coin::coin_random;
+    use crate::error::ErrorKind;
+
+    /// Blocks where the coin goes to the second owner and back, and an empty
+    /// block. Returns the blocks, the state after them and the coin.
+    fn build_blocks(schema: &Schema, 
+                    complexity: usize) -> (Vec<BlockData>, State, U256) {
+        let mut rng = rand::rng();
+        let (key1, public1) = schema.gen_pair(&mut rng);
+        let (key2, public2) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public1);
+
+        // The coin goes to the second owner and back in the next block
+        let transactions_list = vec![
+            vec![Transaction::build(&mut rng, coin.clone(), public2.clone(),
+                                    &key1, 0, schema)],
+            vec![Transaction::build(&mut rng, coin.clone(), public1.clone(),
+                                    &key2, 1, schema)],
+            vec![],
+        ];
+
+        // Build the blocks
+        let mut state = State::new();
+        let mut blocks = Vec::new();
+        for (ix, transactions) in transactions_list.into_iter().enumerate() {
+            let timestamp = 1700000000 + ix as u64;
+            let info = state.get_last_block_info().clone();
+            let senders = Transaction::calc_senders(&transactions, &state,
+                                                    schema);
+            let nonce = Block::mine(&mut rng, &info, &validator,
+                                    timestamp, &transactions, complexity,
+                                    None).unwrap();
+            let block = Block::build(&info, validator.clone(), timestamp,
+                                     &transactions, U256::from_bytes(&nonce),
+                                     complexity, &state, &senders).unwrap();
+            state.roll_up(info.bix + 1, &block, &transactions, schema);
+            blocks.push(BlockData { bix: info.bix + 1, block, transactions });
+        }
+
+        (blocks, state, coin)
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let complexity = 4;
+        let (blocks, state, coin) = build_blocks(&schema, complexity);
+        let owner = blocks[1].transactions[0].addr.clone();
+
+        // Validate from scratch
+        let mut validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity);
+        let mut state2 = State::new();
+        assert_eq!(validator.validate_chain(&blocks, &mut state2, &schema)
+                       .unwrap(), 3);
+        assert_eq!(state2.get_last_block_info().hash,
+                   state.get_last_block_info().hash);
+        assert_eq!(state2.get_owner(&coin), Some(&owner));
+        assert_eq!(validator.get_history().len(), 4);
+
+        // The state stays at the last valid block
+        let mut blocks2 = blocks.clone();
+        blocks2[2].block.nonce = rng.random();
+        let mut validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity);
+        let mut state3 = State::new();
+        let err = validator.validate_chain(&blocks2, &mut state3, &schema)
+                           .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BlockInvalidHash);
+        assert_eq!(err.context().unwrap().bix, Some(3));
+        assert_eq!(state3.get_last_block_info().bix, 2);
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let schema = Schema::new();
+        let complexity = 4;
+        let (blocks, _, _) = build_blocks(&schema, complexity);
+
+        // No built-in checkpoints yet
+        assert_eq!(Checkpoint::get_builtin().len(), CHECKPOINTS.len());
+        let validator = ChainValidator::new(Retarget::default());
+        assert_eq!(validator.get_last_checkpoint(), 
+                   Checkpoint::get_builtin().last().cloned());
+
+        // Matching checkpoint (and one beyond the chain)
+        let checkpoint = Checkpoint::new(2, blocks[1].block.hash.clone());
+        let mut validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity)
+            .with_checkpoints(vec![checkpoint.clone()]);
+        assert_eq!(validator.get_last_checkpoint(), Some(checkpoint));
+        assert!(validator.validate_chain(&blocks, &mut State::new(), &schema)
+                         .is_ok());
+
+        let far = Checkpoint::new(10, U256::from(1));
+        let mut validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity)
+            .with_checkpoints(vec![far.clone()]);
+        assert_eq!(validator.get_last_checkpoint(), Some(far));
+        assert!(validator.validate_chain(&blocks, &mut State::new(), &schema)
+                         .is_ok());
+
+        // Mismatch, the state stays at the block before the checkpoint
+        let hash = blocks[1].block.hash.clone();
+        let mut validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity)
+            .with_checkpoints(vec![Checkpoint::new(2, U256::from(1))]);
+        let mut state = State::new();
+        let err = validator.validate_chain(&blocks, &mut state, &schema)
+                           .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BlockCheckpointMismatch);
+        assert_eq!(err.context().unwrap().bix, Some(2));
+        assert_eq!(state.get_last_block_info().bix, 1);
+
+        // A checkpoint replaces the one of the same height
+        let mut validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity)
+            .with_checkpoints(vec![Checkpoint::new(2, U256::from(1))])
+            .with_checkpoints(vec![Checkpoint::new(2, hash)]);
+        assert!(validator.validate_chain(&blocks, &mut State::new(), &schema)
+                         .is_ok());
+    }
+}
@@ -0,0 +1,240 @@
+//! Canonical binary encoding of the protocol structures.
+//!
+//! The layout is independent of `serde` and `Lbasedb` internals, so nodes
+//! written in other languages can interoperate. The rules are:
+//!
+//! - Integers (`u8`, `u32`, `u64`) are written in little-endian order.
+//! - `U256` values take 32 bytes in little-endian order (the same as
+//!   `U256::to_bytes`, that is used in hashing).
+//! - Optional values are prefixed with a flag byte (`0` or `1`); if the value
+//!   is missing, its place is filled with zeros, so the width does not change.
+//! - Sequences are prefixed with their length as `u32`.
+//!
+//! Decoding is strict: the flags must be `0` or `1`, padding must be zero and
+//! no bytes may remain after the structure, so each value has exactly one
+//! encoding. The layouts of the particular structures are documented in their
+//! `Codec` implementations.
+
+use crate::validate;
+use crate::utils::*;
+
+
+/// Canonical encoding of a structure.
+pub trait Codec: Sized {
+    /// Write the structure into the writer.
+    fn encode(&self, writer: &mut Writer);
+
+    /// Read the structure from the reader.
+    fn decode(reader: &mut Reader) -> UqoinResult<Self>;
+
+    /// Encode the structure into bytes.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Decode the structure from bytes. All the bytes must be consumed.
+    fn from_bytes(bytes: &[u8]) -> UqoinResult<Self> {
+        let mut reader = Reader::new(bytes);
+        let instance = Self::decode(&mut reader)?;
+        validate!(reader.is_empty(), EncodingInvalid)?;
+        Ok(instance)
+    }
+}
+
+
+/// Byte buffer to encode structures into.
+#[derive(Debug, Clone, Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+
+impl Writer {
+    /// Create an empty writer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the written bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Number of the written bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Check if nothing is written.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Write a byte.
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    /// Write `u32`.
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    /// Write `u64`.
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    /// Write `U256`.
+    pub fn write_u256(&mut self, value: &U256) {
+        self.bytes.extend(value.to_bytes());
+    }
+
+    /// Write raw bytes as they are.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Write a flag of an optional value.
+    pub fn write_flag(&mut self, flag: bool) {
+        self.write_u8(flag as u8);
+    }
+
+    /// Write a sequence of structures prefixed with its length.
+    pub fn write_seq<T: Codec>(&mut self, items: &[T]) {
+        self.write_u32(items.len() as u32);
+        for item in items.iter() {
+            item.encode(self);
+        }
+    }
+}
+
+
+/// Cursor over bytes to decode structures from.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+
+impl<'a> Reader<'a> {
+    /// Create a reader at the beginning of the bytes.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    /// Number of remaining bytes.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    /// Check if all the bytes are consumed.
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Read `size` raw bytes.
+    pub fn read_bytes(&mut self, size: usize) -> UqoinResult<&'a [u8]> {
+        validate!(size <= self.remaining(), EncodingInvalid)?;
+        let bytes = &self.bytes[self.pos..self.pos + size];
+        self.pos += size;
+        Ok(bytes)
+    }
+
+    /// Read a byte.
+    pub fn read_u8(&mut self) -> UqoinResult<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    /// Read `u32`.
+    pub fn read_u32(&mut self) -> UqoinResult<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    /// Read `u64`.
+    pub fn read_u64(&mut self) -> UqoinResult<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Read `U256`.
+    pub fn read_u256(&mut self) -> UqoinResult<U256> {
+        Ok(U256::from_bytes(self.read_bytes(32)?))
+    }
+
+    /// Read a flag of an optional value (`0` or `1`).
+    pub fn read_flag(&mut self) -> UqoinResult<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(crate::error::ErrorKind::EncodingInvalid.into()),
+        }
+    }
+
+    /// Read `size` bytes of padding that must be zero.
+    pub fn read_padding(&mut self, size: usize) -> UqoinResult<()> {
+        let bytes = self.read_bytes(size)?;
+        validate!(bytes.iter().all(|b| *b == 0), EncodingInvalid)
+    }
+
+    /// Read a sequence of structures prefixed with its length.
+    pub fn read_seq<T: Codec>(&mut self) -> UqoinResult<Vec<T>> {
+        let size = self.read_u32()? as usize;
+        // Each structure takes at least one byte, it protects from huge
+        // allocations on broken lengths
+        validate!(size <= self.remaining(), EncodingInvalid)?;
+        (0..size).map(|_| T::decode(self)).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_reader_writer() {
+        let mut writer = Writer::new();
+        writer.write_u8(7);
+        writer.write_u32(1000);
+        writer.write_u64(u64::MAX - 1);
+        writer.write_u256(&U256::from(12345));
+        writer.write_flag(true);
+        writer.write_bytes(&[0, 0]);
+        let bytes = writer.into_bytes();
+        assert_eq!(bytes.len(), 1 + 4 + 8 + 32 + 1 + 2);
+        assert_eq!(&bytes[1..5], &[232, 3, 0, 0]);
+
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(reader.read_u8().unwrap(), 7);
+        assert_eq!(reader.read_u32().unwrap(), 1000);
+        assert_eq!(reader.read_u64().unwrap(), u64::MAX - 1);
+        assert_eq!(reader.read_u256().unwrap(), U256::from(12345));
+        assert!(reader.read_flag().unwrap());
+        assert!(reader.read_padding(2).is_ok());
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_u8().unwrap_err().kind(),
+                   ErrorKind::EncodingInvalid);
+    }
+
+    #[test]
+    fn test_reader_strict() {
+        assert!(Reader::new(&[2]).read_flag().is_err());
+        assert!(Reader::new(&[0, 1]).read_padding(2).is_err());
+        assert!(Reader::new(&[255, 255, 255, 255]).read_seq::<Dummy>()
+                .is_err());
+    }
+
+    struct Dummy;
+
+    impl Codec for Dummy {
+        fn encode(&self, _writer: &mut Writer) {}
+
+        fn decode(_reader: &mut Reader) -> UqoinResult<Self> {
+            Ok(Self)
+        }
+    }
+}
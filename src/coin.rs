@@ -13,7 +13,15 @@
 //!
 //! This module includes functions for coin validation, order and value
 //! computation, symbol conversion, random coin generation, and mining.
+//! Since `coin_order` requires hashing, it can be memoized with
+//! `CoinOrderCache`.
+//!
+//! Note: the order hash is not tagged (unlike the messages of the latest
+//! transactions and blocks), because coins carry no version and the orders
+//! of the existing coins must not change.
+
 
+use std::sync::Mutex;
 
 use rand::Rng;
 
@@ -88,6 +96,57 @@ pub fn coin_mine<R: Rng>(rng: &mut R, miner: &U256,
 }
 
 
+/// Memoization cache for `coin_order` keyed on the coin and the miner. The
+/// order is a pure function of its arguments, so a single cache can be shared
+/// (for example, through `Arc`) between `State`, `Group` and `Pool`. When the
+/// capacity is exceeded, the least recently used entry is evicted.
+#[derive(Debug)]
+pub struct CoinOrderCache {
+    inner: Mutex<LruCache<(U256, U256), u64>>,
+}
+
+
+impl CoinOrderCache {
+    /// Create an empty cache keeping at most `capacity` orders.
+    pub fn new(capacity: usize) -> Self {
+        Self { inner: Mutex::new(LruCache::new(capacity)) }
+    }
+
+    /// Maximum number of cached orders.
+    pub fn capacity(&self) -> usize {
+        self.inner.lock().unwrap().capacity()
+    }
+
+    /// Current number of cached orders.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all cached orders.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    /// Get order of the coin from the cache or calculate it with `coin_order`
+    /// on miss.
+    pub fn get(&self, coin: &U256, miner: &U256) -> u64 {
+        let key = (coin.clone(), miner.clone());
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(order) = inner.get(&key) {
+            return *order;
+        }
+        let order = coin_order(coin, miner);
+        inner.insert(key, order);
+        order
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -144,6 +203,32 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_coin_order_cache() {
+        let miner = U256::from_hex(
+            "E7646626CB303A9EEBAAD078ACD56328DC4BFFC745FD5063738D9E10BF513204"
+        );
+
+        let mut rng = rand::rng();
+        let coins = (0..3).map(|_| coin_random(&mut rng, &miner))
+            .collect::<Vec<U256>>();
+
+        let cache = CoinOrderCache::new(2);
+        assert!(cache.is_empty());
+
+        for coin in coins.iter() {
+            assert_eq!(cache.get(coin, &miner), coin_order(coin, &miner));
+        }
+        assert_eq!(cache.len(), 2);
+
+        // The first coin was evicted, so it is recalculated correctly
+        assert_eq!(cache.get(&coins[0], &miner), coin_order(&coins[0], &miner));
+        assert_eq!(cache.len(), 2);
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
     #[bench]
     fn bench_gen_random(bencher: &mut Bencher) {
         let miner = U256::from_hex(
@@ -0,0 +1,201 @@
+//! Multiset commitments to the coin sets.
+//!
+//! `Commitment` is the elliptic curve multiset hash (ECMH) over Ed25519: each
+//! element is hashed to a point of the prime order subgroup and the
+//! commitment is the sum of the points. So it does not depend on the order of
+//! the elements and it is updated incrementally: an element is inserted by
+//! adding its point and removed by subtracting it. Unlike XOR of hashes, the
+//! sum is not linear over the hashes, so a different multiset with the same
+//! commitment cannot be found by solving linear equations (collisions are as
+//! hard as the discrete logarithm).
+//!
+//! The commitment is exposed as the compressed point (see
+//! `Schema::point_to_number`), the empty multiset commits to the neutral
+//! point.
+
+use std::sync::LazyLock;
+
+use finitelib::prelude::*;
+
+use crate::utils::*;
+use crate::schema::Schema;
+
+
+/// Schema of the curve of the commitments.
+static SCHEMA: LazyLock<Schema> = LazyLock::new(Schema::new);
+
+
+/// Multiset commitment: the sum of the points of the elements.
+#[derive(Debug, Clone)]
+pub struct Commitment {
+    point: (U256, U256, U256),
+}
+
+
+impl Commitment {
+    /// Commitment to the empty multiset.
+    pub fn new() -> Self {
+        Self { point: SCHEMA.curve().zero() }
+    }
+
+    /// Restore the commitment from its number (`None` if the number is not
+    /// a point of the curve).
+    pub fn from_number(number: &U256) -> Option<Self> {
+        let point = SCHEMA.point_from_number(number)?;
+        Some(Self { point: SCHEMA.curve().convert_into(&point) })
+    }
+
+    /// Commitment to the elements (in parallel with the `parallel` feature).
+    pub fn of_elements<T, F>(items: &[T], elem: F) -> Self
+            where T: Sync, F: Fn(&T) -> U256 + Sync + Send {
+        let curve = SCHEMA.curve();
+
+        #[cfg(feature = "parallel")]
+        let point = {
+            use rayon::prelude::*;
+            items.par_iter().map(|item| hash_to_point(&elem(item)))
+                .reduce(|| curve.zero(), |acc, p| curve.add(&acc, &p))
+        };
+
+        #[cfg(not(feature = "parallel"))]
+        let point = items.iter().map(|item| hash_to_point(&elem(item)))
+            .fold(curve.zero(), |acc, p| curve.add(&acc, &p));
+
+        Self { point }
+    }
+
+    /// Get the number of the commitment (the compressed point).
+    pub fn to_number(&self) -> U256 {
+        let point = SCHEMA.curve().convert_from(&self.point);
+        SCHEMA.point_to_number(&point)
+    }
+
+    /// Insert the element.
+    pub fn insert(&mut self, elem: &U256) {
+        self.point = SCHEMA.curve().add(&self.point, &hash_to_point(elem));
+    }
+
+    /// Remove the element (it is expected to be inserted before).
+    pub fn remove(&mut self, elem: &U256) {
+        self.point = SCHEMA.curve().sub(&self.point, &hash_to_point(elem));
+    }
+
+    /// Insert the elements of the other commitment.
+    pub fn merge(&mut self, other: &Self) {
+        self.point = SCHEMA.curve().add(&self.point, &other.point);
+    }
+}
+
+
+impl Default for Commitment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl PartialEq for Commitment {
+    fn eq(&self, other: &Self) -> bool {
+        SCHEMA.curve().eq(&self.point, &other.point)
+    }
+}
+
+
+impl Eq for Commitment {}
+
+
+/// Hash the element to a point of the prime order subgroup: the hash of the
+/// element and a counter is taken as a compressed point until it is valid,
+/// then the point is multiplied by the cofactor.
+fn hash_to_point(elem: &U256) -> (U256, U256, U256) {
+    let curve = SCHEMA.curve();
+    let mut ix: u64 = 0;
+    loop {
+        // Candidate with a canonical y
+        let number = hash_of_u256([elem, &U256::from(ix)].into_iter());
+        ix += 1;
+        let mut y = number.clone();
+        y.bit_set(255, false);
+        if y >= curve.base.modulo {
+            continue;
+        }
+
+        // Clear the cofactor (8) with three doublings
+        if let Some(point) = SCHEMA.point_from_number(&number) {
+            let mut point = curve.convert_into(&point);
+            for _ in 0..3 {
+                point = curve.add(&point, &point);
+            }
+            if !curve.eq(&point, &curve.zero()) {
+                return point;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_commitment() {
+        let mut rng = rand::rng();
+        let elems: Vec<U256> = (0..10).map(|_| rng.random()).collect();
+
+        // Empty multiset
+        assert_eq!(Commitment::new().to_number(),
+                   SCHEMA.point_to_number(&SCHEMA.curve().base.zero()));
+
+        // Order does not matter
+        let commitment = Commitment::of_elements(&elems, |elem| elem.clone());
+        let mut reversed = Commitment::new();
+        for elem in elems.iter().rev() {
+            reversed.insert(elem);
+        }
+        assert_eq!(reversed, commitment);
+        assert_eq!(reversed.to_number(), commitment.to_number());
+
+        // Merge of the halves
+        let mut merged = Commitment::of_elements(&elems[..4],
+                                                 |elem| elem.clone());
+        merged.merge(&Commitment::of_elements(&elems[4..],
+                                              |elem| elem.clone()));
+        assert_eq!(merged, commitment);
+
+        // Removal of all elements
+        for elem in elems.iter() {
+            reversed.remove(elem);
+        }
+        assert_eq!(reversed, Commitment::new());
+
+        // Restore from the number
+        let restored = Commitment::from_number(&commitment.to_number())
+            .unwrap();
+        assert_eq!(restored, commitment);
+        assert_eq!(restored.to_number(), commitment.to_number());
+    }
+
+    #[test]
+    fn test_commitment_multiset() {
+        let mut rng = rand::rng();
+        let elems: Vec<U256> = (0..3).map(|_| rng.random()).collect();
+        let commitment = Commitment::of_elements(&elems, |elem| elem.clone());
+
+        // Other multisets
+        let mut other = commitment.clone();
+        other.insert(&elems[0]);
+        assert_ne!(other, commitment);
+        other.remove(&elems[1]);
+        assert_ne!(other, commitment);
+        assert_ne!(Commitment::of_elements(&elems[1..], |elem| elem.clone()),
+                   commitment);
+
+        // The point is of the prime order subgroup
+        let curve = SCHEMA.curve();
+        let point = curve.mul_scalar(&commitment.point,
+                                     curve.base.order.bit_iter());
+        assert!(curve.eq(&point, &curve.zero()));
+    }
+}
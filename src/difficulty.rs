@@ -0,0 +1,341 @@
+//! Retargeting of the block complexity.
+//!
+//! The complexity is the number of leading zero bits required in the block
+//! hash (for an empty block), so increasing it by one doubles the expected 
+//! mining time. To keep the block production rate stable as the hash power
+//! changes, the complexity is recalculated once per `window` blocks from the 
+//! average interval between the timestamps of the last `window` blocks: it is
+//! increased if the blocks were produced more than twice as fast as the 
+//! target interval and decreased if more than twice as slow.
+//!
+//! The complexity of legacy blocks (without timestamps) is the initial one
+//! (`COMPLEXITY` on the mainnet, see `ChainSpec`), the retargeting starts 
+//! after `window` timed blocks. The result must not depend on how many infos
+//! the caller has, so the retarget without the infos of the window is 
+//! `BlockHistoryMissing` instead of keeping the complexity.
+//!
+//! Since the span of the window sets the complexity, the timestamps are 
+//! bounded (see `Retarget::validate_timestamp`): a timestamp cannot be below
+//! the median of the last `median_span` ones (median time past) and it 
+//! cannot be more than `max_future_drift` seconds ahead of the local clock. 
+//! So a validator cannot stretch the span to drive the complexity down.
+//!
+//! `calibrate_complexity` measures the local hash rate and finds the 
+//! complexity that gives the target block time on this machine, it is useful
+//! for node setup tools and private chains.
+
+use std::time::Instant;
+
+use serde::{Serialize, Deserialize};
+
+use crate::validate;
+use crate::utils::*;
+use crate::block::{Block, BlockInfo, COMPLEXITY};
+
+
+/// Target interval between blocks in seconds.
+pub const TARGET_INTERVAL: u64 = 10;
+
+/// Number of blocks between retargets.
+pub const RETARGET_WINDOW: usize = 64;
+
+/// Number of the last blocks for the median time past.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Maximum time of the block ahead of the local clock in seconds.
+pub const MAX_FUTURE_DRIFT: u64 = 300;
+
+
+/// Parameters of the complexity retargeting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Retarget {
+    /// Target interval between blocks in seconds.
+    pub target_interval: u64,
+
+    /// Number of blocks between retargets, it is also the number of intervals
+    /// to average.
+    pub window: usize,
+
+    /// Minimum complexity.
+    pub min_complexity: usize,
+
+    /// Maximum complexity.
+    pub max_complexity: usize,
+
+    /// Complexity of the first blocks and legacy blocks.
+    #[serde(default = "default_initial_complexity")]
+    pub initial_complexity: usize,
+
+    /// Number of the last blocks for the median time past.
+    #[serde(default = "default_median_span")]
+    pub median_span: usize,
+
+    /// Maximum time of the block ahead of the local clock in seconds.
+    #[serde(default = "default_max_future_drift")]
+    pub max_future_drift: u64,
+}
+
+
+fn default_initial_complexity() -> usize {
+    COMPLEXITY
+}
+
+
+fn default_median_span() -> usize {
+    MEDIAN_TIME_SPAN
+}
+
+
+fn default_max_future_drift() -> u64 {
+    MAX_FUTURE_DRIFT
+}
+
+
+impl Default for Retarget {
+    fn default() -> Self {
+        Self {
+            target_interval: TARGET_INTERVAL,
+            window: RETARGET_WINDOW,
+            min_complexity: 1,
+            max_complexity: 128,
+            initial_complexity: COMPLEXITY,
+            median_span: MEDIAN_TIME_SPAN,
+            max_future_drift: MAX_FUTURE_DRIFT,
+        }
+    }
+}
+
+
+impl Retarget {
+    /// Number of the last block infos required by `complexity_for_height`
+    /// and `get_median_time_past`.
+    pub fn get_history_size(&self) -> usize {
+        (self.window + 1).max(self.median_span)
+    }
+
+    /// Calculate the complexity for the next block after the `chain`, that is
+    /// the consecutive infos of the last blocks ordered by `bix`. At the end
+    /// of the window the info of the block `window` blocks back is required 
+    /// (`BlockHistoryMissing` otherwise), so `get_history_size()` infos are 
+    /// enough. The result is used for `Block::build` and `Block::validate`.
+    pub fn complexity_for_height(&self, 
+                                 chain: &[BlockInfo]) -> UqoinResult<usize> {
+        // Complexity of the last block
+        let last = match chain.last() {
+            Some(last) => last,
+            None => return Ok(self.initial_complexity),
+        };
+        let complexity = if last.complexity > 0 {
+            last.complexity as usize
+        } else {
+            self.initial_complexity
+        };
+
+        // Retarget only at the end of the window (never with zero window), 
+        // the window starting from the genesis (untimed) is not retargeted
+        let window = self.window as u64;
+        if (window == 0) || !last.bix.is_multiple_of(window) || 
+                (last.bix <= window) {
+            return Ok(complexity);
+        }
+
+        // Timed blocks of the window
+        let first = Self::get_info(chain, last.bix - window)?;
+        if first.timestamp == 0 {
+            return Ok(complexity);
+        }
+
+        // Compare the time span with the target one
+        let span = last.timestamp.saturating_sub(first.timestamp);
+        let target = self.target_interval * self.window as u64;
+
+        let complexity = if 2 * span < target {
+            complexity + 1
+        } else if span > 2 * target {
+            complexity.saturating_sub(1)
+        } else {
+            complexity
+        };
+
+        Ok(complexity.clamp(self.min_complexity, self.max_complexity))
+    }
+
+    /// Median of the timestamps of the last `median_span` blocks of the 
+    /// `chain` (consecutive infos ordered by `bix`), the genesis is not 
+    /// counted. Fewer blocks are used only in the beginning of the chain, 
+    /// missing infos are `BlockHistoryMissing`.
+    pub fn get_median_time_past(&self, 
+                                chain: &[BlockInfo]) -> UqoinResult<u64> {
+        let last = match chain.last() {
+            Some(last) => last,
+            None => return Ok(0),
+        };
+        let size = (self.median_span as u64).min(last.bix);
+        if size == 0 {
+            return Ok(0);
+        }
+        Self::get_info(chain, last.bix + 1 - size)?;
+        let mut timestamps: Vec<u64> = chain[chain.len() - size as usize ..]
+            .iter().map(|info| info.timestamp).collect();
+        timestamps.sort();
+        Ok(timestamps[timestamps.len() / 2])
+    }
+
+    /// Validate the timestamp of the block that follows the `chain`: it must
+    /// not be below the median time past and not above `now` (the local 
+    /// clock) by more than `max_future_drift`. Legacy blocks (zero 
+    /// timestamp) are not checked.
+    pub fn validate_timestamp(&self, timestamp: u64, chain: &[BlockInfo], 
+                              now: u64) -> UqoinResult<()> {
+        if timestamp == 0 {
+            return Ok(());
+        }
+        let median = self.get_median_time_past(chain)?;
+        validate!(timestamp >= median, BlockInvalidTimestamp)
+            .map_err(|err| err.with_values(format!(">= {}", median), 
+                                           timestamp))?;
+        let limit = now.saturating_add(self.max_future_drift);
+        validate!(timestamp <= limit, BlockInvalidTimestamp)
+            .map_err(|err| err.with_values(format!("<= {}", limit), 
+                                           timestamp))
+    }
+
+    /// Get the info of the block `bix` from the consecutive infos.
+    fn get_info(chain: &[BlockInfo], bix: u64) -> UqoinResult<&BlockInfo> {
+        let last = chain.last().map(|info| info.bix).unwrap_or(0);
+        let info = (last - bix).try_into().ok()
+            .and_then(|back: usize| chain.len().checked_sub(back + 1))
+            .map(|ix| &chain[ix])
+            .filter(|info| info.bix == bix);
+        validate!(info.is_some(), BlockHistoryMissing)
+            .map_err(|err| err.with_values(bix, chain.first()
+                                                     .map(|info| info.bix)
+                                                     .unwrap_or(0)))?;
+        Ok(info.unwrap())
+    }
+}
+
+
+/// Measure the local hash rate (hashes per second in one thread) mining an
+/// empty block message for `sample_iterations` attempts.
+pub fn measure_hash_rate(sample_iterations: usize) -> f64 {
+    let mut rng = rand::rng();
+    let msg = U256::from(0);
+
+    // Maximum complexity, so no nonce is found and all the attempts are made
+    let instant = Instant::now();
+    Block::mine_msg(&mut rng, &msg, 0, 256, Some(sample_iterations));
+    let elapsed = instant.elapsed().as_secs_f64();
+
+    sample_iterations as f64 / elapsed.max(f64::EPSILON)
+}
+
+
+/// Complexity that gives `target_seconds` per empty block on average for the
+/// hash rate (hashes per second). Each extra unit of the complexity doubles
+/// the number of attempts, so it is `log2(target_seconds * hash_rate)` 
+/// rounded and clamped to `1..=256` (the size of the hash in bits).
+pub fn complexity_for_hash_rate(hash_rate: f64, target_seconds: f64) -> usize {
+    let complexity = (target_seconds * hash_rate).log2().round() as usize;
+    complexity.clamp(1, 256)
+}
+
+
+/// Calibrate the complexity for the target block time measuring the local
+/// hash rate (one thread) with `sample_iterations` attempts.
+pub fn calibrate_complexity(target_seconds: f64, 
+                            sample_iterations: usize) -> usize {
+    complexity_for_hash_rate(measure_hash_rate(sample_iterations), 
+                             target_seconds)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn build_chain(size: u64, interval: u64, complexity: u64) -> 
+                   Vec<BlockInfo> {
+        (1..=size).map(|bix| BlockInfo {
+            bix, 
+            offset: 0, 
+            hash: U256::from(bix),
+            timestamp: 1700000000 + bix * interval,
+            complexity,
+        }).collect()
+    }
+
+    #[test]
+    fn test_complexity_for_height() {
+        let retarget = Retarget { window: 4, ..Retarget::default() };
+
+        assert_eq!(retarget.complexity_for_height(&[]).unwrap(), COMPLEXITY);
+        assert_eq!(retarget.complexity_for_height(&[BlockInfo::genesis()])
+                           .unwrap(), COMPLEXITY);
+
+        // Fast blocks increase the complexity at the end of the window
+        let chain = build_chain(8, 2, 20);
+        assert_eq!(retarget.complexity_for_height(&chain).unwrap(), 21);
+        assert_eq!(retarget.complexity_for_height(&chain[..7]).unwrap(), 20);
+
+        // Slow blocks decrease it
+        let chain = build_chain(8, 30, 20);
+        assert_eq!(retarget.complexity_for_height(&chain).unwrap(), 19);
+
+        // Target rate keeps it
+        let chain = build_chain(8, 10, 20);
+        assert_eq!(retarget.complexity_for_height(&chain).unwrap(), 20);
+
+        // Not enough history
+        assert_eq!(retarget.complexity_for_height(&chain[4..]).unwrap_err()
+                           .kind(), ErrorKind::BlockHistoryMissing);
+        assert_eq!(retarget.complexity_for_height(&chain[3..]).unwrap(), 20);
+
+        // Zero window never retargets
+        let retarget = Retarget { window: 0, ..Retarget::default() };
+        assert_eq!(retarget.complexity_for_height(&chain).unwrap(), 20);
+    }
+
+    #[test]
+    fn test_validate_timestamp() {
+        let retarget = Retarget { median_span: 5, max_future_drift: 100, 
+                                  ..Retarget::default() };
+        let chain = build_chain(8, 10, 20);
+        let now = chain[7].timestamp;
+
+        // Median time past
+        assert_eq!(retarget.get_median_time_past(&[]).unwrap(), 0);
+        assert_eq!(retarget.get_median_time_past(&chain[..2]).unwrap(), 
+                   chain[1].timestamp);
+        assert_eq!(retarget.get_median_time_past(&chain).unwrap(), 
+                   chain[5].timestamp);
+        assert_eq!(retarget.get_median_time_past(&chain[4..]).unwrap_err()
+                           .kind(), ErrorKind::BlockHistoryMissing);
+
+        // Bounds
+        assert!(retarget.validate_timestamp(chain[5].timestamp, &chain, now)
+                        .is_ok());
+        assert!(retarget.validate_timestamp(now + 100, &chain, now).is_ok());
+        assert!(retarget.validate_timestamp(0, &chain, now).is_ok());
+        assert_eq!(retarget.validate_timestamp(chain[5].timestamp - 1, 
+                                               &chain, now)
+                           .unwrap_err().kind(), 
+                   ErrorKind::BlockInvalidTimestamp);
+        assert_eq!(retarget.validate_timestamp(now + 101, &chain, now)
+                           .unwrap_err().kind(), 
+                   ErrorKind::BlockInvalidTimestamp);
+    }
+
+    #[test]
+    fn test_calibrate_complexity() {
+        assert_eq!(complexity_for_hash_rate(1048576.0, 16.0), 24);
+        assert_eq!(complexity_for_hash_rate(1000.0, 0.001), 1);
+        assert_eq!(complexity_for_hash_rate(f64::INFINITY, 1.0), 256);
+        assert_eq!(complexity_for_hash_rate(f64::NAN, 1.0), 1);
+        assert!(measure_hash_rate(1000) > 0.0);
+
+        let complexity = calibrate_complexity(1.0, 1000);
+        assert!((1..=64).contains(&complexity));
+    }
+}
@@ -0,0 +1,513 @@
+//! Disk-backed state for large chains.
+//!
+//! `State` keeps all the coins in memory and is serialized as a whole, that
+//! is too much for chains with millions of coins. `DiskState` keeps the coin
+//! infos in an on-disk hash table (a `Lbasedb` column of fixed-size slots
+//! with linear probing) and caches the recently used ones in memory. The
+//! table is doubled when it is half full.
+//!
+//! To validate and apply a block, a partial `State` with the coins of the
+//! block is loaded by `DiskState::load_state`, so the validation works
+//! unchanged. `DiskState::roll_up` and `DiskState::roll_down` apply the block
+//! to the partial state and write the changed coins back.
+//!
+//! The coins of an owner cannot be found without scanning the whole table, so
+//! they are kept in memory for the tracked owners only (e.g. the validator
+//! itself, its coins are needed for extensions), see
+//! `DiskState::track_owner`.
+//!
+//! The chain spec (see `DiskState::with_chain_spec`) and the last block info
+//! are kept in `state.json`. It is replaced atomically after the slots are
+//! synced, so a crash leaves either the previous or the new metadata.
+
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+use tokio::io::{Result as TokioResult, AsyncWriteExt};
+use tokio::sync::Mutex;
+use lbasedb::col::Col;
+use lbasedb::path_concat;
+
+use crate::utils::*;
+use crate::schema::Schema;
+use crate::spec::ChainSpec;
+use crate::blockchain::{sync_file, sync_dir};
+use crate::block::{Block, BlockInfo};
+use crate::transaction::Transaction;
+use crate::state::{State, CoinInfo, CoinInfoMap, OrderCoinsMap};
+
+
+/// Initial number of slots in the coin table.
+pub const DISK_STATE_CAPACITY: usize = 1024;
+
+/// Maximum number of cached coin infos.
+pub const DISK_STATE_CACHE_SIZE: usize = 65536;
+
+/// Number of slots read at once on table scans.
+const SCAN_CHUNK: usize = 4096;
+
+const SLOT_EMPTY: u64 = 0;
+const SLOT_USED: u64 = 1;
+const SLOT_DELETED: u64 = 2;
+
+
+/// Slot of the coin table. Zero bytes mean an empty slot.
+#[derive(Debug, Clone)]
+struct CoinSlot {
+    flag: u64,
+    coin: U256,
+    owner: U256,
+    order: u64,
+    counter: u64,
+}
+
+
+impl CoinSlot {
+    fn new(flag: u64, coin: &U256, coin_info: &CoinInfo) -> Self {
+        Self {
+            flag,
+            coin: coin.clone(),
+            owner: coin_info.owner.clone(),
+            order: coin_info.order,
+            counter: coin_info.counter,
+        }
+    }
+
+    fn get_coin_info(&self) -> CoinInfo {
+        CoinInfo {
+            owner: self.owner.clone(),
+            order: self.order,
+            counter: self.counter,
+        }
+    }
+}
+
+
+/// Metadata of the state stored next to the table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DiskStateMeta {
+    used: usize,
+    deleted: usize,
+    last_block_info: BlockInfo,
+    #[serde(default)]
+    chain_spec: ChainSpec,
+}
+
+
+/// State with the coins stored on disk.
+pub struct DiskState {
+    path: String,
+    slot_col: Mutex<Col<CoinSlot>>,
+    capacity: usize,
+    meta: DiskStateMeta,
+    cache: Mutex<HashMap<U256, Option<CoinInfo>>>,
+    tracked: HashMap<U256, CoinInfoMap>,
+}
+
+
+impl DiskState {
+    /// Open the state at the path or create an empty one.
+    pub async fn new(path: &str) -> TokioResult<Self> {
+        let slot_col = Col::<CoinSlot>::new(
+            path_concat!(path, "coins.col")
+        ).await?;
+        if slot_col.size().await? == 0 {
+            slot_col.resize(DISK_STATE_CAPACITY).await?;
+        }
+        let capacity = slot_col.size().await?;
+
+        // Leftover of the interrupted metadata write
+        let meta_path = path_concat!(path, "state.json");
+        let meta_path_tmp = format!("{}.tmp", meta_path);
+        if tokio::fs::try_exists(&meta_path_tmp).await? {
+            tokio::fs::remove_file(&meta_path_tmp).await?;
+        }
+
+        let meta = if tokio::fs::try_exists(&meta_path).await? {
+            serde_json::from_slice(&tokio::fs::read(&meta_path).await?)?
+        } else {
+            DiskStateMeta {
+                used: 0,
+                deleted: 0,
+                last_block_info: BlockInfo::genesis(),
+                chain_spec: ChainSpec::mainnet(),
+            }
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            slot_col: Mutex::new(slot_col),
+            capacity,
+            meta,
+            cache: Mutex::new(HashMap::new()),
+            tracked: HashMap::new(),
+        })
+    }
+
+    /// Set the chain spec (the mainnet one is the default): the empty state
+    /// starts from the genesis of the chain and the partial states are
+    /// loaded with the spec (see `State::with_chain_spec`). `InvalidData` is
+    /// returned if the state is not empty and was built for another spec.
+    pub async fn with_chain_spec(mut self, 
+                                 spec: ChainSpec) -> TokioResult<Self> {
+        if self.meta.last_block_info.bix == 0 {
+            self.meta.last_block_info = spec.get_genesis_info();
+        } else if self.meta.chain_spec != spec {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        self.meta.chain_spec = spec;
+        self.save_meta().await?;
+        Ok(self)
+    }
+
+    /// Get the chain spec of the state.
+    pub fn get_chain_spec(&self) -> &ChainSpec {
+        &self.meta.chain_spec
+    }
+
+    /// Get last block info.
+    pub fn get_last_block_info(&self) -> &BlockInfo {
+        &self.meta.last_block_info
+    }
+
+    /// Get number of coins.
+    pub fn get_coin_count(&self) -> usize {
+        self.meta.used
+    }
+
+    /// Get coin state by number.
+    pub async fn get_coin_info(&self, coin: &U256) ->
+                               TokioResult<Option<CoinInfo>> {
+        if let Some(coin_info) = self.cache.lock().await.get(coin) {
+            return Ok(coin_info.clone());
+        }
+
+        let (_, slot) = self.find_slot(coin).await?;
+        let coin_info = slot.map(|slot| slot.get_coin_info());
+        self.cache_insert(coin, coin_info.clone()).await;
+        Ok(coin_info)
+    }
+
+    /// Track coins of the owner in memory. It scans the whole table.
+    pub async fn track_owner(&mut self, owner: &U256) -> TokioResult<()> {
+        let mut coin_info_map = CoinInfoMap::new();
+        let mut slot_col = self.slot_col.lock().await;
+        for offset in (0..self.capacity).step_by(SCAN_CHUNK) {
+            let count = SCAN_CHUNK.min(self.capacity - offset);
+            for slot in slot_col.get_many(offset, count).await? {
+                if (slot.flag == SLOT_USED) && (&slot.owner == owner) {
+                    coin_info_map.insert(slot.coin.clone(),
+                                         slot.get_coin_info());
+                }
+            }
+        }
+        self.tracked.insert(owner.clone(), coin_info_map);
+        Ok(())
+    }
+
+    /// Get coins of the tracked owner.
+    pub fn get_coins(&self, owner: &U256) -> Option<OrderCoinsMap> {
+        let coin_info_map = self.tracked.get(owner)?;
+        let mut order_coins_map = OrderCoinsMap::new();
+        for (coin, coin_info) in coin_info_map.iter() {
+            order_coins_map.entry(coin_info.order).or_default()
+                           .insert(coin.clone());
+        }
+        Some(order_coins_map)
+    }
+
+    /// Load a partial state with the coins of the transactions and the coins
+    /// of the tracked owners. It is enough to validate the block of the
+    /// transactions and to prepare the next block.
+    pub async fn load_state(&self, transactions: &[Transaction]) ->
+                            TokioResult<State> {
+        let mut coin_info_map = CoinInfoMap::new();
+        for coin_infos in self.tracked.values() {
+            coin_info_map.extend(coin_infos.iter().map(
+                |(coin, coin_info)| (coin.clone(), coin_info.clone())
+            ));
+        }
+        for tr in transactions.iter() {
+            if let Some(coin_info) = self.get_coin_info(&tr.coin).await? {
+                coin_info_map.insert(tr.coin.clone(), coin_info);
+            }
+        }
+        Ok(State::from_coin_infos(coin_info_map,
+                                  self.meta.last_block_info.clone())
+               .with_chain_spec(&self.meta.chain_spec))
+    }
+
+    /// Roll up the state with the next block.
+    pub async fn roll_up(&mut self, bix: u64, block: &Block,
+                         transactions: &[Transaction],
+                         schema: &Schema) -> TokioResult<()> {
+        let mut state = self.load_state(transactions).await?;
+        state.roll_up(bix, block, transactions, schema);
+        self.apply(&state, transactions).await
+    }
+
+    /// Roll down the state with the last block (see `State::roll_down`).
+    pub async fn roll_down(&mut self, bix: u64, block: &Block,
+                           transactions: &[Transaction],
+                           schema: &Schema) -> TokioResult<()> {
+        let mut state = self.load_state(transactions).await?;
+        state.roll_down(bix, block, transactions, schema);
+        self.apply(&state, transactions).await
+    }
+
+    /// Write the coins of the transactions and the last block info from the
+    /// partial state.
+    async fn apply(&mut self, state: &State,
+                   transactions: &[Transaction]) -> TokioResult<()> {
+        for tr in transactions.iter() {
+            let coin_info = state.get_coin_info(&tr.coin);
+            self.write_coin_info(&tr.coin, coin_info).await?;
+
+            // Update tracked owners
+            for coin_info_map in self.tracked.values_mut() {
+                coin_info_map.remove(&tr.coin);
+            }
+            if let Some(coin_info) = coin_info &&
+                    let Some(coin_info_map) =
+                        self.tracked.get_mut(&coin_info.owner) {
+                coin_info_map.insert(tr.coin.clone(), coin_info.clone());
+            }
+        }
+
+        self.meta.last_block_info = state.get_last_block_info().clone();
+        self.save_meta().await
+    }
+
+    /// Write the coin info (`None` to delete the coin).
+    async fn write_coin_info(&mut self, coin: &U256,
+                             coin_info: Option<&CoinInfo>) -> TokioResult<()> {
+        // Keep the table at most half full
+        if 2 * (self.meta.used + self.meta.deleted + 1) > self.capacity {
+            self.rehash(2 * self.capacity).await?;
+        }
+
+        let (ix, slot) = self.find_slot(coin).await?;
+        match (coin_info, slot) {
+            (Some(coin_info), slot) => {
+                if slot.is_none() {
+                    // The slot may be a deleted one
+                    let prev = self.slot_col.lock().await.get(ix).await?;
+                    if prev.flag == SLOT_DELETED {
+                        self.meta.deleted -= 1;
+                    }
+                    self.meta.used += 1;
+                }
+                let slot = CoinSlot::new(SLOT_USED, coin, coin_info);
+                self.slot_col.lock().await.update(ix, &slot).await?;
+            },
+            (None, Some(mut slot)) => {
+                slot.flag = SLOT_DELETED;
+                self.slot_col.lock().await.update(ix, &slot).await?;
+                self.meta.used -= 1;
+                self.meta.deleted += 1;
+            },
+            (None, None) => {},
+        }
+
+        self.cache_insert(coin, coin_info.cloned()).await;
+        Ok(())
+    }
+
+    /// Find the slot of the coin. If the coin is missing, the index of the
+    /// slot to insert it is returned.
+    async fn find_slot(&self, coin: &U256) ->
+                       TokioResult<(usize, Option<CoinSlot>)> {
+        let mut slot_col = self.slot_col.lock().await;
+        let mut ix = Self::get_position(coin, self.capacity);
+        let mut deleted_ix = None;
+        loop {
+            let slot = slot_col.get(ix).await?;
+            match slot.flag {
+                SLOT_USED if &slot.coin == coin => return Ok((ix, Some(slot))),
+                SLOT_EMPTY => return Ok((deleted_ix.unwrap_or(ix), None)),
+                SLOT_DELETED if deleted_ix.is_none() => deleted_ix = Some(ix),
+                _ => {},
+            }
+            ix = (ix + 1) % self.capacity;
+        }
+    }
+
+    /// Move all the coins into a new table of the capacity.
+    async fn rehash(&mut self, capacity: usize) -> TokioResult<()> {
+        let path = path_concat!(&self.path, "coins.col");
+        let path_tmp = path_concat!(&self.path, "coins.col.tmp");
+
+        {
+            let mut slot_col = self.slot_col.lock().await;
+            let mut slot_col_tmp = Col::<CoinSlot>::new(&path_tmp).await?;
+            slot_col_tmp.resize(0).await?;
+            slot_col_tmp.resize(capacity).await?;
+
+            for offset in (0..self.capacity).step_by(SCAN_CHUNK) {
+                let count = SCAN_CHUNK.min(self.capacity - offset);
+                for slot in slot_col.get_many(offset, count).await? {
+                    if slot.flag != SLOT_USED {
+                        continue;
+                    }
+                    let mut ix = Self::get_position(&slot.coin, capacity);
+                    while slot_col_tmp.get(ix).await?.flag != SLOT_EMPTY {
+                        ix = (ix + 1) % capacity;
+                    }
+                    slot_col_tmp.update(ix, &slot).await?;
+                }
+            }
+        }
+
+        sync_file(&path_tmp).await?;
+        tokio::fs::rename(&path_tmp, &path).await?;
+        self.slot_col = Mutex::new(Col::<CoinSlot>::new(&path).await?);
+        self.capacity = capacity;
+        self.meta.deleted = 0;
+        self.save_meta().await
+    }
+
+    /// Sync the slots and replace the metadata: it is written to a 
+    /// temporary file, synced and renamed.
+    async fn save_meta(&self) -> TokioResult<()> {
+        sync_file(&path_concat!(&self.path, "coins.col")).await?;
+
+        let meta_path = path_concat!(&self.path, "state.json");
+        let meta_path_tmp = format!("{}.tmp", meta_path);
+        let content = serde_json::to_vec(&self.meta)?;
+        let mut file = tokio::fs::File::create(&meta_path_tmp).await?;
+        file.write_all(&content).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&meta_path_tmp, &meta_path).await?;
+        sync_dir(&self.path).await
+    }
+
+    async fn cache_insert(&self, coin: &U256, coin_info: Option<CoinInfo>) {
+        let mut cache = self.cache.lock().await;
+        if cache.len() >= DISK_STATE_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(coin.clone(), coin_info);
+    }
+
+    fn get_position(coin: &U256, capacity: usize) -> usize {
+        let bytes = coin.to_bytes();
+        u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize % capacity
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::coin::{coin_random, coin_order};
+    use crate::testing::TempDir;
+
+    #[tokio::test]
+    async fn test_disk_state() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let tmp = TempDir::new("uqoin-disk-state");
+        let path = tmp.path().to_string();
+
+        let (key1, public1) = schema.gen_pair(&mut rng);
+        let (key2, public2) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public1);
+        let coin_new = coin_random(&mut rng, &public2);
+
+        let transactions_list = [
+            vec![Transaction::build(&mut rng, coin.clone(), public2.clone(),
+                                    &key1, 0, &schema)],
+            vec![Transaction::build(&mut rng, coin.clone(), public1.clone(),
+                                    &key2, 1, &schema),
+                 Transaction::build(&mut rng, coin_new.clone(),
+                                    public1.clone(), &key2, 0, &schema)],
+        ];
+
+        // Apply the blocks to both states
+        let mut state = State::new();
+        let mut disk_state = DiskState::new(&path).await.unwrap();
+        disk_state.track_owner(&public1).await.unwrap();
+        let mut blocks = Vec::new();
+        for transactions in transactions_list.iter() {
+            let info = state.get_last_block_info().clone();
+            let block = Block::new(info.offset, transactions.len() as u64,
+                                   info.hash.clone(), validator.clone(),
+                                   U256::from(0), rng.random());
+            state.roll_up(info.bix + 1, &block, transactions, &schema);
+            disk_state.roll_up(info.bix + 1, &block, transactions, &schema)
+                      .await.unwrap();
+            blocks.push(block);
+        }
+
+        assert_eq!(disk_state.get_coin_count(), 2);
+        assert_eq!(disk_state.get_last_block_info().hash,
+                   state.get_last_block_info().hash);
+        for coin in [&coin, &coin_new] {
+            let coin_info = disk_state.get_coin_info(coin).await.unwrap()
+                                      .unwrap();
+            assert_eq!(coin_info.owner, public1);
+            assert_eq!(coin_info.counter, state.get_coin_counter(coin));
+        }
+        assert_eq!(disk_state.get_coins(&public1).as_ref(),
+                   state.get_coins(&public1));
+
+        // Rehash and reopen
+        disk_state.rehash(2 * DISK_STATE_CAPACITY).await.unwrap();
+        let mut disk_state = DiskState::new(&path).await.unwrap();
+        assert_eq!(disk_state.get_last_block_info().bix, 2);
+        assert_eq!(disk_state.get_coin_info(&coin).await.unwrap().unwrap()
+                       .counter, 2);
+
+        // Roll down the last block
+        disk_state.roll_down(2, &blocks[1], &transactions_list[1], &schema)
+                  .await.unwrap();
+        assert_eq!(disk_state.get_coin_count(), 1);
+        assert!(disk_state.get_coin_info(&coin_new).await.unwrap().is_none());
+        assert_eq!(disk_state.get_coin_info(&coin).await.unwrap().unwrap()
+                       .owner, public2);
+    }
+
+    #[tokio::test]
+    async fn test_chain_spec() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let spec = ChainSpec::new(2, rng.random()).with_replay_protection(1);
+
+        let tmp = TempDir::new("uqoin-disk-state");
+        let path = tmp.path().to_string();
+
+        let mut disk_state = DiskState::new(&path).await.unwrap()
+            .with_chain_spec(spec.clone()).await.unwrap();
+        assert_eq!(disk_state.get_last_block_info().hash, spec.genesis_hash);
+
+        // The senders are recovered for the chain id of the spec
+        let (key, public) = schema.gen_pair(&mut rng);
+        let coin = coin_random(&mut rng, &public);
+        let mut tr = Transaction::new(coin.clone(), U256::from(100), 
+                                      U256::from(0), U256::from(0))
+            .with_replay_protection();
+        tr.sign_for_chain(&mut rng, &key, 0, spec.chain_id, &schema);
+        let info = disk_state.get_last_block_info().clone();
+        let block = Block::new(info.offset, 1, info.hash.clone(), 
+                               U256::from(1), U256::from(0), rng.random());
+        disk_state.roll_up(1, &block, &[tr], &schema).await.unwrap();
+        let state = disk_state.load_state(&[]).await.unwrap();
+        assert_eq!(state.get_chain_id(), spec.chain_id);
+
+        // The spec is stored with the state
+        tokio::fs::write(path_concat!(&path, "state.json.tmp"), b"{")
+            .await.unwrap();
+        let disk_state = DiskState::new(&path).await.unwrap();
+        assert_eq!(disk_state.get_chain_spec(), &spec);
+        let coin_info = disk_state.get_coin_info(&coin).await.unwrap()
+                                  .unwrap();
+        assert_eq!(coin_info.owner, U256::from(100));
+        assert_eq!(coin_info.order, coin_order(&coin, &public));
+        assert_eq!(disk_state.with_chain_spec(ChainSpec::mainnet()).await
+                       .err().unwrap().kind(),
+                   std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,225 @@
+//! Flat data transfer objects for REST APIs.
+//!
+//! The DTOs contain plain fields only: the numbers of `U256` are hex strings
+//! (64 digits), the coin values are decimal strings and the optional fields
+//! are flattened, so they map to OpenAPI schemas directly and the web
+//! frameworks (axum, actix, etc.) expose the chain without knowing `U256`.
+//! They are made from the internal types with `From`, and a transaction can
+//! be restored from its DTO (e.g. for submission) with `TryFrom`.
+
+use serde::{Serialize, Deserialize};
+
+use crate::utils::*;
+use crate::error::{Error, ErrorKind};
+use crate::coin::{coin_symbol, coin_value};
+use crate::transaction::{Type, Validity, Memo, Transaction};
+use crate::block::{Block, BlockData};
+use crate::state::CoinInfo;
+
+
+/// Block without its transactions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockDto {
+    pub offset: u64,
+    pub size: u64,
+    pub hash_prev: String,
+    pub validator: String,
+    pub nonce: String,
+    pub hash: String,
+    pub timestamp: u64,
+    pub complexity: u64,
+    pub version: u8,
+}
+
+
+/// Block with its number and transactions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockDataDto {
+    pub bix: u64,
+    #[serde(flatten)]
+    pub block: BlockDto,
+    pub transactions: Vec<TransactionDto>,
+}
+
+
+/// Transaction with its hash and type (`transfer`, `fee`, `split` or
+/// `merge`). The memo is hex of its bytes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionDto {
+    pub hash: String,
+    #[serde(rename = "type")]
+    pub tp: String,
+    pub coin: String,
+    pub addr: String,
+    pub sign_r: String,
+    pub sign_s: String,
+    pub version: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+
+/// Coin with its owner, symbol and value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoinInfoDto {
+    pub coin: String,
+    pub owner: String,
+    pub order: u64,
+    pub symbol: String,
+    pub value: String,
+    pub counter: u64,
+}
+
+
+impl From<&Block> for BlockDto {
+    fn from(block: &Block) -> Self {
+        Self {
+            offset: block.offset,
+            size: block.size,
+            hash_prev: block.hash_prev.to_hex(),
+            validator: block.validator.to_hex(),
+            nonce: block.nonce.to_hex(),
+            hash: block.hash.to_hex(),
+            timestamp: block.timestamp,
+            complexity: block.complexity,
+            version: block.version,
+        }
+    }
+}
+
+
+impl From<&BlockData> for BlockDataDto {
+    fn from(block_data: &BlockData) -> Self {
+        Self {
+            bix: block_data.bix,
+            block: BlockDto::from(&block_data.block),
+            transactions: block_data.transactions.iter()
+                .map(TransactionDto::from).collect(),
+        }
+    }
+}
+
+
+impl From<&Transaction> for TransactionDto {
+    fn from(tr: &Transaction) -> Self {
+        let tp = match tr.get_type() {
+            Type::Transfer => "transfer",
+            Type::Fee => "fee",
+            Type::Split => "split",
+            Type::Merge => "merge",
+        };
+        Self {
+            hash: tr.get_hash().to_hex(),
+            tp: tp.to_string(),
+            coin: tr.coin.to_hex(),
+            addr: tr.addr.to_hex(),
+            sign_r: tr.sign_r.to_hex(),
+            sign_s: tr.sign_s.to_hex(),
+            version: tr.version,
+            valid_from: tr.validity.as_ref().map(|validity| validity.from),
+            valid_until: tr.validity.as_ref().map(|validity| validity.until),
+            memo: tr.memo.as_ref().map(|memo| bytes_to_hex(memo.as_bytes())),
+        }
+    }
+}
+
+
+/// Restore the transaction. The hash and the type are not used, the hex
+/// strings must be valid (`EncodingInvalid`), the validity window must have
+/// both bounds.
+impl TryFrom<&TransactionDto> for Transaction {
+    type Error = Error;
+
+    fn try_from(dto: &TransactionDto) -> UqoinResult<Self> {
+        let invalid = || Error::from(ErrorKind::EncodingInvalid);
+        let parse = |hex: &str| u256_from_hex(hex).ok_or_else(invalid);
+        let mut tr = Transaction::new(parse(&dto.coin)?, parse(&dto.addr)?,
+                                      parse(&dto.sign_r)?,
+                                      parse(&dto.sign_s)?);
+        tr.version = dto.version;
+        tr.validity = match (dto.valid_from, dto.valid_until) {
+            (Some(from), Some(until)) => Some(Validity::new(from, until)),
+            (None, None) => None,
+            _ => return Err(invalid()),
+        };
+        if let Some(memo) = dto.memo.as_ref() {
+            let bytes = hex_to_bytes(memo).ok_or_else(invalid)?;
+            tr.memo = Some(Memo::new(&bytes)?);
+        }
+        tr.validate_version()?;
+        Ok(tr)
+    }
+}
+
+
+impl From<(&U256, &CoinInfo)> for CoinInfoDto {
+    fn from((coin, coin_info): (&U256, &CoinInfo)) -> Self {
+        Self {
+            coin: coin.to_hex(),
+            owner: coin_info.owner.to_hex(),
+            order: coin_info.order,
+            symbol: coin_symbol(coin_info.order),
+            value: coin_value(coin_info.order).to_decimal(),
+            counter: coin_info.counter,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Schema;
+    use crate::coin::coin_random;
+
+    #[test]
+    fn test_dto() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        // Transaction
+        let coin = coin_random(&mut rng, &public);
+        let mut tr = Transaction::new(coin.clone(), U256::from(1),
+                                      U256::from(0), U256::from(0))
+            .with_validity(Validity::new(5, 10))
+            .with_memo(Memo::new(b"invoice").unwrap());
+        tr.sign(&mut rng, &key, 0, &schema);
+        let dto = TransactionDto::from(&tr);
+        assert_eq!(dto.tp, "split");
+        assert_eq!(dto.valid_until, Some(10));
+        assert_eq!(dto.memo.as_deref(), Some("696E766F696365"));
+
+        let json = serde_json::to_value(&dto).unwrap();
+        assert_eq!(json["type"], "split");
+        assert_eq!(json["coin"].as_str().unwrap().len(), 64);
+
+        let tr2 = Transaction::try_from(&dto).unwrap();
+        assert_eq!(tr2.get_hash(), tr.get_hash());
+
+        let mut broken = dto.clone();
+        broken.valid_from = None;
+        assert!(Transaction::try_from(&broken).is_err());
+        broken.coin = "xyz".to_string();
+        assert!(Transaction::try_from(&broken).is_err());
+
+        // Block
+        let block_data = BlockData::genesis();
+        let dto = BlockDataDto::from(&block_data);
+        let json = serde_json::to_value(&dto).unwrap();
+        assert_eq!(json["bix"], 0);
+        assert_eq!(json["hash"], block_data.block.hash.to_hex());
+
+        // Coin
+        let coin_info = CoinInfo { owner: public.clone(), order: 12,
+                                   counter: 3 };
+        let dto = CoinInfoDto::from((&coin, &coin_info));
+        assert_eq!(dto.symbol, coin_symbol(12));
+        assert_eq!(dto.value, "4096");
+        assert_eq!(dto.owner, public.to_hex());
+    }
+}
@@ -5,6 +5,28 @@
 //! transaction processing, and block verification. By encapsulating these error
 //! conditions, the module facilitates robust error management and propagation
 //! throughout the system.
+//!
+//! Validation errors may carry an `ErrorContext`: the number of the block, 
+//! the index of the offending transaction, its coin and the expected and 
+//! actual values of the failed check, so it is possible to diagnose why a 
+//! block or a group was rejected.
+//!
+//! The report variants of the validation (e.g. `Block::validate_report`) 
+//! collect all the errors with their locations into `ValidationReport`
+//! instead of stopping at the first one. Both variants run the same checks
+//! through an `ErrorCollector`: `FailFast` stops at the first error, the
+//! report keeps going.
+//!
+//! IO, JSON and task errors are converted into `Error` (`StorageError`,
+//! `SerializationError` and `Other` kinds) keeping the original error as the
+//! source, so `?` can be used on them in the functions returning 
+//! `UqoinResult`.
+
+use std::sync::Arc;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+use crate::utils::{U256, UqoinResult};
 
 /// Represents specific categories of errors that can occur within the Uqoin 
 /// protocol:
@@ -14,10 +36,32 @@
 /// threshold.
 /// * TransactionInvalidSender: The sender information in a transaction is 
 /// invalid or cannot be verified.
+/// * TransactionUnsupportedVersion: The transaction format version is not
+///   supported.
+/// * TransactionExpired: The block number is outside of the transaction 
+///   validity window.
+/// * TransactionMemoTooLong: The memo of the transaction exceeds the size 
+///   limit.
+/// * TransactionSelfTransfer: The coin is transferred to its sender, or the 
+///   fee is paid by the validator to itself (since transaction version 4).
+/// * TransactionUnsigned: Some transactions of the group are not signed yet.
 /// * TransactionEmpty: The transaction contains no operations or data.
 /// * TransactionBrokenGroup: The transaction group structure is malformed or 
 /// inconsistent.
 /// * TransactionBrokenExt: Extension data is corrupted or invalid.
+/// * FeeTooLow: The fee of the transaction group does not satisfy the fee 
+///   policy.
+/// * TransactionNotIncluded: The merkle proof does not include the 
+///   transaction into the block.
+/// * PoolCoinConflict: The group spends a coin of a pending group and cannot
+///   replace it.
+/// * PoolFull: The pool is full and the group fee is not enough to evict 
+///   other groups.
+/// * PoolSenderLimit: The sender has too many pending groups in the pool.
+/// * PoolPolicyRejected: The group is rejected by the acceptance policy of 
+///   the pool.
+/// * PoolDuplicate: The group is already pending in the pool or it was seen
+///   before.
 /// * BlockBroken: The block structure is corrupted or fails integrity checks.
 /// * BlockOrderMismatch: The sequence of blocks does not follow the expected 
 /// order.
@@ -30,27 +74,177 @@
 /// * BlockInvalidHash: The block's hash does not meet the required criteria.
 /// * BlockInvalidHashComplexity: The block's hash does not satisfy the 
 /// complexity requirements.
+/// * BlockInvalidTimestamp: The block timestamp is less than the previous 
+///   one or the median time past, or it is too far in the future.
+/// * BlockLegacyHeader: The header of a legacy block cannot be validated 
+///   without transactions.
+/// * BlockUnsupportedVersion: The block format version is not supported.
+/// * BlockTooLarge: The block exceeds the maximum number of transactions or
+///   their encoded size.
+/// * BlockCheckpointMismatch: The block hash differs from the checkpoint one.
+/// * BlockHistoryMissing: The infos of the previous blocks required for the
+///   retarget or the median time are not supplied.
+/// * StateInconsistent: The internal maps of the state do not correspond each
+///   other.
+/// * StateSnapshotUntrusted: The state snapshot is neither checkpointed nor
+///   signed by a known publisher.
+/// * StateCommitmentMismatch: The commitment of the state differs from the
+///   trusted one (or it is of another block).
+/// * StorageError: Reading or writing the storage failed (the source is the 
+///   IO error).
+/// * StorageChecksumMismatch: The stored block or its transactions do not 
+///   match the stored checksum (the data is corrupted on disk).
+/// * CryptoInvalidPoint: The number does not encode a point of the curve 
+///   (e.g. a malformed public key).
+/// * SeedInvalidLength: The number of words in the mnemonic phrase is not 
+///   supported.
+/// * SeedUnknownWord: The word of the mnemonic phrase is not in the 
+///   wordlist.
+/// * SeedInvalidChecksum: The checksum of the mnemonic phrase does not 
+///   match.
+/// * SeedInvalidShares: The shares of the seed are inconsistent or not 
+///   enough to restore it.
+/// * SeedInvalidPath: The derivation path is malformed or it contains 
+///   indices that cannot be derived (non-hardened ones from the private node,
+///   hardened ones from the public node).
+/// * NetworkInvalidMessage: The message from the peer is malformed or 
+///   unexpected.
+/// * NetworkChainMismatch: The peer runs a different chain (chain id or 
+///   genesis).
+/// * NetworkIncompatible: The protocol version or the features of the peer
+///   are not supported.
+/// * EncodingInvalid: The bytes do not correspond the canonical encoding.
+/// * SerializationError: Serializing or deserializing failed (the source is
+///   the serde error).
 /// * Other: A catch-all for unspecified or miscellaneous errors.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// New kinds are added as the crate grows, so the enum is non-exhaustive:
+/// match the categories (see `ErrorKind::category`) to handle groups of kinds.
+/// Each kind has a stable numeric code (see `ErrorKind::code`), the hundreds
+/// correspond the category. The kinds are serialized by their names, unknown
+/// names (e.g. from newer peers) are deserialized as `Other`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+#[repr(u16)]
 pub enum ErrorKind {
-    CoinInvalid,
-    CoinNotUnique,
-    CoinTooCheap,
-    TransactionInvalidSender,
-    TransactionEmpty,
-    TransactionBrokenGroup,
-    TransactionBrokenExt,
-    BlockBroken,
-    BlockOrderMismatch,
-    BlockValidatorMismatch,
-    BlockPreviousHashMismatch,
-    BlockOffsetMismatch,
-    BlockInvalidHash,
-    BlockInvalidHashComplexity,
+    CoinInvalid = 100,
+    CoinNotUnique = 101,
+    CoinTooCheap = 102,
+    TransactionInvalidSender = 200,
+    TransactionUnsupportedVersion = 201,
+    TransactionExpired = 202,
+    TransactionMemoTooLong = 203,
+    TransactionSelfTransfer = 204,
+    TransactionUnsigned = 205,
+    TransactionEmpty = 206,
+    TransactionBrokenGroup = 207,
+    TransactionBrokenExt = 208,
+    FeeTooLow = 209,
+    TransactionNotIncluded = 210,
+    PoolCoinConflict = 300,
+    PoolFull = 301,
+    PoolSenderLimit = 302,
+    PoolPolicyRejected = 303,
+    PoolDuplicate = 304,
+    BlockBroken = 400,
+    BlockOrderMismatch = 401,
+    BlockValidatorMismatch = 402,
+    BlockPreviousHashMismatch = 403,
+    BlockOffsetMismatch = 404,
+    BlockInvalidHash = 405,
+    BlockInvalidHashComplexity = 406,
+    BlockInvalidTimestamp = 407,
+    BlockLegacyHeader = 408,
+    BlockUnsupportedVersion = 409,
+    BlockTooLarge = 410,
+    BlockCheckpointMismatch = 411,
+    BlockHistoryMissing = 412,
+    StateInconsistent = 500,
+    StateSnapshotUntrusted = 501,
+    StateCommitmentMismatch = 502,
+    StorageError = 600,
+    StorageChecksumMismatch = 601,
+    CryptoInvalidPoint = 700,
+    SeedInvalidLength = 800,
+    SeedUnknownWord = 801,
+    SeedInvalidChecksum = 802,
+    SeedInvalidShares = 803,
+    SeedInvalidPath = 804,
+    NetworkInvalidMessage = 900,
+    NetworkChainMismatch = 901,
+    NetworkIncompatible = 902,
+    EncodingInvalid = 1000,
+    SerializationError = 1001,
+    #[serde(other)]
+    Other = 0,
+}
+
+
+/// Categories of the error kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCategory {
+    Coin,
+    Transaction,
+    Pool,
+    Block,
+    State,
+    Storage,
+    Crypto,
+    Seed,
+    Network,
+    Encoding,
     Other,
 }
 
 
+impl ErrorKind {
+    /// Get the numeric code of the kind.
+    pub fn code(&self) -> u16 {
+        self.clone() as u16
+    }
+
+    /// Get the category of the kind.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Self::CoinInvalid | Self::CoinNotUnique | 
+            Self::CoinTooCheap => ErrorCategory::Coin,
+            Self::TransactionInvalidSender | 
+            Self::TransactionUnsupportedVersion | 
+            Self::TransactionExpired | Self::TransactionMemoTooLong | 
+            Self::TransactionSelfTransfer | Self::TransactionUnsigned | 
+            Self::TransactionEmpty | Self::TransactionBrokenGroup | 
+            Self::TransactionBrokenExt | Self::FeeTooLow | 
+            Self::TransactionNotIncluded => ErrorCategory::Transaction,
+            Self::PoolCoinConflict | Self::PoolFull | Self::PoolSenderLimit | 
+            Self::PoolPolicyRejected | 
+            Self::PoolDuplicate => ErrorCategory::Pool,
+            Self::BlockBroken | Self::BlockOrderMismatch | 
+            Self::BlockValidatorMismatch | Self::BlockPreviousHashMismatch | 
+            Self::BlockOffsetMismatch | Self::BlockInvalidHash | 
+            Self::BlockInvalidHashComplexity | Self::BlockInvalidTimestamp | 
+            Self::BlockLegacyHeader | Self::BlockUnsupportedVersion | 
+            Self::BlockTooLarge | Self::BlockCheckpointMismatch | 
+            Self::BlockHistoryMissing => ErrorCategory::Block,
+            Self::StateInconsistent => ErrorCategory::State,
+            Self::StateSnapshotUntrusted => ErrorCategory::State,
+            Self::StateCommitmentMismatch => ErrorCategory::State,
+            Self::StorageError | 
+            Self::StorageChecksumMismatch => ErrorCategory::Storage,
+            Self::CryptoInvalidPoint => ErrorCategory::Crypto,
+            Self::SeedInvalidLength | Self::SeedUnknownWord | 
+            Self::SeedInvalidChecksum | Self::SeedInvalidShares | 
+            Self::SeedInvalidPath => ErrorCategory::Seed,
+            Self::NetworkInvalidMessage | Self::NetworkChainMismatch | 
+            Self::NetworkIncompatible => ErrorCategory::Network,
+            Self::EncodingInvalid | 
+            Self::SerializationError => ErrorCategory::Encoding,
+            Self::Other => ErrorCategory::Other,
+        }
+    }
+}
+
+
 /// Shortcut for converting boolean check into error.
 /// A utility macro to streamline error checking:
 /// ```ignore
@@ -70,38 +264,209 @@ macro_rules! validate {
 }
 
 
+/// Position and values related to the error, all of them are optional:
+/// * bix: Number of the offending block.
+/// * index: Index of the offending transaction in the validated slice (the
+///   block or the group).
+/// * coin: The offending coin.
+/// * expected: Expected value of the failed check.
+/// * actual: Actual value of the failed check.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ErrorContext {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bix: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coin: Option<U256>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+}
+
+
+impl ErrorContext {
+    /// Check if no context is set.
+    pub fn is_empty(&self) -> bool {
+        self.bix.is_none() && self.index.is_none() && 
+            self.coin.is_none() && self.expected.is_none() && 
+            self.actual.is_none()
+    }
+}
+
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(bix) = self.bix {
+            parts.push(format!("block {}", bix));
+        }
+        if let Some(index) = self.index {
+            parts.push(format!("index {}", index));
+        }
+        if let Some(coin) = self.coin.as_ref() {
+            parts.push(format!("coin {}", coin.to_hex()));
+        }
+        if let Some(expected) = self.expected.as_ref() {
+            parts.push(format!("expected {}", expected));
+        }
+        if let Some(actual) = self.actual.as_ref() {
+            parts.push(format!("actual {}", actual));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+
 /// Uqoin error structure. It supports converting into `std::io::Error`.
 /// Encapsulates an error kind along with a descriptive message:
 /// * kind: An instance of ErrorKind representing the type of error.
 /// * message: A human-readable description of the error.
+/// * context: Optional position and values (see `ErrorContext`), it is 
+///   appended to the message on display.
+/// * source: Optional underlying error (e.g. IO one), it is not compared.
 /// Implements the `std::error::Error` and `std::fmt::Display` traits for 
 /// integration with Rust's error handling ecosystem.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Error {
     kind: ErrorKind,
     message: String,
+    context: Option<Box<ErrorContext>>,
+    source: Option<Arc<dyn std::error::Error + Send + Sync>>,
 }
 
 
 impl Error {
     /// Create a new Uqoin error instance.
     pub fn new(kind: ErrorKind, message: String) -> Self {
-        Self { kind, message }
+        Self { kind, message, context: None, source: None }
     }
 
     /// Get kind of the error.
     pub fn kind(&self) -> ErrorKind {
         self.kind.clone()
     }
+
+    /// Get context of the error if it is set.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        self.context.as_deref()
+    }
+
+    /// Set number of the offending block.
+    pub fn with_bix(mut self, bix: u64) -> Self {
+        self.context_mut().bix = Some(bix);
+        self
+    }
+
+    /// Set index of the offending transaction.
+    pub fn with_index(mut self, index: usize) -> Self {
+        self.context_mut().index = Some(index);
+        self
+    }
+
+    /// Shift the index of the offending transaction, it is used when the
+    /// validated slice is a part of a bigger one (e.g. a group in the block).
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        if let Some(context) = self.context.as_mut() {
+            context.index = context.index.map(|index| index + offset);
+        }
+        self
+    }
+
+    /// Set the offending coin.
+    pub fn with_coin(mut self, coin: &U256) -> Self {
+        self.context_mut().coin = Some(coin.clone());
+        self
+    }
+
+    /// Set expected and actual values of the failed check.
+    pub fn with_values<E: ToString, A: ToString>(mut self, expected: E, 
+                                                 actual: A) -> Self {
+        let context = self.context_mut();
+        context.expected = Some(expected.to_string());
+        context.actual = Some(actual.to_string());
+        self
+    }
+
+    /// Set the underlying error.
+    pub fn with_source<E>(mut self, source: E) -> Self
+                          where E: std::error::Error + Send + Sync + 'static {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /// Context is boxed to keep `UqoinResult` small, it is created on demand.
+    fn context_mut(&mut self) -> &mut ErrorContext {
+        self.context.get_or_insert_with(Box::default)
+    }
 }
 
 
-impl std::error::Error for Error {}
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        (self.kind == other.kind) && (self.message == other.message) && 
+            (self.context == other.context)
+    }
+}
+
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_deref().map(|err| err as &dyn std::error::Error)
+    }
+}
 
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+        match self.context() {
+            Some(context) if !context.is_empty() => {
+                write!(f, "{} ({})", self.message, context)
+            },
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+
+/// Serialized form of `Error`, the source is not transported.
+#[derive(Serialize, Deserialize)]
+struct ErrorRepr {
+    kind: ErrorKind,
+    code: u16,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<ErrorContext>,
+}
+
+
+/// The error is serialized with the kind, its code, the message and the 
+/// context (e.g. to send rejection reasons to peers and RPC clients).
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, 
+                                serializer: S) -> Result<S::Ok, S::Error> {
+        ErrorRepr {
+            kind: self.kind.clone(),
+            code: self.kind.code(),
+            message: self.message.clone(),
+            context: self.context().cloned(),
+        }.serialize(serializer)
+    }
+}
+
+
+impl<'de> Deserialize<'de> for Error {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> 
+                                         Result<Self, D::Error> {
+        let repr = ErrorRepr::deserialize(deserializer)?;
+        let mut error = Error::new(repr.kind, repr.message);
+        error.context = repr.context.map(Box::new);
+        Ok(error)
     }
 }
 
@@ -114,9 +479,41 @@ impl From<ErrorKind> for Error {
 }
 
 
+impl From<std::io::Error> for Error {
+    fn from(io_error: std::io::Error) -> Error {
+        let message = format!("{:?}: {}", ErrorKind::StorageError, io_error);
+        Error::new(ErrorKind::StorageError, message).with_source(io_error)
+    }
+}
+
+
+impl From<serde_json::Error> for Error {
+    fn from(json_error: serde_json::Error) -> Error {
+        let message = format!("{:?}: {}", ErrorKind::SerializationError, 
+                              json_error);
+        Error::new(ErrorKind::SerializationError, message)
+            .with_source(json_error)
+    }
+}
+
+
+#[cfg(feature = "blockchain")]
+impl From<tokio::task::JoinError> for Error {
+    fn from(join_error: tokio::task::JoinError) -> Error {
+        let message = format!("{:?}: {}", ErrorKind::Other, join_error);
+        Error::new(ErrorKind::Other, message).with_source(join_error)
+    }
+}
+
+
+/// The IO error converted into `Error` gets its original kind back.
 impl From<Error> for std::io::Error {
     fn from(uqoin_error: Error) -> std::io::Error {
-        std::io::Error::new(std::io::ErrorKind::Other, uqoin_error.to_string())
+        let kind = uqoin_error.source.as_deref()
+            .and_then(|err| err.downcast_ref::<std::io::Error>())
+            .map(|err| err.kind())
+            .unwrap_or(std::io::ErrorKind::Other);
+        std::io::Error::new(kind, uqoin_error.to_string())
     }
 }
 
@@ -129,6 +526,147 @@ impl From<ErrorKind> for std::io::Error {
 }
 
 
+/// Destination of the validation errors, so the same checks either stop at
+/// the first error or collect all of them. The checks that the validation 
+/// cannot continue after are returned directly.
+pub trait ErrorCollector {
+    /// Take the result of a check. The error is returned if the validation
+    /// must stop.
+    fn collect<T>(&mut self, result: UqoinResult<T>) -> UqoinResult<()>;
+
+    /// Check if no errors are collected so far.
+    fn is_ok(&self) -> bool;
+}
+
+
+/// Collector that stops the validation at the first error.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FailFast;
+
+
+impl ErrorCollector for FailFast {
+    fn collect<T>(&mut self, result: UqoinResult<T>) -> UqoinResult<()> {
+        result.map(|_| ())
+    }
+
+    fn is_ok(&self) -> bool {
+        true
+    }
+}
+
+
+/// Errors collected by the report variants of the validation, in the order
+/// of the checks. It dereferences to the slice of the errors.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport(Vec<Error>);
+
+
+impl ValidationReport {
+    /// Create an empty report.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check if no errors are found.
+    pub fn is_ok(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add the error.
+    pub fn push(&mut self, error: Error) {
+        self.0.push(error);
+    }
+
+    /// Add the error of the result if any.
+    pub fn check<T>(&mut self, result: UqoinResult<T>) {
+        self.0.extend(result.err());
+    }
+
+    /// Set number of the block to all the errors.
+    pub fn with_bix(self, bix: u64) -> Self {
+        Self(self.0.into_iter().map(|err| err.with_bix(bix)).collect())
+    }
+
+    /// Get the first error as the result.
+    pub fn into_result(self) -> UqoinResult<()> {
+        match self.0.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the errors.
+    pub fn into_errors(self) -> Vec<Error> {
+        self.0
+    }
+
+    /// Run the validation collecting the errors into a new report. The error
+    /// the validation stops with is added as well.
+    pub fn run<F>(validate: F) -> Self 
+            where F: FnOnce(&mut Self) -> UqoinResult<()> {
+        let mut report = Self::new();
+        if let Err(err) = validate(&mut report) {
+            report.push(err);
+        }
+        report
+    }
+}
+
+
+impl ErrorCollector for ValidationReport {
+    fn collect<T>(&mut self, result: UqoinResult<T>) -> UqoinResult<()> {
+        self.check(result);
+        Ok(())
+    }
+
+    fn is_ok(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+
+impl std::ops::Deref for ValidationReport {
+    type Target = [Error];
+
+    fn deref(&self) -> &[Error] {
+        &self.0
+    }
+}
+
+
+impl Extend<Error> for ValidationReport {
+    fn extend<I: IntoIterator<Item = Error>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+
+impl FromIterator<Error> for ValidationReport {
+    fn from_iter<I: IntoIterator<Item = Error>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+
+impl IntoIterator for ValidationReport {
+    type Item = Error;
+    type IntoIter = std::vec::IntoIter<Error>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let lines = self.0.iter().map(|err| err.to_string())
+                                 .collect::<Vec<String>>();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -181,6 +719,121 @@ mod tests {
         assert_eq!(err_std.to_string(), "CoinInvalid");
     }
 
+    #[test]
+    fn test_context() {
+        let err = Error::from(ErrorKind::BlockOrderMismatch)
+            .with_index(2).with_offset(3).with_values(5, 4);
+        assert_eq!(err.context().unwrap().index, Some(5));
+        assert_eq!(err.context().unwrap().coin, None);
+        assert_eq!(err.to_string(), 
+                   "BlockOrderMismatch (index 5, expected 5, actual 4)");
+
+        assert!(Error::from(ErrorKind::CoinInvalid).context().is_none());
+
+        let err = Error::from(ErrorKind::CoinInvalid)
+            .with_coin(&U256::from(10));
+        assert!(err.to_string().starts_with("CoinInvalid (coin "));
+
+        let err = Error::from(ErrorKind::BlockInvalidHash).with_bix(7)
+            .with_index(1);
+        assert_eq!(err.context().unwrap().bix, Some(7));
+        assert_eq!(err.to_string(), "BlockInvalidHash (block 7, index 1)");
+    }
+
+    #[test]
+    fn test_conversions() {
+        use std::error::Error as _;
+
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, 
+                                           "no file");
+        let err: Error = io_error.into();
+        assert_eq!(err.kind(), ErrorKind::StorageError);
+        assert_eq!(err.to_string(), "StorageError: no file");
+        assert_eq!(err.source().unwrap().to_string(), "no file");
+        let err_std: std::io::Error = err.into();
+        assert_eq!(err_std.kind(), std::io::ErrorKind::NotFound);
+
+        let err: Error = serde_json::from_str::<u64>("x").unwrap_err().into();
+        assert_eq!(err.kind(), ErrorKind::SerializationError);
+        assert!(err.source().is_some());
+
+        assert!(Error::from(ErrorKind::CoinInvalid).source().is_none());
+    }
+
+    #[test]
+    fn test_validation_report() {
+        let mut report = ValidationReport::new();
+        report.check(validate!(true, CoinInvalid));
+        assert!(report.is_ok());
+        assert!(report.clone().into_result().is_ok());
+
+        report.check(validate!(false, CoinInvalid));
+        report.push(Error::from(ErrorKind::BlockInvalidHash).with_index(2));
+        let report = report.with_bix(5);
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[1].context().unwrap().index, Some(2));
+        assert!(report.iter().all(|err| err.context().unwrap().bix == 
+                                        Some(5)));
+        assert_eq!(report.to_string(), "CoinInvalid (block 5)\n\
+                                        BlockInvalidHash (block 5, index 2)");
+        assert_eq!(report.into_result().unwrap_err().kind(), 
+                   ErrorKind::CoinInvalid);
+    }
+
+    #[test]
+    fn test_error_collector() {
+        fn validate<C: ErrorCollector>(errors: &mut C) -> UqoinResult<()> {
+            errors.collect(validate!(false, CoinInvalid))?;
+            errors.collect(validate!(true, CoinTooCheap))?;
+            errors.collect(validate!(false, BlockInvalidHash))?;
+            validate!(errors.is_ok(), BlockBroken)?;
+            errors.collect(validate!(false, BlockOffsetMismatch))
+        }
+
+        assert_eq!(validate(&mut FailFast).unwrap_err().kind(), 
+                   ErrorKind::CoinInvalid);
+
+        let report = ValidationReport::run(validate);
+        let kinds = report.iter().map(|err| err.kind()).collect::<Vec<_>>();
+        assert_eq!(kinds, [ErrorKind::CoinInvalid, ErrorKind::BlockInvalidHash,
+                           ErrorKind::BlockBroken]);
+    }
+
+    #[test]
+    fn test_serde() {
+        assert_eq!(ErrorKind::CoinInvalid.code(), 100);
+        assert_eq!(ErrorKind::BlockTooLarge.code(), 410);
+        assert_eq!(ErrorKind::Other.code(), 0);
+
+        let err = Error::from(ErrorKind::BlockOrderMismatch).with_bix(3)
+            .with_values(5, 4);
+        let json = serde_json::to_string(&err).unwrap();
+        assert_eq!(json, "{\"kind\":\"BlockOrderMismatch\",\"code\":401,\
+                          \"message\":\"BlockOrderMismatch\",\"context\":\
+                          {\"bix\":3,\"expected\":\"5\",\"actual\":\"4\"}}");
+        let err2: Error = serde_json::from_str(&json).unwrap();
+        assert_eq!(err2, err);
+        assert_eq!(err2.to_string(), err.to_string());
+
+        // Unknown kinds are deserialized as other
+        let err: Error = serde_json::from_str(
+            "{\"kind\":\"Unknown\",\"code\":12345,\"message\":\"m\"}"
+        ).unwrap();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert!(err.context().is_none());
+    }
+
+    #[test]
+    fn test_category() {
+        assert_eq!(ErrorKind::FeeTooLow.category(), 
+                   ErrorCategory::Transaction);
+        assert_eq!(ErrorKind::BlockTooLarge.category(), ErrorCategory::Block);
+        assert_eq!(ErrorKind::StorageError.category(), 
+                   ErrorCategory::Storage);
+        assert_eq!(ErrorKind::SeedInvalidPath.category(), 
+                   ErrorCategory::Seed);
+    }
+
     #[test]
     fn test_validate_macro() {
         let result = validate!(true, CoinInvalid);
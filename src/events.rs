@@ -0,0 +1,286 @@
+//! Structured events of the node activity.
+//!
+//! Websocket layers and indexers follow the node through one interface:
+//! they subscribe to `EventBus` and receive `Event` for each committed block
+//! (`NewBlock` followed by `NewTransaction` for its transactions), each
+//! switch of the chain (`Reorg`) and each change of the pending groups
+//! (`PoolUpdate`, published by the pool given the bus with
+//! `Pool::with_events`).
+//!
+//! The bus broadcasts every event to all subscribers: the blocking ones get
+//! `std::sync::mpsc::Receiver`, the async ones (with the `blockchain`
+//! feature) get `tokio::sync::mpsc::Receiver`. The queue of each subscriber
+//! is bounded (`EVENT_BUS_CAPACITY` by default), publishing never blocks:
+//! the subscriber that does not keep up is dropped, so its receiver is
+//! closed after the queued events. A subscription also ends when its
+//! receiver is dropped. The clones of the bus share the subscribers, so the
+//! bus can be passed to the producers freely.
+//!
+//! The events are serialized as `{"event": "new_block", "data": {...}}`
+//! with the numbers of `U256` as hex strings.
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
+
+use serde::{Serialize, Deserialize};
+
+use crate::utils::*;
+use crate::transaction::Transaction;
+use crate::block::{Block, BlockInfo};
+use crate::pool::PoolEvent;
+use crate::fork_choice::Reorg;
+
+
+/// Event of the node activity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    /// The block is committed.
+    NewBlock {
+        info: BlockInfo,
+        size: u64,
+        #[serde(with = "u256_hex")]
+        validator: U256,
+    },
+
+    /// The transaction is included in the committed block.
+    NewTransaction {
+        bix: u64,
+        #[serde(with = "u256_hex")]
+        hash: U256,
+        transaction: Transaction,
+    },
+
+    /// The chain is switched: the blocks are rolled down to the ancestor and
+    /// the blocks of the other chain are rolled up.
+    Reorg {
+        ancestor: BlockInfo,
+        rolled_down: Vec<BlockInfo>,
+        rolled_up: Vec<BlockInfo>,
+    },
+
+    /// The pending groups are changed.
+    PoolUpdate(PoolEvent),
+}
+
+
+impl Event {
+    /// Events of the committed block: `NewBlock` and `NewTransaction` for
+    /// each transaction.
+    pub fn from_block(bix: u64, block: &Block,
+                      transactions: &[Transaction]) -> Vec<Self> {
+        let mut events = vec![Self::NewBlock {
+            info: BlockInfo::from_block(bix, block),
+            size: block.size,
+            validator: block.validator.clone(),
+        }];
+        events.extend(transactions.iter().map(|tr| Self::NewTransaction {
+            bix,
+            hash: tr.get_hash(),
+            transaction: tr.clone(),
+        }));
+        events
+    }
+
+    /// Event of the applied reorg.
+    pub fn from_reorg(reorg: &Reorg) -> Self {
+        Self::Reorg {
+            ancestor: reorg.ancestor.clone(),
+            rolled_down: reorg.roll_down.clone(),
+            rolled_up: reorg.roll_up.clone(),
+        }
+    }
+}
+
+
+/// Default number of the events queued for a subscriber.
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+
+
+/// Broadcast of the events to the subscribers.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    capacity: usize,
+    subscribers: Arc<Mutex<Vec<SyncSender<Event>>>>,
+    #[cfg(feature = "blockchain")]
+    async_subscribers: Arc<Mutex<Vec<tokio::sync::mpsc::Sender<Event>>>>,
+}
+
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::with_capacity(EVENT_BUS_CAPACITY)
+    }
+}
+
+
+impl EventBus {
+    /// Create the bus without subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the bus with the number of the events queued for a subscriber.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            capacity,
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "blockchain")]
+            async_subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Get the number of the events queued for a subscriber.
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Subscribe to the events (blocking receiver).
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = sync_channel(self.capacity);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Subscribe to the events (async receiver).
+    #[cfg(feature = "blockchain")]
+    pub fn subscribe_async(&self) -> tokio::sync::mpsc::Receiver<Event> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(self.capacity);
+        self.async_subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Number of the active subscribers.
+    pub fn get_subscriber_count(&self) -> usize {
+        let count = self.subscribers.lock().unwrap().len();
+        #[cfg(feature = "blockchain")]
+        let count = count + self.async_subscribers.lock().unwrap().len();
+        count
+    }
+
+    /// Send the event to the subscribers dropping the closed and the full
+    /// ones.
+    pub fn publish(&self, event: Event) {
+        self.subscribers.lock().unwrap()
+            .retain(|sub| sub.try_send(event.clone()).is_ok());
+        #[cfg(feature = "blockchain")]
+        self.async_subscribers.lock().unwrap()
+            .retain(|sub| sub.try_send(event.clone()).is_ok());
+    }
+
+    /// Send the events in order.
+    pub fn publish_many(&self, events: Vec<Event>) {
+        for event in events.into_iter() {
+            self.publish(event);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::schema::Schema;
+    use crate::coin::coin_random;
+    use crate::state::State;
+    use crate::pool::Pool;
+
+    #[test]
+    fn test_events() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let addr: U256 = rng.random();
+
+        let bus = EventBus::new();
+        let receiver = bus.subscribe();
+        let dropped = bus.subscribe();
+        drop(dropped);
+
+        // Block events
+        let coin = coin_random(&mut rng, &public);
+        let transactions = vec![
+            Transaction::build(&mut rng, coin, addr.clone(), &key, 0, &schema)
+        ];
+        let info = BlockInfo::genesis();
+        let block = Block::new(info.offset, 1, info.hash.clone(),
+                               U256::from(0), U256::from(0), U256::from(1));
+        bus.publish_many(Event::from_block(1, &block, &transactions));
+        assert_eq!(bus.get_subscriber_count(), 1);
+
+        let events: Vec<Event> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], Event::NewBlock { info, .. }
+                         if info.bix == 1));
+        assert!(matches!(&events[1], Event::NewTransaction { hash, .. }
+                         if hash == &transactions[0].get_hash()));
+
+        // Serialization
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["event"], "new_block");
+        assert_eq!(json["data"]["info"]["bix"], 1);
+        for event in events.iter() {
+            let json = serde_json::to_string(event).unwrap();
+            let event2: Event = serde_json::from_str(&json).unwrap();
+            assert_eq!(serde_json::to_string(&event2).unwrap(), json);
+        }
+
+        // Pool events
+        let state = State::new();
+        let mut pool = Pool::new().with_events(bus.clone());
+        assert!(pool.submit_raw(transactions.clone(), &state,
+                                &schema)[0].is_ok());
+        assert!(matches!(receiver.try_recv().unwrap(), Event::PoolUpdate(
+            PoolEvent::Added { hash }
+        ) if hash == transactions[0].get_hash()));
+        let json = serde_json::to_value(
+            Event::PoolUpdate(PoolEvent::Added { hash: U256::from(1) })
+        ).unwrap();
+        assert_eq!(json["event"], "pool_update");
+
+        // The clones of the pool do not publish to the bus
+        let mut pool2 = pool.clone();
+        pool2.clear();
+        pool2.submit_raw(transactions.clone(), &state, &schema);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let bus = EventBus::with_capacity(2);
+        let receiver = bus.subscribe();
+        let slow = bus.subscribe();
+        let reorg = Event::from_reorg(&Reorg {
+            ancestor: BlockInfo::genesis(),
+            roll_down: Vec::new(),
+            roll_up: Vec::new(),
+        });
+
+        // The subscriber that does not keep up is dropped
+        bus.publish_many(vec![reorg.clone(), reorg.clone()]);
+        assert_eq!(receiver.try_iter().count(), 2);
+        bus.publish(reorg.clone());
+        assert_eq!(bus.get_subscriber_count(), 1);
+        assert_eq!(receiver.try_iter().count(), 1);
+        assert_eq!(slow.try_iter().count(), 2);
+        assert!(matches!(slow.try_recv(),
+                         Err(std::sync::mpsc::TryRecvError::Disconnected)));
+    }
+
+    #[cfg(feature = "blockchain")]
+    #[tokio::test]
+    async fn test_events_async() {
+        let bus = EventBus::new();
+        let mut receiver = bus.subscribe_async();
+        let reorg = Reorg {
+            ancestor: BlockInfo::genesis(),
+            roll_down: Vec::new(),
+            roll_up: Vec::new(),
+        };
+        bus.clone().publish(Event::from_reorg(&reorg));
+        assert!(matches!(receiver.recv().await.unwrap(),
+                         Event::Reorg { .. }));
+    }
+}
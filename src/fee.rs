@@ -0,0 +1,199 @@
+//! Fee rules for transaction groups.
+//!
+//! A fee in Uqoin is a coin transferred to the validator (a transaction of
+//! `Fee` type at the end of the group), so its value is `2^order` of the fee
+//! coin. `FeePolicy` defines the minimum fee order for each group type and an
+//! optional rule that requires a minimum value per transaction the validator
+//! processes (including the fee itself and the extension). The pool checks the
+//! groups against its policy, and wallets can use `FeePolicy::estimate_fee` to
+//! pick the fee coin to attach.
+
+use serde::{Serialize, Deserialize};
+
+use crate::validate;
+use crate::utils::*;
+use crate::error::ErrorKind;
+use crate::coin::coin_value;
+use crate::state::State;
+use crate::transaction::{Type, Group};
+
+
+/// Result of the fee estimation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeeEstimate {
+    /// The group does not require a fee.
+    NotRequired,
+
+    /// The cheapest suitable coin of the owner.
+    Coin(U256),
+
+    /// The fee of the order is required, but the owner has no suitable coin.
+    Unavailable(u64),
+}
+
+
+/// Fee rules. `None` means no restriction. The default policy requires no
+/// fees.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeePolicy {
+    /// Minimum fee order for `Transfer` groups.
+    pub transfer_min_order: Option<u64>,
+
+    /// Minimum fee order for `Split` groups.
+    pub split_min_order: Option<u64>,
+
+    /// Minimum fee order for `Merge` groups.
+    pub merge_min_order: Option<u64>,
+
+    /// The fee value must be at least `2^order` per transaction of the group
+    /// with its extension.
+    pub per_transaction_order: Option<u64>,
+}
+
+
+impl FeePolicy {
+    /// Policy that requires no fees.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of transactions the validator processes for the group of the
+    /// type with a fee (group, fee and extension). `TransactionBrokenGroup`
+    /// for `Fee` since a group cannot start with a fee.
+    pub fn get_transaction_count(group_type: &Type) -> UqoinResult<usize> {
+        match group_type {
+            Type::Transfer => Ok(2),
+            Type::Split => Ok(5),
+            Type::Merge => Ok(5),
+            Type::Fee => Err(ErrorKind::TransactionBrokenGroup.into()),
+        }
+    }
+
+    /// Minimum order of the fee coin for the group type, `None` if the fee is
+    /// not required.
+    pub fn get_min_fee_order(&self, 
+                             group_type: &Type) -> UqoinResult<Option<u64>> {
+        let type_order = match group_type {
+            Type::Transfer => self.transfer_min_order,
+            Type::Split => self.split_min_order,
+            Type::Merge => self.merge_min_order,
+            Type::Fee => return Err(ErrorKind::TransactionBrokenGroup.into()),
+        };
+
+        // Order that covers the value per transaction rounding it up
+        let count = Self::get_transaction_count(group_type)?;
+        let count_order = self.per_transaction_order.map(
+            |order| order + count.next_power_of_two().trailing_zeros() as u64
+        );
+
+        Ok(type_order.max(count_order))
+    }
+
+    /// Check if the fee coin value satisfies the policy.
+    pub fn is_fee_enough(&self, group_type: &Type,
+                         fee_order: Option<u64>) -> UqoinResult<bool> {
+        Ok(match self.get_min_fee_order(group_type)? {
+            Some(min_order) => fee_order.map(|o| o >= min_order)
+                                        .unwrap_or(false),
+            None => true,
+        })
+    }
+
+    /// Validate the fee of the group.
+    pub fn validate_group(&self, group: &Group, state: &State,
+                          senders: &[U256]) -> UqoinResult<()> {
+        let fee_order = group.get_fee_order(state, senders);
+        validate!(self.is_fee_enough(&group.get_type(), fee_order)?, 
+                  FeeTooLow)
+    }
+
+    /// Estimate the fee for the group type choosing the cheapest suitable coin
+    /// of the owner in the state. Coins of the group itself must be passed in
+    /// `exclude`.
+    pub fn estimate_fee(&self, group_type: &Type, state: &State, owner: &U256,
+                        exclude: &[U256]) -> UqoinResult<FeeEstimate> {
+        Ok(if let Some(min_order) = self.get_min_fee_order(group_type)? {
+            let coin = state.get_coins(owner).and_then(|order_coins_map| {
+                order_coins_map.iter()
+                    .filter(|(order, _)| **order >= min_order)
+                    .flat_map(|(order, coins)| {
+                        coins.iter().map(|coin| (*order, coin))
+                    })
+                    .filter(|(_, coin)| !exclude.contains(coin))
+                    .min_by_key(|(order, _)| *order)
+                    .map(|(_, coin)| coin.clone())
+            });
+            match coin {
+                Some(coin) => FeeEstimate::Coin(coin),
+                None => FeeEstimate::Unavailable(min_order),
+            }
+        } else {
+            FeeEstimate::NotRequired
+        })
+    }
+
+    /// Minimum fee value for the group type (zero if the fee is not required).
+    pub fn get_min_fee_value(&self, group_type: &Type) -> UqoinResult<U256> {
+        Ok(self.get_min_fee_order(group_type)?.map(coin_value)
+               .unwrap_or(U256::from(0)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_fee_order() {
+        let policy = FeePolicy::new();
+        assert_eq!(policy.get_min_fee_order(&Type::Transfer).unwrap(), None);
+        assert!(policy.is_fee_enough(&Type::Split, None).unwrap());
+
+        let policy = FeePolicy {
+            transfer_min_order: Some(10),
+            split_min_order: None,
+            merge_min_order: Some(20),
+            per_transaction_order: Some(8),
+        };
+
+        // 2 transactions of order 8 require order 9, so 10 wins
+        assert_eq!(policy.get_min_fee_order(&Type::Transfer).unwrap(), 
+                   Some(10));
+
+        // 5 transactions of order 8 require order 11
+        assert_eq!(policy.get_min_fee_order(&Type::Split).unwrap(), Some(11));
+        assert_eq!(policy.get_min_fee_order(&Type::Merge).unwrap(), Some(20));
+        assert_eq!(policy.get_min_fee_value(&Type::Split).unwrap(), 
+                   coin_value(11));
+
+        assert!(!policy.is_fee_enough(&Type::Transfer, None).unwrap());
+        assert!(!policy.is_fee_enough(&Type::Transfer, Some(9)).unwrap());
+        assert!(policy.is_fee_enough(&Type::Transfer, Some(10)).unwrap());
+
+        // Fee is not a group type
+        assert_eq!(policy.get_min_fee_order(&Type::Fee).unwrap_err().kind(),
+                   ErrorKind::TransactionBrokenGroup);
+        assert_eq!(FeePolicy::get_transaction_count(&Type::Fee)
+                       .unwrap_err().kind(),
+                   ErrorKind::TransactionBrokenGroup);
+    }
+
+    #[test]
+    fn test_estimate_fee() {
+        let policy = FeePolicy {
+            transfer_min_order: Some(10),
+            ..FeePolicy::default()
+        };
+        let state = State::new();
+        let owner = U256::from(1);
+
+        assert_eq!(policy.estimate_fee(&Type::Split, &state, &owner, &[])
+                       .unwrap(),
+                   FeeEstimate::NotRequired);
+        assert_eq!(policy.estimate_fee(&Type::Transfer, &state, &owner, &[])
+                       .unwrap(),
+                   FeeEstimate::Unavailable(10));
+        assert!(policy.estimate_fee(&Type::Fee, &state, &owner, &[]).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! Choice between the competing chains.
+//!
+//! When a peer announces a chain that does not extend the local one, the
+//! node compares them with `ForkChoice::choose` given the infos of their
+//! blocks since some common point. The chain with the greater cumulative
+//! work after the common ancestor wins, the work of a block is
+//! `2^complexity` (legacy blocks have the fixed `COMPLEXITY`), the greater
+//! height breaks the ties and the local chain stays if they are equal (the
+//! first seen one).
+//!
+//! The checkpoints constrain the choice: the candidate chain must have the
+//! checkpoint hashes and the reorg cannot roll down a checkpointed block.
+//! The depth of the reorg can be limited as well, so a long-range fork is
+//! not applied automatically.
+//!
+//! The decision contains the common ancestor and the blocks to roll down
+//! (the local ones from the tip) and to roll up (the candidate ones from the
+//! ancestor), the node applies them with `State::roll_down` and
+//! `State::roll_up` (see `Node`).
+
+use std::collections::{HashMap, BTreeMap};
+
+use crate::validate;
+use crate::utils::*;
+use crate::block::{BlockInfo, COMPLEXITY};
+use crate::chain::Checkpoint;
+
+
+/// Switch from the local chain to the candidate one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reorg {
+    /// The last common block.
+    pub ancestor: BlockInfo,
+
+    /// Local blocks to roll down, from the tip to the ancestor (exclusive).
+    pub roll_down: Vec<BlockInfo>,
+
+    /// Candidate blocks to roll up, from the ancestor (exclusive) to the
+    /// tip.
+    pub roll_up: Vec<BlockInfo>,
+}
+
+
+impl Reorg {
+    /// Number of the local blocks to roll down.
+    pub fn get_depth(&self) -> u64 {
+        self.roll_down.len() as u64
+    }
+}
+
+
+/// Decision of the fork choice.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForkDecision {
+    /// Keep the local chain.
+    Keep,
+
+    /// Switch to the candidate chain.
+    Reorg(Reorg),
+
+    /// The candidate chain is better, but the reorg is deeper than allowed.
+    TooDeep(Reorg),
+}
+
+
+/// Fork choice rule.
+#[derive(Debug, Clone)]
+pub struct ForkChoice {
+    checkpoints: BTreeMap<u64, U256>,
+    max_depth: Option<u64>,
+}
+
+
+impl ForkChoice {
+    /// Create the rule with the built-in checkpoints and unlimited depth.
+    pub fn new() -> Self {
+        Self { checkpoints: BTreeMap::new(), max_depth: None }
+            .with_checkpoints(Checkpoint::get_builtin())
+    }
+
+    /// Add checkpoints (e.g. from the config).
+    pub fn with_checkpoints(mut self, checkpoints: Vec<Checkpoint>) -> Self {
+        self.checkpoints.extend(checkpoints.into_iter()
+                                           .map(|cp| (cp.bix, cp.hash)));
+        self
+    }
+
+    /// Limit the number of the local blocks to roll down.
+    pub fn with_max_depth(mut self, max_depth: u64) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Work of the block: `2^complexity`.
+    pub fn get_block_work(info: &BlockInfo) -> U256 {
+        let complexity = if info.complexity > 0 {
+            info.complexity.min(255) as usize
+        } else {
+            COMPLEXITY
+        };
+        &U256::from(1) << complexity
+    }
+
+    /// Total work of the blocks.
+    pub fn get_chain_work(infos: &[BlockInfo]) -> U256 {
+        let mut work = U256::from(0);
+        for info in infos.iter() {
+            work += &Self::get_block_work(info);
+        }
+        work
+    }
+
+    /// Compare the local chain with the candidate one. Both are consecutive
+    /// infos ending with the tips, they must share a block (e.g. start from
+    /// the same block), otherwise `BlockPreviousHashMismatch` is returned.
+    /// The candidate that breaks the checkpoints is `BlockCheckpointMismatch`.
+    pub fn choose(&self, local: &[BlockInfo],
+                  candidate: &[BlockInfo]) -> UqoinResult<ForkDecision> {
+        // Check the order
+        for infos in [local, candidate] {
+            validate!(!infos.is_empty() && infos.windows(2)
+                      .all(|pair| pair[0].bix + 1 == pair[1].bix),
+                      BlockOrderMismatch)?;
+        }
+
+        // Find the common ancestor
+        let hashes: HashMap<u64, &U256> = local.iter()
+            .map(|info| (info.bix, &info.hash)).collect();
+        let ix = candidate.iter()
+            .rposition(|info| hashes.get(&info.bix) == Some(&&info.hash));
+        validate!(ix.is_some(), BlockPreviousHashMismatch)
+            .map_err(|err| err.with_bix(candidate[0].bix))?;
+        let ix = ix.unwrap();
+        let ancestor = candidate[ix].clone();
+        let roll_up = candidate[ix + 1..].to_vec();
+        let roll_down: Vec<BlockInfo> = local.iter().rev()
+            .take_while(|info| info.bix > ancestor.bix)
+            .cloned().collect();
+
+        // Check the checkpoints
+        for info in roll_up.iter() {
+            if let Some(hash) = self.checkpoints.get(&info.bix) {
+                validate!(&info.hash == hash, BlockCheckpointMismatch)
+                    .map_err(|err| err.with_bix(info.bix))?;
+            }
+        }
+        if let Some(bix) = roll_down.last().map(|info| info.bix) {
+            let tip = local.last().unwrap().bix;
+            let checkpoint = self.checkpoints.range(bix..=tip).next();
+            validate!(checkpoint.is_none(), BlockCheckpointMismatch)
+                .map_err(|err| err.with_bix(*checkpoint.unwrap().0))?;
+        }
+
+        // Compare the work and the height
+        let local_work = Self::get_chain_work(&roll_down);
+        let candidate_work = Self::get_chain_work(&roll_up);
+        let better = (candidate_work > local_work) || (
+            (candidate_work == local_work) &&
+            (roll_up.len() > roll_down.len())
+        );
+        if !better {
+            return Ok(ForkDecision::Keep);
+        }
+
+        let reorg = Reorg { ancestor, roll_down, roll_up };
+        if self.max_depth.is_some_and(|depth| reorg.get_depth() > depth) {
+            Ok(ForkDecision::TooDeep(reorg))
+        } else {
+            Ok(ForkDecision::Reorg(reorg))
+        }
+    }
+}
+
+
+impl Default for ForkChoice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn build_chain(start: &BlockInfo, complexities: &[u64],
+                   seed: u64) -> Vec<BlockInfo> {
+        let mut infos = vec![start.clone()];
+        for (ix, complexity) in complexities.iter().enumerate() {
+            let bix = start.bix + 1 + ix as u64;
+            infos.push(BlockInfo {
+                bix,
+                offset: bix,
+                hash: hash_of_u256([&U256::from(bix),
+                                    &U256::from(seed)].into_iter()),
+                timestamp: 1700000000 + bix,
+                complexity: *complexity,
+            });
+        }
+        infos
+    }
+
+    #[test]
+    fn test_fork_choice() {
+        let genesis = BlockInfo::genesis();
+        let local = build_chain(&genesis, &[20, 20, 20], 1);
+        let fork_choice = ForkChoice::new();
+
+        // Same chain
+        assert_eq!(fork_choice.choose(&local, &local).unwrap(),
+                   ForkDecision::Keep);
+
+        // Heavier fork from the block 1
+        let mut candidate = local[..2].to_vec();
+        candidate.extend(build_chain(&local[1], &[21, 21], 2)
+                             .into_iter().skip(1));
+        match fork_choice.choose(&local, &candidate).unwrap() {
+            ForkDecision::Reorg(reorg) => {
+                assert_eq!(reorg.ancestor, local[1]);
+                assert_eq!(reorg.roll_down, vec![local[3].clone(),
+                                                 local[2].clone()]);
+                assert_eq!(reorg.roll_up, candidate[2..].to_vec());
+            },
+            decision => panic!("Unexpected decision {:?}", decision),
+        }
+
+        // Longer but lighter fork and the tie
+        let lighter = build_chain(&local[1], &[18, 18, 18], 3);
+        assert_eq!(fork_choice.choose(&local, &lighter).unwrap(),
+                   ForkDecision::Keep);
+        let tie = build_chain(&local[1], &[19, 19, 19, 19], 4);
+        assert!(matches!(fork_choice.choose(&local, &tie).unwrap(),
+                         ForkDecision::Reorg(_)));
+
+        // Extension of the local chain
+        let extension = build_chain(&local[3], &[20], 5);
+        match fork_choice.choose(&local, &extension).unwrap() {
+            ForkDecision::Reorg(reorg) => {
+                assert!(reorg.roll_down.is_empty());
+                assert_eq!(reorg.roll_up.len(), 1);
+            },
+            decision => panic!("Unexpected decision {:?}", decision),
+        }
+
+        // Depth limit
+        let limited = ForkChoice::new().with_max_depth(1);
+        assert!(matches!(limited.choose(&local, &candidate).unwrap(),
+                         ForkDecision::TooDeep(_)));
+
+        // Checkpoints
+        let checkpoint = Checkpoint::new(2, local[2].hash.clone());
+        let strict = ForkChoice::new().with_checkpoints(vec![checkpoint]);
+        assert_eq!(strict.choose(&local, &candidate).unwrap_err().kind(),
+                   ErrorKind::BlockCheckpointMismatch);
+        assert!(strict.choose(&local, &extension).is_ok());
+
+        // No common block
+        let foreign = build_chain(&local[1], &[20], 6)[1..].to_vec();
+        assert_eq!(fork_choice.choose(&local, &foreign).unwrap_err().kind(),
+                   ErrorKind::BlockPreviousHashMismatch);
+    }
+}
@@ -10,7 +10,7 @@
 //! ## Features
 //! 
 //! - **Elliptic Curve Cryptography** (Ed25519 signatures and key operations)
-//! - **Deterministic Key Generation** (BIP-39 style mnemonic seeds)
+//! - **Deterministic Key Generation** (BIP-39 style mnemonic seeds, SLIP-0010 paths)
 //! - **Coin Structure and Mining** (unique order-based mining validation)
 //! - **Transaction System** (transfer, fee, split, and merge types)
 //! - **Block Management** (validation, linking, and complexity proofs)
@@ -26,15 +26,34 @@
 //! |:---------------|:-------------------------------------------|
 //! | `utils`        | Utility functions and helpers             |
 //! | `error`        | Unified error types                       |
+//! | `codec`        | Canonical binary encoding                  |
 //! | `edwards`      | Cryptographic curve operations            |
 //! | `schema`       | Signature schemes and key validation      |
+//! | `commitment`   | Multiset commitments to the coin sets      |
 //! | `coin`         | Coin format, mining, and validation        |
+//! | `unit`         | Human-readable units for coin values       |
 //! | `transaction`  | Transaction types and verification         |
+//! | `unsigned`     | Group builder and offline signing          |
 //! | `block`        | Block structure and hash validation        |
+//! | `difficulty`   | Block complexity retargeting               |
+//! | `spec`         | Chain identity and protocol parameters     |
 //! | `state`        | Real-time blockchain state management      |
+//! | `fee`          | Fee policy and fee estimation              |
 //! | `pool`         | Transaction pooling before block creation |
+//! | `template`     | Block templates for validators and miners  |
+//! | `chain`        | Pipelined validation of block sequences    |
+//! | `fork_choice`  | Choice between competing chains            |
+//! | `light`        | Header-only light client and SPV proofs    |
+//! | `events`       | Event stream for subscribers               |
+//! | `reward`       | Validator reward accounting                |
 //! | `seed`         | Mnemonic generation and deterministic keys |
+//! | `net`          | Peer-to-peer messages, sync and relay      |
+//! | `rpc`          | JSON-RPC request and response types        |
+//! | `dto`          | Flat hex-encoded DTOs for REST APIs        |
 //! | `blockchain`   | Persistent blockchain storage              |
+//! | `disk_state`   | Disk-backed state for large chains         |
+//! | `snapshot`     | Periodic and verifiable state snapshots    |
+//! | `node`         | Consistent block and state commits         |
 //! 
 //! ---
 //! 
@@ -56,14 +75,42 @@ extern crate test;
 
 pub mod utils;
 pub mod error;
+pub mod codec;
 pub mod edwards;
 pub mod schema;
+pub mod commitment;
 pub mod coin;
+pub mod unit;
 pub mod transaction;
+pub mod unsigned;
 pub mod block;
+pub mod difficulty;
+pub mod spec;
 pub mod state;
+pub mod fee;
 pub mod pool;
+pub mod template;
+pub mod chain;
+pub mod fork_choice;
+pub mod light;
+pub mod events;
+pub mod reward;
 pub mod seed;
+pub mod net;
+pub mod rpc;
+pub mod dto;
 
 #[cfg(feature = "blockchain")]
 pub mod blockchain;
+
+#[cfg(feature = "blockchain")]
+pub mod disk_state;
+
+#[cfg(feature = "blockchain")]
+pub mod snapshot;
+
+#[cfg(feature = "blockchain")]
+pub mod node;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,259 @@
+//! Light client with simplified payment verification (SPV).
+//!
+//! Mobile and embedded wallets cannot store and replay the blocks with
+//! transactions. `LightClient` keeps the headers only (see `BlockHeader`):
+//! each pushed header is checked for the link to the previous one, the
+//! expected complexity, the hash and the checkpoints (like `SyncManager` does
+//! before downloading the blocks). The headers of legacy blocks do not commit
+//! to their transactions, so only their links are checked.
+//!
+//! A payment is proven with `PaymentProof`: the transaction, its block and
+//! the merkle branch of its hash. A full node makes the proof from the
+//! transactions of the block (`PaymentProof::new`), the wallet checks it
+//! against the stored header with `LightClient::verify_payment`. The headers
+//! can be persisted with `LightBlockchain`.
+
+use serde::{Serialize, Deserialize};
+
+use crate::validate;
+use crate::utils::*;
+use crate::transaction::Transaction;
+use crate::block::{BlockInfo, BlockHeader};
+use crate::spec::ChainSpec;
+use crate::chain::ChainValidator;
+
+
+/// Proof that the transaction is included into the block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaymentProof {
+    /// Number of the block.
+    pub bix: u64,
+
+    /// Index of the transaction in the block.
+    pub index: u64,
+
+    /// The transaction.
+    pub transaction: Transaction,
+
+    /// Merkle branch of the transaction hash (see `merkle_branch`).
+    pub branch: Vec<U256>,
+}
+
+
+impl PaymentProof {
+    /// Make the proof for the transaction `index` of the block `bix`
+    /// (`None` if the index is out of the transactions).
+    pub fn new(bix: u64, transactions: &[Transaction],
+               index: usize) -> Option<Self> {
+        let transaction = transactions.get(index)?.clone();
+        let hashes: Vec<U256> = transactions.iter()
+            .map(Transaction::get_hash).collect();
+        Some(Self {
+            bix,
+            index: index as u64,
+            transaction,
+            branch: merkle_branch(&hashes, index),
+        })
+    }
+}
+
+
+/// Header chain of the light client.
+#[derive(Debug, Clone)]
+pub struct LightClient {
+    spec: ChainSpec,
+    validator: ChainValidator,
+    headers: Vec<BlockHeader>,
+    history: Vec<BlockInfo>,
+}
+
+
+impl LightClient {
+    /// Create the client for the chain with the validator (its retarget
+    /// and checkpoints are used).
+    pub fn new(spec: ChainSpec, validator: ChainValidator) -> Self {
+        let history = vec![spec.get_genesis_info()];
+        Self { spec, validator, headers: Vec::new(), history }
+    }
+
+    /// Get the chain spec.
+    pub fn get_chain_spec(&self) -> &ChainSpec {
+        &self.spec
+    }
+
+    /// Number of the stored headers.
+    pub fn get_block_count(&self) -> u64 {
+        self.headers.len() as u64
+    }
+
+    /// Get the header of the block (1-based).
+    pub fn get_block_header(&self, bix: u64) -> Option<&BlockHeader> {
+        bix.checked_sub(1).and_then(|ix| self.headers.get(ix as usize))
+    }
+
+    /// Get information of the last block.
+    pub fn get_last_block_info(&self) -> &BlockInfo {
+        self.history.last().unwrap()
+    }
+
+    /// Validate the headers that follow the last one and store them. On
+    /// error the valid headers before the broken one are kept. Returns the
+    /// number of the stored headers.
+    pub fn push_headers(&mut self,
+                        headers: &[BlockHeader]) -> UqoinResult<usize> {
+        let size = self.validator.get_retarget().get_history_size();
+        for header in headers.iter() {
+            let info_prev = self.get_last_block_info();
+            let bix = info_prev.bix + 1;
+            if header.is_legacy() {
+                validate!(header.hash_prev == info_prev.hash,
+                          BlockPreviousHashMismatch)
+                    .and(validate!(header.offset == info_prev.offset,
+                                   BlockOffsetMismatch))
+            } else {
+                let history = &self.history;
+                self.validator.validate_timestamp(header.timestamp, history)
+                    .and_then(|_| self.validator.get_complexity(history))
+                    .and_then(|complexity| header.validate(info_prev, 
+                                                           complexity))
+            }.and_then(|_| self.validator.validate_checkpoint(bix,
+                                                              &header.hash))
+             .map_err(|err| err.with_bix(bix))?;
+
+            self.headers.push(header.clone());
+            self.history.push(header.get_block_info(bix));
+            if self.history.len() > size {
+                self.history.remove(0);
+            }
+        }
+        Ok(headers.len())
+    }
+
+    /// Remove the headers above `bix` (e.g. to switch to a better chain).
+    pub fn truncate(&mut self, bix: u64) {
+        self.headers.truncate(bix as usize);
+        let size = self.validator.get_retarget().get_history_size() as u64;
+        let start = (bix + 1).saturating_sub(size);
+        self.history = (start..=bix).map(|hbix| match hbix {
+            0 => self.spec.get_genesis_info(),
+            _ => self.headers[hbix as usize - 1].get_block_info(hbix),
+        }).collect();
+    }
+
+    /// Check that the transaction of the proof is included into the stored
+    /// block (`TransactionNotIncluded` otherwise, `BlockLegacyHeader` for
+    /// the legacy blocks). Returns the number of the confirmations (the
+    /// block itself and the blocks after it).
+    pub fn verify_payment(&self, proof: &PaymentProof) -> UqoinResult<u64> {
+        let header = self.get_block_header(proof.bix);
+        validate!(header.is_some(), TransactionNotIncluded)
+            .map_err(|err| err.with_bix(proof.bix))?;
+        let header = header.unwrap();
+        validate!(!header.is_legacy(), BlockLegacyHeader)
+            .map_err(|err| err.with_bix(proof.bix))?;
+
+        let root = merkle_root_of_branch(
+            &proof.transaction.get_hash(), proof.index as usize,
+            header.size as usize, &proof.branch
+        );
+        validate!(root.as_ref() == Some(&header.merkle_root),
+                  TransactionNotIncluded)
+            .map_err(|err| err.with_bix(proof.bix)
+                              .with_index(proof.index as usize)
+                              .with_coin(&proof.transaction.coin))?;
+
+        Ok(self.get_block_count() - proof.bix + 1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::error::ErrorKind;
+    use crate::schema::Schema;
+    use crate::coin::coin_random;
+    use crate::block::Block;
+    use crate::state::State;
+    use crate::difficulty::Retarget;
+    use crate::chain::Checkpoint;
+
+    #[test]
+    fn test_light_client() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let complexity = 4;
+
+        // Build the chain of three blocks with three transactions each
+        let mut state = State::new();
+        let mut blocks = Vec::new();
+        for ix in 0..3 {
+            let transactions: Vec<Transaction> = (0..3).map(|_| {
+                let coin = coin_random(&mut rng, &public);
+                let addr: U256 = rng.random();
+                Transaction::build(&mut rng, coin, addr, &key, 0, &schema)
+            }).collect();
+            let timestamp = 1700000000 + ix;
+            let info = state.get_last_block_info().clone();
+            let senders = Transaction::calc_senders(&transactions, &state,
+                                                    &schema);
+            let nonce = Block::mine(&mut rng, &info, &validator,
+                                    timestamp, &transactions, complexity,
+                                    None).unwrap();
+            let block = Block::build(&info, validator.clone(), timestamp,
+                                     &transactions, U256::from_bytes(&nonce),
+                                     complexity, &state, &senders).unwrap();
+            state.roll_up(info.bix + 1, &block, &transactions, &schema);
+            blocks.push((block, transactions));
+        }
+        let headers: Vec<BlockHeader> = blocks.iter()
+            .map(|(block, transactions)| block.get_header(transactions))
+            .collect();
+
+        // Push the headers
+        let mut client = LightClient::new(
+            ChainSpec::mainnet(),
+            ChainValidator::new(Retarget::default())
+                .with_complexity(complexity)
+        );
+        assert_eq!(client.push_headers(&headers).unwrap(), 3);
+        assert_eq!(client.get_last_block_info(), state.get_last_block_info());
+
+        // Verify the payments
+        let proof = PaymentProof::new(2, &blocks[1].1, 2).unwrap();
+        let json = serde_json::to_string(&proof).unwrap();
+        let proof: PaymentProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(client.verify_payment(&proof).unwrap(), 2);
+
+        let mut forged = proof.clone();
+        forged.transaction = blocks[0].1[2].clone();
+        assert_eq!(client.verify_payment(&forged).unwrap_err().kind(),
+                   ErrorKind::TransactionNotIncluded);
+        let mut forged = proof.clone();
+        forged.bix = 4;
+        assert_eq!(client.verify_payment(&forged).unwrap_err().kind(),
+                   ErrorKind::TransactionNotIncluded);
+
+        // Broken link and reorg
+        client.truncate(1);
+        assert_eq!(client.get_block_count(), 1);
+        assert!(client.verify_payment(&proof).is_err());
+        assert_eq!(client.push_headers(&headers[2..]).unwrap_err().kind(),
+                   ErrorKind::BlockPreviousHashMismatch);
+        assert_eq!(client.push_headers(&headers[1..]).unwrap(), 2);
+
+        // Checkpoint
+        let mut strict = LightClient::new(
+            ChainSpec::mainnet(),
+            ChainValidator::new(Retarget::default())
+                .with_complexity(complexity)
+                .with_checkpoints(vec![Checkpoint::new(2, U256::from(1))])
+        );
+        assert_eq!(strict.push_headers(&headers).unwrap_err().kind(),
+                   ErrorKind::BlockCheckpointMismatch);
+        assert_eq!(strict.get_block_count(), 1);
+    }
+}
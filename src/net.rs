@@ -0,0 +1,20 @@
+//! Peer-to-peer networking primitives.
+//!
+//! The crate does not implement the transport: nodes exchange the messages
+//! of `messages` over any channel (TCP, WebSocket, etc.) in their canonical
+//! encoding (see `codec`), so node implementations built on uqoin-core agree
+//! on the wire vocabulary. `handshake` checks the compatibility of the peers
+//! on connection, `sync` drives the block synchronization over the
+//! requests the node implements, `relay` validates the gossiped transaction
+//! groups before the pool, `wire` frames the encoded structures for the
+//! stream transports (TCP, QUIC) and `addr_book` keeps the known peers
+//! between restarts.
+
+pub mod messages;
+pub mod handshake;
+pub mod relay;
+pub mod wire;
+pub mod addr_book;
+
+#[cfg(feature = "blockchain")]
+pub mod sync;
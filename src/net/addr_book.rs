@@ -0,0 +1,325 @@
+//! Known addresses of the peers.
+//!
+//! `AddrBook` records the addresses the node learned (from the config, the
+//! peers or its own connections) with the time they were seen last and the
+//! quality score. Successful connections raise the score, failures and
+//! misbehaviour (e.g. the invalid relayed groups, see
+//! `RelayOutcome::is_invalid`) lower it, the peers at `ADDR_SCORE_BAN` or
+//! below are banned and not selected for the connections. The addresses
+//! failing `ADDR_MAX_FAILURES` times in a row are forgotten.
+//!
+//! The book is limited by its capacity: a new address replaces the worst
+//! one. With the `blockchain` feature it is persisted as JSON (`dump` and
+//! `load`), so a restarted node reconnects to the peers it knew instead of
+//! the bootstrap list.
+
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "blockchain")]
+use tokio::io::Result as TokioResult;
+
+use super::messages::{Features, STRING_MAX_SIZE};
+
+
+/// Default number of the stored addresses.
+pub const ADDR_BOOK_CAPACITY: usize = 4096;
+
+/// Maximum score of the peer.
+pub const ADDR_SCORE_MAX: i32 = 100;
+
+/// Score of the banned peer.
+pub const ADDR_SCORE_BAN: i32 = -100;
+
+/// Score gained for the successful connection.
+pub const ADDR_SCORE_SUCCESS: i32 = 10;
+
+/// Score lost for the failed connection.
+pub const ADDR_SCORE_FAILURE: i32 = 5;
+
+/// Number of the failures in a row after which the address is forgotten.
+pub const ADDR_MAX_FAILURES: u32 = 10;
+
+
+/// Known address of the peer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerAddr {
+    /// Address of the peer (`host:port`).
+    pub addr: String,
+
+    /// Time when the address was added.
+    #[serde(default)]
+    pub added: u64,
+
+    /// Time of the last successful connection (0 if never connected).
+    pub last_seen: u64,
+
+    /// Time of the last connection attempt.
+    pub last_attempt: u64,
+
+    /// Quality score from `ADDR_SCORE_BAN` to `ADDR_SCORE_MAX`.
+    pub score: i32,
+
+    /// Number of the failed connections in a row.
+    pub failures: u32,
+
+    /// Features of the peer from its last handshake.
+    #[serde(default)]
+    pub features: Features,
+}
+
+
+impl PeerAddr {
+    /// Create the address that was never connected.
+    pub fn new(addr: &str, added: u64) -> Self {
+        Self {
+            addr: addr.to_string(),
+            added,
+            last_seen: 0,
+            last_attempt: 0,
+            score: 0,
+            failures: 0,
+            features: Features::NONE,
+        }
+    }
+
+    /// Check if the peer is banned.
+    pub fn is_banned(&self) -> bool {
+        self.score <= ADDR_SCORE_BAN
+    }
+}
+
+
+/// Book of the known peer addresses.
+#[derive(Debug, Clone)]
+pub struct AddrBook {
+    peers: HashMap<String, PeerAddr>,
+    capacity: usize,
+}
+
+
+impl AddrBook {
+    /// Create an empty book of `ADDR_BOOK_CAPACITY` addresses.
+    pub fn new() -> Self {
+        Self::with_capacity(ADDR_BOOK_CAPACITY)
+    }
+
+    /// Create an empty book of `capacity` addresses.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self { peers: HashMap::new(), capacity }
+    }
+
+    /// Number of the addresses.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Check if the book is empty.
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Get the address.
+    pub fn get(&self, addr: &str) -> Option<&PeerAddr> {
+        self.peers.get(addr)
+    }
+
+    /// Iterate over the addresses.
+    pub fn iter(&self) -> impl Iterator<Item = &PeerAddr> {
+        self.peers.values()
+    }
+
+    /// Add the address if it is unknown (e.g. announced by a peer). If the
+    /// book is full, the worst address is replaced. Returns `true` if the
+    /// address is added.
+    pub fn add(&mut self, addr: &str, now: u64) -> bool {
+        if addr.is_empty() || (addr.len() > STRING_MAX_SIZE) ||
+                self.peers.contains_key(addr) {
+            return false;
+        }
+        self.insert(PeerAddr::new(addr, now));
+        true
+    }
+
+    /// Record the successful connection with the peer at time `now`.
+    pub fn mark_seen(&mut self, addr: &str, now: u64, features: Features) {
+        self.add(addr, now);
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.last_seen = now;
+            peer.last_attempt = now;
+            peer.failures = 0;
+            peer.features = features;
+            peer.score = (peer.score + ADDR_SCORE_SUCCESS).min(ADDR_SCORE_MAX);
+        }
+    }
+
+    /// Record the failed connection with the peer at time `now`. The address
+    /// is forgotten after `ADDR_MAX_FAILURES` failures in a row.
+    pub fn mark_failed(&mut self, addr: &str, now: u64) {
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.last_attempt = now;
+            peer.failures += 1;
+            peer.score = (peer.score - ADDR_SCORE_FAILURE).max(ADDR_SCORE_BAN);
+            if peer.failures >= ADDR_MAX_FAILURES {
+                self.peers.remove(addr);
+            }
+        }
+    }
+
+    /// Lower the score of the misbehaving peer.
+    pub fn penalize(&mut self, addr: &str, penalty: i32) {
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.score = (peer.score - penalty.max(0)).max(ADDR_SCORE_BAN);
+        }
+    }
+
+    /// Check if the peer is banned.
+    pub fn is_banned(&self, addr: &str) -> bool {
+        self.peers.get(addr).is_some_and(PeerAddr::is_banned)
+    }
+
+    /// Remove the address.
+    pub fn remove(&mut self, addr: &str) -> Option<PeerAddr> {
+        self.peers.remove(addr)
+    }
+
+    /// Select up to `count` addresses to connect to: the not banned ones
+    /// with the best score, the recently seen first. The addresses in
+    /// `exclude` (e.g. the connected peers) are skipped.
+    pub fn select(&self, count: usize, exclude: &[String]) -> Vec<String> {
+        let mut peers: Vec<&PeerAddr> = self.peers.values()
+            .filter(|peer| !peer.is_banned() && !exclude.contains(&peer.addr))
+            .collect();
+        peers.sort_by(|a, b| Self::rank(b).cmp(&Self::rank(a))
+                                          .then(a.addr.cmp(&b.addr)));
+        peers.into_iter().take(count).map(|peer| peer.addr.clone()).collect()
+    }
+
+    /// Forget the addresses that were not added, connected or tried since
+    /// `min_time`. Returns the number of the removed addresses.
+    pub fn prune(&mut self, min_time: u64) -> usize {
+        let size = self.peers.len();
+        self.peers.retain(|_, peer| {
+            peer.added.max(peer.last_seen).max(peer.last_attempt) >= min_time
+        });
+        size - self.peers.len()
+    }
+
+    /// Load the book from a file (see `dump`).
+    #[cfg(feature = "blockchain")]
+    pub async fn load(path: &str) -> TokioResult<Self> {
+        let bytes = tokio::fs::read(path).await?;
+        let peers: Vec<PeerAddr> = serde_json::from_slice(&bytes)?;
+        let mut book = Self::new();
+        for peer in peers.into_iter() {
+            book.insert(peer);
+        }
+        Ok(book)
+    }
+
+    /// Dump the book to a file as the JSON list of the addresses. It is
+    /// written to a temporary file and renamed, so the file is never
+    /// partial.
+    #[cfg(feature = "blockchain")]
+    pub async fn dump(&self, path: &str) -> TokioResult<()> {
+        let mut peers: Vec<&PeerAddr> = self.peers.values().collect();
+        peers.sort_by(|a, b| a.addr.cmp(&b.addr));
+        let path_tmp = format!("{}.tmp", path);
+        tokio::fs::write(&path_tmp, serde_json::to_vec(&peers)?).await?;
+        tokio::fs::rename(&path_tmp, path).await
+    }
+
+    fn rank(peer: &PeerAddr) -> (i32, u64) {
+        (peer.score, peer.last_seen)
+    }
+
+    fn insert(&mut self, peer: PeerAddr) {
+        if !self.peers.contains_key(&peer.addr) &&
+                (self.peers.len() >= self.capacity) {
+            let worst = self.peers.values()
+                .min_by(|a, b| Self::rank(a).cmp(&Self::rank(b))
+                                            .then(b.addr.cmp(&a.addr)))
+                .map(|peer| peer.addr.clone()).unwrap();
+            self.peers.remove(&worst);
+        }
+        self.peers.insert(peer.addr.clone(), peer);
+    }
+}
+
+
+impl Default for AddrBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addr_book() {
+        let mut book = AddrBook::with_capacity(3);
+        assert!(book.add("10.0.0.1:7000", 0));
+        assert!(book.add("10.0.0.2:7000", 0));
+        assert!(!book.add("10.0.0.2:7000", 0));
+        assert!(!book.add("", 0));
+        assert_eq!(book.len(), 2);
+
+        // Scores
+        book.mark_seen("10.0.0.2:7000", 100, Features::HEADERS);
+        book.mark_seen("10.0.0.3:7000", 200, Features::NONE);
+        assert_eq!(book.get("10.0.0.2:7000").unwrap().score,
+                   ADDR_SCORE_SUCCESS);
+        assert_eq!(book.get("10.0.0.2:7000").unwrap().features,
+                   Features::HEADERS);
+        assert_eq!(book.select(2, &[]), vec!["10.0.0.3:7000".to_string(),
+                                             "10.0.0.2:7000".to_string()]);
+        assert_eq!(book.select(5, &["10.0.0.3:7000".to_string()]).len(), 2);
+
+        // The worst address is replaced when full
+        book.mark_failed("10.0.0.1:7000", 300);
+        assert!(book.add("10.0.0.4:7000", 300));
+        assert_eq!(book.len(), 3);
+        assert!(book.get("10.0.0.1:7000").is_none());
+
+        // Ban
+        book.penalize("10.0.0.3:7000", 1000);
+        assert!(book.is_banned("10.0.0.3:7000"));
+        assert!(!book.select(5, &[]).contains(&"10.0.0.3:7000".to_string()));
+
+        // Failures in a row
+        for now in 0..ADDR_MAX_FAILURES as u64 {
+            book.mark_failed("10.0.0.4:7000", 400 + now);
+        }
+        assert!(book.get("10.0.0.4:7000").is_none());
+
+        // Stale addresses
+        assert_eq!(book.prune(150), 1);
+        assert_eq!(book.len(), 1);
+    }
+
+    #[cfg(feature = "blockchain")]
+    #[tokio::test]
+    async fn test_addr_book_dump_load() {
+        use lbasedb::path_concat;
+        use crate::testing::TempDir;
+
+        let tmp = TempDir::new("uqoin-addr-book");
+        let path = path_concat!(tmp.path(), "peers.json");
+
+        let mut book = AddrBook::new();
+        book.mark_seen("peer1.example.com:7000", 100, Features::TX_RELAY);
+        book.add("peer2.example.com:7000", 100);
+        book.dump(&path).await.unwrap();
+
+        let book2 = AddrBook::load(&path).await.unwrap();
+        assert_eq!(book2.len(), 2);
+        assert_eq!(book2.get("peer1.example.com:7000"),
+                   book.get("peer1.example.com:7000"));
+        assert_eq!(book2.select(1, &[]),
+                   vec!["peer1.example.com:7000".to_string()]);
+    }
+}
@@ -0,0 +1,167 @@
+//! Handshake of the peers and negotiation of the protocol.
+//!
+//! Both peers send `Version` as the first message of the connection and
+//! check the received one with `Handshake::negotiate`:
+//! 1. The chain id and the genesis hash must be the same, otherwise it is
+//!    `NetworkChainMismatch`.
+//! 2. The protocol version of the peer must be at least
+//!    `MIN_PROTOCOL_VERSION`, then the lower version of both is used, so the
+//!    nodes of different crate versions interoperate.
+//! 3. The peer must support the required features, the rest of the features
+//!    are used only if both peers support them.
+//!
+//! The incompatible peers (`NetworkIncompatible`) are refused cleanly with
+//! `Reject` made from the error before disconnection.
+
+use crate::validate;
+use crate::utils::*;
+use crate::spec::ChainSpec;
+use crate::block::BlockInfo;
+use super::messages::{Version, Features, PROTOCOL_VERSION};
+
+
+/// Minimum supported version of the protocol.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+
+/// Parameters of the connection agreed in the handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    /// Version of the protocol used in the connection.
+    pub protocol_version: u32,
+
+    /// Features supported by both peers.
+    pub features: Features,
+
+    /// Last block of the peer.
+    pub best: BlockInfo,
+
+    /// Name and version of the software of the peer.
+    pub user_agent: String,
+}
+
+
+/// Local side of the handshake.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    version: Version,
+    required: Features,
+}
+
+
+impl Handshake {
+    /// Create the handshake for the chain with the local best block.
+    pub fn new(spec: &ChainSpec, best: BlockInfo, user_agent: &str,
+               features: Features) -> Self {
+        let version = Version {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: spec.chain_id,
+            genesis_hash: spec.genesis_hash.clone(),
+            best,
+            user_agent: user_agent.to_string(),
+            features,
+        };
+        Self { version, required: Features::NONE }
+    }
+
+    /// Set the features that the peers must support.
+    pub fn with_required(mut self, required: Features) -> Self {
+        self.required = required;
+        self
+    }
+
+    /// Get the message to send.
+    pub fn get_version(&self) -> &Version {
+        &self.version
+    }
+
+    /// Update the local best block (e.g. before a new connection).
+    pub fn set_best(&mut self, best: BlockInfo) {
+        self.version.best = best;
+    }
+
+    /// Check the version of the peer and agree the parameters of the
+    /// connection.
+    pub fn negotiate(&self, remote: &Version) -> UqoinResult<Session> {
+        // Check the chain
+        validate!(remote.chain_id == self.version.chain_id,
+                  NetworkChainMismatch)
+            .map_err(|err| err.with_values(self.version.chain_id,
+                                           remote.chain_id))?;
+        validate!(remote.genesis_hash == self.version.genesis_hash,
+                  NetworkChainMismatch)
+            .map_err(|err| err.with_values(self.version.genesis_hash.to_hex(),
+                                           remote.genesis_hash.to_hex()))?;
+
+        // Check the protocol version
+        validate!(remote.protocol_version >= MIN_PROTOCOL_VERSION,
+                  NetworkIncompatible)
+            .map_err(|err| err.with_values(MIN_PROTOCOL_VERSION,
+                                           remote.protocol_version))?;
+
+        // Check the required features
+        validate!(remote.features.contains(self.required),
+                  NetworkIncompatible)
+            .map_err(|err| err.with_values(self.required.0,
+                                           remote.features.0))?;
+
+        Ok(Session {
+            protocol_version: remote.protocol_version
+                                    .min(self.version.protocol_version),
+            features: remote.features.intersection(self.version.features),
+            best: remote.best.clone(),
+            user_agent: remote.user_agent.clone(),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::net::messages::PROTOCOL_VERSION_1;
+
+    #[test]
+    fn test_handshake() {
+        let spec = ChainSpec::mainnet();
+        let local = Handshake::new(
+            &spec, spec.get_genesis_info(), "uqoin/0.2",
+            Features::HEADERS.union(Features::TX_RELAY)
+        );
+        let remote = Handshake::new(
+            &spec, spec.get_genesis_info(), "uqoin/0.3",
+            Features::HEADERS.union(Features::FULL_HISTORY)
+        );
+
+        // Common features
+        let session = local.negotiate(remote.get_version()).unwrap();
+        assert_eq!(session.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(session.features, Features::HEADERS);
+        assert_eq!(session.user_agent, "uqoin/0.3");
+
+        // Older peer
+        let mut version = remote.get_version().clone();
+        version.protocol_version = PROTOCOL_VERSION_1;
+        version.features = Features::NONE;
+        let session = local.negotiate(&version).unwrap();
+        assert_eq!(session.protocol_version, PROTOCOL_VERSION_1);
+        assert_eq!(session.features, Features::NONE);
+
+        // Required features
+        let strict = local.clone().with_required(Features::TX_RELAY);
+        assert_eq!(strict.negotiate(remote.get_version()).unwrap_err().kind(),
+                   ErrorKind::NetworkIncompatible);
+        version.protocol_version = 0;
+        assert_eq!(local.negotiate(&version).unwrap_err().kind(),
+                   ErrorKind::NetworkIncompatible);
+
+        // Another chain
+        let other = ChainSpec::new(spec.chain_id + 1,
+                                   spec.genesis_hash.clone());
+        let remote = Handshake::new(&other, other.get_genesis_info(),
+                                    "uqoin/0.3", Features::NONE);
+        assert_eq!(local.negotiate(remote.get_version()).unwrap_err().kind(),
+                   ErrorKind::NetworkChainMismatch);
+    }
+}
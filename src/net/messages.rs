@@ -0,0 +1,497 @@
+//! Protocol messages exchanged by the peers.
+//!
+//! Each message is encoded as its type byte followed by the payload in the
+//! canonical encoding (see `codec`). Strings are prefixed with their length
+//! as `u32` and they must be valid UTF-8. Decoding validates the message
+//! (`Message::validate`), so the limits below are enforced for all received
+//! messages and the violations are `NetworkInvalidMessage`.
+//!
+//! Messages:
+//! - `Version`: the first message of the connection with the chain, the
+//!   best block and the features of the peer (see `handshake`).
+//! - `Inv`: hashes of the blocks and transactions the peer has.
+//! - `GetBlocks`: request of the blocks by number.
+//! - `BlockAnnounce`: the block with its transactions (a new one or the
+//!   response to `GetBlocks`).
+//! - `TxAnnounce`: the group of transactions (with its fee) to add to the
+//!   pool, as it is accepted by `Pool::submit_raw`.
+//! - `Reject`: the reason why the message or the item was rejected (the code
+//!   of the `ErrorKind`).
+
+use serde::{Serialize, Deserialize};
+
+use crate::validate;
+use crate::utils::*;
+use crate::error::{Error, ErrorKind};
+use crate::codec::{Codec, Writer, Reader};
+use crate::transaction::Transaction;
+use crate::block::{BlockInfo, BlockData, BLOCK_MAX_SIZE};
+
+
+/// Version 1 of the protocol.
+pub const PROTOCOL_VERSION_1: u32 = 1;
+
+/// Version 2 of the protocol: `Version` carries the features.
+pub const PROTOCOL_VERSION_2: u32 = 2;
+
+/// Current version of the protocol.
+pub const PROTOCOL_VERSION: u32 = PROTOCOL_VERSION_2;
+
+/// Maximum number of items in `Inv`.
+pub const INV_MAX_SIZE: usize = 4096;
+
+/// Maximum number of blocks requested by `GetBlocks`.
+pub const GET_BLOCKS_MAX_COUNT: u64 = 500;
+
+/// Maximum number of transactions in `TxAnnounce` (the merge group with its
+/// fee).
+pub const TX_ANNOUNCE_MAX_SIZE: usize = 4;
+
+/// Maximum length of the strings in bytes.
+pub const STRING_MAX_SIZE: usize = 256;
+
+
+/// Kind of the inventory item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum InvKind {
+    Block = 1,
+    Transaction = 2,
+}
+
+
+/// Item of `Inv`: the hash of the block or the transaction.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InvItem {
+    pub kind: InvKind,
+    #[serde(with = "u256_hex")]
+    pub hash: U256,
+}
+
+
+impl InvItem {
+    /// Create an item.
+    pub fn new(kind: InvKind, hash: U256) -> Self {
+        Self { kind, hash }
+    }
+}
+
+
+/// Set of the optional features supported by the node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, 
+         Deserialize)]
+#[serde(transparent)]
+pub struct Features(pub u64);
+
+
+impl Features {
+    /// No features (the peers of protocol version 1).
+    pub const NONE: Self = Self(0);
+
+    /// Serves the headers for the header-first sync (see `sync`).
+    pub const HEADERS: Self = Self(1 << 0);
+
+    /// Relays the transaction groups (see `relay`).
+    pub const TX_RELAY: Self = Self(1 << 1);
+
+    /// Keeps all the blocks since the genesis.
+    pub const FULL_HISTORY: Self = Self(1 << 2);
+
+    /// Check if all the features of `other` are supported.
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Features supported by both.
+    pub fn intersection(&self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Features supported by any.
+    pub fn union(&self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+
+/// The first message of the connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Version {
+    /// Version of the protocol (`PROTOCOL_VERSION`).
+    pub protocol_version: u32,
+
+    /// Chain id of the peer (see `ChainSpec`).
+    pub chain_id: u64,
+
+    /// Genesis hash of the peer.
+    #[serde(with = "u256_hex")]
+    pub genesis_hash: U256,
+
+    /// Last block of the peer.
+    pub best: BlockInfo,
+
+    /// Name and version of the node software.
+    pub user_agent: String,
+
+    /// Supported features (since protocol version 2).
+    #[serde(default)]
+    pub features: Features,
+}
+
+
+/// Request of `count` blocks starting from the number `bix`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetBlocks {
+    pub bix: u64,
+    pub count: u64,
+}
+
+
+/// Rejection of the message or the item with the hash (zero if it is not
+/// about a particular item).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reject {
+    /// Code of the `ErrorKind`.
+    pub code: u16,
+
+    /// Hash of the rejected block or transaction.
+    #[serde(with = "u256_hex")]
+    pub hash: U256,
+
+    /// Human-readable reason.
+    pub reason: String,
+}
+
+
+impl Reject {
+    /// Create the rejection from the error (the reason is its message
+    /// truncated to `STRING_MAX_SIZE`).
+    pub fn from_error(err: &Error, hash: U256) -> Self {
+        let mut reason = err.to_string();
+        while reason.len() > STRING_MAX_SIZE {
+            reason.pop();
+        }
+        Self { code: err.kind().code(), hash, reason }
+    }
+}
+
+
+/// Protocol message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum Message {
+    Version(Version),
+    Inv(Vec<InvItem>),
+    GetBlocks(GetBlocks),
+    BlockAnnounce(BlockData),
+    TxAnnounce(Vec<Transaction>),
+    Reject(Reject),
+}
+
+
+impl Message {
+    /// Type byte of the message in the encoding.
+    pub fn get_type(&self) -> u8 {
+        match self {
+            Self::Version(_) => 1,
+            Self::Inv(_) => 2,
+            Self::GetBlocks(_) => 3,
+            Self::BlockAnnounce(_) => 4,
+            Self::TxAnnounce(_) => 5,
+            Self::Reject(_) => 6,
+        }
+    }
+
+    /// Check the limits of the message. The content (blocks, transactions)
+    /// is validated by the node against its state.
+    pub fn validate(&self) -> UqoinResult<()> {
+        match self {
+            Self::Version(version) => {
+                validate!(version.user_agent.len() <= STRING_MAX_SIZE,
+                          NetworkInvalidMessage)
+                    .map_err(|err| err.with_values(STRING_MAX_SIZE,
+                                                   version.user_agent.len()))
+            },
+            Self::Inv(items) => {
+                validate!((1..=INV_MAX_SIZE).contains(&items.len()),
+                          NetworkInvalidMessage)
+                    .map_err(|err| err.with_values(
+                        format!("1..={}", INV_MAX_SIZE), items.len()
+                    ))
+            },
+            Self::GetBlocks(get_blocks) => {
+                validate!((1..=GET_BLOCKS_MAX_COUNT)
+                          .contains(&get_blocks.count) &&
+                          (get_blocks.bix > 0), NetworkInvalidMessage)
+                    .map_err(|err| err.with_values(
+                        format!("1..={}", GET_BLOCKS_MAX_COUNT),
+                        get_blocks.count
+                    ))
+            },
+            Self::BlockAnnounce(block_data) => {
+                let size = block_data.transactions.len();
+                validate!((size as u64 == block_data.block.size) &&
+                          (size <= BLOCK_MAX_SIZE), NetworkInvalidMessage)
+                    .map_err(|err| err.with_bix(block_data.bix)
+                                      .with_values(block_data.block.size,
+                                                   size))
+            },
+            Self::TxAnnounce(transactions) => {
+                validate!((1..=TX_ANNOUNCE_MAX_SIZE)
+                          .contains(&transactions.len()),
+                          NetworkInvalidMessage)
+                    .map_err(|err| err.with_values(
+                        format!("1..={}", TX_ANNOUNCE_MAX_SIZE),
+                        transactions.len()
+                    ))
+            },
+            Self::Reject(reject) => {
+                validate!(reject.reason.len() <= STRING_MAX_SIZE,
+                          NetworkInvalidMessage)
+                    .map_err(|err| err.with_values(STRING_MAX_SIZE,
+                                                   reject.reason.len()))
+            },
+        }
+    }
+}
+
+
+/// Canonical encoding of the inventory item (33 bytes): kind (1 byte) and
+/// hash (32 bytes).
+impl Codec for InvItem {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_u8(self.kind as u8);
+        writer.write_u256(&self.hash);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        let kind = match reader.read_u8()? {
+            1 => InvKind::Block,
+            2 => InvKind::Transaction,
+            _ => return Err(ErrorKind::NetworkInvalidMessage.into()),
+        };
+        Ok(Self::new(kind, reader.read_u256()?))
+    }
+}
+
+
+/// Canonical encoding of the message: type (1 byte) and the payload:
+/// - `Version`: protocol version (4 bytes), chain id (8 bytes), genesis hash
+///   (32 bytes), best block info (64 bytes), user agent (string) and, since
+///   protocol version 2, features (8 bytes).
+/// - `Inv`: sequence of the items.
+/// - `GetBlocks`: `bix` and `count` (8 bytes each).
+/// - `BlockAnnounce`: the block data (see `BlockData`).
+/// - `TxAnnounce`: sequence of the transactions.
+/// - `Reject`: code (4 bytes), hash (32 bytes) and reason (string).
+impl Codec for Message {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_u8(self.get_type());
+        match self {
+            Self::Version(version) => {
+                writer.write_u32(version.protocol_version);
+                writer.write_u64(version.chain_id);
+                writer.write_u256(&version.genesis_hash);
+                version.best.encode(writer);
+                write_string(writer, &version.user_agent);
+                if version.protocol_version >= PROTOCOL_VERSION_2 {
+                    writer.write_u64(version.features.0);
+                }
+            },
+            Self::Inv(items) => writer.write_seq(items),
+            Self::GetBlocks(get_blocks) => {
+                writer.write_u64(get_blocks.bix);
+                writer.write_u64(get_blocks.count);
+            },
+            Self::BlockAnnounce(block_data) => block_data.encode(writer),
+            Self::TxAnnounce(transactions) => writer.write_seq(transactions),
+            Self::Reject(reject) => {
+                writer.write_u32(reject.code as u32);
+                writer.write_u256(&reject.hash);
+                write_string(writer, &reject.reason);
+            },
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        let message = match reader.read_u8()? {
+            1 => {
+                let mut version = Version {
+                    protocol_version: reader.read_u32()?,
+                    chain_id: reader.read_u64()?,
+                    genesis_hash: reader.read_u256()?,
+                    best: BlockInfo::decode(reader)?,
+                    user_agent: read_string(reader)?,
+                    features: Features::NONE,
+                };
+                if version.protocol_version >= PROTOCOL_VERSION_2 {
+                    version.features = Features(reader.read_u64()?);
+                }
+                Self::Version(version)
+            },
+            2 => Self::Inv(reader.read_seq()?),
+            3 => Self::GetBlocks(GetBlocks {
+                bix: reader.read_u64()?,
+                count: reader.read_u64()?,
+            }),
+            4 => Self::BlockAnnounce(BlockData::decode(reader)?),
+            5 => Self::TxAnnounce(reader.read_seq()?),
+            6 => {
+                let code = reader.read_u32()?;
+                validate!(code <= u16::MAX as u32, NetworkInvalidMessage)?;
+                Self::Reject(Reject {
+                    code: code as u16,
+                    hash: reader.read_u256()?,
+                    reason: read_string(reader)?,
+                })
+            },
+            tp => return Err(Error::from(ErrorKind::NetworkInvalidMessage)
+                .with_values("1..=6", tp)),
+        };
+        message.validate()?;
+        Ok(message)
+    }
+}
+
+
+/// Write the string prefixed with its length.
+fn write_string(writer: &mut Writer, string: &str) {
+    writer.write_u32(string.len() as u32);
+    writer.write_bytes(string.as_bytes());
+}
+
+
+/// Read the string prefixed with its length, it must be valid UTF-8 not
+/// longer than `STRING_MAX_SIZE`.
+fn read_string(reader: &mut Reader) -> UqoinResult<String> {
+    let size = reader.read_u32()? as usize;
+    validate!(size <= STRING_MAX_SIZE, NetworkInvalidMessage)
+        .map_err(|err| err.with_values(STRING_MAX_SIZE, size))?;
+    let bytes = reader.read_bytes(size)?;
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| ErrorKind::EncodingInvalid.into())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::schema::Schema;
+    use crate::coin::coin_random;
+    use crate::spec::ChainSpec;
+
+    fn roundtrip(message: &Message) -> Message {
+        Message::from_bytes(&message.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        // Version
+        let spec = ChainSpec::mainnet();
+        let version = Version {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: spec.chain_id,
+            genesis_hash: spec.genesis_hash.clone(),
+            best: spec.get_genesis_info(),
+            user_agent: "uqoin/0.1".to_string(),
+            features: Features::HEADERS.union(Features::TX_RELAY),
+        };
+        match roundtrip(&Message::Version(version.clone())) {
+            Message::Version(version2) => assert_eq!(version2, version),
+            _ => panic!("Wrong message type."),
+        }
+
+        // Version 1 without features
+        let version1 = Version {
+            protocol_version: PROTOCOL_VERSION_1,
+            features: Features::NONE,
+            ..version.clone()
+        };
+        let message = Message::Version(version1.clone());
+        assert_eq!(message.to_bytes().len() + 8,
+                   Message::Version(version.clone()).to_bytes().len());
+        match roundtrip(&message) {
+            Message::Version(version2) => assert_eq!(version2, version1),
+            _ => panic!("Wrong message type."),
+        }
+
+        // Inventory
+        let items = vec![InvItem::new(InvKind::Block, rng.random()),
+                         InvItem::new(InvKind::Transaction, rng.random())];
+        match roundtrip(&Message::Inv(items.clone())) {
+            Message::Inv(items2) => assert_eq!(items2, items),
+            _ => panic!("Wrong message type."),
+        }
+
+        // Transactions
+        let coin = coin_random(&mut rng, &public);
+        let addr: U256 = rng.random();
+        let tr = Transaction::build(&mut rng, coin, addr, &key, 0, &schema);
+        match roundtrip(&Message::TxAnnounce(vec![tr.clone()])) {
+            Message::TxAnnounce(trs) => {
+                assert_eq!(trs[0].get_hash(), tr.get_hash())
+            },
+            _ => panic!("Wrong message type."),
+        }
+
+        // Block
+        let block_data = BlockData::genesis();
+        match roundtrip(&Message::BlockAnnounce(block_data.clone())) {
+            Message::BlockAnnounce(block_data2) => {
+                assert_eq!(block_data2.get_block_info(),
+                           block_data.get_block_info())
+            },
+            _ => panic!("Wrong message type."),
+        }
+
+        // Rejection
+        let err = Error::from(ErrorKind::PoolFull);
+        let reject = Reject::from_error(&err, tr.get_hash());
+        assert_eq!(reject.code, 301);
+        match roundtrip(&Message::Reject(reject.clone())) {
+            Message::Reject(reject2) => assert_eq!(reject2, reject),
+            _ => panic!("Wrong message type."),
+        }
+
+        // JSON
+        let message = Message::GetBlocks(GetBlocks { bix: 1, count: 10 });
+        let json = serde_json::to_string(&message).unwrap();
+        assert!(json.starts_with("{\"type\":\"GetBlocks\""));
+        assert_eq!(roundtrip(&message).to_bytes(), message.to_bytes());
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let invalid = |message: Message| {
+            Message::from_bytes(&message.to_bytes()).unwrap_err().kind()
+        };
+
+        assert_eq!(invalid(Message::Inv(vec![])),
+                   ErrorKind::NetworkInvalidMessage);
+        assert_eq!(invalid(Message::GetBlocks(GetBlocks { bix: 1,
+                                                          count: 0 })),
+                   ErrorKind::NetworkInvalidMessage);
+        assert_eq!(invalid(Message::GetBlocks(GetBlocks {
+                       bix: 1, count: GET_BLOCKS_MAX_COUNT + 1
+                   })), ErrorKind::NetworkInvalidMessage);
+        assert_eq!(invalid(Message::TxAnnounce(vec![])),
+                   ErrorKind::NetworkInvalidMessage);
+        assert_eq!(invalid(Message::Reject(Reject {
+            code: 0, hash: U256::from(0), reason: "x".repeat(300),
+        })), ErrorKind::NetworkInvalidMessage);
+
+        // Unknown type and trailing bytes
+        assert_eq!(Message::from_bytes(&[7]).unwrap_err().kind(),
+                   ErrorKind::NetworkInvalidMessage);
+        let mut bytes = Message::GetBlocks(GetBlocks { bix: 1, count: 1 })
+            .to_bytes();
+        bytes.push(0);
+        assert_eq!(Message::from_bytes(&bytes).unwrap_err().kind(),
+                   ErrorKind::EncodingInvalid);
+    }
+}
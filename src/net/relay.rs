@@ -0,0 +1,290 @@
+//! Validation of the gossiped transaction groups before the pool.
+//!
+//! The groups received from the peers (`Message::TxAnnounce`) are checked by
+//! `Relay` from the cheapest checks to the most expensive ones, so the junk
+//! is dropped before the signatures are recovered:
+//! 1. Structure: the size, the known hashes, the versions, the unique coins
+//!    and the validity window.
+//! 2. Signatures: the senders are recovered from the signatures.
+//! 3. Coin tails: the senders own the coins or the new coins are mined by
+//!    them.
+//! 4. Group and pool policy: the group structure, the fee policy and the
+//!    acceptance policies of the pool (see `Pool::check_policies`).
+//!
+//! The outcome tells the node what to do with the group: submit the accepted
+//! groups to the pool (`Pool::submit_raw`) and relay them further, keep the
+//! deferred ones as orphans (`Pool::add_orphan`), drop the rejected ones
+//! (the peer may be penalized for the invalid ones, see `is_invalid`).
+
+use std::collections::HashSet;
+
+use crate::validate;
+use crate::utils::*;
+use crate::error::{Error, ErrorKind};
+use crate::schema::Schema;
+use crate::transaction::{Transaction, Group};
+use crate::state::State;
+use crate::pool::Pool;
+use super::messages::TX_ANNOUNCE_MAX_SIZE;
+
+
+/// Default number of the remembered group hashes.
+pub const RELAY_SEEN_CAPACITY: usize = 65536;
+
+
+/// Outcome of the relay validation, the errors are the reasons.
+#[derive(Debug)]
+pub enum RelayOutcome {
+    /// The group passed all the checks: submit it to the pool and relay it.
+    Accept { hash: U256, sender: U256 },
+
+    /// The group is invalid or already known, drop it.
+    Reject(Error),
+
+    /// The group may become valid later (its validity window has not
+    /// started yet or it spends a coin of a pending group), keep it as an
+    /// orphan without relaying.
+    Defer(Error),
+}
+
+
+impl RelayOutcome {
+    /// Check if the group is accepted.
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, Self::Accept { .. })
+    }
+
+    /// Check if the group is rejected as invalid (not as a known one), so
+    /// the peer relaying it misbehaves.
+    pub fn is_invalid(&self) -> bool {
+        match self {
+            Self::Reject(err) => err.kind() != ErrorKind::PoolDuplicate,
+            _ => false,
+        }
+    }
+
+    /// Get the reason of the rejection or deferral.
+    pub fn reason(&self) -> Option<&Error> {
+        match self {
+            Self::Accept { .. } => None,
+            Self::Reject(err) | Self::Defer(err) => Some(err),
+        }
+    }
+}
+
+
+/// Cheap-first validation of the gossiped groups. It remembers the hashes of
+/// the accepted groups, so the repeated announces are rejected at once. The
+/// rejected groups are not remembered: the hash of a group is the hash of its
+/// first transaction, so a broken copy must not shadow the valid group.
+pub struct Relay {
+    schema: Schema,
+    seen: LruCache<U256, ()>,
+}
+
+
+impl Relay {
+    /// Create a relay remembering `RELAY_SEEN_CAPACITY` hashes.
+    pub fn new() -> Self {
+        Self::with_seen_capacity(RELAY_SEEN_CAPACITY)
+    }
+
+    /// Create a relay remembering `capacity` hashes.
+    pub fn with_seen_capacity(capacity: usize) -> Self {
+        Self { schema: Schema::new(), seen: LruCache::new(capacity) }
+    }
+
+    /// Check if the group hash was seen.
+    pub fn is_seen(&self, hash: &U256) -> bool {
+        self.seen.contains(hash)
+    }
+
+    /// Forget the group hash (e.g. if the pool dropped the group, so it can
+    /// be accepted again).
+    pub fn forget(&mut self, hash: &U256) {
+        self.seen.remove(hash);
+    }
+
+    /// Validate the gossiped group for the state and the pool. Only the
+    /// accepted groups are remembered, so the rejected and deferred ones can
+    /// be checked again later.
+    pub fn check(&mut self, transactions: &[Transaction], state: &State,
+                 pool: &Pool) -> RelayOutcome {
+        // Check the size before hashing
+        if let Err(err) = validate!(
+            (1..=TX_ANNOUNCE_MAX_SIZE).contains(&transactions.len()),
+            NetworkInvalidMessage
+        ) {
+            return RelayOutcome::Reject(
+                err.with_values(TX_ANNOUNCE_MAX_SIZE, transactions.len())
+            );
+        }
+
+        // Check the known groups
+        let hash = transactions[0].get_hash();
+        if self.seen.contains(&hash) || pool.contains_hash(&hash) {
+            return RelayOutcome::Reject(ErrorKind::PoolDuplicate.into());
+        }
+
+        let outcome = self.check_new(transactions, state, pool);
+        if outcome.is_accepted() {
+            self.seen.insert(hash, ());
+        }
+        outcome
+    }
+
+    fn check_new(&self, transactions: &[Transaction], state: &State,
+                 pool: &Pool) -> RelayOutcome {
+        // Check versions, unique coins and validity
+        let bix = state.get_last_block_info().bix + 1;
+        let mut coins = HashSet::new();
+        for (ix, tr) in transactions.iter().enumerate() {
+            let check = tr.validate_version()
+                .and(validate!(coins.insert(&tr.coin), CoinNotUnique))
+                .and(validate!(!tr.is_expired_at(bix), TransactionExpired))
+                .map_err(|err| err.with_index(ix).with_coin(&tr.coin));
+            if let Err(err) = check {
+                return RelayOutcome::Reject(err);
+            }
+        }
+        for (ix, tr) in transactions.iter().enumerate() {
+            if !tr.is_valid_at(bix) {
+                return RelayOutcome::Defer(
+                    Error::from(ErrorKind::TransactionExpired)
+                        .with_index(ix).with_coin(&tr.coin)
+                );
+            }
+        }
+
+        // Recover the senders
+        let senders = Transaction::calc_senders(transactions, state,
+                                                &self.schema);
+
+        // Check ownership and coin tails, the coins of the pending groups
+        // may be signed for the next counter
+        for (ix, (tr, sender)) in transactions.iter().zip(senders.iter())
+                                              .enumerate() {
+            if let Err(err) = tr.validate_coin(state, sender) {
+                let err = err.with_index(ix).with_coin(&tr.coin);
+                return if pool.get_group_of_coin(&tr.coin).is_some() {
+                    RelayOutcome::Defer(err)
+                } else {
+                    RelayOutcome::Reject(err)
+                };
+            }
+        }
+
+        // Check the group and the pool policy
+        let result = Group::new(transactions.to_vec(), state, &senders)
+            .and_then(|group| pool.check_policies(&group, &senders[0],
+                                                  state));
+        match result {
+            Ok(()) => RelayOutcome::Accept {
+                hash: transactions[0].get_hash(),
+                sender: senders[0].clone(),
+            },
+            Err(err) => RelayOutcome::Reject(err),
+        }
+    }
+}
+
+
+impl Default for Relay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use rand::Rng;
+    use crate::coin::coin_random;
+    use crate::transaction::Validity;
+    use crate::pool::GroupFilter;
+
+    #[test]
+    fn test_relay() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+        let mut pool = Pool::new();
+        let mut relay = Relay::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let addr: U256 = rng.random();
+
+        // Accept and reject the repeated group
+        let coin = coin_random(&mut rng, &public);
+        let trs = vec![
+            Transaction::build(&mut rng, coin, addr.clone(), &key, 0, &schema)
+        ];
+        match relay.check(&trs, &state, &pool) {
+            RelayOutcome::Accept { hash, sender } => {
+                assert_eq!(hash, trs[0].get_hash());
+                assert_eq!(sender, public);
+            },
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        let outcome = relay.check(&trs, &state, &pool);
+        assert_eq!(outcome.reason().unwrap().kind(), ErrorKind::PoolDuplicate);
+        assert!(!outcome.is_invalid());
+
+        // Known to the pool
+        assert!(pool.submit_raw(trs.clone(), &state, &schema)[0].is_ok());
+        relay.forget(&trs[0].get_hash());
+        let outcome = relay.check(&trs, &state, &pool);
+        assert_eq!(outcome.reason().unwrap().kind(), ErrorKind::PoolDuplicate);
+
+        // Too many transactions
+        let coin = coin_random(&mut rng, &public);
+        let tr = Transaction::build(&mut rng, coin, addr.clone(), &key, 0,
+                                    &schema);
+        let outcome = relay.check(&vec![tr; 5], &state, &pool);
+        assert_eq!(outcome.reason().unwrap().kind(),
+                   ErrorKind::NetworkInvalidMessage);
+
+        // Foreign coin tail
+        let coin: U256 = rng.random();
+        let trs = vec![
+            Transaction::build(&mut rng, coin, addr.clone(), &key, 0, &schema)
+        ];
+        let outcome = relay.check(&trs, &state, &pool);
+        assert!(outcome.is_invalid());
+        assert_eq!(outcome.reason().unwrap().kind(), ErrorKind::CoinInvalid);
+
+        // A broken copy does not shadow the valid group of the same hash
+        let coin = coin_random(&mut rng, &public);
+        let tr = Transaction::build(&mut rng, coin, addr.clone(), &key, 0,
+                                    &schema);
+        let outcome = relay.check(&[tr.clone(), trs[0].clone()], &state, 
+                                  &pool);
+        assert!(outcome.is_invalid());
+        assert!(!relay.is_seen(&tr.get_hash()));
+        assert!(relay.check(&[tr], &state, &pool).is_accepted());
+
+        // Validity window
+        let coin = coin_random(&mut rng, &public);
+        let mut tr = Transaction::new(coin, addr.clone(), U256::from(0),
+                                      U256::from(0))
+            .with_validity(Validity::new(10, 20));
+        tr.sign(&mut rng, &key, 0, &schema);
+        let outcome = relay.check(&[tr.clone()], &state, &pool);
+        assert!(matches!(outcome, RelayOutcome::Defer(_)));
+        assert!(!relay.is_seen(&tr.get_hash()));
+
+        // Pool policy
+        let coin = coin_random(&mut rng, &public);
+        let trs = vec![
+            Transaction::build(&mut rng, coin, addr.clone(), &key, 0, &schema)
+        ];
+        let mut filter = GroupFilter::default();
+        filter.denied_senders.insert(public.clone());
+        pool.add_policy(Arc::new(filter));
+        let outcome = relay.check(&trs, &state, &pool);
+        assert_eq!(outcome.reason().unwrap().kind(),
+                   ErrorKind::PoolPolicyRejected);
+        assert!(!relay.is_seen(&trs[0].get_hash()));
+    }
+}
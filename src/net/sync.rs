@@ -0,0 +1,511 @@
+//! Header-first block synchronization.
+//!
+//! `SyncManager` drives the sync of the node from its peers independently of
+//! the transport: the node implements `SyncSource` requesting the headers and
+//! the blocks from a peer (e.g. with `GetBlocks`) and informs the manager of
+//! the peers and their best blocks (e.g. from `Version` and the announces).
+//! Each step the manager makes one request:
+//! 1. If there are no downloaded headers, it chooses the peer with the
+//!    highest best block above the local one, downloads the next headers from
+//!    it and validates them (links, complexity, hash and checkpoints) without
+//!    the transactions, so a peer serving a fake chain is detected before the
+//!    bodies are downloaded. The headers of legacy blocks are only linked, they
+//!    are validated with their bodies.
+//! 2. Otherwise it downloads the blocks of the headers from the same peer,
+//!    checks them against the headers, validates them with `ChainValidator`
+//!    rolling up the state and pushes them to the blockchain.
+//!
+//! A peer that fails a request or serves invalid data gets a failure, its
+//! headers are dropped and the sync continues from another peer. After
+//! `max_failures` failures the peer is banned (it is not chosen until it is
+//! added again). Forks are not resolved here: the peers must extend the
+//! local chain.
+
+use std::future::Future;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::validate;
+use crate::utils::*;
+use crate::error::Error;
+use crate::schema::Schema;
+use crate::transaction::Transaction;
+use crate::block::{Block, BlockInfo, BlockHeader, BlockData};
+use crate::chain::ChainValidator;
+use crate::state::State;
+use crate::blockchain::Blockchain;
+use super::messages::GET_BLOCKS_MAX_COUNT;
+
+
+/// Default number of failures before the peer is banned.
+pub const SYNC_MAX_FAILURES: usize = 3;
+
+
+/// Identifier of the peer assigned by the node.
+pub type PeerId = u64;
+
+
+/// Transport of the sync: requests to the peers. The peer may return fewer
+/// items than requested, but not more.
+pub trait SyncSource {
+    /// Request `count` headers starting from the block `bix`.
+    fn get_headers(&mut self, peer: PeerId, bix: u64, count: u64) ->
+                   impl Future<Output = UqoinResult<Vec<BlockHeader>>>;
+
+    /// Request `count` blocks with their transactions starting from the
+    /// block `bix`.
+    fn get_blocks(&mut self, peer: PeerId, bix: u64, count: u64) ->
+                  impl Future<Output = UqoinResult<Vec<BlockData>>>;
+}
+
+
+/// Status of the peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerStatus {
+    /// Best block of the peer.
+    pub best: BlockInfo,
+
+    /// Number of the failed requests.
+    pub failures: usize,
+
+    /// The peer is not used for the sync.
+    pub banned: bool,
+}
+
+
+/// Progress of the sync.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncProgress {
+    /// Last applied block.
+    pub bix: u64,
+
+    /// Last validated header.
+    pub header_bix: u64,
+
+    /// Highest best block of the peers that are not banned.
+    pub target_bix: u64,
+}
+
+
+/// Result of a sync step.
+#[derive(Debug)]
+pub enum SyncStep {
+    /// Nothing to sync.
+    Idle,
+
+    /// The number of validated headers.
+    Headers(usize),
+
+    /// The number of applied blocks.
+    Blocks(usize),
+
+    /// The peer failed the request or served invalid data.
+    PeerFailed(PeerId, Error),
+}
+
+
+/// Transport-agnostic block synchronization.
+pub struct SyncManager {
+    validator: ChainValidator,
+    schema: Schema,
+    peers: BTreeMap<PeerId, PeerStatus>,
+    headers: Vec<BlockHeader>,
+    headers_peer: Option<PeerId>,
+    batch_size: u64,
+    max_failures: usize,
+    progress: SyncProgress,
+}
+
+
+impl SyncManager {
+    /// Create a manager validating the blocks with the validator.
+    pub fn new(validator: ChainValidator) -> Self {
+        Self {
+            validator,
+            schema: Schema::new(),
+            peers: BTreeMap::new(),
+            headers: Vec::new(),
+            headers_peer: None,
+            batch_size: GET_BLOCKS_MAX_COUNT,
+            max_failures: SYNC_MAX_FAILURES,
+            progress: SyncProgress::default(),
+        }
+    }
+
+    /// Set the number of headers and blocks per request (up to
+    /// `GET_BLOCKS_MAX_COUNT`).
+    pub fn with_batch_size(mut self, batch_size: u64) -> Self {
+        assert!((1..=GET_BLOCKS_MAX_COUNT).contains(&batch_size));
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Set the number of failures before the peer is banned.
+    pub fn with_max_failures(mut self, max_failures: usize) -> Self {
+        assert!(max_failures > 0);
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Get the validator.
+    pub fn get_validator(&self) -> &ChainValidator {
+        &self.validator
+    }
+
+    /// Add the peer with its best block. The existing peer is reset (it is
+    /// unbanned too).
+    pub fn add_peer(&mut self, peer: PeerId, best: BlockInfo) {
+        self.peers.insert(peer, PeerStatus { best, failures: 0,
+                                             banned: false });
+        self.update_target();
+    }
+
+    /// Update the best block of the peer (e.g. on its announce).
+    pub fn update_peer(&mut self, peer: PeerId, best: BlockInfo) {
+        if let Some(status) = self.peers.get_mut(&peer) {
+            status.best = best;
+            self.update_target();
+        }
+    }
+
+    /// Remove the peer (e.g. on disconnection).
+    pub fn remove_peer(&mut self, peer: PeerId) {
+        self.peers.remove(&peer);
+        if self.headers_peer == Some(peer) {
+            self.drop_headers();
+        }
+        self.update_target();
+    }
+
+    /// Get status of the peer.
+    pub fn get_peer(&self, peer: PeerId) -> Option<&PeerStatus> {
+        self.peers.get(&peer)
+    }
+
+    /// Get the progress.
+    pub fn get_progress(&self) -> &SyncProgress {
+        &self.progress
+    }
+
+    /// Check if there is nothing to sync from the peers.
+    pub fn is_synced(&self) -> bool {
+        self.progress.bix >= self.progress.target_bix
+    }
+
+    /// Run the steps until there is nothing to sync. Returns the number of
+    /// applied blocks. The errors are the local ones (e.g. of the storage),
+    /// the failures of the peers are handled.
+    pub async fn run<S: SyncSource>(&mut self, source: &mut S,
+                                    blockchain: &Blockchain,
+                                    state: &mut State) -> UqoinResult<u64> {
+        let mut applied = 0;
+        loop {
+            match self.step(source, blockchain, state).await? {
+                SyncStep::Idle => break,
+                SyncStep::Blocks(count) => applied += count as u64,
+                _ => {},
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Make one request to a peer: download the headers or the blocks. The
+    /// state must correspond the last block of the blockchain. The errors
+    /// are the local ones, in case of a storage error the state may be ahead
+    /// of the blockchain, so they must be reloaded (see `Node`).
+    pub async fn step<S: SyncSource>(&mut self, source: &mut S,
+                                     blockchain: &Blockchain,
+                                     state: &mut State) ->
+                                     UqoinResult<SyncStep> {
+        // Check the state corresponds the blockchain
+        let bix = state.get_last_block_info().bix;
+        let count = blockchain.get_block_count().await?;
+        validate!(count == bix, StateInconsistent)
+            .map_err(|err| err.with_values(count, bix))?;
+        self.progress.bix = bix;
+
+        // Load the history of the last blocks for the retarget
+        if self.validator.get_history().last().map(|info| info.bix) !=
+                Some(bix) {
+            let history = self.load_history(blockchain, state).await?;
+            self.validator.set_history(history);
+        }
+
+        if let Some(peer) = self.headers_peer {
+            // Download the blocks of the headers
+            match self.download_blocks(source, peer, blockchain, state)
+                      .await {
+                Ok(Ok(applied)) => Ok(SyncStep::Blocks(applied)),
+                Ok(Err(err)) => Ok(self.fail_peer(peer, err)),
+                Err(err) => Err(err),
+            }
+        } else if let Some(peer) = self.choose_peer(bix) {
+            // Download the next headers
+            match self.download_headers(source, peer, bix).await {
+                Ok(count) => Ok(SyncStep::Headers(count)),
+                Err(err) => Ok(self.fail_peer(peer, err)),
+            }
+        } else {
+            Ok(SyncStep::Idle)
+        }
+    }
+
+    /// Download and validate the headers following the block `bix`.
+    async fn download_headers<S: SyncSource>(&mut self, source: &mut S,
+                                             peer: PeerId,
+                                             bix: u64) -> UqoinResult<usize> {
+        let best = self.peers[&peer].best.bix;
+        let count = self.batch_size.min(best - bix);
+        let headers = source.get_headers(peer, bix + 1, count).await?;
+        validate!((1..=count as usize).contains(&headers.len()),
+                  NetworkInvalidMessage)
+            .map_err(|err| err.with_values(count, headers.len()))?;
+
+        // Validate the headers
+        let size = self.validator.get_retarget().get_history_size();
+        let mut history = self.validator.get_history().to_vec();
+        for (ix, header) in headers.iter().enumerate() {
+            let hbix = bix + 1 + ix as u64;
+            let info_prev = history.last().unwrap();
+            if header.is_legacy() {
+                validate!(header.hash_prev == info_prev.hash,
+                          BlockPreviousHashMismatch)
+                    .and(validate!(header.offset == info_prev.offset,
+                                   BlockOffsetMismatch))
+            } else {
+                self.validator.validate_timestamp(header.timestamp, &history)
+                    .and_then(|_| self.validator.get_complexity(&history))
+                    .and_then(|complexity| header.validate(info_prev, 
+                                                           complexity))
+            }.and_then(|_| self.validator.validate_checkpoint(hbix,
+                                                              &header.hash))
+             .map_err(|err| err.with_bix(hbix))?;
+
+            history.push(header.get_block_info(hbix));
+            if history.len() > size {
+                history.remove(0);
+            }
+        }
+
+        self.progress.header_bix = bix + headers.len() as u64;
+        self.headers = headers;
+        self.headers_peer = Some(peer);
+        Ok(self.headers.len())
+    }
+
+    /// Download the blocks of the headers, validate and apply them. The
+    /// outer error is the local one, the inner one is of the peer.
+    async fn download_blocks<S: SyncSource>(&mut self, source: &mut S,
+                                            peer: PeerId,
+                                            blockchain: &Blockchain,
+                                            state: &mut State) ->
+                                            UqoinResult<UqoinResult<usize>> {
+        let bix = state.get_last_block_info().bix;
+        let count = self.batch_size.min(self.headers.len() as u64);
+        let blocks = match source.get_blocks(peer, bix + 1, count).await {
+            Ok(blocks) => blocks,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        // Check the blocks correspond the headers
+        let check = validate!((1..=count as usize).contains(&blocks.len()),
+                              NetworkInvalidMessage)
+            .map_err(|err| err.with_values(count, blocks.len()))
+            .and_then(|_| blocks.iter().zip(self.headers.iter()).enumerate()
+                .try_for_each(|(ix, (block_data, header))| {
+                    let hbix = bix + 1 + ix as u64;
+                    validate!((block_data.bix == hbix) &&
+                              (block_data.block.hash == header.hash),
+                              BlockBroken)
+                        .and_then(|_| header.validate_body(
+                            &block_data.transactions
+                        ))
+                        .map_err(|err| err.with_bix(hbix))
+                }));
+        if let Err(err) = check {
+            return Ok(Err(err));
+        }
+
+        // Validate the blocks rolling up the state
+        let result = self.validator.validate_chain(&blocks, state,
+                                                   &self.schema);
+
+        // Push the valid blocks
+        let applied = (state.get_last_block_info().bix - bix) as usize;
+        if applied > 0 {
+            let pairs: Vec<(Block, Vec<Transaction>)> = blocks[..applied]
+                .iter()
+                .map(|bd| (bd.block.clone(), bd.transactions.clone()))
+                .collect();
+            blockchain.push_new_blocks(&pairs).await?;
+            self.headers.drain(..applied);
+            self.progress.bix = bix + applied as u64;
+        }
+        if self.headers.is_empty() {
+            self.headers_peer = None;
+        }
+
+        Ok(result.map(|_| applied))
+    }
+
+    /// Load the infos of the last blocks up to the state for the retarget.
+    async fn load_history(&self, blockchain: &Blockchain,
+                          state: &State) -> UqoinResult<Vec<BlockInfo>> {
+        let bix = state.get_last_block_info().bix;
+        let size = self.validator.get_retarget().get_history_size() as u64;
+        let mut history = Vec::new();
+        for hbix in bix.saturating_sub(size) + 1 .. bix {
+            history.push(blockchain.get_block_info(hbix).await?);
+        }
+        history.push(state.get_last_block_info().clone());
+        Ok(history)
+    }
+
+    /// Choose the peer with the highest best block above `bix` (the first
+    /// one of them by id).
+    fn choose_peer(&self, bix: u64) -> Option<PeerId> {
+        self.peers.iter()
+            .filter(|(_, status)| !status.banned && (status.best.bix > bix))
+            .max_by_key(|(peer, status)| (status.best.bix, Reverse(**peer)))
+            .map(|(peer, _)| *peer)
+    }
+
+    /// Register the failure of the peer dropping its headers.
+    fn fail_peer(&mut self, peer: PeerId, err: Error) -> SyncStep {
+        if self.headers_peer == Some(peer) {
+            self.drop_headers();
+        }
+        if let Some(status) = self.peers.get_mut(&peer) {
+            status.failures += 1;
+            status.banned = status.failures >= self.max_failures;
+        }
+        self.update_target();
+        SyncStep::PeerFailed(peer, err)
+    }
+
+    fn drop_headers(&mut self) {
+        self.headers.clear();
+        self.headers_peer = None;
+        self.progress.header_bix = self.progress.bix;
+    }
+
+    fn update_target(&mut self) {
+        self.progress.target_bix = self.peers.values()
+            .filter(|status| !status.banned)
+            .map(|status| status.best.bix)
+            .max().unwrap_or(0);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::coin::coin_random;
+    use crate::difficulty::Retarget;
+    use crate::error::ErrorKind;
+    use crate::testing::TempDir;
+
+    struct MockSource {
+        blocks: Vec<BlockData>,
+        broken: PeerId,
+    }
+
+    impl SyncSource for MockSource {
+        async fn get_headers(&mut self, _peer: PeerId, bix: u64,
+                             count: u64) -> UqoinResult<Vec<BlockHeader>> {
+            Ok(self.blocks.iter()
+                .skip(bix as usize - 1).take(count as usize)
+                .map(|bd| bd.block.get_header(&bd.transactions))
+                .collect())
+        }
+
+        async fn get_blocks(&mut self, peer: PeerId, bix: u64,
+                            count: u64) -> UqoinResult<Vec<BlockData>> {
+            // The broken peer serves a wrong body of the second block
+            let mut blocks: Vec<BlockData> = self.blocks.iter()
+                .skip(bix as usize - 1).take(count as usize)
+                .cloned().collect();
+            if (peer == self.broken) && (bix <= 2) && (blocks.len() > 1) {
+                blocks[2 - bix as usize].transactions[0].addr = U256::from(1);
+            }
+            Ok(blocks)
+        }
+    }
+
+    fn build_chain(size: usize, complexity: usize) -> Vec<BlockData> {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+
+        let mut state = State::new();
+        let mut blocks = Vec::new();
+        for ix in 0..size {
+            let coin = coin_random(&mut rng, &public);
+            let addr: U256 = rng.random();
+            let transactions = vec![
+                Transaction::build(&mut rng, coin, addr, &key, 0, &schema)
+            ];
+            let timestamp = 1700000000 + ix as u64;
+            let info = state.get_last_block_info().clone();
+            let senders = Transaction::calc_senders(&transactions, &state,
+                                                    &schema);
+            let nonce = Block::mine(&mut rng, &info, &validator,
+                                    timestamp, &transactions, complexity,
+                                    None).unwrap();
+            let block = Block::build(&info, validator.clone(), timestamp,
+                                     &transactions, U256::from_bytes(&nonce),
+                                     complexity, &state, &senders).unwrap();
+            state.roll_up(info.bix + 1, &block, &transactions, &schema);
+            blocks.push(BlockData { bix: info.bix + 1, block, transactions });
+        }
+        blocks
+    }
+
+    #[tokio::test]
+    async fn test_sync() {
+        let complexity = 4;
+        let blocks = build_chain(3, complexity);
+        let best = blocks.last().unwrap().get_block_info();
+
+        let mut source = MockSource { blocks, broken: 1 };
+
+        let tmp = TempDir::new("uqoin-sync");
+        let path = tmp.path().to_string();
+        let blockchain = Blockchain::new(&path).await.unwrap();
+        let mut state = State::new();
+
+        let validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity);
+        let mut manager = SyncManager::new(validator).with_batch_size(2)
+                                                     .with_max_failures(1);
+        manager.add_peer(1, best.clone());
+        manager.add_peer(2, best.clone());
+        assert!(!manager.is_synced());
+
+        // Headers and the valid first block from the first peer
+        let step = manager.step(&mut source, &blockchain, &mut state).await;
+        assert!(matches!(step.unwrap(), SyncStep::Headers(2)));
+        match manager.step(&mut source, &blockchain, &mut state).await
+                     .unwrap() {
+            SyncStep::PeerFailed(peer, err) => {
+                assert_eq!(peer, 1);
+                assert_eq!(err.kind(), ErrorKind::BlockBroken);
+                assert_eq!(err.context().unwrap().bix, Some(2));
+            },
+            step => panic!("Unexpected step {:?}", step),
+        }
+        assert!(manager.get_peer(1).unwrap().banned);
+        assert_eq!(manager.get_progress().bix, 0);
+
+        // The rest from the second peer
+        assert_eq!(manager.run(&mut source, &blockchain, &mut state).await
+                          .unwrap(), 3);
+        assert!(manager.is_synced());
+        assert_eq!(state.get_last_block_info(), &best);
+        assert_eq!(blockchain.get_block_count().await.unwrap(), 3);
+        assert_eq!(blockchain.get_block_info(3).await.unwrap(), best);
+    }
+}
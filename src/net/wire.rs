@@ -0,0 +1,358 @@
+//! Length-prefixed framing of the encoded structures for stream transports.
+//!
+//! TCP and QUIC streams carry bytes, not messages, so each structure in its
+//! canonical encoding (see `codec`) is wrapped into a frame:
+//!
+//! - magic `WIRE_MAGIC` (4 bytes),
+//! - version of the framing (1 byte),
+//! - kind of the payload (1 byte, see `FrameKind`),
+//! - length of the payload (4 bytes, little-endian),
+//! - the payload.
+//!
+//! The header is checked before the payload is buffered: a wrong magic, an
+//! unknown kind or a length above the limit of the kind is rejected at once
+//! (`NetworkInvalidMessage`), and a frame of unsupported version is
+//! `NetworkIncompatible`. So a broken or hostile peer cannot make the node
+//! allocate more than the limit, and the decoding never panics. The stream
+//! cannot be resynchronized after an error, the connection should be closed.
+//!
+//! `FrameDecoder` splits the received chunks into frames, `Frame::get_item`
+//! decodes the payload strictly (the whole payload must be consumed).
+
+use bytes::{Bytes, BytesMut, BufMut, Buf};
+
+use crate::validate;
+use crate::utils::*;
+use crate::error::{Error, ErrorKind};
+use crate::codec::Codec;
+use crate::transaction::Transaction;
+use crate::block::{Block, BlockData};
+use crate::net::messages::Message;
+
+
+/// Magic bytes of the frame.
+pub const WIRE_MAGIC: [u8; 4] = *b"UQON";
+
+/// Version 1 of the framing.
+pub const WIRE_VERSION_1: u8 = 1;
+
+/// Current version of the framing.
+pub const WIRE_VERSION: u8 = WIRE_VERSION_1;
+
+/// Size of the frame header in bytes.
+pub const FRAME_HEADER_SIZE: usize = 10;
+
+/// Maximum size of the payload of blocks with transactions and messages
+/// (1 MiB, a full block takes about 200 KiB).
+pub const FRAME_MAX_SIZE: usize = 1 << 20;
+
+
+/// Kind of the frame payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum FrameKind {
+    Transaction = 1,
+    Block = 2,
+    BlockData = 3,
+    Message = 4,
+}
+
+
+impl FrameKind {
+    /// Get the kind by its byte.
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Transaction),
+            2 => Some(Self::Block),
+            3 => Some(Self::BlockData),
+            4 => Some(Self::Message),
+            _ => None,
+        }
+    }
+
+    /// Maximum size of the payload: the encoded transaction and block are
+    /// bounded by their versions (with room for the extensions), the others
+    /// by `FRAME_MAX_SIZE`.
+    pub fn get_max_size(&self) -> usize {
+        match self {
+            Self::Transaction => 256,
+            Self::Block => 1024,
+            Self::BlockData | Self::Message => FRAME_MAX_SIZE,
+        }
+    }
+}
+
+
+/// Structure that can be sent in a frame.
+pub trait WireItem: Codec {
+    /// Kind of the frame.
+    const KIND: FrameKind;
+}
+
+
+impl WireItem for Transaction {
+    const KIND: FrameKind = FrameKind::Transaction;
+}
+
+
+impl WireItem for Block {
+    const KIND: FrameKind = FrameKind::Block;
+}
+
+
+impl WireItem for BlockData {
+    const KIND: FrameKind = FrameKind::BlockData;
+}
+
+
+impl WireItem for Message {
+    const KIND: FrameKind = FrameKind::Message;
+}
+
+
+/// Frame with the encoded payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub version: u8,
+    pub kind: FrameKind,
+    pub payload: Bytes,
+}
+
+
+impl Frame {
+    /// Create the frame of the current version with the encoded item
+    /// (`NetworkInvalidMessage` if it exceeds the limit of the kind).
+    pub fn new<T: WireItem>(item: &T) -> UqoinResult<Self> {
+        let payload = Bytes::from(item.to_bytes());
+        let max_size = T::KIND.get_max_size();
+        validate!(payload.len() <= max_size, NetworkInvalidMessage)
+            .map_err(|err| err.with_values(max_size, payload.len()))?;
+        Ok(Self { version: WIRE_VERSION, kind: T::KIND, payload })
+    }
+
+    /// Size of the encoded frame in bytes.
+    pub fn get_size(&self) -> usize {
+        FRAME_HEADER_SIZE + self.payload.len()
+    }
+
+    /// Encode the frame with its header.
+    pub fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::with_capacity(self.get_size());
+        bytes.put_slice(&WIRE_MAGIC);
+        bytes.put_u8(self.version);
+        bytes.put_u8(self.kind as u8);
+        bytes.put_u32_le(self.payload.len() as u32);
+        bytes.put_slice(&self.payload);
+        bytes.freeze()
+    }
+
+    /// Decode exactly one frame from the bytes.
+    pub fn decode(bytes: &[u8]) -> UqoinResult<Self> {
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(bytes);
+        let frame = decoder.next_frame()?;
+        validate!(frame.is_some() && decoder.get_buffered_size() == 0,
+                  EncodingInvalid)?;
+        Ok(frame.unwrap())
+    }
+
+    /// Decode the payload as the item (`NetworkInvalidMessage` if the kind
+    /// differs).
+    pub fn get_item<T: WireItem>(&self) -> UqoinResult<T> {
+        validate!(self.kind == T::KIND, NetworkInvalidMessage)
+            .map_err(|err| err.with_values(T::KIND as u8, self.kind as u8))?;
+        T::from_bytes(&self.payload)
+    }
+}
+
+
+/// Encode the item into a frame.
+pub fn encode<T: WireItem>(item: &T) -> UqoinResult<Bytes> {
+    Ok(Frame::new(item)?.encode())
+}
+
+
+/// Decode the item from exactly one frame.
+pub fn decode<T: WireItem>(bytes: &[u8]) -> UqoinResult<T> {
+    Frame::decode(bytes)?.get_item()
+}
+
+
+/// Splitter of the received bytes into frames.
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    buffer: BytesMut,
+    max_size: usize,
+}
+
+
+impl FrameDecoder {
+    /// Create the decoder with the limits of the kinds.
+    pub fn new() -> Self {
+        Self { buffer: BytesMut::new(), max_size: FRAME_MAX_SIZE }
+    }
+
+    /// Limit the size of the payload of any kind further (e.g. for the
+    /// peers that are not trusted yet).
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Number of the buffered bytes that are not decoded yet.
+    pub fn get_buffered_size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Append the received bytes.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Take the next frame if it is received completely. The header is
+    /// checked as soon as it is received.
+    pub fn next_frame(&mut self) -> UqoinResult<Option<Frame>> {
+        // Check the header
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+        validate!(self.buffer[..4] == WIRE_MAGIC, NetworkInvalidMessage)?;
+        let version = self.buffer[4];
+        validate!((WIRE_VERSION_1..=WIRE_VERSION).contains(&version),
+                  NetworkIncompatible)
+            .map_err(|err| err.with_values(WIRE_VERSION, version))?;
+        let kind = FrameKind::from_u8(self.buffer[5])
+            .ok_or_else(|| Error::from(ErrorKind::NetworkInvalidMessage)
+                .with_values("1..=4", self.buffer[5]))?;
+        let size = u32::from_le_bytes(self.buffer[6..10].try_into().unwrap())
+            as usize;
+        let max_size = kind.get_max_size().min(self.max_size);
+        validate!(size <= max_size, NetworkInvalidMessage)
+            .map_err(|err| err.with_values(max_size, size))?;
+
+        // Take the payload
+        if self.buffer.len() < FRAME_HEADER_SIZE + size {
+            self.buffer.reserve(FRAME_HEADER_SIZE + size - self.buffer.len());
+            return Ok(None);
+        }
+        self.buffer.advance(FRAME_HEADER_SIZE);
+        let payload = self.buffer.split_to(size).freeze();
+        Ok(Some(Frame { version, kind, payload }))
+    }
+}
+
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::schema::Schema;
+    use crate::coin::coin_random;
+    use crate::net::messages::GetBlocks;
+
+    #[test]
+    fn test_wire() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        // Items
+        let coin = coin_random(&mut rng, &public);
+        let addr: U256 = rng.random();
+        let tr = Transaction::build(&mut rng, coin, addr, &key, 0, &schema);
+        let bytes = encode(&tr).unwrap();
+        assert_eq!(bytes.len(), FRAME_HEADER_SIZE + tr.get_encoded_size());
+        assert_eq!(&bytes[..4], b"UQON");
+        assert_eq!(decode::<Transaction>(&bytes).unwrap().get_hash(),
+                   tr.get_hash());
+
+        let block_data = BlockData::genesis();
+        let block: Block = decode(&encode(&block_data.block).unwrap())
+            .unwrap();
+        assert_eq!(block.hash, block_data.block.hash);
+        let block_data2: BlockData = decode(&encode(&block_data).unwrap())
+            .unwrap();
+        assert_eq!(block_data2.get_block_info(), block_data.get_block_info());
+
+        // Stream of frames received in small chunks
+        let message = Message::GetBlocks(GetBlocks { bix: 1, count: 10 });
+        let mut stream = Vec::new();
+        stream.extend(encode(&message).unwrap());
+        stream.extend(encode(&tr).unwrap());
+        let mut decoder = FrameDecoder::new();
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(7) {
+            decoder.extend(chunk);
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(decoder.get_buffered_size(), 0);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].get_item::<Message>().unwrap().to_bytes(),
+                   message.to_bytes());
+        assert_eq!(frames[1].kind, FrameKind::Transaction);
+        assert_eq!(frames[1].get_item::<Message>().unwrap_err().kind(),
+                   ErrorKind::NetworkInvalidMessage);
+    }
+
+    #[test]
+    fn test_wire_invalid() {
+        let message = Message::GetBlocks(GetBlocks { bix: 1, count: 10 });
+        let bytes = encode(&message).unwrap().to_vec();
+        let kind = |bytes: &[u8]| {
+            decode::<Message>(bytes).unwrap_err().kind()
+        };
+
+        // Header
+        let mut broken = bytes.clone();
+        broken[0] = b'X';
+        assert_eq!(kind(&broken), ErrorKind::NetworkInvalidMessage);
+        let mut broken = bytes.clone();
+        broken[4] = WIRE_VERSION + 1;
+        assert_eq!(kind(&broken), ErrorKind::NetworkIncompatible);
+        let mut broken = bytes.clone();
+        broken[5] = 9;
+        assert_eq!(kind(&broken), ErrorKind::NetworkInvalidMessage);
+
+        // Huge length is rejected before the payload is received
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&WIRE_MAGIC);
+        decoder.extend(&[WIRE_VERSION, FrameKind::Transaction as u8]);
+        decoder.extend(&u32::MAX.to_le_bytes());
+        assert_eq!(decoder.next_frame().unwrap_err().kind(),
+                   ErrorKind::NetworkInvalidMessage);
+        let mut decoder = FrameDecoder::new().with_max_size(8);
+        decoder.extend(&bytes);
+        assert!(decoder.next_frame().is_err());
+
+        // Truncated, trailing and malformed payload
+        assert_eq!(kind(&bytes[..bytes.len() - 1]), ErrorKind::EncodingInvalid);
+        let mut broken = bytes.clone();
+        broken.push(0);
+        assert_eq!(kind(&broken), ErrorKind::EncodingInvalid);
+        let mut broken = bytes.clone();
+        broken[FRAME_HEADER_SIZE] = 7;
+        assert_eq!(kind(&broken), ErrorKind::NetworkInvalidMessage);
+
+        // Random bytes never panic
+        let mut rng = rand::rng();
+        for _ in 0..1000 {
+            let size = rng.random_range(0..64);
+            let mut noise: Vec<u8> = (0..size).map(|_| rng.random())
+                .collect();
+            if rng.random_bool(0.5) && size >= FRAME_HEADER_SIZE {
+                noise[..6].copy_from_slice(&bytes[..6]);
+            }
+            let _ = decode::<Message>(&noise);
+            let _ = decode::<BlockData>(&noise);
+        }
+    }
+}
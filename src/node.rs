@@ -0,0 +1,390 @@
+//! Consistent commits of blocks and the state.
+//!
+//! A node keeps the blockchain and the state of its last block. If the block
+//! is pushed to the blockchain and the node crashes before the state is
+//! saved, they are out of sync on the next start. `Node` commits a block as
+//! one step: the block is pushed to the blockchain (the write is journaled,
+//! see `Blockchain::recover`), the state is rolled up and the state
+//! checkpoint is saved (each `checkpoint_interval`-th block). If any step
+//! fails, the block is removed from the blockchain and the state is rolled
+//! back.
+//!
+//! The checkpoint is written to a temporary file and renamed, so it is never
+//! partial, and it always corresponds to a block of the stored chain. On
+//! opening the state is loaded from the checkpoint and rolled up with the
+//! blocks stored after it, so a crash at any moment leaves the node
+//! consistent.
+//!
+//! A new node may start from a verified state snapshot of another node
+//! instead of replaying the blockchain (see `Node::sync_from_snapshot`): the
+//! blocks of the snapshot are stored as pruned ones and the state becomes
+//! the checkpoint.
+//!
+//! The committed blocks are published to the event bus of the node (see
+//! `Node::get_events`).
+
+use tokio::io::{Result as TokioResult, ErrorKind, AsyncWriteExt};
+use lbasedb::path_concat;
+
+use crate::validate;
+use crate::utils::*;
+use crate::schema::Schema;
+use crate::transaction::Transaction;
+use crate::block::Block;
+use crate::state::{State, StateDiff};
+use crate::spec::ChainSpec;
+use crate::chain::ChainValidator;
+use crate::blockchain::Blockchain;
+use crate::snapshot::StateSnapshot;
+use crate::events::{Event, EventBus};
+
+
+/// Number of blocks read from the blockchain at once on the catch up.
+const NODE_CATCH_UP_BATCH: u64 = 1000;
+
+
+/// Blockchain with the state of its last block.
+pub struct Node {
+    blockchain: Blockchain,
+    state: State,
+    schema: Schema,
+    path: String,
+    checkpoint_interval: u64,
+    events: EventBus,
+}
+
+
+impl Node {
+    /// Open the node in the directory: the blockchain and the state
+    /// checkpoint (`state.bin`). The state is rolled up with the blocks
+    /// stored after the checkpoint.
+    pub async fn open(path: &str, spec: ChainSpec) -> TokioResult<Self> {
+        let blockchain = Blockchain::new(path).await?
+            .with_chain_spec(spec.clone()).await?;
+
+        // Load the checkpoint
+        let checkpoint_path = path_concat!(path, "state.bin");
+        let state = if tokio::fs::try_exists(&checkpoint_path).await? {
+            State::load_binary(&checkpoint_path).await?
+        } else {
+            State::new()
+        }.with_chain_spec(&spec);
+
+        let mut instance = Self {
+            blockchain,
+            state,
+            schema: Schema::new(),
+            path: path.to_string(),
+            checkpoint_interval: 1,
+            events: EventBus::new(),
+        };
+        instance.catch_up().await?;
+        instance.state.set_diff_tracking(true);
+        Ok(instance)
+    }
+
+    /// Set the number of blocks between the state checkpoints (1 by
+    /// default). The larger interval makes commits faster and opening
+    /// slower.
+    pub fn with_checkpoint_interval(mut self, interval: u64) -> Self {
+        assert!(interval > 0);
+        self.checkpoint_interval = interval;
+        self
+    }
+
+    /// Use the shared event bus (e.g. with the pool events).
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Get the event bus: `NewBlock` and `NewTransaction` are published on
+    /// each commit.
+    pub fn get_events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Get the blockchain.
+    pub fn get_blockchain(&self) -> &Blockchain {
+        &self.blockchain
+    }
+
+    /// Get the state of the last block.
+    pub fn get_state(&self) -> &State {
+        &self.state
+    }
+
+    /// Get the schema.
+    pub fn get_schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Commit the block that follows the last one: push it to the blockchain,
+    /// roll up the state and save the checkpoint if it is due. The block must
+    /// be validated before. On error nothing is changed. Returns the changes
+    /// of the state.
+    pub async fn commit_block(&mut self, block: &Block,
+                              transactions: &[Transaction]) ->
+                              TokioResult<StateDiff> {
+        // Check the links (the state would panic on them)
+        let info_prev = self.state.get_last_block_info().clone();
+        validate!(block.hash_prev == info_prev.hash,
+                  BlockPreviousHashMismatch)?;
+        validate!(block.offset == info_prev.offset, BlockOffsetMismatch)
+            .map_err(|err| err.with_values(info_prev.offset, block.offset))?;
+        validate!(block.size == transactions.len() as u64, BlockBroken)
+            .map_err(|err| err.with_values(block.size, transactions.len()))?;
+
+        // Push the block
+        let senders = Transaction::calc_senders(transactions, &self.state,
+                                                &self.schema);
+        let bix = self.blockchain.push_new_block_with_senders(
+            block, transactions, &senders
+        ).await?;
+        if bix != info_prev.bix + 1 {
+            self.blockchain.truncate(bix - 1).await?;
+            return Err(ErrorKind::InvalidData.into());
+        }
+
+        // Roll up the state
+        let diff = self.state.roll_up_with_senders(bix, block, transactions,
+                                                   &senders);
+
+        // Save the checkpoint rolling back on error
+        if bix.is_multiple_of(self.checkpoint_interval)
+                && let Err(err) = self.save_checkpoint().await {
+            self.blockchain.truncate(info_prev.bix).await?;
+            self.state.roll_down_with_info(&info_prev, block, transactions,
+                                           &self.schema);
+            return Err(err);
+        }
+
+        // The state of the node tracks the diffs (except on the catch up)
+        self.events.publish_many(Event::from_block(bix, block, transactions));
+        Ok(diff.unwrap())
+    }
+
+    /// Start the empty node (`InvalidInput` otherwise) from the snapshot. It
+    /// is verified for the checkpoints of the validator and the trusted
+    /// publishers (see `StateSnapshot::verify`), then its blocks are stored
+    /// without transactions and its state is saved as the checkpoint. The
+    /// next blocks are committed as usual.
+    pub async fn sync_from_snapshot(&mut self, snapshot: &StateSnapshot,
+                                    validator: &ChainValidator,
+                                    publishers: &[U256]) -> TokioResult<()> {
+        // Check the node is empty
+        if self.blockchain.get_block_count().await? > 0 {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+
+        // Verify the snapshot
+        let spec = self.blockchain.get_chain_spec().clone();
+        snapshot.verify(validator, publishers, &spec, &self.schema)?;
+
+        // Store the blocks and the state
+        self.blockchain.push_pruned_blocks(&snapshot.blocks).await?;
+        self.state = snapshot.state.clone().with_chain_spec(&spec)
+                                           .with_diff_tracking(true);
+        self.save_checkpoint().await
+    }
+
+    /// Save the state checkpoint.
+    pub async fn save_checkpoint(&self) -> TokioResult<()> {
+        let path = self.get_checkpoint_path();
+        let path_tmp = format!("{}.tmp", path);
+        let mut file = tokio::fs::File::create(&path_tmp).await?;
+        file.write_all(&self.state.to_binary()).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&path_tmp, &path).await
+    }
+
+    fn get_checkpoint_path(&self) -> String {
+        path_concat!(self.path.as_str(), "state.bin")
+    }
+
+    /// Roll up the state with the blocks stored after it. The stored blocks
+    /// are trusted (they were validated before the commit).
+    async fn catch_up(&mut self) -> TokioResult<()> {
+        let total = self.blockchain.get_block_count().await?;
+        let bix = self.state.get_last_block_info().bix;
+        if bix > total {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        if bix > 0 {
+            let info = self.blockchain.get_block_info(bix).await?;
+            if info.hash != self.state.get_last_block_info().hash {
+                return Err(ErrorKind::InvalidData.into());
+            }
+        }
+
+        while self.state.get_last_block_info().bix < total {
+            let bix_next = self.state.get_last_block_info().bix + 1;
+            let count = NODE_CATCH_UP_BATCH.min(total - bix_next + 1);
+            let blocks = self.blockchain.get_block_data_many(bix_next, count)
+                                        .await?;
+            self.state.roll_up_many(&blocks, &self.schema);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::codec::Codec;
+    use crate::coin::coin_random;
+    use crate::block::BlockInfo;
+    use crate::difficulty::Retarget;
+    use crate::chain::Checkpoint;
+    use crate::testing::TempDir;
+
+    #[tokio::test]
+    async fn test_commit_block() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let receiver: U256 = rng.random();
+
+        let tmp = TempDir::new("uqoin-node");
+        let path = tmp.path().to_string();
+
+        let mut build = |info: &BlockInfo| {
+            let coin = coin_random(&mut rng, &public);
+            let transactions = vec![Transaction::build(
+                &mut rng, coin, receiver.clone(), &key, 0, &schema
+            )];
+            let block = Block::new(info.offset, 1, info.hash.clone(),
+                                   U256::from(0), U256::from(0),
+                                   U256::from(info.bix + 1));
+            (block, transactions)
+        };
+
+        // Two blocks are checkpointed, the third one is replayed on opening
+        let mut node = Node::open(&path, ChainSpec::mainnet()).await.unwrap()
+            .with_checkpoint_interval(2);
+        let events = node.get_events().subscribe();
+        let mut coins = Vec::new();
+        for _ in 0..3 {
+            let (block, transactions) = build(
+                node.get_state().get_last_block_info()
+            );
+            node.commit_block(&block, &transactions).await.unwrap();
+            coins.push(transactions[0].coin.clone());
+        }
+        assert_eq!(State::load_binary(&node.get_checkpoint_path()).await
+                         .unwrap().get_last_block_info().bix, 2);
+        assert_eq!(events.try_iter().count(), 6);
+
+        let mut node = Node::open(&path, ChainSpec::mainnet()).await.unwrap();
+        assert_eq!(node.get_state().get_last_block_info().bix, 3);
+        assert!(coins.iter().all(
+            |coin| node.get_state().get_owner(coin) == Some(&receiver)
+        ));
+
+        // A broken link is rejected without changes
+        let (mut block, transactions) = build(
+            node.get_state().get_last_block_info()
+        );
+        block.hash_prev = U256::from(1);
+        assert!(node.commit_block(&block, &transactions).await.is_err());
+        assert_eq!(node.get_blockchain().get_block_count().await.unwrap(), 3);
+
+        // A failed checkpoint rolls the block back
+        let (block, transactions) = build(
+            node.get_state().get_last_block_info()
+        );
+        let info = node.get_state().get_last_block_info().clone();
+        tokio::fs::create_dir_all(format!("{}.tmp", 
+                                          node.get_checkpoint_path())).await
+                  .unwrap();
+        assert!(node.commit_block(&block, &transactions).await.is_err());
+        assert_eq!(node.get_blockchain().get_block_count().await.unwrap(), 3);
+        assert_eq!(node.get_state().get_last_block_info(), &info);
+        assert!(node.get_state().get_owner(&transactions[0].coin).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sync_from_snapshot() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let (publisher_key, publisher) = schema.gen_pair(&mut rng);
+        let receiver: U256 = rng.random();
+
+        let tmp = TempDir::new("uqoin-node-sync");
+        let path_a = path_concat!(tmp.path(), "a");
+        let path_b = path_concat!(tmp.path(), "b");
+        tokio::fs::create_dir_all(&path_a).await.unwrap();
+        tokio::fs::create_dir_all(&path_b).await.unwrap();
+
+        let mut build = |info: &BlockInfo| {
+            let coin = coin_random(&mut rng, &public);
+            let transactions = vec![Transaction::build(
+                &mut rng, coin, receiver.clone(), &key, 0, &schema
+            )];
+            let block = Block::new(info.offset, 1, info.hash.clone(),
+                                   U256::from(0), U256::from(0),
+                                   U256::from(info.bix + 1));
+            (block, transactions)
+        };
+
+        // Full node
+        let mut node_a = Node::open(&path_a, ChainSpec::mainnet()).await
+                                                                 .unwrap();
+        for _ in 0..3 {
+            let (block, transactions) = build(
+                node_a.get_state().get_last_block_info()
+            );
+            node_a.commit_block(&block, &transactions).await.unwrap();
+        }
+        let snapshot = StateSnapshot::create(node_a.get_blockchain(),
+                                             node_a.get_state()).await
+                                    .unwrap();
+        let mut snapshot = StateSnapshot::from_bytes(&snapshot.to_bytes())
+            .unwrap();
+
+        // Untrusted snapshot
+        let mut node_b = Node::open(&path_b, ChainSpec::mainnet()).await
+                                                                 .unwrap();
+        let validator = ChainValidator::new(Retarget::default());
+        let publishers = vec![publisher];
+        assert!(node_b.sync_from_snapshot(&snapshot, &validator,
+                                          &publishers).await
+                      .is_err());
+        assert_eq!(node_b.get_blockchain().get_block_count().await.unwrap(),
+                   0);
+
+        // Checkpointed and tampered snapshots
+        let info = snapshot.info.clone();
+        let checkpointed = ChainValidator::new(Retarget::default())
+            .with_checkpoints(vec![Checkpoint::new(info.bix, info.hash)]);
+        let spec = ChainSpec::mainnet();
+        assert!(snapshot.verify(&checkpointed, &[], &spec, &schema).is_ok());
+        let mut tampered = snapshot.clone();
+        tampered.commitment = U256::from(1);
+        assert_eq!(tampered.verify(&checkpointed, &[], &spec, &schema)
+                           .unwrap_err().kind(),
+                   crate::error::ErrorKind::StateInconsistent);
+
+        // Signed snapshot
+        snapshot.sign(&mut rand::rng(), &publisher_key, &schema);
+        node_b.sync_from_snapshot(&snapshot, &validator, &publishers)
+              .await.unwrap();
+        assert_eq!(node_b.get_state().get_last_block_info(),
+                   node_a.get_state().get_last_block_info());
+        assert_eq!(node_b.get_blockchain().get_pruned_block_count(), 3);
+
+        // The next block is committed and the node is reopened
+        let (block, transactions) = build(
+            node_b.get_state().get_last_block_info()
+        );
+        node_b.commit_block(&block, &transactions).await.unwrap();
+        let node_b = Node::open(&path_b, ChainSpec::mainnet()).await
+                                                             .unwrap();
+        assert_eq!(node_b.get_state().get_last_block_info().bix, 4);
+        assert_eq!(node_b.get_state().get_owner(&transactions[0].coin),
+                   Some(&receiver));
+    }
+}
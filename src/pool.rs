@@ -7,73 +7,890 @@
 //! whenever the state changes to ensure all groups remain relevant and valid.
 //! This module is essential for preparing
 //! transactions for block creation using the `prepare` function.
+//!
+//! By default the groups are prepared in the order of arrival. With
+//! `GroupOrdering::FeeThenHash` the order depends on the groups only, so two
+//! validators with identical pools prepare identical blocks.
+//!
+//! The pool is bounded by `PoolLimits`: when it is full, the groups with the
+//! lowest fee (the oldest ones first) are evicted for a new group with a 
+//! higher fee, and the groups that stay in the pool for too many blocks are
+//! dropped on `Pool::update`.
+//!
+//! Groups that cannot be validated for the current state yet (e.g. they spend
+//! a coin of a pending group with the next counter) can be kept as orphans
+//! (see `Pool::add_orphan`). They are retried after each update until they
+//! become valid or expire.
+//!
+//! Node operators can customize the acceptance rules implementing 
+//! `MempoolPolicy` (see `Pool::add_policy`), e.g. `GroupFilter` restricts the
+//! group types and the addresses.
+//!
+//! Wallet services can follow the pending groups with `Pool::subscribe`: the
+//! pool publishes `PoolEvent` to its event bus (the bus of the node given
+//! with `Pool::with_events`) when the groups are added, replaced, evicted or
+//! included in a block (see `Pool::remove_included`). The clones of the pool
+//! get a new bus, so they do not publish to the subscribers of the original.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
 
-use rand::Rng;
+use rand::{Rng, CryptoRng};
+use serde::{Serialize, Deserialize};
 
+use crate::validate;
 use crate::utils::*;
+use crate::fee::FeePolicy;
+use crate::coin::coin_value;
 use crate::transaction::{Type, Transaction, Group};
+use crate::block::Block;
 use crate::schema::Schema;
-use crate::state::{State, OrderCoinsMap};
+use crate::state::{State, StateDiff, OrderCoinsMap};
+use crate::events::{Event, EventBus};
+
+
+/// Default maximum number of groups in the pool.
+pub const POOL_MAX_GROUPS: usize = 65536;
+
+/// Default maximum encoded size of the groups in the pool in bytes.
+pub const POOL_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Default number of blocks a group may stay in the pool.
+pub const POOL_GROUP_TTL: u64 = 1024;
+
+/// Default maximum number of pending groups of one sender.
+pub const POOL_MAX_GROUPS_PER_SENDER: usize = 64;
+
+/// Default maximum number of orphan groups.
+pub const POOL_MAX_ORPHANS: usize = 1024;
+
+
+/// Capacity limits of the pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolLimits {
+    /// Maximum number of groups.
+    pub max_groups: usize,
+
+    /// Maximum total encoded size of the groups in bytes.
+    pub max_bytes: usize,
+
+    /// Number of blocks the group may stay in the pool after its arrival.
+    pub ttl: u64,
+
+    /// Maximum number of pending groups of one sender.
+    pub max_groups_per_sender: usize,
+
+    /// Maximum number of orphan groups.
+    pub max_orphans: usize,
+}
+
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        Self {
+            max_groups: POOL_MAX_GROUPS,
+            max_bytes: POOL_MAX_BYTES,
+            ttl: POOL_GROUP_TTL,
+            max_groups_per_sender: POOL_MAX_GROUPS_PER_SENDER,
+            max_orphans: POOL_MAX_ORPHANS,
+        }
+    }
+}
+
+
+/// Reason of the group eviction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictReason {
+    /// The pool is full and a group with a higher fee arrived.
+    Capacity,
+
+    /// The group stayed in the pool longer than the TTL or its transactions
+    /// expired.
+    Expired,
+
+    /// The group became invalid for the new state.
+    Invalid,
+}
+
+
+/// Change of the pending group, the groups are identified by their hashes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolEvent {
+    /// The group is added.
+    Added {
+        #[serde(with = "u256_hex")]
+        hash: U256,
+    },
+
+    /// The group is replaced by another one spending the same coins.
+    Replaced {
+        #[serde(with = "u256_hex")]
+        hash: U256,
+        #[serde(with = "u256_hex")]
+        by: U256,
+    },
+
+    /// The group is evicted.
+    Evicted {
+        #[serde(with = "u256_hex")]
+        hash: U256,
+        reason: EvictReason,
+    },
+
+    /// The group is included in the block.
+    Included {
+        #[serde(with = "u256_hex")]
+        hash: U256,
+        bix: u64,
+    },
+}
+
+
+/// Statistics of the pending groups (see `Pool::stats`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolStats {
+    /// Number of the transfer groups.
+    pub transfers: usize,
+
+    /// Number of the split groups.
+    pub splits: usize,
+
+    /// Number of the merge groups.
+    pub merges: usize,
+
+    /// Total value of the coins of the groups.
+    pub value: U256,
+
+    /// Number of the groups by fee order.
+    pub fee_orders: BTreeMap<u64, usize>,
 
+    /// Number of the groups without fee.
+    pub no_fee: usize,
 
-/// Validator pool that keeps requested transactions.
+    /// Age of the oldest group in blocks.
+    pub oldest_age: Option<u64>,
+
+    /// Total encoded size of the groups in bytes.
+    pub bytes: usize,
+
+    /// Number of the added groups that spent coins of pending groups.
+    pub conflicts_detected: u64,
+}
+
+
+/// Acceptance rule of the groups consulted on `Pool::add` and 
+/// `Pool::submit_raw`.
+pub trait MempoolPolicy: std::fmt::Debug + Send + Sync {
+    /// Check if the group of the sender can be accepted for the state.
+    fn check_group(&self, group: &Group, sender: &U256, 
+                   state: &State) -> UqoinResult<()>;
+}
+
+
+impl MempoolPolicy for FeePolicy {
+    fn check_group(&self, group: &Group, sender: &U256, 
+                   state: &State) -> UqoinResult<()> {
+        let senders = vec![sender.clone(); group.len()];
+        self.validate_group(group, state, &senders)
+    }
+}
+
+
+/// Policy that filters the groups by type and addresses, 
+/// `PoolPolicyRejected` is returned for the filtered groups:
+/// * allowed_types: Accepted group types (all if `None`).
+/// * denied_senders: Senders whose groups are rejected.
+/// * denied_receivers: Addresses that cannot receive transfers.
+#[derive(Debug, Default)]
+pub struct GroupFilter {
+    pub allowed_types: Option<Vec<Type>>,
+    pub denied_senders: HashSet<U256>,
+    pub denied_receivers: HashSet<U256>,
+}
+
+
+impl MempoolPolicy for GroupFilter {
+    fn check_group(&self, group: &Group, sender: &U256, 
+                   _state: &State) -> UqoinResult<()> {
+        if let Some(allowed_types) = self.allowed_types.as_ref() {
+            validate!(allowed_types.contains(&group.get_type()), 
+                      PoolPolicyRejected)?;
+        }
+        validate!(!self.denied_senders.contains(sender), PoolPolicyRejected)?;
+        for tr in group.transactions().iter() {
+            if tr.get_type() == Type::Transfer {
+                validate!(!self.denied_receivers.contains(&tr.addr), 
+                          PoolPolicyRejected)
+                    .map_err(|err| err.with_coin(&tr.coin))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Order of the groups on `Pool::prepare`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GroupOrdering {
+    /// The order of arrival to the pool.
+    #[default]
+    Arrival,
+
+    /// Fee order descending (groups without fee go last), then group hash.
+    FeeThenHash,
+}
+
+
+/// Pending group with its sender and the number of the block it arrived at.
+#[derive(Debug, Clone)]
+struct PoolEntry {
+    group: Group,
+    sender: U256,
+    arrival: u64,
+}
+
+
+/// Validator pool that keeps requested transactions. The pending groups are
+/// stored by ids that increase in the order of arrival, so removing a group
+/// does not shift the others in the indices.
 #[derive(Debug, Clone)]
 pub struct Pool {
-    groups: Vec<Group>,
-    senders: Vec<U256>,
+    entries: BTreeMap<u64, PoolEntry>,
+    next_id: u64,
+    coin_index: HashMap<U256, u64>,
+    hash_index: HashMap<U256, u64>,
+    sender_index: HashMap<U256, BTreeSet<u64>>,
+    bytes: usize,
+    conflicts_detected: u64,
+    fee_policy: FeePolicy,
+    ordering: GroupOrdering,
+    limits: PoolLimits,
+    events: Detached<EventBus>,
+    orphans: Vec<(Vec<Transaction>, u64)>,
+    policies: Vec<Arc<dyn MempoolPolicy>>,
+    revision: u64,
 }
 
 
 impl Pool {
     /// Create an empty pool.
     pub fn new() -> Self {
+        Self::with_fee_policy(FeePolicy::default())
+    }
+
+    /// Create an empty pool that accepts groups according to the fee policy.
+    pub fn with_fee_policy(fee_policy: FeePolicy) -> Self {
         Self {
-            groups: Vec::new(),
-            senders: Vec::new(),
+            entries: BTreeMap::new(),
+            next_id: 0,
+            coin_index: HashMap::new(),
+            hash_index: HashMap::new(),
+            sender_index: HashMap::new(),
+            bytes: 0,
+            conflicts_detected: 0,
+            fee_policy,
+            ordering: GroupOrdering::default(),
+            limits: PoolLimits::default(),
+            events: Detached::default(),
+            orphans: Vec::new(),
+            policies: Vec::new(),
+            revision: 0,
+        }
+    }
+
+    /// Calculate statistics of the pending groups for the state the pool is
+    /// updated to.
+    pub fn stats(&self, state: &State) -> PoolStats {
+        let mut stats = PoolStats {
+            transfers: 0,
+            splits: 0,
+            merges: 0,
+            value: U256::from(0),
+            fee_orders: BTreeMap::new(),
+            no_fee: 0,
+            oldest_age: self.entries.values().map(|entry| entry.arrival).min()
+                .map(|arrival| state.get_last_block_info().bix
+                                        .saturating_sub(arrival)),
+            bytes: self.bytes,
+            conflicts_detected: self.conflicts_detected,
+        };
+
+        for PoolEntry { group, sender, .. } in self.entries.values() {
+            let senders = vec![sender.clone(); group.len()];
+
+            // Type
+            match group.get_type() {
+                Type::Transfer => stats.transfers += 1,
+                Type::Split => stats.splits += 1,
+                Type::Merge => stats.merges += 1,
+                Type::Fee => {},
+            }
+
+            // Value and fee
+            let order = group.get_order(state, &senders);
+            stats.value = &stats.value + &coin_value(order);
+            match group.get_fee_order(state, &senders) {
+                Some(fee_order) => {
+                    *stats.fee_orders.entry(fee_order).or_insert(0) += 1;
+                },
+                None => stats.no_fee += 1,
+            }
         }
+
+        stats
+    }
+
+    /// Publish the events of the pool to the bus (e.g. the bus of the node).
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Detached(events);
+        self
+    }
+
+    /// Get the event bus of the pool.
+    pub fn get_events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Subscribe to the events of the pool (`Event::PoolUpdate`). The
+    /// subscription ends when the receiver is dropped.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Get capacity limits.
+    pub fn get_limits(&self) -> &PoolLimits {
+        &self.limits
+    }
+
+    /// Set capacity limits. They are applied to the next groups, call 
+    /// `update` to drop the expired groups.
+    pub fn set_limits(&mut self, limits: PoolLimits) {
+        self.limits = limits;
+    }
+
+    /// Number of the pending groups.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if there are no pending groups.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total encoded size of the pending groups in bytes.
+    pub fn get_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Get ordering of the groups on prepare.
+    pub fn get_ordering(&self) -> GroupOrdering {
+        self.ordering
+    }
+
+    /// Set ordering of the groups on prepare.
+    pub fn set_ordering(&mut self, ordering: GroupOrdering) {
+        self.ordering = ordering;
+    }
+
+    /// Get fee policy.
+    pub fn get_fee_policy(&self) -> &FeePolicy {
+        &self.fee_policy
+    }
+
+    /// Set fee policy. Call `update` to drop the groups that do not satisfy
+    /// the new policy.
+    pub fn set_fee_policy(&mut self, fee_policy: FeePolicy) {
+        self.fee_policy = fee_policy;
+    }
+
+    /// Add the acceptance policy, it is consulted besides the fee policy. 
+    /// Call `update` to drop the groups that do not satisfy it.
+    pub fn add_policy(&mut self, policy: Arc<dyn MempoolPolicy>) {
+        self.policies.push(policy);
+    }
+
+    /// Remove all the acceptance policies.
+    pub fn clear_policies(&mut self) {
+        self.policies.clear();
     }
 
     /// Clear pool.
     pub fn clear(&mut self) {
-        self.groups.clear();
-        self.senders.clear();
+        self.orphans.clear();
+        self.entries.clear();
+        self.coin_index.clear();
+        self.hash_index.clear();
+        self.sender_index.clear();
+        self.bytes = 0;
+        self.revision += 1;
+    }
+
+    /// Revision of the pending groups, it is increased on each change, so 
+    /// block templates can detect that they are outdated.
+    pub fn get_revision(&self) -> u64 {
+        self.revision
     }
 
     /// Add a new group. `sender` must correspond to the group sender that is
-    /// required on group creation.
-    pub fn add(&mut self, group: Group, sender: U256) {
-        self.groups.push(group);
-        self.senders.push(sender);
+    /// required on group creation. The group fee is checked against the fee
+    /// policy. If the group spends coins of pending groups (replace-by-fee),
+    /// they are replaced only if all of them belong to the same sender and the
+    /// new fee order is strictly higher than theirs (no fee is the lowest).
+    /// The number of the pending groups of the sender is limited. 
+    /// If the pool is full, the groups with the lowest fee (the oldest ones
+    /// first) are evicted if their fee order is lower than the new one. 
+    /// Returns the evicted groups.
+    pub fn add(&mut self, group: Group, sender: U256, 
+               state: &State) -> UqoinResult<Vec<Group>> {
+        let arrival = state.get_last_block_info().bix;
+        let hash = group.get_hash();
+        let evicted = self.add_at(group, sender, state, arrival)?;
+        self.emit(PoolEvent::Added { hash });
+        Ok(evicted)
+    }
+
+    /// Add the groups of raw transactions (without extensions) calculating
+    /// their senders. Returns the hash of each added group or the error why it
+    /// was rejected in the order of the groups. If the rest of the
+    /// transactions cannot be groupped, the last item is the error with the
+    /// index of the offending transaction in `transactions`.
+    pub fn submit_raw(&mut self, transactions: Vec<Transaction>, 
+                      state: &State, schema: &Schema) -> 
+                      Vec<UqoinResult<U256>> {
+        let senders = Transaction::calc_senders(&transactions, state, schema);
+        let mut results = Vec::new();
+        let mut offset = 0;
+
+        while offset < transactions.len() {
+            // Split the leading group
+            let group = match Group::from_slice(&transactions[offset..], state, 
+                                                &senders[offset..]) {
+                Ok(group) => group.into_owned(),
+                Err(err) => {
+                    results.push(Err(err.with_offset(offset)));
+                    break;
+                },
+            };
+            let sender = senders[offset].clone();
+            offset += group.len();
+
+            // Add the group
+            let hash = group.get_hash();
+            results.push(self.add(group, sender, state).map(|_| hash));
+        }
+
+        results
+    }
+
+    /// Check the group of the sender against the fee policy and the 
+    /// acceptance policies without adding it (e.g. before relaying it).
+    pub fn check_policies(&self, group: &Group, sender: &U256, 
+                          state: &State) -> UqoinResult<()> {
+        let senders = vec![sender.clone(); group.len()];
+        self.fee_policy.validate_group(group, state, &senders)?;
+        for policy in self.policies.iter() {
+            policy.check_group(group, sender, state)?;
+        }
+        Ok(())
+    }
+
+    /// Keep the transactions of a group that is not valid for the state yet
+    /// as an orphan. It is retried on each update. If there are too many
+    /// orphans, the oldest one is dropped. `TransactionEmpty` if there are no
+    /// transactions.
+    pub fn add_orphan(&mut self, transactions: Vec<Transaction>, 
+                      state: &State) -> UqoinResult<()> {
+        validate!(!transactions.is_empty(), TransactionEmpty)?;
+        if self.orphans.len() >= self.limits.max_orphans {
+            self.orphans.remove(0);
+        }
+        self.orphans.push((transactions, state.get_last_block_info().bix));
+        Ok(())
+    }
+
+    /// Number of the orphan groups.
+    pub fn get_orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Try to add the orphans that became valid for the state and drop the
+    /// expired ones.
+    fn retry_orphans(&mut self, state: &State, schema: &Schema) {
+        let bix = state.get_last_block_info().bix + 1;
+        for (transactions, arrival) in std::mem::take(&mut self.orphans) {
+            // Drop expired
+            if transactions.iter().any(|tr| tr.is_expired_at(bix))
+                    || (bix - 1 > arrival.saturating_add(self.limits.ttl)) {
+                continue;
+            }
+
+            // Try to add
+            let senders = Transaction::calc_senders(&transactions, state, 
+                                                    schema);
+            let hash = transactions[0].get_hash();
+            match Group::new(transactions.clone(), state, &senders) {
+                Ok(group) => {
+                    if self.add_at(group, senders[0].clone(), state, 
+                                   arrival).is_ok() {
+                        self.emit(PoolEvent::Added { hash });
+                    }
+                },
+                Err(_) => self.orphans.push((transactions, arrival)),
+            }
+        }
+    }
+
+    /// Remove the pending groups included in the block (call it before 
+    /// `update` with the new state). Returns the removed groups.
+    pub fn remove_included(&mut self, bix: u64, 
+                           transactions: &[Transaction]) -> Vec<Group> {
+        let hashes: HashSet<U256> = transactions.iter()
+            .map(|tr| tr.get_hash()).collect();
+        let ids: Vec<u64> = self.hash_index.iter()
+            .filter(|(hash, _)| hashes.contains(hash))
+            .map(|(_, id)| *id).collect::<BTreeSet<u64>>()
+            .into_iter().collect();
+        let mut removed = Vec::new();
+        for id in ids.into_iter() {
+            let group = self.remove_group(id);
+            self.emit(PoolEvent::Included { hash: group.get_hash(), bix });
+            removed.push(group);
+        }
+        removed
+    }
+
+    /// Add the group that arrived at the block `arrival` (see `add`).
+    fn add_at(&mut self, group: Group, sender: U256, state: &State,
+              arrival: u64) -> UqoinResult<Vec<Group>> {
+        let senders = vec![sender.clone(); group.len()];
+
+        // Check fee policy and acceptance policies
+        self.check_policies(&group, &sender, state)?;
+
+        // Find conflicting groups
+        let conflicts = self.get_conflict_ids(&group);
+        if !conflicts.is_empty() {
+            self.conflicts_detected += 1;
+        }
+
+        // Check replacement rules
+        let fee_order = group.get_fee_order(state, &senders);
+        for id in conflicts.iter() {
+            let coin = self.get_coin_conflicts(&group).into_iter()
+                .find(|coin| self.coin_index[coin] == *id).unwrap();
+            let other = &self.entries[id];
+            validate!(other.sender == sender, PoolCoinConflict)
+                .map_err(|err| err.with_coin(&coin))?;
+            let other_senders = vec![sender.clone(); other.group.len()];
+            let other_fee_order = other.group.get_fee_order(
+                state, &other_senders
+            );
+            validate!(fee_order > other_fee_order, FeeTooLow)
+                .map_err(|err| err.with_coin(&coin))?;
+        }
+
+        // Check the sender limit (the conflicting groups are of the sender)
+        let sender_count = self.get_sender_count(&sender) - conflicts.len();
+        validate!(sender_count < self.limits.max_groups_per_sender, 
+                  PoolSenderLimit)
+            .map_err(|err| err.with_values(self.limits.max_groups_per_sender,
+                                           sender_count + 1))?;
+
+        // Find groups to evict for capacity
+        let group_bytes = Block::get_encoded_size(group.transactions());
+        let victims = self.get_capacity_victims(&conflicts, group_bytes, 
+                                                fee_order, state)?;
+
+        // Evict groups
+        let hash = group.get_hash();
+        let mut ids: Vec<u64> = conflicts.iter().cloned()
+            .chain(victims).collect();
+        ids.sort();
+        let mut evicted = Vec::new();
+        for id in ids.into_iter() {
+            let other = self.remove_group(id);
+            let other_hash = other.get_hash();
+            self.emit(if conflicts.contains(&id) {
+                PoolEvent::Replaced { hash: other_hash, by: hash.clone() }
+            } else {
+                PoolEvent::Evicted { 
+                    hash: other_hash, reason: EvictReason::Capacity,
+                }
+            });
+            evicted.push(other);
+        }
+
+        // Insert the group
+        let id = self.next_id;
+        self.next_id += 1;
+        for tr in group.transactions().iter() {
+            self.coin_index.insert(tr.coin.clone(), id);
+        }
+        self.hash_index.insert(hash, id);
+        self.sender_index.entry(sender.clone()).or_default().insert(id);
+        self.entries.insert(id, PoolEntry { group, sender, arrival });
+        self.bytes += group_bytes;
+        self.revision += 1;
+
+        Ok(evicted)
+    }
+
+    /// Get ids of the groups to evict so the new group fits the limits
+    /// (besides the conflicting ones). The lowest fee groups are taken, the
+    /// oldest ones first, all of them must have lower fee order than the new
+    /// group.
+    fn get_capacity_victims(&self, conflicts: &[u64], group_bytes: usize,
+                            fee_order: Option<u64>, 
+                            state: &State) -> UqoinResult<Vec<u64>> {
+        validate!(group_bytes <= self.limits.max_bytes, PoolFull)?;
+
+        // Size of the pool without the conflicting groups
+        let mut count = self.entries.len() - conflicts.len();
+        let mut bytes = self.bytes - conflicts.iter().map(
+            |id| Block::get_encoded_size(self.entries[id].group.transactions())
+        ).sum::<usize>();
+
+        let is_full = |count: usize, bytes: usize| {
+            (count + 1 > self.limits.max_groups) || 
+                (bytes + group_bytes > self.limits.max_bytes)
+        };
+
+        let mut victims = Vec::new();
+        if !is_full(count, bytes) {
+            return Ok(victims);
+        }
+
+        // Candidates from the lowest fee and the oldest
+        let mut candidates: Vec<(Option<u64>, u64, u64)> = self.entries.iter()
+            .filter(|(id, _)| !conflicts.contains(id))
+            .map(|(id, entry)| {
+                let senders = vec![entry.sender.clone(); entry.group.len()];
+                let other_fee_order = entry.group.get_fee_order(
+                    state, &senders
+                );
+                (other_fee_order, entry.arrival, *id)
+            }).collect();
+        candidates.sort();
+
+        for (other_fee_order, _, id) in candidates.into_iter() {
+            if !is_full(count, bytes) {
+                break;
+            }
+            validate!(fee_order > other_fee_order, PoolFull)?;
+            count -= 1;
+            bytes -= Block::get_encoded_size(
+                self.entries[&id].group.transactions()
+            );
+            victims.push(id);
+        }
+
+        validate!(!is_full(count, bytes), PoolFull)?;
+        Ok(victims)
+    }
+
+    /// Publish the event to the bus.
+    fn emit(&mut self, event: PoolEvent) {
+        self.events.publish(Event::PoolUpdate(event));
+    }
+
+    /// Remove the group by id.
+    fn remove_group(&mut self, id: u64) -> Group {
+        let PoolEntry { group, sender, .. } = self.entries.remove(&id)
+                                                  .unwrap();
+
+        // Update the indices
+        for tr in group.transactions().iter() {
+            self.coin_index.remove(&tr.coin);
+        }
+        self.hash_index.remove(&group.get_hash());
+        self.unindex_sender(&sender, id);
+
+        self.bytes -= Block::get_encoded_size(group.transactions());
+        self.revision += 1;
+        group
+    }
+
+    /// Number of the pending groups of the sender.
+    pub fn get_sender_count(&self, sender: &U256) -> usize {
+        self.sender_index.get(sender).map_or(0, |ids| ids.len())
+    }
+
+    /// Get the pending groups of the sender in the order of arrival.
+    pub fn get_groups_by_sender(&self, sender: &U256) -> Vec<&Group> {
+        self.sender_index.get(sender).map_or(vec![], |ids| {
+            ids.iter().map(|id| &self.entries[id].group).collect()
+        })
+    }
+
+    /// Get the pending group by its hash.
+    pub fn get_group_by_hash(&self, hash: &U256) -> Option<&Group> {
+        self.hash_index.get(hash).map(|id| &self.entries[id].group)
+    }
+
+    /// Check if the group with the hash is pending.
+    pub fn contains_hash(&self, hash: &U256) -> bool {
+        self.hash_index.contains_key(hash)
+    }
+
+    /// Remove the group id from the sender index.
+    fn unindex_sender(&mut self, sender: &U256, id: u64) {
+        if let Some(ids) = self.sender_index.get_mut(sender) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.sender_index.remove(sender);
+            }
+        }
+    }
+
+    /// Get hashes of the pending groups that spend any coin of the group in
+    /// the order of arrival.
+    pub fn get_conflicts(&self, group: &Group) -> Vec<U256> {
+        self.get_conflict_ids(group).iter()
+            .map(|id| self.entries[id].group.get_hash()).collect()
+    }
+
+    /// Get ids of the pending groups that spend any coin of the group in
+    /// ascending order.
+    fn get_conflict_ids(&self, group: &Group) -> Vec<u64> {
+        let ids: BTreeSet<u64> = group.transactions().iter()
+            .filter_map(|tr| self.coin_index.get(&tr.coin).copied())
+            .collect();
+        ids.into_iter().collect()
+    }
+
+    /// Get the coins of the group that are spent by pending groups.
+    pub fn get_coin_conflicts(&self, group: &Group) -> Vec<U256> {
+        group.transactions().iter()
+            .filter(|tr| self.coin_index.contains_key(&tr.coin))
+            .map(|tr| tr.coin.clone())
+            .collect()
+    }
+
+    /// Get the pending group that spends the coin.
+    pub fn get_group_of_coin(&self, coin: &U256) -> Option<&Group> {
+        self.coin_index.get(coin).map(|id| &self.entries[id].group)
     }
 
     /// Update the pool according to the given state. Valid group in one state
     /// may be invalid in another. This function recalculates senders based on
-    /// the state, so it may take a while.
+    /// the state, so it may take a while. Expired groups, groups that stay in
+    /// the pool longer than the TTL and groups that do not satisfy the fee 
+    /// policy are removed.
     pub fn update(&mut self, state: &State, schema: &Schema) {
-        let old_groups = self.groups.clone();
-        self.groups = Vec::new();
-        self.senders = Vec::new();
-        for old_group in old_groups.iter() {
+        let bix = state.get_last_block_info().bix + 1;
+        let old_entries = std::mem::take(&mut self.entries);
+        let orphans = std::mem::take(&mut self.orphans);
+        self.clear();
+        self.orphans = orphans;
+        for PoolEntry { group: old_group, arrival, .. } in 
+                old_entries.into_values() {
+            let hash = old_group.get_hash();
+            if old_group.transactions().iter().any(|tr| tr.is_expired_at(bix))
+                    || (bix - 1 > arrival.saturating_add(self.limits.ttl)) {
+                self.emit(PoolEvent::Evicted { 
+                    hash, reason: EvictReason::Expired,
+                });
+                continue;
+            }
             let senders = Transaction::calc_senders(&old_group.transactions(), 
                                                     state, schema);
-            if let Ok(group) = Group::new(old_group.transactions().to_vec(), 
-                                          state, &senders) {
-                self.add(group, senders[0].clone());
+            let added = Group::new(old_group.transactions().to_vec(), state, 
+                                   &senders)
+                .and_then(|group| self.add_at(group, senders[0].clone(), 
+                                              state, arrival));
+            if added.is_err() {
+                self.emit(PoolEvent::Evicted { 
+                    hash, reason: EvictReason::Invalid,
+                });
+            }
+        }
+        self.retry_orphans(state, schema);
+    }
+
+    /// Update the pool according to the state after the changes of the diff
+    /// (e.g. returned by `State::roll_up` with the diffs tracked). Only the
+    /// groups that spend the changed coins are revalidated (with their
+    /// senders and against the policies, see `check_policies`), the others
+    /// stay valid. Expired groups and groups that stay in the pool longer 
+    /// than the TTL are removed as well.
+    pub fn update_with_diff(&mut self, state: &State, diff: &StateDiff,
+                            schema: &Schema) {
+        let bix = state.get_last_block_info().bix + 1;
+        let coins: HashSet<&U256> = diff.coins.iter()
+            .map(|change| &change.coin).collect();
+
+        let ids: Vec<u64> = self.entries.keys().copied().collect();
+        for id in ids.into_iter() {
+            let PoolEntry { group, sender, arrival } = &self.entries[&id];
+            let hash = group.get_hash();
+
+            // Check expiration
+            if group.transactions().iter().any(|tr| tr.is_expired_at(bix))
+                    || (bix - 1 > arrival.saturating_add(self.limits.ttl)) {
+                self.remove_group(id);
+                self.emit(PoolEvent::Evicted { 
+                    hash, reason: EvictReason::Expired,
+                });
+                continue;
+            }
+
+            // Revalidate the group with the changed coins
+            if group.transactions().iter().any(|tr| coins.contains(&tr.coin)) {
+                let senders = Transaction::calc_senders(group.transactions(), 
+                                                        state, schema);
+                let valid = Group::new(group.transactions().to_vec(), state, 
+                                       &senders)
+                    .and_then(|group| self.check_policies(&group, &senders[0], 
+                                                          state));
+                if valid.is_ok() {
+                    let sender = sender.clone();
+                    self.unindex_sender(&sender, id);
+                    self.sender_index.entry(senders[0].clone()).or_default()
+                                     .insert(id);
+                    self.entries.get_mut(&id).unwrap().sender = 
+                        senders[0].clone();
+                } else {
+                    self.remove_group(id);
+                    self.emit(PoolEvent::Evicted { 
+                        hash, reason: EvictReason::Invalid,
+                    });
+                }
             }
         }
+        self.retry_orphans(state, schema);
     }
 
     /// Prepare transactions and senders for the next block. The pool must be
-    /// updated according to this state.
-    pub fn prepare<R: Rng>(&self, rng: &mut R, state: &State, schema: &Schema,
-                           validator_key: &U256, groups_max: Option<usize>) -> 
-                           (Vec<Transaction>, Vec<U256>) {
+    /// updated according to this state. The groups are taken in the order 
+    /// defined by the pool ordering.
+    pub fn prepare<R: Rng + CryptoRng>(&self, rng: &mut R, state: &State, 
+                                       schema: &Schema, validator_key: &U256, 
+                                       groups_max: Option<usize>) -> 
+                                       (Vec<Transaction>, Vec<U256>) {
+        let (transactions, senders, _) = self.prepare_with_report(
+            rng, state, schema, validator_key, groups_max
+        );
+        (transactions, senders)
+    }
+
+    /// The same as `prepare` but it also returns the number of validator 
+    /// coins by order that were missing to include split and merge groups, 
+    /// so the validator can mine or merge the needed denominations.
+    pub fn prepare_with_report<R: Rng + CryptoRng>(
+        &self, rng: &mut R, state: &State, schema: &Schema, 
+        validator_key: &U256, groups_max: Option<usize>
+    ) -> (Vec<Transaction>, Vec<U256>, BTreeMap<u64, usize>) {
         // Transactions and senders to fill
         let mut transactions = Vec::new();
         let mut senders = Vec::new();
 
+        // Missing validator coins by order
+        let mut missing = BTreeMap::new();
+
         // Validator public
         let validator = schema.get_public(validator_key);
 
@@ -87,8 +904,16 @@ impl Pool {
         // Counter of added groups
         let mut counter = 0;
 
+        // Encoded size of the added transactions
+        let mut bytes = 0;
+
+        // Number of the next block
+        let bix = state.get_last_block_info().bix + 1;
+
         // Loop for groups and corresponding senders
-        for (group, sender) in self.groups.iter().zip(self.senders.iter()) {
+        for id in self.get_ordered_ids(state) {
+            let PoolEntry { group, sender, .. } = &self.entries[&id];
+
             // Leave if groups_max is reached
             if let Some(groups_max) = groups_max {
                 if counter >= groups_max {
@@ -96,6 +921,17 @@ impl Pool {
                 }
             }
 
+            // Skip if the group cannot be included into the next block
+            if !group.transactions().iter().all(|tr| tr.is_valid_at(bix)) {
+                continue;
+            }
+
+            // Skip if the validator would pay the fee to itself
+            if (sender == &validator) && group.get_fee()
+                    .is_some_and(|fee| fee.forbids_self_transfer()) {
+                continue;
+            }
+
             // Skip if the group contains any seen coin
             if group.transactions().iter()
                     .any(|tr| coins_seen.contains(&tr.coin)) {
@@ -113,32 +949,55 @@ impl Pool {
             // Get order
             let order = group.get_order(state, &group_senders);
 
-            // Calculate ext transactions
-            let ext_trs: Option<Vec<Transaction>> = match group.get_type() {
-                Type::Transfer => Some(vec![]),
-                Type::Merge => [order].iter().map(|ord| {
-                    let coin = Self::get_validator_coin(
-                        ord, &mut validator_resource, &coins_seen
-                    )?;
-                    let counter = state.get_coin_counter(&coin);
-                    coins_seen.insert(coin.clone());
-                    Some(Transaction::build(rng, coin, sender.clone(), 
-                                            validator_key, counter, schema))
-                }).collect(),
-                Type::Split => [order-1, order-2, order-2].iter().map(|ord| {
-                    let coin = Self::get_validator_coin(
-                        ord, &mut validator_resource, &coins_seen
-                    )?;
+            // Orders of the validator coins to pay
+            let ext_orders = match group.get_type() {
+                Type::Transfer => vec![],
+                Type::Merge => vec![order],
+                Type::Split => vec![order - 1, order - 2, order - 2],
+                _ => panic!("Invalid group type"),
+            };
+
+            // Take validator coins
+            let ext_coins = ext_orders.iter().map(|ord| {
+                Self::get_validator_coin(ord, &mut validator_resource, 
+                                         &coins_seen)
+            }).collect::<Vec<Option<U256>>>();
+
+            // Calculate ext transactions or return the coins and report the
+            // missing ones if there are not enough validator coins
+            let ext_trs = if ext_coins.iter().all(|coin| coin.is_some()) {
+                Some(ext_coins.into_iter().flatten().map(|coin| {
                     let counter = state.get_coin_counter(&coin);
                     coins_seen.insert(coin.clone());
-                    Some(Transaction::build(rng, coin, sender.clone(), 
-                                            validator_key, counter, schema))
-                }).collect(),
-                _ => panic!("Invalid group type"),
+                    Transaction::build(rng, coin, sender.clone(), 
+                                       validator_key, counter, schema)
+                }).collect::<Vec<Transaction>>())
+            } else {
+                for (ord, coin) in ext_orders.iter().zip(ext_coins) {
+                    match coin {
+                        Some(coin) => {
+                            validator_resource.entry(*ord).or_default()
+                                              .insert(coin);
+                        },
+                        None => *missing.entry(*ord).or_insert(0) += 1,
+                    }
+                }
+                None
             };
 
             // Extend transactions and senders if ext was added
             if let Some(ext_trs) = ext_trs {
+                // Skip if the block limits would be exceeded
+                let size = group.len() + ext_trs.len();
+                let group_bytes = Block::get_encoded_size(group.transactions())
+                                + Block::get_encoded_size(&ext_trs);
+                if !state.get_params()
+                         .is_size_allowed(transactions.len() + size, 
+                                          bytes + group_bytes) {
+                    continue;
+                }
+                bytes += group_bytes;
+
                 senders.extend(group_senders);
                 senders.extend(vec![validator.clone(); ext_trs.len()]);
 
@@ -149,8 +1008,22 @@ impl Pool {
             }
         }
 
-        // Return transactions and senders
-        (transactions, senders)
+        // Return transactions, senders and missing validator coins
+        (transactions, senders, missing)
+    }
+
+    /// Get ids of the groups in the order of the pool ordering.
+    fn get_ordered_ids(&self, state: &State) -> Vec<u64> {
+        let mut ids = self.entries.keys().copied().collect::<Vec<u64>>();
+        if self.ordering == GroupOrdering::FeeThenHash {
+            ids.sort_by_cached_key(|id| {
+                let PoolEntry { group, sender, .. } = &self.entries[id];
+                let senders = vec![sender.clone(); group.len()];
+                let fee_order = group.get_fee_order(state, &senders);
+                (std::cmp::Reverse(fee_order), group.get_hash().to_bytes())
+            });
+        }
+        ids
     }
 
     /// Pop coin from the resource by order ignoring specified coins.
@@ -168,3 +1041,551 @@ impl Pool {
         None
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin::coin_random;
+    use crate::error::ErrorKind;
+    use crate::block::BlockInfo;
+    use crate::state::CoinInfoMap;
+    use crate::testing::TestAccount;
+
+    fn next_event(events: &Receiver<Event>) -> Option<PoolEvent> {
+        match events.try_recv().ok()? {
+            Event::PoolUpdate(event) => Some(event),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let coin = coin_random(&mut rng, &sender);
+        let fee_coin = coin_random(&mut rng, &sender);
+        let addr: U256 = rng.random();
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let group_free = account.group(&mut rng, coin.clone(), &addr, None, 
+                                       &state);
+        let group_fee = account.group(&mut rng, coin.clone(), &addr, 
+                                      Some(fee_coin.clone()), &state);
+
+        let mut pool = Pool::new();
+        assert!(pool.add(group_free.clone(), sender.clone(), &state)
+                    .unwrap().is_empty());
+        assert_eq!(pool.get_conflicts(&group_fee), 
+                   vec![group_free.get_hash()]);
+        let stats = pool.stats(&state);
+        let senders_one = vec![sender.clone()];
+        assert_eq!((stats.transfers, stats.no_fee, stats.oldest_age), 
+                   (1, 1, Some(0)));
+        assert_eq!(stats.value, 
+                   coin_value(group_free.get_order(&state, &senders_one)));
+        assert_eq!(pool.get_coin_conflicts(&group_fee), vec![coin.clone()]);
+        assert_eq!(pool.get_group_of_coin(&coin).unwrap().get_hash(), 
+                   group_free.get_hash());
+        assert!(pool.get_group_of_coin(&fee_coin).is_none());
+
+        // Higher fee replaces the group
+        let evicted = pool.add(group_fee.clone(), sender.clone(), 
+                               &state).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].get_hash(), group_free.get_hash());
+
+        let stats = pool.stats(&state);
+        assert_eq!((stats.no_fee, stats.fee_orders.len()), (0, 1));
+        assert_eq!(stats.conflicts_detected, 1);
+
+        // Lower or equal fee is rejected
+        assert_eq!(pool.add(group_free.clone(), sender.clone(), &state)
+                       .unwrap_err().kind(), ErrorKind::FeeTooLow);
+        assert_eq!(pool.add(group_fee.clone(), sender.clone(), &state)
+                       .unwrap_err().kind(), ErrorKind::FeeTooLow);
+
+        // Another sender cannot replace the group
+        let err = pool.add(group_fee.clone(), U256::from(5), &state)
+                      .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PoolCoinConflict);
+        assert_eq!(err.context().unwrap().coin, Some(coin.clone()));
+        assert_eq!(pool.get_group_of_coin(&fee_coin).unwrap().get_hash(), 
+                   group_fee.get_hash());
+    }
+
+    #[test]
+    fn test_stats() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let addr = U256::from(100);
+
+        let mut pool = Pool::new();
+        assert_eq!(pool.stats(&state).oldest_age, None);
+        let group_free = account.random_group(&mut rng, &addr, false, &state);
+        let group_fee = account.random_group(&mut rng, &addr, true, &state);
+        pool.add(group_free.clone(), sender.clone(), &state).unwrap();
+
+        // The group arrived after the state (e.g. after a roll down)
+        pool.add_at(group_fee.clone(), sender.clone(), &state, 5).unwrap();
+
+        let senders = vec![sender.clone(); 2];
+        let stats = pool.stats(&state);
+        assert_eq!((stats.transfers, stats.splits, stats.merges), (2, 0, 0));
+        assert_eq!(stats.no_fee, 1);
+        assert_eq!(stats.fee_orders, BTreeMap::from([
+            (group_fee.get_fee_order(&state, &senders).unwrap(), 1)
+        ]));
+        assert_eq!(stats.value, 
+                   &coin_value(group_free.get_order(&state, &senders)) + 
+                   &coin_value(group_fee.get_order(&state, &senders)));
+        assert_eq!(stats.oldest_age, Some(0));
+
+        let info = BlockInfo { bix: 3, ..BlockInfo::genesis() };
+        let state2 = State::from_coin_infos(CoinInfoMap::new(), info);
+        assert_eq!(pool.stats(&state2).oldest_age, Some(3));
+    }
+
+    #[test]
+    fn test_limits() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let mut state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let addr = U256::from(100);
+        let mut build_group = |fee: bool, state: &State| {
+            account.random_group(&mut rng, &addr, fee, state)
+        };
+
+        let mut pool = Pool::new();
+        pool.set_limits(PoolLimits { max_groups: 2, ..PoolLimits::default() });
+
+        let group1 = build_group(false, &state);
+        let group2 = build_group(false, &state);
+        pool.add(group1.clone(), sender.clone(), &state).unwrap();
+        pool.add(group2.clone(), sender.clone(), &state).unwrap();
+        assert_eq!(pool.get_bytes(), 2 * 129);
+
+        // No fee group cannot evict anything
+        assert_eq!(pool.add(build_group(false, &state), sender.clone(), &state)
+                       .unwrap_err().kind(), ErrorKind::PoolFull);
+
+        // The group with fee evicts the oldest one
+        let evicted = pool.add(build_group(true, &state), sender.clone(), 
+                               &state).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].get_hash(), group1.get_hash());
+        assert_eq!(pool.len(), 2);
+
+        // Events
+        let events = pool.subscribe();
+        let group3 = build_group(true, &state);
+        pool.add(group3.clone(), sender.clone(), &state).unwrap();
+        assert_eq!(next_event(&events).unwrap(), PoolEvent::Evicted { 
+            hash: group2.get_hash(), reason: EvictReason::Capacity,
+        });
+        assert_eq!(next_event(&events).unwrap(), 
+                   PoolEvent::Added { hash: group3.get_hash() });
+        assert_eq!(pool.remove_included(1, group3.transactions()).len(), 1);
+        assert_eq!(next_event(&events).unwrap(), 
+                   PoolEvent::Included { hash: group3.get_hash(), bix: 1 });
+        assert!(next_event(&events).is_none());
+        pool.add(group2.clone(), sender.clone(), &state).unwrap();
+
+        // TTL
+        let hash_prev = state.get_last_block_info().hash.clone();
+        state.roll_up(1, &Block::new(0, 0, hash_prev, U256::from(0), 
+                                     U256::from(0), U256::from(1)), 
+                      &[], &schema);
+        pool.set_limits(PoolLimits { ttl: 1, ..PoolLimits::default() });
+        pool.update(&state, &schema);
+        assert_eq!(pool.len(), 2);
+
+        pool.set_limits(PoolLimits { ttl: u64::MAX, ..PoolLimits::default() });
+        pool.update(&state, &schema);
+        assert_eq!(pool.len(), 2);
+
+        pool.set_limits(PoolLimits { ttl: 0, ..PoolLimits::default() });
+        pool.update(&state, &schema);
+        assert!(pool.is_empty());
+        assert_eq!(pool.get_bytes(), 0);
+    }
+
+    #[test]
+    fn test_sender_limit() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+        let (key2, sender2) = schema.gen_pair(&mut rng);
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let account2 = TestAccount::new(&key2, &sender2, &schema);
+        let addr = U256::from(100);
+
+        let mut pool = Pool::new();
+        pool.set_limits(PoolLimits { 
+            max_groups_per_sender: 2, ..PoolLimits::default() 
+        });
+        assert_eq!(pool.get_sender_count(&sender), 0);
+
+        let coin = account.coin(&mut rng);
+        let group1 = account.group(&mut rng, coin.clone(), &addr, None, 
+                                   &state);
+        pool.add(group1.clone(), sender.clone(), &state).unwrap();
+        pool.add(account.random_group(&mut rng, &addr, false, &state), 
+                 sender.clone(), &state).unwrap();
+        assert_eq!(pool.get_sender_count(&sender), 2);
+
+        // One more group of the sender is rejected
+        let err = pool.add(account.random_group(&mut rng, &addr, true, &state),
+                           sender.clone(), &state).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PoolSenderLimit);
+        assert_eq!(err.context().unwrap().expected, Some("2".to_string()));
+        assert_eq!(err.context().unwrap().actual, Some("3".to_string()));
+        assert_eq!(pool.len(), 2);
+
+        // Other senders are not affected
+        pool.add(account2.random_group(&mut rng, &addr, false, &state), 
+                 sender2.clone(), &state).unwrap();
+        assert_eq!(pool.get_sender_count(&sender2), 1);
+
+        // Replacing a pending group of the sender does not add a group
+        let fee_coin = account.coin(&mut rng);
+        let group1_fee = account.group(&mut rng, coin.clone(), &addr, 
+                                       Some(fee_coin), &state);
+        let evicted = pool.add(group1_fee, sender.clone(), &state).unwrap();
+        assert_eq!(evicted[0].get_hash(), group1.get_hash());
+        assert_eq!(pool.get_sender_count(&sender), 2);
+
+        // The slot is free once a group leaves the pool
+        let group = pool.get_groups_by_sender(&sender)[0].clone();
+        pool.remove_included(1, group.transactions());
+        assert_eq!(pool.get_sender_count(&sender), 1);
+        pool.add(account.random_group(&mut rng, &addr, false, &state), 
+                 sender.clone(), &state).unwrap();
+        assert_eq!(pool.get_sender_count(&sender), 2);
+    }
+
+    #[test]
+    fn test_update_with_diff() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let mut state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+        let validator: U256 = rng.random();
+
+        let mut groups = Vec::new();
+        for _ in 0..2 {
+            let coin = coin_random(&mut rng, &sender);
+            let transactions = vec![Transaction::build(
+                &mut rng, coin, U256::from(100), &key, 0, &schema
+            )];
+            let senders = vec![sender.clone(); transactions.len()];
+            groups.push(Group::new(transactions, &state, &senders).unwrap());
+        }
+
+        let mut pool = Pool::new();
+        for group in groups.iter() {
+            pool.add(group.clone(), sender.clone(), &state).unwrap();
+        }
+
+        // The coin of the first group is spent by another transaction
+        let transactions = vec![Transaction::build(
+            &mut rng, groups[0].transactions()[0].coin.clone(), 
+            U256::from(200), &key, 0, &schema
+        )];
+        let info = state.get_last_block_info().clone();
+        let block = Block::new(info.offset, 1, info.hash.clone(), validator,
+                               U256::from(0), U256::from(1));
+        state.set_diff_tracking(true);
+        let diff = state.roll_up(1, &block, &transactions, &schema).unwrap();
+
+        let events = pool.subscribe();
+        pool.update_with_diff(&state, &diff, &schema);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.get_group_of_coin(&groups[1].transactions()[0].coin)
+                       .unwrap().get_hash(), groups[1].get_hash());
+        assert_eq!(next_event(&events).unwrap(), PoolEvent::Evicted {
+            hash: groups[0].get_hash(), reason: EvictReason::Invalid,
+        });
+
+        // The revalidated groups are checked against the policies
+        pool.add_policy(Arc::new(GroupFilter {
+            denied_senders: HashSet::from([sender.clone()]),
+            ..GroupFilter::default()
+        }));
+        let coin = groups[1].transactions()[0].coin.clone();
+        let diff = StateDiff {
+            block_before: state.get_last_block_info().clone(),
+            block_after: state.get_last_block_info().clone(),
+            coins: vec![crate::state::CoinChange { 
+                coin, before: None, after: None,
+            }],
+        };
+        pool.update_with_diff(&state, &diff, &schema);
+        assert!(pool.is_empty());
+        assert_eq!(next_event(&events).unwrap(), PoolEvent::Evicted {
+            hash: groups[1].get_hash(), reason: EvictReason::Invalid,
+        });
+    }
+
+    #[test]
+    fn test_orphans() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let mut state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+        let (key2, receiver) = schema.gen_pair(&mut rng);
+        let validator: U256 = rng.random();
+
+        // The receiver spends the freshly mined coin before it is in a block
+        let coin = coin_random(&mut rng, &sender);
+        let transactions = vec![Transaction::build(
+            &mut rng, coin.clone(), receiver.clone(), &key, 0, &schema
+        )];
+        let transactions2 = vec![Transaction::build(
+            &mut rng, coin.clone(), U256::from(100), &key2, 1, &schema
+        )];
+        let senders = Transaction::calc_senders(&transactions2, &state, 
+                                                &schema);
+        assert!(Group::new(transactions2.clone(), &state, &senders).is_err());
+
+        let mut pool = Pool::new();
+        assert_eq!(pool.add_orphan(vec![], &state).unwrap_err().kind(),
+                   ErrorKind::TransactionEmpty);
+        pool.add_orphan(transactions2.clone(), &state).unwrap();
+        pool.update(&state, &schema);
+        assert_eq!(pool.len(), 0);
+        assert_eq!(pool.get_orphan_count(), 1);
+
+        // The orphan becomes valid once the first transfer is in a block
+        let info = state.get_last_block_info().clone();
+        let block = Block::new(info.offset, 1, info.hash.clone(), validator,
+                               U256::from(0), U256::from(1));
+        state.set_diff_tracking(true);
+        let diff = state.roll_up(1, &block, &transactions, &schema).unwrap();
+
+        let events = pool.subscribe();
+        pool.update_with_diff(&state, &diff, &schema);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.get_orphan_count(), 0);
+        assert_eq!(pool.get_sender_count(&receiver), 1);
+        assert_eq!(next_event(&events).unwrap(), PoolEvent::Added {
+            hash: transactions2[0].get_hash(),
+        });
+
+        // Orphans expire after the TTL
+        pool.clear();
+        pool.set_limits(PoolLimits { ttl: 0, ..PoolLimits::default() });
+        pool.add_orphan(transactions2.clone(), &State::new()).unwrap();
+        pool.update(&state, &schema);
+        assert_eq!(pool.get_orphan_count(), 0);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn test_lookups() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+        let (key2, sender2) = schema.gen_pair(&mut rng);
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let account2 = TestAccount::new(&key2, &sender2, &schema);
+        let addr = U256::from(100);
+        let groups = [account.random_group(&mut rng, &addr, false, &state),
+                      account2.random_group(&mut rng, &addr, false, &state),
+                      account.random_group(&mut rng, &addr, false, &state)];
+
+        let mut pool = Pool::new();
+        pool.add(groups[0].clone(), sender.clone(), &state).unwrap();
+        pool.add(groups[1].clone(), sender2.clone(), &state).unwrap();
+        pool.add(groups[2].clone(), sender.clone(), &state).unwrap();
+
+        let hashes = |groups: Vec<&Group>| {
+            groups.iter().map(|group| group.get_hash()).collect::<Vec<U256>>()
+        };
+        assert_eq!(hashes(pool.get_groups_by_sender(&sender)), 
+                   vec![groups[0].get_hash(), groups[2].get_hash()]);
+        assert!(pool.get_groups_by_sender(&U256::from(1)).is_empty());
+        assert!(pool.contains_hash(&groups[1].get_hash()));
+        assert_eq!(pool.get_group_by_hash(&groups[2].get_hash()).unwrap()
+                       .get_hash(), groups[2].get_hash());
+
+        // Other groups keep their ids on removal
+        pool.remove_group(1);
+        assert!(!pool.contains_hash(&groups[1].get_hash()));
+        assert!(pool.get_groups_by_sender(&sender2).is_empty());
+        pool.add(groups[1].clone(), sender2.clone(), &state).unwrap();
+        pool.remove_group(0);
+        assert!(!pool.contains_hash(&groups[0].get_hash()));
+        assert_eq!(pool.get_sender_count(&sender), 1);
+        assert_eq!(hashes(pool.get_groups_by_sender(&sender)), 
+                   vec![groups[2].get_hash()]);
+        assert_eq!(hashes(pool.get_groups_by_sender(&sender2)), 
+                   vec![groups[1].get_hash()]);
+        assert_eq!(pool.get_group_of_coin(&groups[2].transactions()[0].coin)
+                       .unwrap().get_hash(), groups[2].get_hash());
+    }
+
+    #[test]
+    fn test_submit_raw() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let mut transactions = Vec::new();
+        for addr in [100, 101, 0] {
+            let coin = coin_random(&mut rng, &sender);
+            transactions.push(Transaction::build(
+                &mut rng, coin, U256::from(addr), &key, 0, &schema
+            ));
+        }
+
+        // The first transfer takes the fee, the second one is alone
+        let mut pool = Pool::new();
+        let results = pool.submit_raw(transactions.clone(), &state, &schema);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap(), &transactions[0].get_hash());
+        assert_eq!(results[1].as_ref().unwrap(), &transactions[1].get_hash());
+        assert_eq!(pool.get_sender_count(&sender), 2);
+
+        // The repeated transfer with the fee replaces the first one, the
+        // trailing fee cannot be groupped
+        let mut pool = Pool::new();
+        let results = pool.submit_raw(
+            vec![transactions[1].clone(), transactions[1].clone(), 
+                 transactions[2].clone(), transactions[2].clone()], 
+            &state, &schema
+        );
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok() && results[1].is_ok());
+        let err = results[2].as_ref().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TransactionBrokenGroup);
+        assert_eq!(err.context().unwrap().index, Some(3));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_policy() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let mut build_group = |addr: u64| {
+            account.random_group(&mut rng, &U256::from(addr), false, &state)
+        };
+
+        let mut pool = Pool::new();
+        pool.add_policy(Arc::new(GroupFilter {
+            allowed_types: Some(vec![Type::Transfer]),
+            denied_receivers: HashSet::from([U256::from(200)]),
+            ..GroupFilter::default()
+        }));
+
+        let err = pool.add(build_group(1), sender.clone(), &state).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PoolPolicyRejected);
+        let err = pool.add(build_group(200), sender.clone(), &state)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PoolPolicyRejected);
+        assert!(pool.add(build_group(100), sender.clone(), &state).is_ok());
+
+        // Fee policy as an acceptance policy
+        pool.add_policy(Arc::new(FeePolicy {
+            transfer_min_order: Some(0), ..FeePolicy::default()
+        }));
+        let err = pool.add(build_group(100), sender.clone(), &state)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FeeTooLow);
+
+        pool.clear_policies();
+        assert!(pool.add(build_group(1), sender.clone(), &state).is_ok());
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_ordering() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+        let validator_key = schema.gen_key(&mut rng);
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let addr = U256::from(100);
+        let groups = [false, false, true].map(
+            |fee| account.random_group(&mut rng, &addr, fee, &state)
+        );
+
+        // Pools with the same groups in different order
+        let mut pool1 = Pool::new();
+        let mut pool2 = Pool::new();
+        for (group1, group2) in groups.iter().zip(groups.iter().rev()) {
+            pool1.add(group1.clone(), sender.clone(), &state).unwrap();
+            pool2.add(group2.clone(), sender.clone(), &state).unwrap();
+        }
+
+        let mut prepare = |pool: &Pool| {
+            pool.prepare(&mut rng, &state, &schema, &validator_key, None).0
+                .iter().map(|tr| tr.get_hash()).collect::<Vec<U256>>()
+        };
+
+        assert_eq!(pool1.get_ordering(), GroupOrdering::Arrival);
+        assert_ne!(prepare(&pool1), prepare(&pool2));
+
+        pool1.set_ordering(GroupOrdering::FeeThenHash);
+        pool2.set_ordering(GroupOrdering::FeeThenHash);
+        let hashes = prepare(&pool1);
+        assert_eq!(hashes, prepare(&pool2));
+
+        // The group with fee goes first
+        assert_eq!(hashes[0], groups[2].get_hash());
+    }
+
+    #[test]
+    fn test_prepare_with_report() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, sender) = schema.gen_pair(&mut rng);
+        let validator_key = schema.gen_key(&mut rng);
+
+        let account = TestAccount::new(&key, &sender, &schema);
+        let mut build_group = |addr: U256| {
+            let coin = account.coin_of_order(&mut rng, 2);
+            account.group(&mut rng, coin, &addr, None, &state)
+        };
+
+        let split = build_group(U256::from(1));
+        let transfer = build_group(U256::from(100));
+
+        let mut pool = Pool::new();
+        pool.add(split.clone(), sender.clone(), &state).unwrap();
+        pool.add(transfer.clone(), sender.clone(), &state).unwrap();
+
+        // The validator has no coins to pay for the split
+        let (transactions, senders, missing) = pool.prepare_with_report(
+            &mut rng, &state, &schema, &validator_key, None
+        );
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].get_hash(), transfer.get_hash());
+        assert_eq!(senders, vec![sender.clone()]);
+
+        let order = split.get_order(&state, std::slice::from_ref(&sender));
+        assert_eq!(missing, BTreeMap::from([(order - 1, 1), (order - 2, 2)]));
+    }
+}
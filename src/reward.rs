@@ -0,0 +1,388 @@
+//! Accounting of the validator rewards.
+//!
+//! The validator of a block gets the fee coins of the groups and the coins of
+//! split and merge groups, and spends its own coins in the extensions of
+//! these groups. For a valid block the values of the received and spent coins
+//! in splits and merges are equal, so the earnings of the validator are the
+//! fees. `Reward` keeps all these values for a block or a range of blocks,
+//! and `RewardLedger` aggregates them per validator, so operators can
+//! reconcile their income. The ledger of a range of blocks is built rolling
+//! up the state before the range (see `RewardLedger::of_range`), the ledgers
+//! of several ranges are merged with `RewardLedger::merge`.
+
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::validate;
+use crate::utils::*;
+use crate::coin::coin_value;
+use crate::schema::Schema;
+use crate::block::{Block, BlockData};
+use crate::state::State;
+use crate::transaction::{Type, Transaction, try_group_transactions};
+
+
+/// Reward of the validator in one or more blocks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reward {
+    /// Number of blocks.
+    pub blocks: u64,
+
+    /// Total value of the fee coins.
+    pub fees: U256,
+
+    /// Received fee coins.
+    pub fee_coins: Vec<U256>,
+
+    /// Total value of the coins received in split and merge groups.
+    pub received: U256,
+
+    /// Total value of the validator coins spent in the extensions.
+    pub spent: U256,
+}
+
+
+impl Reward {
+    /// Empty reward.
+    pub fn new() -> Self {
+        Self {
+            blocks: 0,
+            fees: U256::from(0),
+            fee_coins: Vec::new(),
+            received: U256::from(0),
+            spent: U256::from(0),
+        }
+    }
+
+    /// Calculate the reward of the validator for the block transactions. The
+    /// state must be the one before the block, `senders` correspond the 
+    /// transactions.
+    pub fn of_block(transactions: &[Transaction], state: &State, 
+                    senders: &[U256]) -> UqoinResult<Self> {
+        let mut reward = Self::new();
+        reward.blocks = 1;
+
+        for item in try_group_transactions(transactions, state, senders) {
+            let (offset, group, ext) = item?;
+
+            // Get senders
+            let group_senders = &senders[offset .. offset + group.len()];
+            let ext_senders = &senders[
+                offset + group.len() .. offset + group.len() + ext.len()
+            ];
+
+            // Fee coin
+            if let Some(fee) = group.get_fee() {
+                let order = fee.get_order(state, &group_senders[0]);
+                reward.fees = &reward.fees + &coin_value(order);
+                reward.fee_coins.push(fee.coin.clone());
+            }
+
+            // Coins of split and merge groups and the extension
+            if group.get_type() != Type::Transfer {
+                let group_order = group.get_order(state, group_senders);
+                let ext_order = ext.get_order(state, ext_senders);
+                reward.received = &reward.received + &coin_value(group_order);
+                reward.spent = &reward.spent + &coin_value(ext_order);
+            }
+        }
+
+        Ok(reward)
+    }
+
+    /// Add another reward.
+    pub fn add(&mut self, other: &Self) {
+        self.blocks += other.blocks;
+        self.fees = &self.fees + &other.fees;
+        self.fee_coins.extend(other.fee_coins.iter().cloned());
+        self.received = &self.received + &other.received;
+        self.spent = &self.spent + &other.spent;
+    }
+}
+
+
+impl Default for Reward {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Rewards aggregated per validator over a range of blocks.
+#[derive(Debug, Clone, Default)]
+pub struct RewardLedger {
+    rewards: HashMap<U256, Reward>,
+    range: Option<(u64, u64)>,
+}
+
+
+impl RewardLedger {
+    /// Empty ledger.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the reward of the block to its validator. The state must be the
+    /// one before the block, `senders` correspond the transactions.
+    pub fn add_block(&mut self, block: &Block, transactions: &[Transaction],
+                     state: &State, senders: &[U256]) -> UqoinResult<()> {
+        let reward = Reward::of_block(transactions, state, senders)?;
+        self.rewards.entry(block.validator.clone()).or_default().add(&reward);
+        Ok(())
+    }
+
+    /// Add the rewards of the consecutive blocks. The state must be the one
+    /// before the first block, it is rolled up with the blocks. The errors
+    /// carry the number of the block.
+    pub fn add_blocks(&mut self, blocks: &[BlockData], state: &mut State, 
+                      schema: &Schema) -> UqoinResult<()> {
+        for block_data in blocks.iter() {
+            // Check the block follows the state
+            let info = state.get_last_block_info();
+            let bix = info.bix + 1;
+            validate!(block_data.bix == bix, BlockBroken)
+                .map_err(|err| err.with_values(bix, block_data.bix)
+                                  .with_bix(block_data.bix))?;
+            validate!(block_data.block.hash_prev == info.hash, 
+                      BlockPreviousHashMismatch)
+                .map_err(|err| err.with_bix(bix))?;
+            validate!(block_data.block.offset == info.offset, 
+                      BlockOffsetMismatch)
+                .map_err(|err| err.with_bix(bix))?;
+
+            // Add the reward
+            let transactions = &block_data.transactions;
+            let senders = Transaction::calc_senders(transactions, state, 
+                                                    schema);
+            self.add_block(&block_data.block, transactions, state, &senders)
+                .map_err(|err| err.with_bix(bix))?;
+            self.extend_range(bix, bix);
+
+            // Roll up the state
+            state.roll_up_with_senders(bix, &block_data.block, transactions, 
+                                       &senders);
+        }
+        Ok(())
+    }
+
+    /// Ledger of the consecutive blocks, the state must be the one before
+    /// the first block (see `add_blocks`).
+    pub fn of_range(blocks: &[BlockData], state: &State, 
+                    schema: &Schema) -> UqoinResult<Self> {
+        let mut ledger = Self::new();
+        ledger.add_blocks(blocks, &mut state.clone(), schema)?;
+        Ok(ledger)
+    }
+
+    /// Merge the ledger of another range of blocks.
+    pub fn merge(&mut self, other: &Self) {
+        for (validator, reward) in other.rewards.iter() {
+            self.rewards.entry(validator.clone()).or_default().add(reward);
+        }
+        if let Some((first, last)) = other.range {
+            self.extend_range(first, last);
+        }
+    }
+
+    /// Get the numbers of the first and the last blocks added with
+    /// `add_blocks`.
+    pub fn get_range(&self) -> Option<(u64, u64)> {
+        self.range
+    }
+
+    /// Get the total reward of all validators.
+    pub fn get_total(&self) -> Reward {
+        let mut total = Reward::new();
+        for reward in self.rewards.values() {
+            total.add(reward);
+        }
+        total
+    }
+
+    /// Get the reward of the validator.
+    pub fn get(&self, validator: &U256) -> Option<&Reward> {
+        self.rewards.get(validator)
+    }
+
+    /// Iterate the validators with their rewards.
+    pub fn iter(&self) -> impl Iterator<Item = (&U256, &Reward)> {
+        self.rewards.iter()
+    }
+
+    /// Extend the range of the blocks.
+    fn extend_range(&mut self, first: u64, last: u64) {
+        self.range = Some(match self.range {
+            Some((first0, last0)) => (first0.min(first), last0.max(last)),
+            None => (first, last),
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, CryptoRng};
+    use crate::coin::coin_random;
+    use crate::error::ErrorKind;
+    use crate::block::BlockInfo;
+    use crate::testing::TestAccount;
+
+    #[test]
+    fn test_reward() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public);
+        let fee_coin = coin_random(&mut rng, &public);
+        let fee_order = state.calc_coin_order(&fee_coin, &public);
+
+        let transactions = vec![
+            Transaction::build(&mut rng, coin, U256::from(100), &key, 0,
+                               &schema),
+            Transaction::build(&mut rng, fee_coin.clone(), U256::from(0),
+                               &key, 0, &schema),
+        ];
+        let senders = vec![public; 2];
+        let block = Block::new(0, 2, U256::from(0), validator.clone(),
+                               U256::from(0), U256::from(0));
+
+        let reward = Reward::of_block(&transactions, &state, 
+                                      &senders).unwrap();
+        assert_eq!(reward.fees, coin_value(fee_order));
+        assert_eq!(reward.fee_coins, vec![fee_coin]);
+        assert_eq!(reward.received, U256::from(0));
+
+        // Two blocks of the same validator
+        let mut ledger = RewardLedger::new();
+        ledger.add_block(&block, &transactions, &state, &senders).unwrap();
+        ledger.add_block(&block, &transactions[..1], &state,
+                         &senders[..1]).unwrap();
+        let total = ledger.get(&validator).unwrap();
+        assert_eq!(total.blocks, 2);
+        assert_eq!(total.fees, coin_value(fee_order));
+        assert_eq!(ledger.iter().count(), 1);
+    }
+
+    /// Split and merge groups with the extensions of the validator.
+    fn build_split_merge<R: Rng + CryptoRng>(
+        rng: &mut R, account: &TestAccount, validator: &TestAccount
+    ) -> Vec<Transaction> {
+        let mut transactions = Vec::new();
+        let mut push = |sender: &TestAccount, order: u64, addr: &U256| {
+            let coin = sender.coin_with_order(rng, order);
+            transactions.push(sender.transfer(rng, coin, addr));
+        };
+
+        // Split of the coin of the order 4
+        push(account, 4, &U256::from(1));
+        for order in [3, 2, 2] {
+            push(validator, order, account.public);
+        }
+
+        // Merge into the coin of the order 5
+        for order in [4, 3, 3] {
+            push(account, order, &U256::from(2));
+        }
+        push(validator, 5, account.public);
+
+        transactions
+    }
+
+    #[test]
+    fn test_split_merge() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+
+        let (key, public) = schema.gen_pair(&mut rng);
+        let (validator_key, validator) = schema.gen_pair(&mut rng);
+        let account = TestAccount::new(&key, &public, &schema);
+        let validator_account = TestAccount::new(&validator_key, &validator, 
+                                                 &schema);
+
+        let transactions = build_split_merge(&mut rng, &account, 
+                                             &validator_account);
+        let senders = Transaction::calc_senders(&transactions, &state, 
+                                                &schema);
+        let reward = Reward::of_block(&transactions, &state, 
+                                      &senders).unwrap();
+
+        // The validator receives and spends the same value, no fees
+        let value = &coin_value(4) + &coin_value(5);
+        assert_eq!(reward.received, value);
+        assert_eq!(reward.spent, value);
+        assert_eq!(reward.fees, U256::from(0));
+        assert!(reward.fee_coins.is_empty());
+
+        // The broken extension is an error
+        assert!(Reward::of_block(&transactions[..3], &state, 
+                                 &senders[..3]).is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+
+        let (key, public) = schema.gen_pair(&mut rng);
+        let (validator_key, validator) = schema.gen_pair(&mut rng);
+        let validator2: U256 = schema.gen_pair(&mut rng).1;
+        let account = TestAccount::new(&key, &public, &schema);
+        let validator_account = TestAccount::new(&validator_key, &validator, 
+                                                 &schema);
+
+        // Transfer with fee, then splits and merges of another validator
+        let addr = U256::from(100);
+        let mut info = state.get_last_block_info().clone();
+        let blocks: Vec<BlockData> = (1..4).map(|bix| {
+            let (validator, transactions) = if bix == 1 {
+                (validator2.clone(), 
+                 account.random_group(&mut rng, &addr, true, &state)
+                        .transactions().to_vec())
+            } else {
+                (validator.clone(), 
+                 build_split_merge(&mut rng, &account, &validator_account))
+            };
+            let block = Block::new(info.offset, transactions.len() as u64, 
+                                   info.hash.clone(), validator, 
+                                   U256::from(0), U256::from(bix));
+            info = BlockInfo::from_block(bix, &block);
+            BlockData { bix, block, transactions }
+        }).collect();
+
+        let ledger = RewardLedger::of_range(&blocks, &state, 
+                                            &schema).unwrap();
+        assert_eq!(ledger.get_range(), Some((1, 3)));
+        assert_eq!(ledger.get(&validator2).unwrap().blocks, 1);
+        assert_eq!(ledger.get(&validator2).unwrap().fee_coins.len(), 1);
+        let reward = ledger.get(&validator).unwrap();
+        assert_eq!(reward.blocks, 2);
+        assert_eq!(reward.received, reward.spent);
+        assert_eq!(reward.received, 
+                   &U256::from(2) * &(&coin_value(4) + &coin_value(5)));
+        assert_eq!(ledger.get_total().blocks, 3);
+
+        // Merged ranges give the same ledger
+        let mut state2 = state.clone();
+        let mut ledger2 = RewardLedger::new();
+        ledger2.add_blocks(&blocks[..1], &mut state2, &schema).unwrap();
+        let ledger3 = RewardLedger::of_range(&blocks[1..], &state2, 
+                                             &schema).unwrap();
+        assert_eq!(ledger3.get_range(), Some((2, 3)));
+        ledger2.merge(&ledger3);
+        assert_eq!(ledger2.get_range(), Some((1, 3)));
+        assert_eq!(ledger2.get(&validator), ledger.get(&validator));
+        assert_eq!(ledger2.get(&validator2), ledger.get(&validator2));
+
+        // The blocks must follow the state
+        let err = RewardLedger::of_range(&blocks[1..], &state, &schema)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BlockBroken);
+    }
+}
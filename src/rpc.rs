@@ -0,0 +1,421 @@
+//! JSON-RPC types of the node API.
+//!
+//! The servers built on the crate accept `RpcRequest` and answer with
+//! `RpcResponse` (JSON-RPC 2.0), so the clients of different nodes use the
+//! same methods and structures:
+//! - `get_block`: the block with its transactions by number (`BlockData`).
+//! - `get_transaction`: the transaction by number (`TransactionResponse`).
+//! - `get_balance`: the balance and the coins of the address
+//!   (`BalanceResponse`).
+//! - `submit_transactions`: raw transactions to add to the pool, the result
+//!   for each group (`SubmitResult`, see `Pool::submit_raw`).
+//! - `get_pool_stats`: statistics of the pool (`PoolStats`).
+//!
+//! The requests are served by `handle` (the `blockchain` feature) over
+//! `Blockchain`, `State` and `Pool`. The errors of the protocol (invalid
+//! requests, missing items) have the JSON-RPC codes, the validation errors
+//! have the codes of their `ErrorKind` with the error as the data.
+
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::utils::*;
+use crate::error::Error;
+use crate::transaction::Transaction;
+use crate::block::BlockData;
+use crate::pool::PoolStats;
+
+#[cfg(feature = "blockchain")]
+use crate::{schema::Schema, state::State, pool::Pool,
+            blockchain::Blockchain};
+
+
+/// Version of JSON-RPC.
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Maximum number of transactions in `submit_transactions`.
+pub const RPC_SUBMIT_MAX_SIZE: usize = 1024;
+
+/// The request is not valid JSON.
+pub const RPC_PARSE_ERROR: i64 = -32700;
+
+/// The request is not a valid request object.
+pub const RPC_INVALID_REQUEST: i64 = -32600;
+
+/// The method does not exist.
+pub const RPC_METHOD_NOT_FOUND: i64 = -32601;
+
+/// The parameters of the method are invalid.
+pub const RPC_INVALID_PARAMS: i64 = -32602;
+
+/// Internal error of the server (e.g. of the storage).
+pub const RPC_INTERNAL_ERROR: i64 = -32603;
+
+/// The requested block or transaction does not exist.
+pub const RPC_NOT_FOUND: i64 = -32001;
+
+
+/// Identifier of the request (a number or a string).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RpcId {
+    Number(u64),
+    String(String),
+}
+
+
+/// Method of the request with its parameters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum RpcMethod {
+    GetBlock {
+        bix: u64,
+    },
+    GetTransaction {
+        tix: u64,
+    },
+    GetBalance {
+        #[serde(with = "u256_hex")]
+        address: U256,
+    },
+    SubmitTransactions {
+        transactions: Vec<Transaction>,
+    },
+    GetPoolStats,
+}
+
+
+/// Request object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub id: RpcId,
+    #[serde(flatten)]
+    pub method: RpcMethod,
+}
+
+
+impl RpcRequest {
+    /// Create the request.
+    pub fn new(id: RpcId, method: RpcMethod) -> Self {
+        Self { jsonrpc: JSONRPC_VERSION.to_string(), id, method }
+    }
+}
+
+
+/// Transaction by its number.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionResponse {
+    pub tix: u64,
+    #[serde(with = "u256_hex")]
+    pub hash: U256,
+    pub transaction: Transaction,
+}
+
+
+/// Balance of the address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceResponse {
+    #[serde(with = "u256_hex")]
+    pub address: U256,
+
+    /// Total value of the coins.
+    #[serde(with = "u256_hex")]
+    pub balance: U256,
+
+    /// Number of the coins by order.
+    pub order_counts: BTreeMap<u64, usize>,
+}
+
+
+/// Result of the submitted group: its hash or the reason of rejection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmitResult {
+    Accepted(#[serde(with = "u256_hex")] U256),
+    Rejected(Error),
+}
+
+
+/// Result of the method.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RpcResult {
+    Block(BlockData),
+    Transaction(TransactionResponse),
+    Balance(BalanceResponse),
+    Submit(Vec<SubmitResult>),
+    PoolStats(PoolStats),
+}
+
+
+/// Error object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Error>,
+}
+
+
+impl RpcError {
+    /// Create the error with the code.
+    pub fn new(code: i64, message: &str) -> Self {
+        Self { code, message: message.to_string(), data: None }
+    }
+
+    /// Create the error of the validation with the code of its kind.
+    pub fn from_error(err: Error) -> Self {
+        Self {
+            code: err.kind().code() as i64,
+            message: err.to_string(),
+            data: Some(err),
+        }
+    }
+}
+
+
+/// Result or error of the request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcOutcome {
+    Result(RpcResult),
+    Error(RpcError),
+}
+
+
+/// Response object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: RpcId,
+    #[serde(flatten)]
+    pub outcome: RpcOutcome,
+}
+
+
+impl RpcResponse {
+    /// Create the response with the result.
+    pub fn result(id: RpcId, result: RpcResult) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(), id,
+            outcome: RpcOutcome::Result(result),
+        }
+    }
+
+    /// Create the response with the error.
+    pub fn error(id: RpcId, error: RpcError) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(), id,
+            outcome: RpcOutcome::Error(error),
+        }
+    }
+}
+
+
+/// Serve the request. The pool is changed by `submit_transactions` only.
+#[cfg(feature = "blockchain")]
+pub async fn handle(request: RpcRequest, blockchain: &Blockchain,
+                    state: &State, pool: &mut Pool,
+                    schema: &Schema) -> RpcResponse {
+    let id = request.id;
+    if request.jsonrpc != JSONRPC_VERSION {
+        return RpcResponse::error(
+            id, RpcError::new(RPC_INVALID_REQUEST, "Unsupported jsonrpc")
+        );
+    }
+    match handle_method(request.method, blockchain, state, pool,
+                        schema).await {
+        Ok(result) => RpcResponse::result(id, result),
+        Err(error) => RpcResponse::error(id, error),
+    }
+}
+
+
+#[cfg(feature = "blockchain")]
+async fn handle_method(method: RpcMethod, blockchain: &Blockchain,
+                       state: &State, pool: &mut Pool,
+                       schema: &Schema) -> Result<RpcResult, RpcError> {
+    let internal = |err: std::io::Error| RpcError {
+        code: RPC_INTERNAL_ERROR,
+        message: err.to_string(),
+        data: Some(Error::from(err)),
+    };
+
+    match method {
+        RpcMethod::GetBlock { bix } => {
+            let count = blockchain.get_block_count().await
+                .map_err(internal)?;
+            if bix > count {
+                return Err(RpcError::new(RPC_NOT_FOUND, "Block not found"));
+            }
+            let block_data = blockchain.get_block_data(bix).await
+                .map_err(internal)?;
+            Ok(RpcResult::Block(block_data))
+        },
+
+        RpcMethod::GetTransaction { tix } => {
+            let count = blockchain.get_transaction_count().await
+                .map_err(internal)?;
+            if (tix == 0) || (tix > count) {
+                return Err(RpcError::new(RPC_NOT_FOUND,
+                                         "Transaction not found"));
+            }
+            let transaction = blockchain.get_transaction(tix).await
+                .map_err(internal)?;
+            Ok(RpcResult::Transaction(TransactionResponse {
+                tix, hash: transaction.get_hash(), transaction,
+            }))
+        },
+
+        RpcMethod::GetBalance { address } => {
+            Ok(RpcResult::Balance(BalanceResponse {
+                balance: state.get_balance(&address),
+                order_counts: state.get_order_counts(&address),
+                address,
+            }))
+        },
+
+        RpcMethod::SubmitTransactions { transactions } => {
+            if !(1..=RPC_SUBMIT_MAX_SIZE).contains(&transactions.len()) {
+                return Err(RpcError::new(RPC_INVALID_PARAMS,
+                                         "Invalid number of transactions"));
+            }
+            let results = pool.submit_raw(transactions, state, schema)
+                .into_iter()
+                .map(|result| match result {
+                    Ok(hash) => SubmitResult::Accepted(hash),
+                    Err(err) => SubmitResult::Rejected(err),
+                })
+                .collect();
+            Ok(RpcResult::Submit(results))
+        },
+
+        RpcMethod::GetPoolStats => Ok(RpcResult::PoolStats(pool.stats(state))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_rpc_json() {
+        // Requests
+        let request: RpcRequest = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"get_block",
+                "params":{"bix":5}}"#
+        ).unwrap();
+        assert_eq!(request.id, RpcId::Number(1));
+        assert!(matches!(request.method, RpcMethod::GetBlock { bix: 5 }));
+
+        let request: RpcRequest = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":"a","method":"get_pool_stats"}"#
+        ).unwrap();
+        assert_eq!(request.id, RpcId::String("a".to_string()));
+        assert!(matches!(request.method, RpcMethod::GetPoolStats));
+
+        let request = RpcRequest::new(RpcId::Number(2),
+                                      RpcMethod::GetBalance {
+                                          address: U256::from(255),
+                                      });
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains(r#""method":"get_balance""#));
+        assert!(json.contains(r#""address":"#));
+
+        // Responses
+        let response = RpcResponse::error(
+            RpcId::Number(3), RpcError::new(RPC_NOT_FOUND, "Block not found")
+        );
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(json, r#"{"jsonrpc":"2.0","id":3,"error":{"code":-32001,"#
+                         .to_string() + r#""message":"Block not found"}}"#);
+
+        let response = RpcResponse::result(
+            RpcId::Number(4), RpcResult::Submit(vec![
+                SubmitResult::Accepted(U256::from(1)),
+                SubmitResult::Rejected(ErrorKind::FeeTooLow.into()),
+            ])
+        );
+        let json = serde_json::to_string(&response).unwrap();
+        let response: RpcResponse = serde_json::from_str(&json).unwrap();
+        match response.outcome {
+            RpcOutcome::Result(RpcResult::Submit(results)) => {
+                assert_eq!(results.len(), 2);
+                assert!(matches!(&results[1], SubmitResult::Rejected(err)
+                                 if err.kind().code() == 209));
+            },
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+    }
+
+    #[cfg(feature = "blockchain")]
+    #[tokio::test]
+    async fn test_handle() {
+        use rand::Rng;
+        use crate::coin::coin_random;
+        use crate::testing::TempDir;
+
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+        let mut pool = Pool::new();
+
+        let tmp = TempDir::new("uqoin-rpc");
+        let path = tmp.path().to_string();
+        let blockchain = Blockchain::new(&path).await.unwrap();
+
+        let mut call = async |method: RpcMethod| {
+            let request = RpcRequest::new(RpcId::Number(1), method);
+            handle(request, &blockchain, &state, &mut pool, &schema).await
+                .outcome
+        };
+
+        // Genesis and missing items
+        match call(RpcMethod::GetBlock { bix: 0 }).await {
+            RpcOutcome::Result(RpcResult::Block(block_data)) => {
+                assert_eq!(block_data.bix, 0)
+            },
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        match call(RpcMethod::GetTransaction { tix: 1 }).await {
+            RpcOutcome::Error(error) => assert_eq!(error.code, RPC_NOT_FOUND),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+
+        // Submit a transfer
+        let (key, public) = schema.gen_pair(&mut rng);
+        let coin = coin_random(&mut rng, &public);
+        let addr: U256 = rng.random();
+        let tr = Transaction::build(&mut rng, coin, addr, &key, 0, &schema);
+        let method = RpcMethod::SubmitTransactions {
+            transactions: vec![tr.clone()],
+        };
+        match call(method).await {
+            RpcOutcome::Result(RpcResult::Submit(results)) => {
+                assert!(matches!(&results[0], SubmitResult::Accepted(hash)
+                                 if hash == &tr.get_hash()));
+            },
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        match call(RpcMethod::GetPoolStats).await {
+            RpcOutcome::Result(RpcResult::PoolStats(stats)) => {
+                assert_eq!(stats.transfers, 1)
+            },
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+
+        // Empty balance
+        match call(RpcMethod::GetBalance { address: public.clone() }).await {
+            RpcOutcome::Result(RpcResult::Balance(balance)) => {
+                assert_eq!(balance.balance, U256::from(0));
+                assert!(balance.order_counts.is_empty());
+            },
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+    }
+}
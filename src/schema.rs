@@ -7,7 +7,7 @@
 //! It is used in the Uqoin protocol to ensure the cryptographic security of 
 //! transactions.
 
-use rand::Rng;
+use rand::{Rng, CryptoRng};
 use finitelib::prelude::*;
 use finitelib::gf::prime::Prime;
 
@@ -40,7 +40,7 @@ impl Schema {
     }
 
     /// Generates a random private key.
-    pub fn gen_key<R: Rng>(&self, rng: &mut R) -> U256 {
+    pub fn gen_key<R: Rng + CryptoRng>(&self, rng: &mut R) -> U256 {
         &rng.random::<U256>() % &self.curve.base.order
     }
 
@@ -52,7 +52,7 @@ impl Schema {
     }
 
     /// Generates a new key pair (private and public keys).
-    pub fn gen_pair<R: Rng>(&self, rng: &mut R) -> (U256, U256) {
+    pub fn gen_pair<R: Rng + CryptoRng>(&self, rng: &mut R) -> (U256, U256) {
         let key = self.gen_key(rng);
         let public = self.get_public(&key);
         (key, public)
@@ -64,8 +64,9 @@ impl Schema {
     }
 
     /// Creates a digital signature for a given message using the private key.
-    pub fn build_signature<R: Rng>(&self, rng: &mut R, msg: &U256, 
-                                   key: &U256) -> Signature {
+    pub fn build_signature<R: Rng + CryptoRng>(&self, rng: &mut R, 
+                                               msg: &U256, 
+                                               key: &U256) -> Signature {
         let t = self.gen_key(rng);
         let rj = self.curve.power(t.bit_iter());
         let r = self.curve.convert_from(&rj);
@@ -1,64 +1,139 @@
 //! Provides functionality for generating and handling mnemonic phrases and 
 //! seeds, facilitating deterministic key generation for wallets.
 //!
-//! This module utilizes the BIP-39 standard to create 12-word English mnemonic 
-//! phrases and derive corresponding 128-bit seeds. These seeds can 
-//! deterministically generate sequences of cryptographic keys compatible with
-//! the Uqoin protocol.
+//! This module utilizes the BIP-39 standard to create English mnemonic 
+//! phrases of 12, 15, 18, 21 or 24 words and derive corresponding seeds of 
+//! 128 to 256 bits. These seeds can deterministically generate sequences of 
+//! cryptographic keys compatible with the Uqoin protocol. The 12-word seeds
+//! are used by default.
+//!
+//! Keys can also be derived by paths like `m/44'/0'/0'/1'` (see
+//! `Seed::derive`), so wallets can separate accounts and addresses, and the
+//! keys are compatible with other path-based wallets. The derivation follows
+//! SLIP-0010 for ed25519: the master node is taken from the BIP-39 seed of 
+//! the mnemonic (empty passphrase) and only hardened indices are supported.
+//!
+//! For watch-only wallets the node can be exported as `ExtendedPublicKey`
+//! (the public key and the chain code). It derives the public keys of the 
+//! non-hardened children, so a server can generate receive addresses while
+//! the seed stays offline. The child key is the parent one plus the tweak
+//! `HMAC-SHA512(chain code, public || index)` modulo the curve order, so the
+//! same keys are given by `ExtendedKey::derive_private`. Note: the private 
+//! key of the child together with the extended public key reveal the private
+//! key of the node, so the hardened nodes (e.g. accounts) should be exported.
+//!
+//! The seed can be split into shares for distributed backups (see 
+//! `seed::shamir`).
 //!
 //! Note: While this implementation follows BIP-39, it is not a formal part of 
 //! the Uqoin specification and should be considered a recommended approach.
 
-use rand::Rng;
+use rand::{Rng, CryptoRng};
+use serde::{Serialize, Deserialize};
 use rand::distr::{Distribution, StandardUniform};
 use bip39::{Mnemonic as Bip39Mnemonic, Language};
 use finitelib::group::Group;
+use bitcoin_hashes::{Hash, HashEngine, Hmac, HmacEngine, sha512};
 
+use crate::validate;
+use crate::error::{Error, ErrorKind};
 use crate::utils::*;
 use crate::schema::Schema;
 
+pub mod shamir;
+
+
+/// Represents an English mnemonic phrase used for seed generation, its length
+/// is one of `MNEMONIC_LENGTHS`.
+pub type Mnemonic = Vec<String>;
+
+
+/// Supported numbers of words in the mnemonic phrase.
+pub const MNEMONIC_LENGTHS: [usize; 5] = [12, 15, 18, 21, 24];
 
-/// Represents a 12-word English mnemonic phrase used for seed generation.
-pub type Mnemonic = [String; 12];
 
+/// Offset of the hardened indices in the derivation path.
+pub const HARDENED: u32 = 0x8000_0000;
 
-/// Encapsulates a 128-bit seed derived from a BIP-39 mnemonic phrase.
-/// Provides methods for seed creation, retrieval, and key generation.
+
+/// HMAC key of the master node in SLIP-0010 for ed25519.
+const SLIP10_ED25519_KEY: &[u8] = b"ed25519 seed";
+
+
+/// Encapsulates a seed (128 to 256 bits) derived from a BIP-39 mnemonic 
+/// phrase. Provides methods for seed creation, retrieval, and key generation.
 pub struct Seed(Bip39Mnemonic);
 
 
 impl Seed {
-    /// Generates a new random seed using the provided random number generator.
-    pub fn random<R: Rng>(rng: &mut R) -> Self {
+    /// Generates a new random 12-word seed using the provided random number 
+    /// generator.
+    pub fn random<R: Rng + CryptoRng>(rng: &mut R) -> Self {
         rng.random()
     }
 
-    /// Creates a seed from a given 256-bit value, utilizing only the first 128
-    /// bits.
+    /// Generates a new random seed with the given number of words.
+    pub fn random_with_words<R: Rng + CryptoRng>(
+        rng: &mut R, words: usize
+    ) -> UqoinResult<Self> {
+        let size = Self::entropy_size(words)?;
+        let entropy: [u8; 32] = rng.random();
+        Ok(Self::from_entropy(&entropy[..size]))
+    }
+
+    /// Creates a 12-word seed from a given 256-bit value, utilizing only the 
+    /// first 128 bits.
     pub fn from_value(value: &U256) -> Self {
-        let entropy: [u8; 16] = value.to_bytes()[..16].try_into().unwrap();
-        Self::from_entropy(&entropy)
+        Self::from_value_with_words(value, 12).unwrap()
     }
 
-    /// Constructs a seed from a provided 12-word mnemonic phrase.
-    pub fn from_mnemonic(mnemonic: &Mnemonic) -> Self {
-        let phrase = mnemonic.join(" ");
-        let bip93_mnemonic = Bip39Mnemonic::parse_normalized(&phrase).unwrap();
-        Self(bip93_mnemonic)
+    /// Creates a seed with the given number of words from a 256-bit value, 
+    /// utilizing only the first bits of the seed size (`words * 32 / 3`).
+    pub fn from_value_with_words(value: &U256, 
+                                 words: usize) -> UqoinResult<Self> {
+        let size = Self::entropy_size(words)?;
+        Ok(Self::from_entropy(&value.to_bytes()[..size]))
     }
 
-    /// Retrieves the 128-bit seed value as a `U256` type.
+    /// Constructs a seed from a provided mnemonic phrase. Errors:
+    /// * SeedInvalidLength: the number of words is not supported.
+    /// * SeedUnknownWord: the word is not in the English wordlist, the index
+    ///   of the word is in the context (the word itself is not, as it is 
+    ///   secret).
+    /// * SeedInvalidChecksum: the checksum of the phrase does not match.
+    pub fn from_mnemonic(mnemonic: &[String]) -> UqoinResult<Self> {
+        Self::entropy_size(mnemonic.len())?;
+        let words = mnemonic.iter().map(|word| word.trim().to_lowercase())
+                                   .collect::<Vec<String>>();
+        if let Some(index) = words.iter().position(
+            |word| Language::English.find_word(word).is_none()
+        ) {
+            return Err(Error::from(ErrorKind::SeedUnknownWord)
+                .with_index(index));
+        }
+        let bip93_mnemonic = Bip39Mnemonic::parse_in_normalized(
+            Language::English, &words.join(" ")
+        ).map_err(|_| Error::from(ErrorKind::SeedInvalidChecksum))?;
+        Ok(Self(bip93_mnemonic))
+    }
+
+    /// Retrieves the seed value as a `U256` type (the higher bits are zeros 
+    /// if the seed is shorter than 256 bits).
     pub fn value(&self) -> U256 {
-        // TODO: Maybe I need a different way to generate 256-bit of the seed.
-        let entropy: [u8; 16] = self.0.to_entropy().try_into().unwrap();
-        u128::from_ne_bytes(entropy).into()
+        let mut bytes = [0u8; 32];
+        let entropy = self.0.to_entropy();
+        bytes[..entropy.len()].copy_from_slice(&entropy);
+        U256::from_bytes(&bytes)
     }
 
-    /// Returns the 12-word mnemonic phrase associated with the seed.
+    /// Returns the mnemonic phrase associated with the seed.
     pub fn mnemonic(&self) -> Mnemonic {
-        // Take 12 words only
-        self.0.words().take(12).map(|w| w.to_string())
-            .collect::<Vec<String>>().try_into().unwrap()
+        self.0.words().map(|w| w.to_string()).collect()
+    }
+
+    /// Returns the number of words in the mnemonic phrase.
+    pub fn word_count(&self) -> usize {
+        self.0.word_count()
     }
 
     /// Generates an infinite, deterministic sequence of private keys from the
@@ -83,12 +158,48 @@ impl Seed {
         })
     }
 
-    fn from_entropy(entropy: &[u8; 16]) -> Self {
-        // 128-bit (16 bytes) entropy for exactly 12 words
+    /// Returns the fingerprint of the seed: 4 bytes of the hash of the first
+    /// public key given by `gen_keys` as a hex string. It identifies the seed
+    /// (e.g. in backups or logs) without revealing secret data.
+    pub fn fingerprint(&self, schema: &Schema) -> String {
+        let key = self.gen_keys(schema).next().unwrap();
+        let hash = hash_of_u256([schema.get_public(&key)].iter());
+        bytes_to_hex(&hash.to_bytes()[..4])
+    }
+
+    /// Get the master node of the path-based derivation.
+    pub fn master_key(&self) -> ExtendedKey {
+        ExtendedKey::master(&self.0.to_seed(""))
+    }
+
+    /// Derive the private key by the path like `m/44'/0'/0'/1'`. All indices
+    /// must be hardened.
+    pub fn derive(&self, path: &str, schema: &Schema) -> UqoinResult<U256> {
+        let path = DerivationPath::parse(path)?;
+        let node = self.master_key().derive_path(&path)?;
+        Ok(node.private_key(schema))
+    }
+
+    /// Export the node by the path for watch-only derivation.
+    pub fn export_public(&self, path: &str, 
+                         schema: &Schema) -> UqoinResult<ExtendedPublicKey> {
+        let path = DerivationPath::parse(path)?;
+        let node = self.master_key().derive_path(&path)?;
+        Ok(node.to_public(schema))
+    }
+
+    fn from_entropy(entropy: &[u8]) -> Self {
+        // 4 bytes of entropy for each 3 words
         let bip93_mnemonic = Bip39Mnemonic
             ::from_entropy_in(Language::English, entropy).unwrap();
         Self(bip93_mnemonic)
     }
+
+    fn entropy_size(words: usize) -> UqoinResult<usize> {
+        validate!(MNEMONIC_LENGTHS.contains(&words), SeedInvalidLength)
+            .map_err(|err| err.with_values("12, 15, 18, 21 or 24", words))?;
+        Ok(words / 3 * 4)
+    }
 }
 
 
@@ -100,6 +211,142 @@ impl Distribution<Seed> for StandardUniform {
 }
 
 
+/// Derivation path: indices of the nodes from the master one, the hardened
+/// ones are shifted by `HARDENED`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivationPath(Vec<u32>);
+
+
+impl DerivationPath {
+    /// Parse the path like `m/44'/0'/0'/1'` (`h` or `H` can be used instead 
+    /// of `'`).
+    pub fn parse(path: &str) -> UqoinResult<Self> {
+        let mut parts = path.split('/');
+        validate!(parts.next() == Some("m"), SeedInvalidPath)?;
+        let indices = parts.map(|part| {
+            let (number, hardened) = match part.strip_suffix(['\'', 'h', 'H']) {
+                Some(number) => (number, true),
+                None => (part, false),
+            };
+            let index = number.parse::<u32>().ok()
+                .filter(|index| *index < HARDENED)
+                .ok_or(ErrorKind::SeedInvalidPath)?;
+            Ok(if hardened { index + HARDENED } else { index })
+        }).collect::<UqoinResult<Vec<u32>>>()?;
+        Ok(Self(indices))
+    }
+
+    /// Get the indices.
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+
+/// Node of the derivation tree: the secret and the chain code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedKey {
+    pub secret: [u8; 32],
+    pub chain_code: [u8; 32],
+}
+
+
+impl ExtendedKey {
+    /// Create the master node from the seed bytes.
+    pub fn master(seed: &[u8]) -> Self {
+        Self::from_hmac(SLIP10_ED25519_KEY, &[seed])
+    }
+
+    /// Derive the child node by the index, it must be hardened.
+    pub fn derive_child(&self, index: u32) -> UqoinResult<Self> {
+        validate!(index >= HARDENED, SeedInvalidPath)
+            .map_err(|err| err.with_values(index + HARDENED, index))?;
+        Ok(Self::from_hmac(&self.chain_code, 
+                           &[&[0], &self.secret, &index.to_be_bytes()]))
+    }
+
+    /// Derive the node by the path from this one.
+    pub fn derive_path(&self, path: &DerivationPath) -> UqoinResult<Self> {
+        path.indices().iter()
+            .try_fold(self.clone(), |node, index| node.derive_child(*index))
+    }
+
+    /// Get the private key of the node.
+    pub fn private_key(&self, schema: &Schema) -> U256 {
+        &U256::from_bytes(&self.secret) % &schema.curve().base.order
+    }
+
+    /// Get the extended public key of the node.
+    pub fn to_public(&self, schema: &Schema) -> ExtendedPublicKey {
+        ExtendedPublicKey {
+            public: schema.get_public(&self.private_key(schema)),
+            chain_code: self.chain_code,
+        }
+    }
+
+    /// Derive the private key of the non-hardened child, it corresponds the
+    /// public key given by `ExtendedPublicKey::derive_public`.
+    pub fn derive_private(&self, index: u32, 
+                          schema: &Schema) -> UqoinResult<U256> {
+        let public = self.to_public(schema);
+        let tweak = public.calc_tweak(index, schema)?;
+        let order = &schema.curve().base.order;
+        Ok(&(&self.private_key(schema) + &tweak) % order)
+    }
+
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut engine = HmacEngine::<sha512::Hash>::new(key);
+        for chunk in data {
+            engine.input(chunk);
+        }
+        let bytes = Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+        Self {
+            secret: bytes[..32].try_into().unwrap(),
+            chain_code: bytes[32..].try_into().unwrap(),
+        }
+    }
+}
+
+
+/// Public part of the node for watch-only derivation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtendedPublicKey {
+    pub public: U256,
+    pub chain_code: [u8; 32],
+}
+
+
+impl ExtendedPublicKey {
+    /// Derive the public key of the non-hardened child.
+    pub fn derive_public(&self, index: u32, 
+                         schema: &Schema) -> UqoinResult<U256> {
+        let tweak = self.calc_tweak(index, schema)?;
+        let point = schema.point_from_number(&self.public)
+            .ok_or(ErrorKind::CryptoInvalidPoint)?;
+        let curve = schema.curve();
+        let child = curve.add(&curve.convert_into(&point), 
+                              &curve.power(tweak.bit_iter()));
+        Ok(schema.point_to_number(&curve.convert_from(&child)))
+    }
+
+    /// Derive the public keys of the children in the range of indices.
+    pub fn derive_public_range(&self, indices: std::ops::Range<u32>, 
+                               schema: &Schema) -> UqoinResult<Vec<U256>> {
+        indices.map(|index| self.derive_public(index, schema)).collect()
+    }
+
+    fn calc_tweak(&self, index: u32, schema: &Schema) -> UqoinResult<U256> {
+        validate!(index < HARDENED, SeedInvalidPath)
+            .map_err(|err| err.with_values(index - HARDENED, index))?;
+        let node = ExtendedKey::from_hmac(
+            &self.chain_code, 
+            &[&self.public.to_bytes(), &index.to_be_bytes()]
+        );
+        Ok(node.private_key(schema))
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -119,10 +366,167 @@ mod tests {
         assert_eq!(seed_from_value.gen_keys(&schema).nth(3),
                    seed.gen_keys(&schema).nth(3));
 
-        let seed_from_mnemonic = Seed::from_mnemonic(&mnemonic);
+        let seed_from_mnemonic = Seed::from_mnemonic(&mnemonic).unwrap();
         assert_eq!(seed_from_mnemonic.value(), value);
         assert_eq!(seed_from_mnemonic.mnemonic(), mnemonic);
         assert_eq!(seed_from_mnemonic.gen_keys(&schema).nth(3),
                    seed.gen_keys(&schema).nth(3));
     }
+
+    #[test]
+    fn test_fingerprint() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+
+        let seed = Seed::from_value(&U256::from(1));
+        let fingerprint = seed.fingerprint(&schema);
+        assert_eq!(fingerprint.len(), 8);
+        assert_eq!(Seed::from_mnemonic(&seed.mnemonic()).unwrap()
+                       .fingerprint(&schema), fingerprint);
+        assert_ne!(Seed::random(&mut rng).fingerprint(&schema), fingerprint);
+    }
+
+    #[test]
+    fn test_watch_only() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let seed: Seed = rng.random();
+
+        let account = seed.master_key().derive_path(
+            &DerivationPath::parse("m/44'/0'/0'").unwrap()
+        ).unwrap();
+        let exported = seed.export_public("m/44'/0'/0'", &schema).unwrap();
+        assert_eq!(exported, account.to_public(&schema));
+
+        // Public keys correspond the private ones
+        let publics = exported.derive_public_range(3..6, &schema).unwrap();
+        assert_eq!(publics.len(), 3);
+        for (index, public) in (3..6).zip(publics.iter()) {
+            let key = account.derive_private(index, &schema).unwrap();
+            assert!(schema.check_pair(&key, public));
+        }
+        assert_ne!(publics[0], publics[1]);
+
+        // Hardened indices cannot be derived from the public data
+        assert_eq!(exported.derive_public(HARDENED, &schema).unwrap_err()
+                           .kind(), ErrorKind::SeedInvalidPath);
+        assert!(account.derive_private(HARDENED + 1, &schema).is_err());
+
+        // Exported data is serializable
+        let json = serde_json::to_string(&exported).unwrap();
+        let restored: ExtendedPublicKey = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.derive_public(4, &schema).unwrap(), publics[1]);
+    }
+
+    #[test]
+    fn test_seed_words() {
+        let mut rng = rand::rng();
+
+        // The value of a 12-word seed is 128-bit
+        let seed = Seed::from_value(&U256::from_hex(
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"
+        ));
+        assert_eq!(seed.word_count(), 12);
+        assert_eq!(seed.value(), U256::from(u128::MAX));
+
+        for words in MNEMONIC_LENGTHS {
+            let seed = Seed::random_with_words(&mut rng, words).unwrap();
+            let mnemonic = seed.mnemonic();
+            assert_eq!(mnemonic.len(), words);
+            assert_eq!(seed.word_count(), words);
+
+            let seed_from_value = Seed::from_value_with_words(&seed.value(), 
+                                                              words).unwrap();
+            assert_eq!(seed_from_value.mnemonic(), mnemonic);
+
+            let seed_from_mnemonic = Seed::from_mnemonic(&mnemonic).unwrap();
+            assert_eq!(seed_from_mnemonic.value(), seed.value());
+        }
+
+        for words in [0, 11, 13, 27] {
+            assert_eq!(Seed::random_with_words(&mut rng, words).err().unwrap()
+                           .kind(), ErrorKind::SeedInvalidLength);
+        }
+    }
+
+    #[test]
+    fn test_mnemonic_errors() {
+        let mut rng = rand::rng();
+        let mnemonic = Seed::random(&mut rng).mnemonic();
+
+        // Case and spaces are normalized
+        let mut words = mnemonic.clone();
+        words[0] = format!(" {} ", words[0].to_uppercase());
+        assert_eq!(Seed::from_mnemonic(&words).unwrap().mnemonic(), mnemonic);
+
+        // Wrong length
+        let err = Seed::from_mnemonic(&mnemonic[..11]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::SeedInvalidLength);
+        assert_eq!(err.context().unwrap().actual, Some("11".to_string()));
+
+        // Unknown word
+        let mut words = mnemonic.clone();
+        words[5] = "uqoin".to_string();
+        let err = Seed::from_mnemonic(&words).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::SeedUnknownWord);
+        assert_eq!(err.context().unwrap().index, Some(5));
+
+        // Bad checksum (one of 16 last words passes it by chance)
+        let mut words = mnemonic.clone();
+        let last = words[11].clone();
+        let err = (0..16).map(|i| Language::English.word_list()[i])
+            .filter(|word| *word != last)
+            .map(|word| {
+                words[11] = word.to_string();
+                Seed::from_mnemonic(&words)
+            })
+            .find_map(|res| res.err()).unwrap();
+        assert_eq!(err.kind(), ErrorKind::SeedInvalidChecksum);
+    }
+
+    #[test]
+    fn test_derive() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+
+        // SLIP-0010 test vector 1 for ed25519
+        let hex = |s: &str| (0..s.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect::<Vec<u8>>();
+        let master = ExtendedKey::master(
+            &hex("000102030405060708090a0b0c0d0e0f")
+        );
+        assert_eq!(master.chain_code.to_vec(), hex(
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"
+        ));
+        assert_eq!(master.secret.to_vec(), hex(
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        ));
+        let node = master.derive_path(&DerivationPath::parse("m/0'/1'")
+                                                      .unwrap()).unwrap();
+        assert_eq!(node.chain_code.to_vec(), hex(
+            "a320425f77d1b5c2505a6b1b27382b37368ee640e3557c315416801243552f14"
+        ));
+        assert_eq!(node.secret.to_vec(), hex(
+            "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2"
+        ));
+
+        // Paths
+        assert_eq!(DerivationPath::parse("m/44'/1h/0").unwrap().indices(),
+                   &[44 + HARDENED, 1 + HARDENED, 0]);
+        assert!(DerivationPath::parse("m").unwrap().indices().is_empty());
+        for path in ["", "44'/0'", "m/", "m/x'", "m/2147483648'", "m//1'"] {
+            assert!(DerivationPath::parse(path).is_err());
+        }
+
+        // Derived keys are deterministic and distinct
+        let seed: Seed = rng.random();
+        let key = seed.derive("m/44'/0'/0'/1'", &schema).unwrap();
+        assert_eq!(Seed::from_mnemonic(&seed.mnemonic()).unwrap()
+                       .derive("m/44'/0'/0'/1'", &schema).unwrap(), key);
+        assert_ne!(seed.derive("m/44'/0'/0'/2'", &schema).unwrap(), key);
+        assert_ne!(seed.derive("m/44'/0'/1'/1'", &schema).unwrap(), key);
+        assert_eq!(seed.derive("m/44'/0'/0'/1", &schema).unwrap_err().kind(),
+                   ErrorKind::SeedInvalidPath);
+    }
 }
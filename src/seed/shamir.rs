@@ -0,0 +1,226 @@
+//! Shamir secret sharing of the seed.
+//!
+//! The seed value is split into `count` shares so that any `threshold` of
+//! them restore it, and fewer shares give no information about the seed.
+//! Each byte of the seed is shared independently over GF(256) (the AES
+//! field). Every share is encoded as its own mnemonic phrase: a header word
+//! (the index of the share and the threshold) followed by the BIP-39 phrase
+//! of the share bytes, so it is one word longer than the seed phrase. Up to
+//! `SHARES_MAX` shares are supported.
+//!
+//! Note: the shares do not carry a digest of the seed, so mixing shares of
+//! different splits restores a wrong seed without an error.
+
+use rand::{Rng, CryptoRng};
+use bip39::Language;
+
+use crate::validate;
+use crate::utils::*;
+use crate::error::{Error, ErrorKind};
+use super::{Seed, Mnemonic};
+
+
+/// Maximum number of shares.
+pub const SHARES_MAX: usize = 16;
+
+
+/// Share of the seed.
+pub struct Share {
+    index: u8,
+    threshold: u8,
+    seed: Seed,
+}
+
+
+impl Share {
+    /// Index of the share (from 1 to `SHARES_MAX`).
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    /// Number of shares required to restore the seed.
+    pub fn threshold(&self) -> usize {
+        self.threshold as usize
+    }
+
+    /// Returns the mnemonic phrase of the share: the header word and the
+    /// phrase of the share bytes.
+    pub fn mnemonic(&self) -> Mnemonic {
+        let header = ((self.threshold - 1) << 4) | (self.index - 1);
+        let word = Language::English.word_list()[header as usize];
+        std::iter::once(word.to_string()).chain(self.seed.mnemonic())
+            .collect()
+    }
+
+    /// Constructs the share from the mnemonic phrase. The errors are the
+    /// ones of `Seed::from_mnemonic` (the word indices include the header)
+    /// and `SeedInvalidShares` if the header word is not a valid one.
+    pub fn from_mnemonic(mnemonic: &[String]) -> UqoinResult<Self> {
+        let (header, words) = mnemonic.split_first()
+            .ok_or(Error::from(ErrorKind::SeedInvalidLength)
+                .with_values("13, 16, 19, 22 or 25", 0))?;
+        let header = Language::English.find_word(&header.trim()
+                                                        .to_lowercase())
+            .ok_or(Error::from(ErrorKind::SeedUnknownWord).with_index(0))?;
+        validate!(header < 256, SeedInvalidShares)?;
+        let seed = Seed::from_mnemonic(words).map_err(|err| {
+            let err = err.with_offset(1);
+            if err.kind() == ErrorKind::SeedInvalidLength {
+                let actual = mnemonic.len();
+                err.with_values("13, 16, 19, 22 or 25", actual)
+            } else {
+                err
+            }
+        })?;
+        Ok(Self {
+            index: (header & 15) as u8 + 1,
+            threshold: (header >> 4) as u8 + 1,
+            seed,
+        })
+    }
+}
+
+
+/// Split the seed into `count` shares, `threshold` of them are required to
+/// restore it.
+pub fn split<R: Rng + CryptoRng>(rng: &mut R, seed: &Seed,
+                                 threshold: usize,
+                                 count: usize) -> UqoinResult<Vec<Share>> {
+    validate!((1..=count).contains(&threshold) && count <= SHARES_MAX,
+              SeedInvalidShares)
+        .map_err(|err| err.with_values(format!("1..={}", SHARES_MAX),
+                                       format!("{} of {}", threshold,
+                                               count)))?;
+
+    // Random polynomial for each byte, the free term is the byte
+    let secret = seed.0.to_entropy();
+    let coefs = secret.iter().map(|byte| {
+        std::iter::once(*byte).chain((1..threshold).map(|_| rng.random()))
+            .collect::<Vec<u8>>()
+    }).collect::<Vec<Vec<u8>>>();
+
+    // Evaluate the polynomials in the points 1..=count
+    Ok((1..=count as u8).map(|index| {
+        let bytes = coefs.iter()
+            .map(|coefs| coefs.iter().rev().fold(
+                0, |acc, coef| gf_mul(acc, index) ^ coef
+            ))
+            .collect::<Vec<u8>>();
+        Share {
+            index,
+            threshold: threshold as u8,
+            seed: Seed::from_entropy(&bytes),
+        }
+    }).collect())
+}
+
+
+/// Restore the seed from the shares. There must be at least `threshold`
+/// shares with distinct indices and the same threshold and length,
+/// otherwise `SeedInvalidShares` is returned.
+pub fn combine(shares: &[Share]) -> UqoinResult<Seed> {
+    let threshold = shares.first().map(Share::threshold).unwrap_or(1);
+    validate!(shares.len() >= threshold, SeedInvalidShares)
+        .map_err(|err| err.with_values(threshold, shares.len()))?;
+    let shares = &shares[..threshold];
+    let size = shares[0].seed.word_count();
+    for (ix, share) in shares.iter().enumerate() {
+        validate!(share.threshold() == threshold &&
+                  share.seed.word_count() == size &&
+                  shares[..ix].iter().all(|s| s.index != share.index),
+                  SeedInvalidShares)
+            .map_err(|err| err.with_index(ix))?;
+    }
+
+    // Lagrange interpolation in zero
+    let ys = shares.iter().map(|share| share.seed.0.to_entropy())
+                          .collect::<Vec<Vec<u8>>>();
+    let mut secret = vec![0u8; ys[0].len()];
+    for (j, share) in shares.iter().enumerate() {
+        let basis = shares.iter().filter(|s| s.index != share.index)
+            .fold(1, |acc, s| gf_mul(acc, gf_div(s.index,
+                                                 s.index ^ share.index)));
+        for (byte, y) in secret.iter_mut().zip(ys[j].iter()) {
+            *byte ^= gf_mul(*y, basis);
+        }
+    }
+
+    Ok(Seed::from_entropy(&secret))
+}
+
+
+/// Multiplication in GF(256) modulo x^8 + x^4 + x^3 + x + 1.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut res = 0;
+    while b > 0 {
+        if b & 1 == 1 {
+            res ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 > 0 { 0x1B } else { 0 };
+        b >>= 1;
+    }
+    res
+}
+
+
+/// Division in GF(256), the inverse is `b^254`.
+fn gf_div(a: u8, b: u8) -> u8 {
+    let inv = (0..254).fold(1, |acc, _| gf_mul(acc, b));
+    gf_mul(a, inv)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed::MNEMONIC_LENGTHS;
+
+    #[test]
+    fn test_shamir() {
+        let mut rng = rand::rng();
+
+        for words in MNEMONIC_LENGTHS {
+            let seed = Seed::random_with_words(&mut rng, words).unwrap();
+            let shares = split(&mut rng, &seed, 3, 5).unwrap();
+            assert_eq!(shares.len(), 5);
+
+            // Any 3 shares restore the seed
+            for ixs in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+                let subset = ixs.iter().map(|ix| Share::from_mnemonic(
+                    &shares[*ix].mnemonic()
+                ).unwrap()).collect::<Vec<Share>>();
+                assert_eq!(subset[0].index(), ixs[0] + 1);
+                assert_eq!(subset[0].threshold(), 3);
+                assert_eq!(subset[0].mnemonic().len(), words + 1);
+                assert_eq!(combine(&subset).unwrap().value(), seed.value());
+            }
+
+            // 2 shares are not enough
+            assert_eq!(combine(&shares[..2]).err().unwrap().kind(),
+                       ErrorKind::SeedInvalidShares);
+        }
+
+        // Threshold 1 makes copies
+        let seed = Seed::random(&mut rng);
+        let shares = split(&mut rng, &seed, 1, 2).unwrap();
+        assert_eq!(shares[1].seed.value(), seed.value());
+
+        // Invalid parameters
+        assert!(split(&mut rng, &seed, 0, 2).is_err());
+        assert!(split(&mut rng, &seed, 3, 2).is_err());
+        assert!(split(&mut rng, &seed, 2, SHARES_MAX + 1).is_err());
+
+        // Duplicated shares
+        let shares = split(&mut rng, &seed, 2, 3).unwrap();
+        let twice = (0..2).map(|_| Share::from_mnemonic(&shares[0].mnemonic())
+                                         .unwrap()).collect::<Vec<Share>>();
+        assert_eq!(combine(&twice).err().unwrap().context().unwrap().index,
+                   Some(1));
+
+        // Invalid header word
+        let mut mnemonic = shares[0].mnemonic();
+        mnemonic[0] = Language::English.word_list()[256].to_string();
+        assert_eq!(Share::from_mnemonic(&mnemonic).err().unwrap().kind(),
+                   ErrorKind::SeedInvalidShares);
+    }
+}
@@ -0,0 +1,345 @@
+//! Periodic snapshots of the state.
+//!
+//! Replaying the whole blockchain from genesis on each restart or deep reorg
+//! is slow. `StateSnapshotStore` keeps the state dumps of every `interval`-th
+//! block in a directory (only the last `keep` of them), so the state of any
+//! block can be restored from the nearest snapshot below it replaying the
+//! remaining blocks only (see `State::restore_to`). The dumps are in the
+//! binary format (see `State::to_binary`).
+//!
+//! A new node does not have to replay the blockchain at all: a full node
+//! produces `StateSnapshot` (the state of its last block, its commitment and
+//! the blocks without transactions) with `StateSnapshot::create`, and the new
+//! node checks it and starts from it with `Node::sync_from_snapshot`. The
+//! snapshot is trusted if its last block is a checkpoint of the validator or
+//! it is signed by a known publisher (see `StateSnapshot::verify`).
+
+use tokio::io::{Result as TokioResult, ErrorKind};
+use rand::{Rng, CryptoRng};
+use lbasedb::path_concat;
+
+use crate::validate;
+use crate::utils::*;
+use crate::codec::{Codec, Writer, Reader};
+use crate::schema::Schema;
+use crate::block::{Block, BlockInfo};
+use crate::spec::ChainSpec;
+use crate::chain::ChainValidator;
+use crate::blockchain::Blockchain;
+use crate::state::State;
+
+
+/// Default number of blocks between snapshots.
+pub const SNAPSHOT_INTERVAL: u64 = 10000;
+
+/// Default number of the kept snapshots.
+pub const SNAPSHOT_KEEP: usize = 4;
+
+/// Tag of the signed message of the snapshot.
+pub const TAG_SNAPSHOT_MSG: &str = "uqoin/snapshot/msg";
+
+
+/// Directory of the state snapshots.
+#[derive(Debug, Clone)]
+pub struct StateSnapshotStore {
+    path: String,
+    interval: u64,
+    keep: usize,
+}
+
+
+impl StateSnapshotStore {
+    /// Open the store in the directory (it is created if missing).
+    pub async fn new(path: &str) -> TokioResult<Self> {
+        tokio::fs::create_dir_all(path).await?;
+        Ok(Self {
+            path: path.to_string(),
+            interval: SNAPSHOT_INTERVAL,
+            keep: SNAPSHOT_KEEP,
+        })
+    }
+
+    /// Set the number of blocks between snapshots.
+    pub fn with_interval(mut self, interval: u64) -> Self {
+        assert!(interval > 0);
+        self.interval = interval;
+        self
+    }
+
+    /// Set the number of the kept snapshots.
+    pub fn with_keep(mut self, keep: usize) -> Self {
+        assert!(keep > 0);
+        self.keep = keep;
+        self
+    }
+
+    /// Check whether the state of the block must be snapshotted.
+    pub fn is_due(&self, bix: u64) -> bool {
+        (bix > 0) && bix.is_multiple_of(self.interval)
+    }
+
+    /// Save the snapshot if it is due for the last block of the state (call
+    /// it after each roll up). Returns `true` if the snapshot was saved.
+    pub async fn save_if_due(&self, state: &State) -> TokioResult<bool> {
+        if self.is_due(state.get_last_block_info().bix) {
+            self.save(state).await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Save the snapshot of the state and remove the oldest ones over
+    /// `keep`.
+    pub async fn save(&self, state: &State) -> TokioResult<()> {
+        // Dump to a temporary file and rename, so a snapshot is never partial
+        let path = self.get_snapshot_path(state.get_last_block_info().bix);
+        let path_tmp = format!("{}.tmp", path);
+        state.dump_binary(&path_tmp).await?;
+        tokio::fs::rename(&path_tmp, &path).await?;
+
+        // Remove the oldest snapshots
+        let bixes = self.list().await?;
+        if bixes.len() > self.keep {
+            for bix in bixes[.. bixes.len() - self.keep].iter() {
+                tokio::fs::remove_file(self.get_snapshot_path(*bix)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Block numbers of the stored snapshots in ascending order.
+    pub async fn list(&self) -> TokioResult<Vec<u64>> {
+        let mut bixes = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(bix) = Self::parse_snapshot_name(&name) {
+                bixes.push(bix);
+            }
+        }
+        bixes.sort();
+        Ok(bixes)
+    }
+
+    /// Get the block number of the nearest snapshot not above `bix`.
+    pub async fn get_nearest(&self, bix: u64) -> TokioResult<Option<u64>> {
+        let bixes = self.list().await?;
+        Ok(bixes.into_iter().rev().find(|b| *b <= bix))
+    }
+
+    /// Load the snapshot of the block.
+    pub async fn load(&self, bix: u64) -> TokioResult<State> {
+        let path = self.get_snapshot_path(bix);
+        if tokio::fs::try_exists(&path).await? {
+            State::load_binary(&path).await
+        } else {
+            Err(ErrorKind::NotFound.into())
+        }
+    }
+
+    /// Remove the snapshots above `bix` (e.g. after a reorg).
+    pub async fn truncate(&self, bix: u64) -> TokioResult<()> {
+        for snapshot_bix in self.list().await? {
+            if snapshot_bix > bix {
+                let path = self.get_snapshot_path(snapshot_bix);
+                tokio::fs::remove_file(path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_snapshot_path(&self, bix: u64) -> String {
+        path_concat!(self.path.as_str(), format!("state-{:020}.bin", bix))
+    }
+
+    fn parse_snapshot_name(name: &str) -> Option<u64> {
+        name.strip_prefix("state-")?.strip_suffix(".bin")?.parse().ok()
+    }
+}
+
+
+/// Verifiable snapshot to join the network without replaying the blocks.
+#[derive(Debug, Clone)]
+pub struct StateSnapshot {
+    /// Last block of the snapshot.
+    pub info: BlockInfo,
+
+    /// Commitment to the coins of the state (see `State::get_commitment`).
+    pub commitment: U256,
+
+    /// Signature of the publisher (see `StateSnapshot::sign`).
+    pub signature: Option<Signature>,
+
+    /// Blocks from the first one to the last one without transactions.
+    pub blocks: Vec<Block>,
+
+    /// State of the last block.
+    pub state: State,
+}
+
+
+impl StateSnapshot {
+    /// Make the snapshot of the state that corresponds to the last block of
+    /// the blockchain (`InvalidInput` otherwise).
+    pub async fn create(blockchain: &Blockchain,
+                        state: &State) -> TokioResult<Self> {
+        let info = state.get_last_block_info().clone();
+        if blockchain.get_block_count().await? != info.bix {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        let blocks = blockchain.get_block_many(0, info.bix as usize).await?;
+        if blocks.last().is_some_and(|block| block.hash != info.hash) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        Ok(Self {
+            info,
+            commitment: state.calc_commitment(),
+            signature: None,
+            blocks,
+            state: state.clone(),
+        })
+    }
+
+    /// Message signed by the publisher.
+    pub fn get_msg(&self) -> U256 {
+        let elems = [
+            self.info.hash.clone(),
+            U256::from(self.info.bix),
+            U256::from(self.info.offset),
+            self.commitment.clone(),
+        ];
+        hash_tagged(TAG_SNAPSHOT_MSG, elems.iter())
+    }
+
+    /// Sign the snapshot with the key of the publisher.
+    pub fn sign<R: Rng + CryptoRng>(&mut self, rng: &mut R, key: &U256,
+                                    schema: &Schema) {
+        self.signature = Some(schema.build_signature(rng, &self.get_msg(),
+                                                     key));
+    }
+
+    /// Get the publisher that signed the snapshot (`None` if it is not
+    /// signed or the signature is malformed).
+    pub fn get_publisher(&self, schema: &Schema) -> Option<U256> {
+        let signature = self.signature.as_ref()?;
+        schema.point_from_number(&signature.0)?;
+        Some(schema.extract_public(&self.get_msg(), signature))
+    }
+
+    /// Check the snapshot for the chain:
+    /// 1. The state corresponds to the last block and the commitment
+    ///    (`StateInconsistent`).
+    /// 2. The blocks are linked from the genesis to the last block
+    ///    (`BlockPreviousHashMismatch`, `BlockOffsetMismatch`).
+    /// 3. The last block is a checkpoint of the validator
+    ///    (`BlockCheckpointMismatch` if its hash differs) or the snapshot is
+    ///    signed by one of the publishers (`StateSnapshotUntrusted`).
+    pub fn verify(&self, validator: &ChainValidator, publishers: &[U256],
+                  spec: &ChainSpec, schema: &Schema) -> UqoinResult<()> {
+        // Check the state
+        validate!(self.state.get_last_block_info() == &self.info,
+                  StateInconsistent)
+            .map_err(|err| err.with_bix(self.info.bix))?;
+        validate!(self.state.calc_commitment() == self.commitment,
+                  StateInconsistent)
+            .map_err(|err| err.with_bix(self.info.bix))?;
+
+        // Check the links
+        let mut info = spec.get_genesis_info();
+        for block in self.blocks.iter() {
+            let bix = info.bix + 1;
+            validate!(block.hash_prev == info.hash, BlockPreviousHashMismatch)
+                .map_err(|err| err.with_bix(bix))?;
+            validate!(block.offset == info.offset, BlockOffsetMismatch)
+                .map_err(|err| err.with_bix(bix)
+                                  .with_values(info.offset, block.offset))?;
+            info = BlockInfo::from_block(bix, block);
+        }
+        validate!(info == self.info, BlockPreviousHashMismatch)
+            .map_err(|err| err.with_bix(self.info.bix))?;
+
+        // Check the trust
+        if let Some(hash) = validator.get_checkpoint(self.info.bix) {
+            validate!(hash == &self.info.hash, BlockCheckpointMismatch)
+                .map_err(|err| err.with_bix(self.info.bix))
+        } else {
+            let publisher = self.get_publisher(schema);
+            validate!(publisher.is_some_and(|pk| publishers.contains(&pk)),
+                      StateSnapshotUntrusted)
+                .map_err(|err| err.with_bix(self.info.bix))
+        }
+    }
+}
+
+
+/// Encoding of the snapshot: the last block info, the commitment, the
+/// optional signature (a flag and 64 bytes), the blocks (prefixed with their
+/// number as `u32`) and the state.
+impl Codec for StateSnapshot {
+    fn encode(&self, writer: &mut Writer) {
+        self.info.encode(writer);
+        writer.write_u256(&self.commitment);
+        writer.write_flag(self.signature.is_some());
+        if let Some((sign_r, sign_s)) = self.signature.as_ref() {
+            writer.write_u256(sign_r);
+            writer.write_u256(sign_s);
+        }
+        writer.write_seq(&self.blocks);
+        self.state.encode(writer);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        let info = BlockInfo::decode(reader)?;
+        let commitment = reader.read_u256()?;
+        let signature = if reader.read_flag()? {
+            Some((reader.read_u256()?, reader.read_u256()?))
+        } else {
+            None
+        };
+        let blocks = reader.read_seq()?;
+        let state = State::decode(reader)?;
+        Ok(Self { info, commitment, signature, blocks, state })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockInfo;
+    use crate::state::CoinInfoMap;
+    use crate::testing::TempDir;
+
+    fn state_at(bix: u64) -> State {
+        let info = BlockInfo {
+            bix, offset: 0, hash: U256::from(bix), timestamp: 0, complexity: 0,
+        };
+        State::from_coin_infos(CoinInfoMap::new(), info)
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_store() {
+        let tmp = TempDir::new("uqoin-snapshot");
+        let path = path_concat!(tmp.path(), "snapshots");
+
+        let store = StateSnapshotStore::new(&path).await.unwrap()
+            .with_interval(10).with_keep(2);
+
+        assert!(!store.save_if_due(&state_at(5)).await.unwrap());
+        for bix in [10, 20, 30] {
+            assert!(store.save_if_due(&state_at(bix)).await.unwrap());
+        }
+        assert_eq!(store.list().await.unwrap(), vec![20, 30]);
+
+        assert_eq!(store.get_nearest(29).await.unwrap(), Some(20));
+        assert_eq!(store.get_nearest(15).await.unwrap(), None);
+        assert_eq!(store.load(30).await.unwrap().get_last_block_info().hash,
+                   U256::from(30));
+        assert!(store.load(10).await.is_err());
+
+        store.truncate(25).await.unwrap();
+        assert_eq!(store.list().await.unwrap(), vec![20]);
+    }
+}
@@ -0,0 +1,359 @@
+//! Chain identity and protocol parameters.
+//!
+//! `ChainSpec` describes the network the node runs on: the chain id, the hash
+//! of the genesis (zero) block and the consensus parameters
+//! (`ProtocolParams`: the initial complexity, the minimum order of the newly
+//! mined coins, the retargeting and the block limits). The mainnet is the
+//! default one (it corresponds `GENESIS_HASH`, `COMPLEXITY` and the other
+//! constants), testnets and private networks can be run from the same binary
+//! with their own specs loaded from the config (see `ChainSpec::from_json`,
+//! the parameters are flattened into the spec and the missing ones are the
+//! mainnet ones, other serde formats such as TOML work the same way):
+//! - `BlockInfo::genesis_of` and `BlockData::genesis_of` give the genesis
+//!   block of the spec.
+//! - `State::with_chain_spec` starts the state from the genesis and rejects
+//!   the new coins cheaper than the minimum order (`CoinTooCheap`), and it
+//!   recovers the senders of the transactions of version 5 for the chain id.
+//! - `ChainSpec::replay_protection_bix` is the first block whose
+//!   transactions must commit to the chain id (version 5, see `Transaction`),
+//!   so the transactions cannot be replayed from other chains.
+//! - `ChainSpec::get_retarget` gives the retargeting parameters starting from
+//!   the initial complexity (e.g. for `ChainValidator::new`).
+//! - The block limits are checked by the block validation and respected by
+//!   `Pool::prepare` and `BlockTemplate` through the state (see
+//!   `State::get_params`).
+//! - `Blockchain::with_chain_spec` checks that the stored chain starts from
+//!   the genesis of the spec.
+//!
+//! The parameters are checked on load (see `ProtocolParams::validate`), so a
+//! broken config cannot stall the chain (e.g. with zero complexity or empty
+//! blocks). The group structure (a split into three coins, a merge of three 
+//! coins) is defined by the coin values, so it is not a parameter.
+
+use serde::{Serialize, Deserialize};
+
+use crate::validate;
+use crate::utils::*;
+use crate::error::{Error, ErrorKind};
+use crate::codec::{Codec, Writer, Reader};
+use crate::block::{BlockInfo, GENESIS_HASH, COMPLEXITY, BLOCK_MAX_SIZE,
+                   BLOCK_MAX_BYTES};
+use crate::difficulty::{Retarget, TARGET_INTERVAL, RETARGET_WINDOW};
+
+
+/// Chain id of the mainnet.
+pub const CHAIN_ID_MAINNET: u64 = 1;
+
+
+/// Consensus parameters of the chain, the defaults are the mainnet ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProtocolParams {
+    /// Complexity of the first blocks.
+    pub complexity: usize,
+
+    /// Minimum order of the newly mined coins.
+    pub min_coin_order: u64,
+
+    /// Target interval between blocks in seconds.
+    pub target_interval: u64,
+
+    /// Number of blocks between retargets.
+    pub retarget_window: usize,
+
+    /// Maximum number of transactions in a block.
+    pub block_max_size: usize,
+
+    /// Maximum encoded size of the transactions of a block in bytes.
+    pub block_max_bytes: usize,
+}
+
+
+impl Default for ProtocolParams {
+    fn default() -> Self {
+        Self {
+            complexity: COMPLEXITY,
+            min_coin_order: 0,
+            target_interval: TARGET_INTERVAL,
+            retarget_window: RETARGET_WINDOW,
+            block_max_size: BLOCK_MAX_SIZE,
+            block_max_bytes: BLOCK_MAX_BYTES,
+        }
+    }
+}
+
+
+impl ProtocolParams {
+    /// Check if `size` transactions of `bytes` encoded size fit the block 
+    /// limits.
+    pub fn is_size_allowed(&self, size: usize, bytes: usize) -> bool {
+        (size <= self.block_max_size) && (bytes <= self.block_max_bytes)
+    }
+
+    /// Validate the parameters (`EncodingInvalid` otherwise): the complexity
+    /// is within the retargeting bounds, the target interval, the retarget 
+    /// window and the block limits are positive.
+    pub fn validate(&self) -> UqoinResult<()> {
+        let retarget = Retarget::default();
+        validate!((retarget.min_complexity..=retarget.max_complexity)
+                      .contains(&self.complexity), EncodingInvalid)
+            .map_err(|err| err.with_values(
+                format!("{}..={}", retarget.min_complexity, 
+                        retarget.max_complexity), 
+                self.complexity
+            ))?;
+        validate!(self.target_interval > 0, EncodingInvalid)
+            .map_err(|err| err.with_values("> 0", self.target_interval))?;
+        validate!(self.retarget_window > 0, EncodingInvalid)
+            .map_err(|err| err.with_values("> 0", self.retarget_window))?;
+        validate!(self.block_max_size > 0, EncodingInvalid)
+            .map_err(|err| err.with_values("> 0", self.block_max_size))?;
+        validate!(self.block_max_bytes > 0, EncodingInvalid)
+            .map_err(|err| err.with_values("> 0", self.block_max_bytes))
+    }
+}
+
+
+/// Canonical encoding of the parameters: complexity, minimum coin order, 
+/// target interval, retarget window, block maximum size and block maximum 
+/// bytes (8 bytes each).
+impl Codec for ProtocolParams {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_u64(self.complexity as u64);
+        writer.write_u64(self.min_coin_order);
+        writer.write_u64(self.target_interval);
+        writer.write_u64(self.retarget_window as u64);
+        writer.write_u64(self.block_max_size as u64);
+        writer.write_u64(self.block_max_bytes as u64);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        let params = Self {
+            complexity: reader.read_u64()? as usize,
+            min_coin_order: reader.read_u64()?,
+            target_interval: reader.read_u64()?,
+            retarget_window: reader.read_u64()? as usize,
+            block_max_size: reader.read_u64()? as usize,
+            block_max_bytes: reader.read_u64()? as usize,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+
+/// Parameters of the chain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainSpec {
+    /// Network identifier.
+    pub chain_id: u64,
+
+    /// Hash of the genesis block.
+    pub genesis_hash: U256,
+
+    /// Consensus parameters.
+    #[serde(flatten)]
+    pub params: ProtocolParams,
+
+    /// First block whose transactions must commit to the chain id (not
+    /// required if not set).
+    #[serde(default)]
+    pub replay_protection_bix: Option<u64>,
+}
+
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+
+impl ChainSpec {
+    /// Create a spec with the chain id and the genesis hash, other parameters
+    /// are the mainnet ones.
+    pub fn new(chain_id: u64, genesis_hash: U256) -> Self {
+        Self {
+            chain_id,
+            genesis_hash,
+            params: ProtocolParams::default(),
+            replay_protection_bix: None,
+        }
+    }
+
+    /// Spec of the mainnet.
+    pub fn mainnet() -> Self {
+        Self::new(CHAIN_ID_MAINNET, U256::from_hex(GENESIS_HASH))
+    }
+
+    /// Load the spec from JSON (`EncodingInvalid` on error or invalid 
+    /// parameters).
+    pub fn from_json(json: &str) -> UqoinResult<Self> {
+        let spec: Self = serde_json::from_str(json)
+            .map_err(|_| Error::from(ErrorKind::EncodingInvalid))?;
+        spec.params.validate()?;
+        Ok(spec)
+    }
+
+    /// Dump the spec to JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Set the consensus parameters (`EncodingInvalid` if they are invalid,
+    /// see `ProtocolParams::validate`).
+    pub fn with_params(mut self, 
+                       params: ProtocolParams) -> UqoinResult<Self> {
+        params.validate()?;
+        self.params = params;
+        Ok(self)
+    }
+
+    /// Set the initial complexity.
+    pub fn with_complexity(mut self, complexity: usize) -> Self {
+        self.params.complexity = complexity;
+        self
+    }
+
+    /// Set the minimum order of the newly mined coins.
+    pub fn with_min_coin_order(mut self, min_coin_order: u64) -> Self {
+        self.params.min_coin_order = min_coin_order;
+        self
+    }
+
+    /// Require the transactions to commit to the chain id from the block
+    /// `bix` on.
+    pub fn with_replay_protection(mut self, bix: u64) -> Self {
+        self.replay_protection_bix = Some(bix);
+        self
+    }
+
+    /// Check if it is the mainnet spec.
+    pub fn is_mainnet(&self) -> bool {
+        self == &Self::mainnet()
+    }
+
+    /// Get information of the genesis block.
+    pub fn get_genesis_info(&self) -> BlockInfo {
+        BlockInfo::genesis_of(self)
+    }
+
+    /// Get the retargeting parameters of the spec (the complexity bounds are
+    /// the default ones).
+    pub fn get_retarget(&self) -> Retarget {
+        Retarget {
+            target_interval: self.params.target_interval,
+            window: self.params.retarget_window,
+            initial_complexity: self.params.complexity,
+            ..Retarget::default()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::schema::Schema;
+    use crate::coin::{coin_random, coin_order};
+    use crate::transaction::Transaction;
+    use crate::block::Block;
+    use crate::state::State;
+
+    #[test]
+    fn test_chain_spec() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        // Mainnet
+        let spec = ChainSpec::default();
+        assert!(spec.is_mainnet());
+        assert_eq!(spec.get_genesis_info().hash, BlockInfo::genesis().hash);
+        assert_eq!(spec.get_retarget(), Retarget::default());
+
+        // Testnet
+        let genesis_hash: U256 = rng.random();
+        let spec = ChainSpec::new(2, genesis_hash.clone())
+            .with_complexity(8)
+            .with_min_coin_order(64);
+        assert!(!spec.is_mainnet());
+        assert_eq!(spec.get_genesis_info().hash, genesis_hash);
+        assert_eq!(spec.get_retarget().complexity_for_height(&[]).unwrap(), 
+                   8);
+
+        let state = State::new().with_chain_spec(&spec);
+        assert_eq!(state.get_last_block_info().hash, genesis_hash);
+
+        // A cheap coin is rejected by the testnet state only
+        let (key, public) = schema.gen_pair(&mut rng);
+        let coin = loop {
+            let coin = coin_random(&mut rng, &public);
+            if coin_order(&coin, &public) < 64 {
+                break coin;
+            }
+        };
+        let tr = Transaction::build(&mut rng, coin, U256::from(1), &key, 0,
+                                    &schema);
+        assert_eq!(tr.validate_coin(&state, &public).unwrap_err().kind(),
+                   ErrorKind::CoinTooCheap);
+        assert!(tr.validate_coin(&State::new(), &public).is_ok());
+    }
+
+    #[test]
+    fn test_protocol_params() {
+        // The missing parameters are the mainnet ones
+        let genesis_hash = ChainSpec::mainnet().genesis_hash.to_hex();
+        let json = format!(r#"{{
+            "chain_id": 3, "genesis_hash": "{}", "complexity": 6,
+            "target_interval": 30, "block_max_size": 2
+        }}"#, genesis_hash);
+        let spec = ChainSpec::from_json(&json).unwrap();
+        assert_eq!(spec.chain_id, 3);
+        assert_eq!(spec.params.complexity, 6);
+        assert_eq!(spec.params.block_max_size, 2);
+        assert_eq!(spec.params.block_max_bytes, BLOCK_MAX_BYTES);
+        assert_eq!(spec.params.min_coin_order, 0);
+        assert_eq!(ChainSpec::from_json(&spec.to_json()).unwrap(), spec);
+        assert!(ChainSpec::from_json("{}").is_err());
+
+        // Invalid parameters are rejected
+        for field in ["complexity", "target_interval", "retarget_window",
+                      "block_max_size", "block_max_bytes"] {
+            let json = format!(r#"{{
+                "chain_id": 3, "genesis_hash": "{}", "{}": 0
+            }}"#, genesis_hash, field);
+            assert_eq!(ChainSpec::from_json(&json).unwrap_err().kind(),
+                       ErrorKind::EncodingInvalid);
+        }
+        let params = ProtocolParams { complexity: 1000, 
+                                      ..ProtocolParams::default() };
+        assert!(ChainSpec::mainnet().with_params(params).is_err());
+        let params = ProtocolParams { complexity: 6, 
+                                      ..ProtocolParams::default() };
+        assert_eq!(ChainSpec::mainnet().with_params(params).unwrap()
+                             .params.complexity, 6);
+
+        let retarget = spec.get_retarget();
+        assert_eq!(retarget.target_interval, 30);
+        assert_eq!(retarget.window, RETARGET_WINDOW);
+        assert_eq!(retarget.initial_complexity, 6);
+
+        // The block limits are taken from the state
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let transactions: Vec<Transaction> = (0..3).map(|_| {
+            let coin = coin_random(&mut rng, &public);
+            Transaction::build(&mut rng, coin, U256::from(1), &key, 0,
+                               &schema)
+        }).collect();
+        let state = State::new().with_chain_spec(&spec);
+        assert!(!state.get_params().is_size_allowed(3, 0));
+        assert_eq!(Block::validate_size_with(&transactions, 
+                                             state.get_params())
+                       .unwrap_err().kind(),
+                   ErrorKind::BlockTooLarge);
+        assert!(Block::validate_size(&transactions).is_ok());
+    }
+}
@@ -11,24 +11,61 @@
 //! when necessary,
 //! maintaining full integrity at each step.
 
-use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet, BTreeMap};
 
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Serializer, Deserialize};
 
 #[cfg(feature = "blockchain")]
 use tokio::io::{Result as TokioResult};
 
+#[cfg(feature = "blockchain")]
+use crate::blockchain::Blockchain;
+#[cfg(feature = "blockchain")]
+use crate::chain::ChainValidator;
+#[cfg(feature = "blockchain")]
+use crate::snapshot::StateSnapshotStore;
+
+use crate::validate;
 use crate::utils::*;
 use crate::schema::Schema;
-use crate::coin::coin_order;
-use crate::block::{Block, BlockInfo};
+use crate::error::ValidationReport;
+use crate::codec::{Codec, Writer, Reader};
+use crate::commitment::Commitment;
+use crate::coin::{coin_order, CoinOrderCache};
+use crate::unit::order_map_value;
+use crate::block::{Block, BlockInfo, BlockData};
+use crate::spec::{ChainSpec, ProtocolParams, CHAIN_ID_MAINNET};
 use crate::transaction::{Transaction, Type};
 
 
+/// Number of blocks read from the blockchain at once on the state rebuild.
+#[cfg(feature = "blockchain")]
+pub const STATE_REBUILD_BATCH: u64 = 1000;
+
+
+/// Version of the binary format of the state without the chain spec (the
+/// state of the mainnet).
+pub const STATE_FORMAT_VERSION_1: u8 = 1;
+
+/// Version of the binary format of the state with the former commitment of
+/// the watch mode (XOR of the hashes of the coins).
+pub const STATE_FORMAT_VERSION_2: u8 = 2;
+
+/// Version of the binary format of the state.
+pub const STATE_FORMAT_VERSION: u8 = 3;
+
+
+/// Estimated memory of hash maps relative to the size of their entries in
+/// percents (control bytes and free slots).
+pub const STATE_MAP_OVERHEAD_PERCENT: usize = 130;
+
+
 /// State information about coin.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CoinInfo {
     /// Current owner.
+    #[serde(with = "u256_hex")]
     pub owner: U256,
 
     /// Order (it does not change).
@@ -39,6 +76,441 @@ pub struct CoinInfo {
 }
 
 
+/// Serialized form of the owner map: compact (owner to the sorted coins) or
+/// nested (owner to order to coins, as in older dumps). The orders of the 
+/// nested form are taken from the coin infos as well (the keys of untagged 
+/// variants can be read as strings only).
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OwnerCoinsData {
+    Compact(HashMap<U256, Vec<U256>>),
+    Nested(HashMap<U256, HashMap<String, Vec<U256>>>),
+}
+
+
+/// Deserialized form of `State`.
+#[derive(Deserialize)]
+struct StateData {
+    coin_info_map: CoinInfoMap,
+    owner_coins_map: OwnerCoinsData,
+    last_block_info: BlockInfo,
+    #[serde(default)]
+    watched: Option<HashSet<U256>>,
+    #[serde(default)]
+    coin_counter_map: CoinCounterMap,
+    #[serde(default = "default_commitment", with = "u256_hex")]
+    commitment: U256,
+    #[serde(default)]
+    unverified: HashSet<U256>,
+    #[serde(default)]
+    params: ProtocolParams,
+    #[serde(default = "default_chain_id")]
+    chain_id: u64,
+    #[serde(default)]
+    replay_protection_bix: Option<u64>,
+}
+
+
+impl From<StateData> for State {
+    fn from(data: StateData) -> Self {
+        let mut instance = Self::new();
+        instance.last_block_info = data.last_block_info;
+        instance.watched = data.watched;
+        instance.coin_counter_map = data.coin_counter_map;
+        instance.commitment = data.commitment;
+        instance.unverified = data.unverified;
+        instance.params = data.params;
+        instance.chain_id = data.chain_id;
+        instance.replay_protection_bix = data.replay_protection_bix;
+
+        let owner_coins: Vec<(U256, Vec<U256>)> = match data.owner_coins_map {
+            OwnerCoinsData::Compact(owner_coins) => {
+                owner_coins.into_iter().collect()
+            },
+            OwnerCoinsData::Nested(owner_coins_map) => {
+                owner_coins_map.into_iter().map(|(owner, coins_map)| {
+                    (owner, coins_map.into_values().flatten().collect())
+                }).collect()
+            },
+        };
+
+        // Build the owner map with the orders of the coin infos
+        instance.coin_info_map = data.coin_info_map;
+        for (owner, coins) in owner_coins.iter() {
+            for coin in coins.iter() {
+                if instance.coin_info_map.contains_key(coin) {
+                    instance.owner_coin_add(owner, coin);
+                }
+            }
+        }
+
+        instance
+    }
+}
+
+
+/// Serialize the owner map in the compact form (see `State`).
+fn serialize_owner_coins<S>(owner_coins_map: &OwnerCoinsMap, 
+                            serializer: S) -> Result<S::Ok, S::Error>
+                            where S: Serializer {
+    let owner_coins: HashMap<&U256, Vec<&U256>> = owner_coins_map.iter()
+        .map(|(owner, coins_map)| {
+            let mut coins: Vec<&U256> = coins_map.values().flatten()
+                                                 .collect();
+            coins.sort();
+            (owner, coins)
+        }).collect();
+    owner_coins.serialize(serializer)
+}
+
+
+/// Commitment of the state without coins.
+fn default_commitment() -> U256 {
+    Commitment::new().to_number()
+}
+
+
+/// Chain id of the older dumps.
+fn default_chain_id() -> u64 {
+    CHAIN_ID_MAINNET
+}
+
+
+/// Element of the coin in the commitment (see `State::get_commitment`).
+fn coin_element(coin: &U256, coin_info: &CoinInfo) -> U256 {
+    hash_of_u256([coin, &coin_info.owner, 
+                  &U256::from(coin_info.counter)].into_iter())
+}
+
+
+/// Canonical encoding of the state: format version (1 byte), last block info
+/// (64 bytes), number of coins (8 bytes) and the coins in ascending order, 
+/// each one as coin number, owner (32 bytes each), order and counter (8 
+/// bytes each). Then the watch mode flag (1 byte); in the watch mode it is 
+/// followed by the watched addresses (prefixed with their number as `u32`)
+/// in ascending order, the untracked coins (prefixed with their number as
+/// `u64`) in ascending order as coin number, order and counter (48 bytes), 
+/// the commitment (32 bytes) and the coins of unverified origin (prefixed 
+/// with their number as `u64`) in ascending order. The watch states of the 
+/// older format versions are not accepted, their commitment is of the former
+/// scheme. Then the chain spec of the state: chain id (8 
+/// bytes), replay protection flag (1 byte) followed by its block number (8 
+/// bytes) if set and the protocol parameters (see `ProtocolParams`); the 
+/// states of the first format version have no spec, they are the mainnet 
+/// ones. The coin order cache is not encoded.
+impl Codec for State {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_u8(STATE_FORMAT_VERSION);
+        self.last_block_info.encode(writer);
+        writer.write_u64(self.coin_info_map.len() as u64);
+
+        let mut coins: Vec<&U256> = self.coin_info_map.keys().collect();
+        coins.sort();
+        for coin in coins.into_iter() {
+            let coin_info = &self.coin_info_map[coin];
+            writer.write_u256(coin);
+            writer.write_u256(&coin_info.owner);
+            writer.write_u64(coin_info.order);
+            writer.write_u64(coin_info.counter);
+        }
+
+        writer.write_flag(self.watched.is_some());
+        if let Some(watched) = self.watched.as_ref() {
+            let mut addresses: Vec<&U256> = watched.iter().collect();
+            addresses.sort();
+            writer.write_u32(addresses.len() as u32);
+            for addr in addresses.into_iter() {
+                writer.write_u256(addr);
+            }
+
+            let mut coins: Vec<&U256> = self.coin_counter_map.keys().collect();
+            coins.sort();
+            writer.write_u64(coins.len() as u64);
+            for coin in coins.into_iter() {
+                let coin_counter = &self.coin_counter_map[coin];
+                writer.write_u256(coin);
+                writer.write_u64(coin_counter.order);
+                writer.write_u64(coin_counter.counter);
+            }
+
+            writer.write_u256(&self.commitment);
+
+            let mut coins: Vec<&U256> = self.unverified.iter().collect();
+            coins.sort();
+            writer.write_u64(coins.len() as u64);
+            for coin in coins.into_iter() {
+                writer.write_u256(coin);
+            }
+        }
+
+        writer.write_u64(self.chain_id);
+        writer.write_flag(self.replay_protection_bix.is_some());
+        if let Some(bix) = self.replay_protection_bix {
+            writer.write_u64(bix);
+        }
+        self.params.encode(writer);
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        // Check version
+        let version = reader.read_u8()?;
+        validate!((STATE_FORMAT_VERSION_1..=STATE_FORMAT_VERSION)
+                      .contains(&version), EncodingInvalid)
+            .map_err(|err| err.with_values(STATE_FORMAT_VERSION, version))?;
+
+        // Last block info
+        let last_block_info = BlockInfo::decode(reader)?;
+
+        // Coins (each one takes 80 bytes, it protects from huge allocations
+        // on broken sizes)
+        let size = reader.read_u64()?;
+        validate!(size <= reader.remaining() as u64 / 80, EncodingInvalid)?;
+        let mut coin_info_map = CoinInfoMap::with_capacity(size as usize);
+        for _ in 0..size {
+            let coin = reader.read_u256()?;
+            let coin_info = CoinInfo {
+                owner: reader.read_u256()?,
+                order: reader.read_u64()?,
+                counter: reader.read_u64()?,
+            };
+            validate!(coin_info_map.insert(coin, coin_info).is_none(), 
+                      EncodingInvalid)?;
+        }
+
+        let mut instance = Self::from_coin_infos(coin_info_map, 
+                                                 last_block_info);
+
+        // Watch mode
+        if reader.read_flag()? {
+            validate!(version > STATE_FORMAT_VERSION_2, EncodingInvalid)
+                .map_err(|err| err.with_values(STATE_FORMAT_VERSION, 
+                                               version))?;
+
+            let size = reader.read_u32()? as usize;
+            validate!(size <= reader.remaining() / 32, EncodingInvalid)?;
+            let watched: HashSet<U256> = (0..size)
+                .map(|_| reader.read_u256()).collect::<UqoinResult<_>>()?;
+            validate!(watched.len() == size, EncodingInvalid)?;
+
+            let size = reader.read_u64()?;
+            validate!(size <= reader.remaining() as u64 / 48, 
+                      EncodingInvalid)?;
+            for _ in 0..size {
+                let coin = reader.read_u256()?;
+                let coin_counter = CoinCounter {
+                    order: reader.read_u64()?,
+                    counter: reader.read_u64()?,
+                };
+                validate!(!instance.coin_info_map.contains_key(&coin) &&
+                          instance.coin_counter_map.insert(coin, coin_counter)
+                                                   .is_none(), 
+                          EncodingInvalid)?;
+            }
+
+            instance.watched = Some(watched);
+            instance.commitment = reader.read_u256()?;
+            validate!(Commitment::from_number(&instance.commitment).is_some(),
+                      EncodingInvalid)?;
+
+            let size = reader.read_u64()?;
+            validate!(size <= reader.remaining() as u64 / 32, 
+                      EncodingInvalid)?;
+            for _ in 0..size {
+                let coin = reader.read_u256()?;
+                validate!(instance.coin_info_map.contains_key(&coin) &&
+                          instance.unverified.insert(coin), EncodingInvalid)?;
+            }
+        }
+
+        // Chain spec
+        if version > STATE_FORMAT_VERSION_1 {
+            instance.chain_id = reader.read_u64()?;
+            instance.replay_protection_bix = if reader.read_flag()? {
+                Some(reader.read_u64()?)
+            } else {
+                None
+            };
+            instance.params = ProtocolParams::decode(reader)?;
+        }
+
+        Ok(instance)
+    }
+}
+
+
+/// Change of the coin state (`None` if the coin does not exist).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoinChange {
+    /// Coin number.
+    pub coin: U256,
+
+    /// Coin state before.
+    pub before: Option<CoinInfo>,
+
+    /// Coin state after.
+    pub after: Option<CoinInfo>,
+}
+
+
+impl CoinChange {
+    /// Check whether the coin is created.
+    pub fn is_created(&self) -> bool {
+        self.before.is_none() && self.after.is_some()
+    }
+
+    /// Check whether the coin is removed (by a roll down).
+    pub fn is_removed(&self) -> bool {
+        self.before.is_some() && self.after.is_none()
+    }
+
+    /// Check whether the owner of the coin is changed.
+    pub fn is_owner_changed(&self) -> bool {
+        self.before.as_ref().map(|ci| &ci.owner) !=
+            self.after.as_ref().map(|ci| &ci.owner)
+    }
+}
+
+
+/// Incremental update of the state between two blocks (for wallets and
+/// indexers). It is returned by `State::roll_up` and `State::roll_down` if
+/// the state tracks the diffs (see `State::set_diff_tracking`), or can be
+/// calculated between two states with `StateDiff::between`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDiff {
+    /// Last block info before.
+    pub block_before: BlockInfo,
+
+    /// Last block info after.
+    pub block_after: BlockInfo,
+
+    /// Changes of the coins.
+    pub coins: Vec<CoinChange>,
+}
+
+
+impl StateDiff {
+    /// Calculate the diff between two states. This may take a while, it
+    /// compares all coins of the states. The changes are sorted by coin.
+    pub fn between(before: &State, after: &State) -> Self {
+        let coin_set: HashSet<&U256> = before.coin_info_map.keys()
+            .chain(after.coin_info_map.keys()).collect();
+        let mut coins: Vec<CoinChange> = coin_set.into_iter()
+            .filter_map(|coin| {
+                let change = CoinChange {
+                    coin: coin.clone(),
+                    before: before.get_coin_info(coin).cloned(),
+                    after: after.get_coin_info(coin).cloned(),
+                };
+                (change.before != change.after).then_some(change)
+            }).collect();
+        coins.sort_by(|a, b| a.coin.cmp(&b.coin));
+
+        Self {
+            block_before: before.last_block_info.clone(),
+            block_after: after.last_block_info.clone(),
+            coins,
+        }
+    }
+
+    /// Get the reverse diff.
+    pub fn reverse(&self) -> Self {
+        Self {
+            block_before: self.block_after.clone(),
+            block_after: self.block_before.clone(),
+            coins: self.coins.iter().map(|change| CoinChange {
+                coin: change.coin.clone(),
+                before: change.after.clone(),
+                after: change.before.clone(),
+            }).collect(),
+        }
+    }
+
+    /// Extend the diff with the next one (e.g. of the next block), so it
+    /// covers both of them.
+    pub fn extend(&mut self, other: StateDiff) {
+        assert_eq!(self.block_after.bix, other.block_before.bix);
+        self.block_after = other.block_after;
+
+        // Merge the changes of the same coins
+        let mut positions: HashMap<U256, usize> = self.coins.iter()
+            .enumerate().map(|(ix, change)| (change.coin.clone(), ix))
+            .collect();
+        for change in other.coins.into_iter() {
+            if let Some(ix) = positions.get(&change.coin) {
+                self.coins[*ix].after = change.after;
+            } else {
+                positions.insert(change.coin.clone(), self.coins.len());
+                self.coins.push(change);
+            }
+        }
+
+        // Remove the changes that cancelled out
+        self.coins.retain(|change| change.before != change.after);
+    }
+
+    /// Iterate the created coins.
+    pub fn iter_created(&self) -> impl Iterator<Item = &CoinChange> {
+        self.coins.iter().filter(|change| change.is_created())
+    }
+
+    /// Iterate the coins with the changed owner.
+    pub fn iter_transferred(&self) -> impl Iterator<Item = &CoinChange> {
+        self.coins.iter().filter(|change| change.is_owner_changed())
+    }
+}
+
+
+/// Order and counter of the coin not owned by the watched addresses (see
+/// `State::set_watched`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoinCounter {
+    /// Order (it does not change).
+    pub order: u64,
+
+    /// Counter of transfers.
+    pub counter: u64,
+}
+
+
+/// Observer of the confirmed coin changes (e.g. for indexers, webhooks and
+/// wallets). It is called by `State::roll_up` and `State::roll_down` after
+/// the state is updated, for each coin of the block.
+pub trait StateObserver: std::fmt::Debug + Send + Sync {
+    /// The coin is created (mined) by the rolled up block.
+    fn on_coin_created(&self, _coin: &U256, _coin_info: &CoinInfo) {}
+
+    /// The coin is transferred from `owner_prev` by the rolled up block.
+    fn on_coin_transferred(&self, _coin: &U256, _owner_prev: &U256, 
+                           _coin_info: &CoinInfo) {}
+
+    /// The change of the coin is unwound by the rolled down block.
+    fn on_coin_unwound(&self, _change: &CoinChange) {}
+}
+
+
+/// Size metrics of the state (see `State::stats`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateStats {
+    /// Number of the coins with full information.
+    pub coin_count: usize,
+
+    /// Number of the untracked coins (in the watch mode).
+    pub untracked_count: usize,
+
+    /// Number of the coins of unverified origin (in the watch mode).
+    pub unverified_count: usize,
+
+    /// Number of the owners.
+    pub owner_count: usize,
+
+    /// Number of the coins by order.
+    pub orders: BTreeMap<u64, usize>,
+
+    /// Estimated memory footprint in bytes.
+    pub memory_bytes: usize,
+}
+
+
 /// Map coin-state
 pub type CoinInfoMap = HashMap<U256, CoinInfo>;
 
@@ -48,14 +520,55 @@ pub type OrderCoinsMap = HashMap<u64, HashSet<U256>>;
 /// Map owner-coins
 pub type OwnerCoinsMap = HashMap<U256, OrderCoinsMap>;
 
+/// Map coin-counter (for the untracked coins)
+pub type CoinCounterMap = HashMap<U256, CoinCounter>;
+
 
 /// Uqoin state for fast access to the last block, coin and ownership
-/// information.
+/// information. An optional `CoinOrderCache` can be attached to memoize order
+/// calculations of the new coins, it is shared by the clones of the state.
+///
+/// In the watch mode (see `set_watched`) the state keeps full information
+/// about the coins of the watched addresses only (e.g. for wallet nodes). For
+/// the other coins only the order and the counter are kept, that is enough
+/// to recover the senders and to group the transactions. The owners of these
+/// coins are unknown, so the ownership of their transactions is not checked
+/// (see `Transaction::validate_coin`): the senders are assumed to be the 
+/// owners and the coins they pass to the watched addresses are marked as of
+/// unverified origin (see `is_verified_coin`). The state maintains the 
+/// commitment to all coins (see `get_commitment`), the marks are cleared 
+/// once it matches the one of a trusted full node (see `verify_commitment`).
+///
+/// The owner map is serialized in the compact form: the sorted coins of each
+/// owner, their orders are taken from the coin infos on load. The nested form
+/// of the older dumps is still accepted. The chain spec of the state (see 
+/// `with_chain_spec`) is serialized too, the older dumps are the mainnet ones.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StateData")]
 pub struct State {
     coin_info_map: CoinInfoMap,
+    #[serde(serialize_with = "serialize_owner_coins")]
     owner_coins_map: OwnerCoinsMap,
+    #[serde(skip)]
+    order_coins_map: OrderCoinsMap,
     last_block_info: BlockInfo,
+    #[serde(default)]
+    watched: Option<HashSet<U256>>,
+    #[serde(default)]
+    coin_counter_map: CoinCounterMap,
+    #[serde(default = "default_commitment", with = "u256_hex")]
+    commitment: U256,
+    #[serde(default)]
+    unverified: HashSet<U256>,
+    #[serde(skip)]
+    coin_order_cache: Option<Arc<CoinOrderCache>>,
+    params: ProtocolParams,
+    chain_id: u64,
+    replay_protection_bix: Option<u64>,
+    #[serde(skip)]
+    diff_tracking: bool,
+    #[serde(skip)]
+    observers: Detached<Vec<Arc<dyn StateObserver>>>,
 }
 
 
@@ -65,7 +578,192 @@ impl State {
         Self {
             coin_info_map: CoinInfoMap::new(),
             owner_coins_map: OwnerCoinsMap::new(),
+            order_coins_map: OrderCoinsMap::new(),
             last_block_info: BlockInfo::genesis(),
+            watched: None,
+            coin_counter_map: CoinCounterMap::new(),
+            commitment: default_commitment(),
+            unverified: HashSet::new(),
+            coin_order_cache: None,
+            params: ProtocolParams::default(),
+            chain_id: CHAIN_ID_MAINNET,
+            replay_protection_bix: None,
+            diff_tracking: false,
+            observers: Detached::default(),
+        }
+    }
+
+    /// Apply the chain spec: the empty state starts from the genesis of the 
+    /// chain, the new coins must have at least the minimum order of the
+    /// spec, the blocks are validated with the parameters of the spec and 
+    /// the senders are recovered for the chain id.
+    pub fn with_chain_spec(mut self, spec: &ChainSpec) -> Self {
+        if self.last_block_info.bix == 0 {
+            self.last_block_info = spec.get_genesis_info();
+        }
+        self.params = spec.params.clone();
+        self.chain_id = spec.chain_id;
+        self.replay_protection_bix = spec.replay_protection_bix;
+        self
+    }
+
+    /// Chain id the transaction messages are signed for (the mainnet one by
+    /// default).
+    pub fn get_chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// Check if the transactions of the next block must commit to the chain
+    /// id (see `ChainSpec::replay_protection_bix`).
+    pub fn requires_replay_protection(&self) -> bool {
+        self.replay_protection_bix
+            .is_some_and(|bix| self.last_block_info.bix + 1 >= bix)
+    }
+
+    /// Minimum order of the newly mined coins (zero if it is not limited).
+    pub fn get_min_coin_order(&self) -> u64 {
+        self.params.min_coin_order
+    }
+
+    /// Consensus parameters of the chain (the mainnet ones by default).
+    pub fn get_params(&self) -> &ProtocolParams {
+        &self.params
+    }
+
+    /// Create a state from the coin infos and the last block info (e.g. a
+    /// partial state with the coins of a block only). It is the mainnet 
+    /// state, other chains need `with_chain_spec`.
+    pub fn from_coin_infos(coin_info_map: CoinInfoMap, 
+                           last_block_info: BlockInfo) -> Self {
+        let mut instance = Self::new();
+        instance.coin_info_map = coin_info_map;
+        instance.last_block_info = last_block_info;
+
+        // Build the owner map
+        let coin_owners: Vec<(U256, U256)> = instance.coin_info_map.iter()
+            .map(|(coin, info)| (coin.clone(), info.owner.clone())).collect();
+        for (coin, owner) in coin_owners.iter() {
+            instance.owner_coin_add(owner, coin);
+        }
+
+        instance.commitment = instance.calc_commitment();
+        instance
+    }
+
+    /// Switch to the watch mode keeping full information about the coins of
+    /// the `addresses` only. The information about the other coins is
+    /// dropped, so it cannot be switched back.
+    pub fn set_watched(&mut self, addresses: HashSet<U256>) {
+        self.watched = Some(addresses);
+        let coins: Vec<U256> = self.coin_info_map.keys().cloned().collect();
+        self.watch_evict(coins.iter());
+    }
+
+    /// Get the watched addresses (`None` if it is not the watch mode).
+    pub fn get_watched(&self) -> Option<&HashSet<U256>> {
+        self.watched.as_ref()
+    }
+
+    /// Check whether the address is tracked (any address is tracked if it is
+    /// not the watch mode).
+    pub fn is_tracked(&self, owner: &U256) -> bool {
+        self.watched.as_ref().is_none_or(|watched| watched.contains(owner))
+    }
+
+    /// Get the commitment to all coins: the multiset commitment (see 
+    /// `Commitment`) to the hashes of coin number, owner and counter for 
+    /// each coin. It is updated with each block.
+    pub fn get_commitment(&self) -> &U256 {
+        &self.commitment
+    }
+
+    /// Compare the commitment with the one of a trusted full node (or a 
+    /// trusted snapshot) at the same block `bix`. If they match, the owners
+    /// assumed in the watch mode are the real ones, so the coins are 
+    /// verified.
+    pub fn verify_commitment(&mut self, bix: u64, 
+                             commitment: &U256) -> UqoinResult<()> {
+        validate!(bix == self.last_block_info.bix, StateCommitmentMismatch)
+            .map_err(|err| err.with_values(bix, self.last_block_info.bix))?;
+        validate!(*commitment == self.commitment, StateCommitmentMismatch)
+            .map_err(|err| err.with_values(commitment.to_hex(), 
+                                           self.commitment.to_hex()))?;
+        self.unverified.clear();
+        Ok(())
+    }
+
+    /// Check the coin is not of unverified origin: it came to a watched 
+    /// address from an untracked coin and the commitment has not been 
+    /// verified since then (see `verify_commitment`).
+    pub fn is_verified_coin(&self, coin: &U256) -> bool {
+        !self.unverified.contains(coin)
+    }
+
+    /// Get the coins of unverified origin (see `is_verified_coin`).
+    pub fn get_unverified_coins(&self) -> impl Iterator<Item = &U256> {
+        self.unverified.iter()
+    }
+
+    /// Calculate the commitment from scratch (it is possible for full states
+    /// only, in the watch mode the maintained one is returned).
+    pub fn calc_commitment(&self) -> U256 {
+        if self.watched.is_some() {
+            self.commitment.clone()
+        } else {
+            let coins = self.coin_info_map.iter()
+                .collect::<Vec<(&U256, &CoinInfo)>>();
+            Commitment::of_elements(&coins, |(coin, ci)| coin_element(coin, ci))
+                .to_number()
+        }
+    }
+
+    /// Attach (or detach with `None`) a cache for coin order calculations.
+    pub fn set_coin_order_cache(&mut self, 
+                                cache: Option<Arc<CoinOrderCache>>) {
+        self.coin_order_cache = cache;
+    }
+
+    /// Get the attached cache for coin order calculations.
+    pub fn get_coin_order_cache(&self) -> Option<&Arc<CoinOrderCache>> {
+        self.coin_order_cache.as_ref()
+    }
+
+    /// Enable or disable the diffs returned by `roll_up` and `roll_down` 
+    /// (disabled by default). Collecting the changes costs a copy of the
+    /// coin infos of each block, so the diffs are opt-in.
+    pub fn set_diff_tracking(&mut self, enabled: bool) {
+        self.diff_tracking = enabled;
+    }
+
+    /// Enable or disable the diffs (see `set_diff_tracking`).
+    pub fn with_diff_tracking(mut self, enabled: bool) -> Self {
+        self.set_diff_tracking(enabled);
+        self
+    }
+
+    /// Check the state tracks the diffs.
+    pub fn is_diff_tracking(&self) -> bool {
+        self.diff_tracking
+    }
+
+    /// Add an observer of the coin changes, it is not carried into the
+    /// clones of the state.
+    pub fn add_observer(&mut self, observer: Arc<dyn StateObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Remove all observers.
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    /// Calculate order of the coin mined by `miner` using the attached cache
+    /// if there is any.
+    pub fn calc_coin_order(&self, coin: &U256, miner: &U256) -> u64 {
+        if let Some(cache) = self.coin_order_cache.as_ref() {
+            cache.get(coin, miner)
+        } else {
+            coin_order(coin, miner)
         }
     }
 
@@ -74,7 +772,11 @@ impl State {
     pub async fn load(path: &str) -> TokioResult<Self> {
         let bytes = tokio::fs::read(path).await?;
         let content = String::from_utf8(bytes).unwrap();
-        let instance = serde_json::from_str(&content)?;
+        let mut instance: Self = serde_json::from_str(&content)?;
+        if instance.watched.is_none() {
+            // Older dumps have no commitment or the former one
+            instance.commitment = instance.calc_commitment();
+        }
         Ok(instance)
     }
 
@@ -85,19 +787,141 @@ impl State {
         tokio::fs::write(path, content.as_bytes()).await
     }
 
-    /// Get owner of the coin by number.
+    /// Load from a file in the binary format (see `from_binary`).
+    #[cfg(feature = "blockchain")]
+    pub async fn load_binary(path: &str) -> TokioResult<Self> {
+        let bytes = tokio::fs::read(path).await?;
+        Ok(Self::from_binary(&bytes)?)
+    }
+
+    /// Dump to a file in the binary format (see `to_binary`).
+    #[cfg(feature = "blockchain")]
+    pub async fn dump_binary(&self, path: &str) -> TokioResult<()> {
+        tokio::fs::write(path, self.to_binary()).await
+    }
+
+    /// Encode in the binary format: the canonical encoding followed by its
+    /// SHA3-256 checksum (32 bytes). It is several times more compact and
+    /// faster than JSON.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut bytes = Codec::to_bytes(self);
+        let checksum = hash_of_bytes(&bytes);
+        bytes.extend(checksum.to_bytes());
+        bytes
+    }
+
+    /// Decode from the binary format checking the checksum.
+    pub fn from_binary(bytes: &[u8]) -> UqoinResult<Self> {
+        validate!(bytes.len() >= 32, EncodingInvalid)?;
+        let (body, checksum) = bytes.split_at(bytes.len() - 32);
+        let expected = hash_of_bytes(body);
+        let actual = U256::from_bytes(checksum);
+        validate!(actual == expected, EncodingInvalid)
+            .map_err(|err| err.with_values(expected.to_hex(), 
+                                           actual.to_hex()))?;
+        <Self as Codec>::from_bytes(body)
+    }
+
+    /// Rebuild the state from scratch replaying all stored blocks. Each block
+    /// is verified by `validator` before the roll up. `progress` is called
+    /// after each batch of blocks with the number of the last replayed block
+    /// and the total number of blocks.
+    #[cfg(feature = "blockchain")]
+    pub async fn rebuild<F>(blockchain: &Blockchain, schema: &Schema, 
+                            validator: &mut ChainValidator, 
+                            progress: F) -> TokioResult<Self>
+                            where F: FnMut(u64, u64) {
+        let total = blockchain.get_block_count().await?;
+        let mut instance = Self::new()
+            .with_chain_spec(blockchain.get_chain_spec());
+        instance.replay(blockchain, total, schema, validator, progress).await?;
+        Ok(instance)
+    }
+
+    /// Restore the state of the block `bix` loading the nearest snapshot
+    /// from the store and replaying the remaining blocks (verified by
+    /// `validator`). Without a suitable snapshot the replay starts from 
+    /// genesis.
+    #[cfg(feature = "blockchain")]
+    pub async fn restore_to(bix: u64, blockchain: &Blockchain, 
+                            store: &StateSnapshotStore, schema: &Schema,
+                            validator: &mut ChainValidator) -> 
+                            TokioResult<Self> {
+        // Load the nearest snapshot
+        let mut instance = match store.get_nearest(bix).await? {
+            Some(snapshot_bix) => store.load(snapshot_bix).await?,
+            None => Self::new(),
+        }.with_chain_spec(blockchain.get_chain_spec());
+
+        // Load the history of the last blocks for the retarget
+        let last_bix = instance.last_block_info.bix;
+        if last_bix > 0 {
+            let size = validator.get_retarget().get_history_size() as u64;
+            let mut history = Vec::new();
+            for hbix in last_bix.saturating_sub(size) + 1 ..= last_bix {
+                history.push(blockchain.get_block_info(hbix).await?);
+            }
+            validator.set_history(history);
+        }
+
+        // Replay the remaining blocks
+        instance.replay(blockchain, bix, schema, validator, |_, _| {}).await?;
+        Ok(instance)
+    }
+
+    /// Replay the stored blocks up to `bix` (see `rebuild`).
+    #[cfg(feature = "blockchain")]
+    async fn replay<F>(&mut self, blockchain: &Blockchain, bix: u64,
+                       schema: &Schema, validator: &mut ChainValidator,
+                       mut progress: F) -> TokioResult<()>
+                       where F: FnMut(u64, u64) {
+        while self.last_block_info.bix < bix {
+            // Read the next batch
+            let bix_next = self.last_block_info.bix + 1;
+            let count = STATE_REBUILD_BATCH.min(bix - bix_next + 1);
+            let blocks = blockchain.get_block_data_many(bix_next, count)
+                                   .await?;
+
+            // Validate and roll up
+            validator.validate_chain(&blocks, self, schema)?;
+            progress(self.last_block_info.bix, bix);
+        }
+        Ok(())
+    }
+
+    /// Get owner of the coin by number (`None` for the untracked coins in the
+    /// watch mode).
     pub fn get_owner(&self, coin: &U256) -> Option<&U256> {
         self.coin_info_map.get(coin).map(|cs| &cs.owner)
     }
 
-    /// Get coin state by number.
+    /// Get coin state by number (`None` for the untracked coins in the watch
+    /// mode).
     pub fn get_coin_info(&self, coin: &U256) -> Option<&CoinInfo> {
         self.coin_info_map.get(coin)
     }
 
     /// Get coin state by number.
     pub fn get_coin_counter(&self, coin: &U256) -> u64 {
-        self.coin_info_map.get(coin).map(|cs| cs.counter).unwrap_or(0)
+        match self.coin_info_map.get(coin) {
+            Some(cs) => cs.counter,
+            None => self.coin_counter_map.get(coin)
+                        .map(|cc| cc.counter).unwrap_or(0),
+        }
+    }
+
+    /// Get order of the existing coin (the untracked ones in the watch mode 
+    /// too).
+    pub fn get_coin_order(&self, coin: &U256) -> Option<u64> {
+        match self.coin_info_map.get(coin) {
+            Some(cs) => Some(cs.order),
+            None => self.coin_counter_map.get(coin).map(|cc| cc.order),
+        }
+    }
+
+    /// Check whether the coin exists but it is untracked (in the watch mode).
+    pub fn is_untracked_coin(&self, coin: &U256) -> bool {
+        self.coin_counter_map.contains_key(coin)
     }
 
     /// Get coins of the owner.
@@ -116,21 +940,233 @@ impl State {
         }
     }
 
+    /// Get total value of the owner coins (zero for unknown owner).
+    pub fn get_balance(&self, owner: &U256) -> U256 {
+        self.owner_coins_map.get(owner).map(order_map_value)
+            .unwrap_or(U256::from(0))
+    }
+
+    /// Get numbers of the owner coins by order.
+    pub fn get_order_counts(&self, owner: &U256) -> BTreeMap<u64, usize> {
+        self.owner_coins_map.get(owner).map(|coins_map| coins_map.iter()
+            .map(|(order, coins)| (*order, coins.len())).collect()
+        ).unwrap_or_default()
+    }
+
+    /// Iterate the coins of the order across all owners (the untracked coins
+    /// of the watch mode are not included).
+    pub fn get_coins_of_order(&self, 
+                              order: u64) -> impl Iterator<Item = &U256> {
+        self.order_coins_map.get(&order).into_iter().flatten()
+    }
+
+    /// Get numbers of all coins by order (the supply per denomination).
+    pub fn get_order_supply(&self) -> BTreeMap<u64, usize> {
+        let mut supply = BTreeMap::new();
+        for coin_info in self.coin_info_map.values() {
+            *supply.entry(coin_info.order).or_insert(0) += 1;
+        }
+        for coin_counter in self.coin_counter_map.values() {
+            *supply.entry(coin_counter.order).or_insert(0) += 1;
+        }
+        supply
+    }
+
+    /// Iterate all owners with their balances (in arbitrary order).
+    pub fn iter_balances(&self) -> impl Iterator<Item = (&U256, U256)> {
+        self.owner_coins_map.iter()
+            .map(|(owner, coins_map)| (owner, order_map_value(coins_map)))
+    }
+
+    /// Get the page of owners with their balances sorted by the balance in 
+    /// descending order (ties are sorted by the owner). This may take a
+    /// while, so it is recommended to cache the result for often use.
+    pub fn get_rich_list(&self, offset: usize, 
+                         limit: usize) -> Vec<(U256, U256)> {
+        let mut balances: Vec<(U256, U256)> = self.iter_balances()
+            .map(|(owner, balance)| (owner.clone(), balance)).collect();
+        balances.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        balances.into_iter().skip(offset).take(limit).collect()
+    }
+
+    /// Calculate size metrics of the state. It iterates all coins, so it may
+    /// take a while for large states. The memory footprint is estimated from
+    /// the sizes of the map entries with the overhead of hash maps.
+    pub fn stats(&self) -> StateStats {
+        // Memory footprint
+        let order_set_count: usize = self.owner_coins_map.values()
+            .map(|coins_map| coins_map.len()).sum::<usize>() + 
+            self.order_coins_map.len();
+        let entries = 
+            self.coin_info_map.capacity() * 
+                size_of::<(U256, CoinInfo)>() +
+            self.coin_counter_map.capacity() * 
+                size_of::<(U256, CoinCounter)>() +
+            self.owner_coins_map.capacity() * 
+                size_of::<(U256, OrderCoinsMap)>() +
+            order_set_count * size_of::<(u64, HashSet<U256>)>() +
+            2 * self.coin_info_map.len() * size_of::<U256>();
+        let memory_bytes = entries * STATE_MAP_OVERHEAD_PERCENT / 100 + 
+                           size_of::<Self>();
+
+        StateStats {
+            coin_count: self.coin_info_map.len(),
+            untracked_count: self.coin_counter_map.len(),
+            unverified_count: self.unverified.len(),
+            owner_count: self.owner_coins_map.len(),
+            orders: self.get_order_supply(),
+            memory_bytes,
+        }
+    }
+
+    /// Cross-check the internal maps of the state (e.g. after a crash or a
+    /// snapshot restore): each coin must be listed under its owner and order
+    /// exactly once and in the index of its order, no other coins and no 
+    /// empty sets may be listed, the untracked coins must be untracked indeed,
+    /// the coins of unverified origin must be tracked and the commitment must
+    /// correspond the coins. The state is consistent
+    /// if the report is ok.
+    pub fn verify_integrity(&self) -> ValidationReport {
+        let mut errors = ValidationReport::new();
+
+        // Each coin is listed under its owner and order
+        for (coin, coin_info) in self.coin_info_map.iter() {
+            let listed = self.owner_coins_map.get(&coin_info.owner)
+                .and_then(|coins_map| coins_map.get(&coin_info.order))
+                .is_some_and(|coins| coins.contains(coin)) && 
+                self.order_coins_map.get(&coin_info.order)
+                .is_some_and(|coins| coins.contains(coin));
+            errors.extend(validate!(listed, StateInconsistent)
+                .map_err(|err| err.with_coin(coin)).err());
+            errors.extend(validate!(self.is_tracked(&coin_info.owner), 
+                                    StateInconsistent)
+                .map_err(|err| err.with_coin(coin)).err());
+        }
+
+        // No dangling coins and empty sets
+        for (owner, coins_map) in self.owner_coins_map.iter() {
+            errors.extend(validate!(!coins_map.is_empty(), StateInconsistent)
+                .map_err(|err| err.with_values("coins", "empty")).err());
+            for (order, coins) in coins_map.iter() {
+                errors.extend(validate!(!coins.is_empty(), StateInconsistent)
+                    .map_err(|err| err.with_values("coins", "empty")).err());
+                for coin in coins.iter() {
+                    let actual = self.coin_info_map.get(coin)
+                        .map(|ci| (&ci.owner, ci.order));
+                    errors.extend(validate!(actual == Some((owner, *order)), 
+                                            StateInconsistent)
+                        .map_err(|err| err.with_coin(coin)).err());
+                }
+            }
+        }
+
+        // No dangling coins and empty sets in the order index
+        for (order, coins) in self.order_coins_map.iter() {
+            errors.extend(validate!(!coins.is_empty(), StateInconsistent)
+                .map_err(|err| err.with_values("coins", "empty")).err());
+            for coin in coins.iter() {
+                let actual = self.coin_info_map.get(coin).map(|ci| ci.order);
+                errors.extend(validate!(actual == Some(*order), 
+                                        StateInconsistent)
+                    .map_err(|err| err.with_coin(coin)).err());
+            }
+        }
+
+        // Untracked coins
+        for coin in self.coin_counter_map.keys() {
+            let untracked = self.watched.is_some() && 
+                            !self.coin_info_map.contains_key(coin);
+            errors.extend(validate!(untracked, StateInconsistent)
+                .map_err(|err| err.with_coin(coin)).err());
+        }
+
+        // Coins of unverified origin
+        for coin in self.unverified.iter() {
+            errors.extend(validate!(self.coin_info_map.contains_key(coin), 
+                                    StateInconsistent)
+                .map_err(|err| err.with_coin(coin)).err());
+        }
+
+        // Commitment
+        let expected = self.calc_commitment();
+        errors.extend(validate!(self.commitment == expected, 
+                                StateInconsistent)
+            .map_err(|err| err.with_values(expected.to_hex(), 
+                                           self.commitment.to_hex())).err());
+
+        errors
+    }
+
     /// Get last block info.
     pub fn get_last_block_info(&self) -> &BlockInfo {
         &self.last_block_info
     }
 
-    /// Roll up the state with the next block.
+    /// Roll up the state with the next block. Returns the changes if the
+    /// diffs are tracked.
     pub fn roll_up(&mut self, bix: u64, block: &Block, 
-                   transactions: &[Transaction], schema: &Schema) {
+                   transactions: &[Transaction], schema: &Schema) -> 
+                   Option<StateDiff> {
+        // Calc senders (it is important to calculate it before counter updates)
+        let senders = Transaction::calc_senders(transactions, self, schema);
+
+        // Roll up
+        self.roll_up_with_senders(bix, block, transactions, &senders)
+    }
+
+    /// Roll up the state with several consecutive blocks. The senders of all
+    /// the blocks are calculated at once (see 
+    /// `Transaction::calc_senders_of_blocks`). Returns the aggregated changes
+    /// if the diffs are tracked.
+    pub fn roll_up_many(&mut self, blocks: &[BlockData], 
+                        schema: &Schema) -> Option<StateDiff> {
+        // Calc senders of all blocks
+        let transactions_list: Vec<&[Transaction]> = blocks.iter()
+            .map(|block_data| block_data.transactions.as_slice()).collect();
+        let senders_list = Transaction::calc_senders_of_blocks(
+            &transactions_list, self, schema
+        );
+
+        // Roll up
+        let mut diff = self.diff_tracking.then(|| StateDiff {
+            block_before: self.last_block_info.clone(),
+            block_after: self.last_block_info.clone(),
+            coins: Vec::new(),
+        });
+        for (block_data, senders) in blocks.iter().zip(senders_list.iter()) {
+            let block_diff = self.roll_up_with_senders(
+                block_data.bix, &block_data.block, &block_data.transactions, 
+                senders
+            );
+            if let (Some(diff), Some(block_diff)) = (diff.as_mut(), 
+                                                     block_diff) {
+                diff.extend(block_diff);
+            }
+        }
+        diff
+    }
+
+    /// Roll up the state with the next block and the senders of its 
+    /// transactions calculated for this state (e.g. the ones used for the
+    /// block validation), so they are not calculated again. Returns the
+    /// changes if the diffs are tracked.
+    pub fn roll_up_with_senders(&mut self, bix: u64, block: &Block, 
+                                transactions: &[Transaction], 
+                                senders: &[U256]) -> Option<StateDiff> {
         // Check the block
         assert_eq!(bix, self.last_block_info.bix + 1);
         assert_eq!(block.offset, self.last_block_info.offset);
         assert_eq!(block.hash_prev, self.last_block_info.hash);
+        assert_eq!(transactions.len(), senders.len());
 
-        // Calc senders (it is important to calculate it before counter updates)
-        let senders = Transaction::calc_senders(&transactions, self, &schema);
+        // Restore the untracked coins (their owners are the senders)
+        self.watch_restore(transactions, senders.iter());
+
+        // Keep the coins before
+        let block_before = self.last_block_info.clone();
+        let coins_before = self.collects_changes()
+            .then(|| self.get_coin_infos_of(transactions));
+        self.commit_coins(transactions, false);
 
         // Iterate transactions
         for (transaction, sender) in transactions.iter().zip(senders.iter()) {
@@ -155,7 +1191,7 @@ impl State {
                 self.owner_coin_add(&receiver, &transaction.coin);
             } else {
                 // Calculate coin order
-                let order = coin_order(&transaction.coin, &sender);
+                let order = self.calc_coin_order(&transaction.coin, sender);
 
                 // Create new coin state
                 let coin_info = CoinInfo {
@@ -175,21 +1211,53 @@ impl State {
         self.last_block_info.bix = bix;
         self.last_block_info.offset += transactions.len() as u64;
         self.last_block_info.hash = block.hash.clone();
+        self.last_block_info.timestamp = block.timestamp;
+        self.last_block_info.complexity = block.complexity;
+
+        // Collect the changes and drop the untracked coins
+        self.commit_coins(transactions, true);
+        let diff = coins_before.map(|coins_before| {
+            self.get_diff_of(block_before, coins_before, transactions)
+        });
+        self.watch_evict(transactions.iter().map(|tr| &tr.coin));
+        self.publish_diff(diff, false)
     }
 
-    /// Roll down the state with the last block.
+    /// Roll down the state with the last block. Timestamp and complexity of
+    /// the previous block are unknown from the block, so they are reset to 
+    /// zero (take the full info from `Blockchain::get_block_info` if needed).
+    /// Returns the changes if the diffs are tracked.
     pub fn roll_down(&mut self, bix: u64, block: &Block, 
-                     transactions: &[Transaction], schema: &Schema) {
+                     transactions: &[Transaction], schema: &Schema) -> 
+                     Option<StateDiff> {
         // Check the block
         assert_eq!(bix, self.last_block_info.bix);
         assert_eq!(block.offset + transactions.len() as u64, 
                    self.last_block_info.offset);
         assert_eq!(block.hash, self.last_block_info.hash);
 
+        // Restore the untracked coins (their owners are the receivers)
+        let receivers = transactions.iter().map(
+            |tr| if tr.get_type() == Type::Transfer { 
+                &tr.addr 
+            } else { 
+                &block.validator 
+            }
+        );
+        self.watch_restore(transactions, receivers);
+
+        // Keep the coins before
+        let block_before = self.last_block_info.clone();
+        let coins_before = self.collects_changes()
+            .then(|| self.get_coin_infos_of(transactions));
+        self.commit_coins(transactions, false);
+
         // Update last block info
         self.last_block_info.bix -= 1;
         self.last_block_info.offset = block.offset;
         self.last_block_info.hash = block.hash_prev.clone();
+        self.last_block_info.timestamp = 0;
+        self.last_block_info.complexity = 0;
 
         // First decrement counters in each coin so the message of the 
         // transaction will be correct to calculate the sender
@@ -220,6 +1288,7 @@ impl State {
 
                 // Remove from coin owner map
                 self.coin_info_map.remove(&transaction.coin);
+                self.unverified.remove(&transaction.coin);
             } else {
                 // Update coin owner
                 coin_info.owner = sender.clone();
@@ -231,6 +1300,168 @@ impl State {
                 self.owner_coin_add(&sender, &transaction.coin);
             }
         }
+
+        // Collect the changes and drop the untracked coins
+        self.commit_coins(transactions, true);
+        let diff = coins_before.map(|coins_before| {
+            self.get_diff_of(block_before, coins_before, transactions)
+        });
+        self.watch_evict(transactions.iter().map(|tr| &tr.coin));
+        self.publish_diff(diff, true)
+    }
+
+    /// Roll down the state with the last block as `roll_down` does, but 
+    /// restore the full info of the previous block (e.g. from 
+    /// `Blockchain::get_block_info`).
+    pub fn roll_down_with_info(&mut self, info_prev: &BlockInfo, 
+                               block: &Block, transactions: &[Transaction], 
+                               schema: &Schema) -> Option<StateDiff> {
+        assert_eq!(info_prev.hash, block.hash_prev);
+        let mut diff = self.roll_down(info_prev.bix + 1, block, transactions,
+                                      schema);
+        self.last_block_info = info_prev.clone();
+        if let Some(diff) = diff.as_mut() {
+            diff.block_after = info_prev.clone();
+        }
+        diff
+    }
+
+    /// Apply the changes collected by `StateOverlay` over this state.
+    pub fn apply_changes(&mut self, changes: StateChanges) {
+        let mut commitment = Commitment::from_number(&self.commitment)
+            .unwrap_or_default();
+        for (coin, coin_info) in changes.coin_info_map.into_iter() {
+            // Remove the coin from the previous owner
+            if let Some(ci) = self.coin_info_map.get(&coin).cloned() {
+                commitment.remove(&coin_element(&coin, &ci));
+                self.owner_coin_remove(&ci.owner, &coin);
+            }
+
+            // The owner of an untracked coin is assumed by the overlay
+            if self.coin_counter_map.remove(&coin).is_some() {
+                self.unverified.insert(coin.clone());
+            }
+
+            // Update the coin info and add the coin to the new owner
+            let owner = coin_info.owner.clone();
+            commitment.insert(&coin_element(&coin, &coin_info));
+            self.coin_info_map.insert(coin.clone(), coin_info);
+            self.owner_coin_add(&owner, &coin);
+            self.watch_evict([&coin].into_iter());
+        }
+        self.commitment = commitment.to_number();
+        self.last_block_info = changes.last_block_info;
+    }
+
+    /// Move the untracked coins of the transactions into the coin info map
+    /// with the given owners (they are assumed, so the coins are marked as of
+    /// unverified origin).
+    fn watch_restore<'a, I>(&mut self, transactions: &[Transaction], 
+                            owners: I) where I: Iterator<Item = &'a U256> {
+        for (tr, owner) in transactions.iter().zip(owners) {
+            if let Some(cc) = self.coin_counter_map.remove(&tr.coin) {
+                let coin_info = CoinInfo { 
+                    owner: owner.clone(), order: cc.order, counter: cc.counter,
+                };
+                self.coin_info_map.insert(tr.coin.clone(), coin_info);
+                self.owner_coin_add(owner, &tr.coin);
+                self.unverified.insert(tr.coin.clone());
+            }
+        }
+    }
+
+    /// Move the coins of the not watched owners into the coin counter map
+    /// (in the watch mode only).
+    fn watch_evict<'a, I>(&mut self, coins: I) 
+                          where I: Iterator<Item = &'a U256> {
+        if self.watched.is_none() {
+            return;
+        }
+        for coin in coins {
+            let owner = match self.coin_info_map.get(coin) {
+                Some(ci) if !self.is_tracked(&ci.owner) => ci.owner.clone(),
+                _ => continue,
+            };
+            self.owner_coin_remove(&owner, coin);
+            self.unverified.remove(coin);
+            let ci = self.coin_info_map.remove(coin).unwrap();
+            self.coin_counter_map.insert(coin.clone(), CoinCounter {
+                order: ci.order, counter: ci.counter,
+            });
+        }
+    }
+
+    /// Check the changes of the blocks are collected (for the diffs or the
+    /// observers).
+    fn collects_changes(&self) -> bool {
+        self.diff_tracking || !self.observers.is_empty()
+    }
+
+    /// Call the observers for the changes and return them if the diffs are
+    /// tracked.
+    fn publish_diff(&self, diff: Option<StateDiff>, 
+                    unwound: bool) -> Option<StateDiff> {
+        if let Some(diff) = diff.as_ref() {
+            self.notify_observers(diff, unwound);
+        }
+        diff.filter(|_| self.diff_tracking)
+    }
+
+    /// Call the observers for the changes of the rolled up or rolled down
+    /// block.
+    fn notify_observers(&self, diff: &StateDiff, unwound: bool) {
+        for observer in self.observers.iter() {
+            for change in diff.coins.iter() {
+                match (unwound, &change.before, &change.after) {
+                    (true, _, _) => observer.on_coin_unwound(change),
+                    (false, None, Some(after)) => 
+                        observer.on_coin_created(&change.coin, after),
+                    (false, Some(before), Some(after)) => 
+                        observer.on_coin_transferred(&change.coin, 
+                                                     &before.owner, after),
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    /// Insert (or remove) the coins of the transactions into the 
+    /// commitment.
+    fn commit_coins(&mut self, transactions: &[Transaction], insert: bool) {
+        let mut commitment = Commitment::from_number(&self.commitment)
+            .unwrap_or_default();
+        for tr in transactions.iter() {
+            if let Some(ci) = self.coin_info_map.get(&tr.coin) {
+                let elem = coin_element(&tr.coin, ci);
+                if insert {
+                    commitment.insert(&elem);
+                } else {
+                    commitment.remove(&elem);
+                }
+            }
+        }
+        self.commitment = commitment.to_number();
+    }
+
+    fn get_coin_infos_of(&self, transactions: &[Transaction]) -> 
+                         Vec<Option<CoinInfo>> {
+        transactions.iter()
+            .map(|tr| self.coin_info_map.get(&tr.coin).cloned()).collect()
+    }
+
+    fn get_diff_of(&self, block_before: BlockInfo, 
+                   coins_before: Vec<Option<CoinInfo>>,
+                   transactions: &[Transaction]) -> StateDiff {
+        StateDiff {
+            block_before,
+            block_after: self.last_block_info.clone(),
+            coins: transactions.iter().zip(coins_before)
+                .map(|(tr, before)| CoinChange {
+                    coin: tr.coin.clone(),
+                    before,
+                    after: self.coin_info_map.get(&tr.coin).cloned(),
+                }).collect(),
+        }
     }
 
     fn owner_coin_add(&mut self, owner: &U256, coin: &U256) {
@@ -251,6 +1482,9 @@ impl State {
         // Insert the coin
         self.owner_coins_map.get_mut(owner).unwrap()
             .get_mut(&order).unwrap().insert(coin.clone());
+
+        // Index the coin by order
+        self.order_coins_map.entry(order).or_default().insert(coin.clone());
     }
 
     fn owner_coin_remove(&mut self, owner: &U256, coin: &U256) {
@@ -270,5 +1504,936 @@ impl State {
         if self.owner_coins_map[owner].is_empty() {
             self.owner_coins_map.remove(owner);
         }
+
+        // Remove the coin from the order index
+        let coins = self.order_coins_map.get_mut(&order).unwrap();
+        coins.remove(coin);
+        if coins.is_empty() {
+            self.order_coins_map.remove(&order);
+        }
+    }
+}
+
+
+/// Changes of the state collected by `StateOverlay`.
+#[derive(Debug, Clone)]
+pub struct StateChanges {
+    /// New infos of the changed coins.
+    pub coin_info_map: CoinInfoMap,
+
+    /// New last block info.
+    pub last_block_info: BlockInfo,
+}
+
+
+/// Copy-on-write view of the state: the rolled up blocks are kept as changes
+/// over the base state, so a candidate block can be applied speculatively
+/// without cloning the whole state. The changes are committed with 
+/// `State::apply_changes` or discarded with the overlay.
+#[derive(Debug, Clone)]
+pub struct StateOverlay<'a> {
+    base: &'a State,
+    coin_info_map: CoinInfoMap,
+    last_block_info: BlockInfo,
+}
+
+
+impl<'a> StateOverlay<'a> {
+    /// Create an overlay without changes.
+    pub fn new(base: &'a State) -> Self {
+        Self {
+            base,
+            coin_info_map: CoinInfoMap::new(),
+            last_block_info: base.last_block_info.clone(),
+        }
+    }
+
+    /// Get the base state.
+    pub fn get_base(&self) -> &State {
+        self.base
+    }
+
+    /// Get owner of the coin by number.
+    pub fn get_owner(&self, coin: &U256) -> Option<&U256> {
+        self.get_coin_info(coin).map(|cs| &cs.owner)
+    }
+
+    /// Get coin state by number.
+    pub fn get_coin_info(&self, coin: &U256) -> Option<&CoinInfo> {
+        self.coin_info_map.get(coin)
+            .or_else(|| self.base.get_coin_info(coin))
+    }
+
+    /// Get counter of the coin by number.
+    pub fn get_coin_counter(&self, coin: &U256) -> u64 {
+        match self.coin_info_map.get(coin) {
+            Some(cs) => cs.counter,
+            None => self.base.get_coin_counter(coin),
+        }
+    }
+
+    /// Get last block info.
+    pub fn get_last_block_info(&self) -> &BlockInfo {
+        &self.last_block_info
+    }
+
+    /// Check whether there are any changes.
+    pub fn is_changed(&self) -> bool {
+        self.last_block_info.bix != self.base.last_block_info.bix
+    }
+
+    /// Build a partial state with the coins of the transactions as they are
+    /// in the overlay. It is enough to validate the block of the 
+    /// transactions (or the groups in the pool) on top of the overlay.
+    pub fn load_state(&self, transactions: &[Transaction]) -> State {
+        let coin_info_map = transactions.iter()
+            .filter_map(|tr| self.get_coin_info(&tr.coin)
+                                 .map(|ci| (tr.coin.clone(), ci.clone())))
+            .collect();
+        let mut state = State::from_coin_infos(coin_info_map, 
+                                               self.last_block_info.clone());
+        state.set_coin_order_cache(self.base.coin_order_cache.clone());
+        state.params = self.base.params.clone();
+        state.chain_id = self.base.chain_id;
+        state.replay_protection_bix = self.base.replay_protection_bix;
+
+        // Untracked coins of the watch mode
+        for tr in transactions.iter() {
+            if !self.coin_info_map.contains_key(&tr.coin) &&
+                    let Some(cc) = self.base.coin_counter_map.get(&tr.coin) {
+                state.coin_counter_map.insert(tr.coin.clone(), cc.clone());
+            }
+        }
+
+        state
+    }
+
+    /// Roll up the overlay with the next block.
+    pub fn roll_up(&mut self, bix: u64, block: &Block, 
+                   transactions: &[Transaction], schema: &Schema) {
+        let state = self.load_state(transactions);
+        let senders = Transaction::calc_senders(transactions, &state, schema);
+        self.roll_up_state(state, bix, block, transactions, &senders);
+    }
+
+    /// Roll up the overlay with the next block and the senders of its
+    /// transactions calculated for the overlay.
+    pub fn roll_up_with_senders(&mut self, bix: u64, block: &Block, 
+                                transactions: &[Transaction], 
+                                senders: &[U256]) {
+        let state = self.load_state(transactions);
+        self.roll_up_state(state, bix, block, transactions, senders);
+    }
+
+    /// Take the changes to commit them into the base state.
+    pub fn into_changes(self) -> StateChanges {
+        StateChanges {
+            coin_info_map: self.coin_info_map,
+            last_block_info: self.last_block_info,
+        }
+    }
+
+    fn roll_up_state(&mut self, mut state: State, bix: u64, block: &Block, 
+                     transactions: &[Transaction], senders: &[U256]) {
+        // Roll up the partial state
+        state.roll_up_with_senders(bix, block, transactions, senders);
+
+        // Keep the changed coins
+        for tr in transactions.iter() {
+            self.coin_info_map.insert(tr.coin.clone(), 
+                                      state.coin_info_map[&tr.coin].clone());
+        }
+        self.last_block_info = state.last_block_info;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin::coin_random;
+    use crate::error::ErrorKind;
+    use crate::testing::TestAccount;
+
+    #[test]
+    fn test_overlay() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let (key1, public1) = schema.gen_pair(&mut rng);
+        let public2: U256 = schema.gen_pair(&mut rng).1;
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public1);
+
+        let transactions = vec![
+            Transaction::build(&mut rng, coin.clone(), public2.clone(), 
+                               &key1, 0, &schema),
+        ];
+        let info = BlockInfo::genesis();
+        let block = Block::new(info.offset, 1, info.hash.clone(), 
+                               validator, U256::from(0), U256::from(1));
+
+        // Apply to the overlay only
+        let mut state = State::new();
+        let mut overlay = StateOverlay::new(&state);
+        overlay.roll_up(1, &block, &transactions, &schema);
+        assert!(overlay.is_changed());
+        assert_eq!(overlay.get_owner(&coin), Some(&public2));
+        assert_eq!(overlay.get_coin_counter(&coin), 1);
+        assert_eq!(overlay.get_last_block_info().bix, 1);
+        assert_eq!(overlay.load_state(&transactions).get_owner(&coin), 
+                   Some(&public2));
+        assert_eq!(state.get_owner(&coin), None);
+
+        // Commit
+        let changes = overlay.into_changes();
+        let mut state2 = state.clone().with_diff_tracking(true);
+        state.apply_changes(changes);
+        let diff = state2.roll_up(1, &block, &transactions, &schema)
+                         .unwrap();
+        assert_eq!(state.get_owner(&coin), Some(&public2));
+        assert_eq!(state.get_coins(&public2), state2.get_coins(&public2));
+        assert_eq!(state.get_last_block_info().hash, 
+                   state2.get_last_block_info().hash);
+
+        // Diff
+        assert_eq!(diff.iter_created().count(), 1);
+        assert_eq!(diff.iter_transferred().next().unwrap().coin, coin);
+        assert_eq!(StateDiff::between(&State::new(), &state2), diff);
+        let diff_down = state2.roll_down(1, &block, &transactions, &schema)
+                              .unwrap();
+        assert!(diff_down.coins[0].is_removed());
+        assert_eq!(diff_down.coins, diff.reverse().coins);
+    }
+
+    #[test]
+    fn test_watch_mode() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let (key1, public1) = schema.gen_pair(&mut rng);
+        let (key2, public2) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin1 = coin_random(&mut rng, &public1);
+        let coin2 = coin_random(&mut rng, &public2);
+
+        // The second coin goes to the first owner in the second block
+        let transactions_list = [
+            vec![
+                Transaction::build(&mut rng, coin1.clone(), public1.clone(),
+                                   &key1, 0, &schema),
+                Transaction::build(&mut rng, coin2.clone(), public2.clone(),
+                                   &key2, 0, &schema),
+            ],
+            vec![
+                Transaction::build(&mut rng, coin2.clone(), public1.clone(),
+                                   &key2, 1, &schema),
+            ],
+        ];
+
+        let mut state = State::new();
+        let mut watch = State::new();
+        watch.set_watched(HashSet::from([public1.clone()]));
+        let mut blocks = Vec::new();
+        for (ix, transactions) in transactions_list.iter().enumerate() {
+            let info = state.get_last_block_info().clone();
+            let block = Block::new(info.offset, transactions.len() as u64,
+                                   info.hash.clone(), validator.clone(),
+                                   U256::from(0), U256::from(ix as u64 + 1));
+            state.roll_up(info.bix + 1, &block, transactions, &schema);
+            watch.roll_up(info.bix + 1, &block, transactions, &schema);
+            assert_eq!(watch.get_commitment(), state.get_commitment());
+            blocks.push(block);
+        }
+
+        // The coin of the watched address is known with its counter
+        assert_eq!(watch.get_coin_info(&coin2), state.get_coin_info(&coin2));
+        assert_eq!(watch.get_balance(&public1), state.get_balance(&public1));
+        assert_eq!(state.get_commitment(), &state.calc_commitment());
+
+        // Binary format keeps the watch mode
+        let watch2 = State::from_binary(&watch.to_binary()).unwrap();
+        assert_eq!(watch2.get_watched(), watch.get_watched());
+        assert_eq!(watch2.get_commitment(), watch.get_commitment());
+
+        // Roll down
+        state.roll_down(2, &blocks[1], &transactions_list[1], &schema);
+        watch.roll_down(2, &blocks[1], &transactions_list[1], &schema);
+        assert_eq!(watch.get_commitment(), state.get_commitment());
+        assert_eq!(watch.get_owner(&coin2), None);
+        assert_eq!(watch.get_coin_counter(&coin2), 1);
+    }
+
+    #[test]
+    fn test_watch_mode_untracked_coin() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let public1: U256 = schema.gen_pair(&mut rng).1;
+        let (key2, public2) = schema.gen_pair(&mut rng);
+        let (key3, public3) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin2 = coin_random(&mut rng, &public2);
+
+        // The coin mined by the second owner goes to the third one, then it
+        // is spent to the watched address
+        let transactions_list = [
+            vec![
+                Transaction::build(&mut rng, coin2.clone(), public3.clone(),
+                                   &key2, 0, &schema),
+            ],
+            vec![
+                Transaction::build(&mut rng, coin2.clone(), public1.clone(),
+                                   &key3, 1, &schema),
+            ],
+        ];
+
+        let mut state = State::new();
+        let mut watch = State::new();
+        watch.set_watched(HashSet::from([public1.clone()]));
+        for (ix, transactions) in transactions_list.iter().enumerate() {
+            // The block is validated and grouped in the watch mode
+            let senders = Transaction::calc_senders(transactions, &watch, 
+                                                    &schema);
+            assert_eq!(senders, Transaction::calc_senders(transactions, 
+                                                          &state, &schema));
+            Block::validate_transactions(transactions, &validator, &watch, 
+                                         &senders).unwrap();
+            assert_eq!(transactions[0].get_order(&watch, &senders[0]),
+                       transactions[0].get_order(&state, &senders[0]));
+
+            let info = state.get_last_block_info().clone();
+            let block = Block::new(info.offset, transactions.len() as u64,
+                                   info.hash.clone(), validator.clone(),
+                                   U256::from(0), U256::from(ix as u64 + 1));
+            state.roll_up(info.bix + 1, &block, transactions, &schema);
+            watch.roll_up(info.bix + 1, &block, transactions, &schema);
+            assert_eq!(watch.get_commitment(), state.get_commitment());
+        }
+        assert!(!watch.is_untracked_coin(&coin2));
+        assert_eq!(watch.get_coin_info(&coin2), state.get_coin_info(&coin2));
+
+        // The ownership of the tracked coins is still checked
+        let tr = Transaction::build(&mut rng, coin2, public3.clone(), &key3, 
+                                    2, &schema);
+        assert_eq!(tr.validate_coin(&watch, &public3).unwrap_err().kind(),
+                   ErrorKind::TransactionInvalidSender);
+    }
+
+    #[test]
+    fn test_watch_mode_unverified() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let public1: U256 = schema.gen_pair(&mut rng).1;
+        let (key2, public2) = schema.gen_pair(&mut rng);
+        let (key3, public3) = schema.gen_pair(&mut rng);
+        let (key4, public4) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin2 = coin_random(&mut rng, &public2);
+
+        // The coin mined by the second owner goes to the third one
+        let mut state = State::new();
+        let mut watch = State::new();
+        watch.set_watched(HashSet::from([public1.clone()]));
+        let transactions = vec![
+            Transaction::build(&mut rng, coin2.clone(), public3.clone(),
+                               &key2, 0, &schema),
+        ];
+        let block = Block::new(0, 1, state.get_last_block_info().hash.clone(),
+                               validator.clone(), U256::from(0),
+                               U256::from(1));
+        state.roll_up(1, &block, &transactions, &schema);
+        watch.roll_up(1, &block, &transactions, &schema);
+        assert!(watch.is_untracked_coin(&coin2));
+
+        // The honest spend of the third owner and the forged one of the
+        // fourth, the latter is accepted in the watch mode only
+        let honest = vec![
+            Transaction::build(&mut rng, coin2.clone(), public1.clone(),
+                               &key3, 1, &schema),
+        ];
+        let forged = vec![
+            Transaction::build(&mut rng, coin2.clone(), public1.clone(),
+                               &key4, 1, &schema),
+        ];
+        assert_eq!(forged[0].validate_coin(&state, &public4).unwrap_err()
+                                                            .kind(),
+                   ErrorKind::TransactionInvalidSender);
+        forged[0].validate_coin(&watch, &public4).unwrap();
+
+        let mut forged_watch = watch.clone();
+        for (transactions, watch) in [(&honest, &mut watch),
+                                      (&forged, &mut forged_watch)] {
+            let block = Block::new(1, 1, block.hash.clone(),
+                                   validator.clone(), U256::from(0),
+                                   U256::from(2));
+            watch.roll_up(2, &block, transactions, &schema);
+
+            // The coin is credited but its origin is unverified
+            assert_eq!(watch.get_owner(&coin2), Some(&public1));
+            assert!(!watch.is_verified_coin(&coin2));
+            assert_eq!(watch.stats().unverified_count, 1);
+            assert!(watch.verify_integrity().is_ok());
+
+            // The marks are kept in the binary form
+            let watch2 = State::from_binary(&watch.to_binary()).unwrap();
+            assert_eq!(watch2.get_unverified_coins().collect::<Vec<_>>(),
+                       vec![&coin2]);
+            assert_eq!(watch2.get_commitment(), watch.get_commitment());
+        }
+        let block = Block::new(1, 1, block.hash.clone(), validator.clone(),
+                               U256::from(0), U256::from(2));
+        state.roll_up(2, &block, &honest, &schema);
+
+        // The forged spend breaks the commitment
+        assert_ne!(forged_watch.get_commitment(), state.get_commitment());
+        assert_eq!(forged_watch.verify_commitment(2, state.get_commitment())
+                               .unwrap_err().kind(),
+                   ErrorKind::StateCommitmentMismatch);
+        assert!(!forged_watch.is_verified_coin(&coin2));
+
+        // The honest one is verified at the same block only
+        assert_eq!(watch.verify_commitment(1, state.get_commitment())
+                        .unwrap_err().kind(),
+                   ErrorKind::StateCommitmentMismatch);
+        watch.verify_commitment(2, state.get_commitment()).unwrap();
+        assert!(watch.is_verified_coin(&coin2));
+        assert_eq!(watch.get_unverified_coins().count(), 0);
+
+        // The watch states of the older formats have the former commitment
+        let mut bytes = watch.to_binary();
+        bytes.truncate(bytes.len() - 32);
+        bytes[0] = STATE_FORMAT_VERSION_2;
+        bytes.extend(hash_of_bytes(&bytes).to_bytes());
+        assert_eq!(State::from_binary(&bytes).unwrap_err().kind(),
+                   ErrorKind::EncodingInvalid);
+    }
+
+    #[test]
+    fn test_roll_up_many() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let (key1, public1) = schema.gen_pair(&mut rng);
+        let (key2, public2) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public1);
+
+        // The coin goes to the second owner and back
+        let transactions_list = [
+            vec![Transaction::build(&mut rng, coin.clone(), public2.clone(),
+                                    &key1, 0, &schema)],
+            vec![Transaction::build(&mut rng, coin.clone(), public1.clone(),
+                                    &key2, 1, &schema)],
+        ];
+
+        let mut state = State::new().with_diff_tracking(true);
+        let mut blocks = Vec::new();
+        let mut diffs = Vec::new();
+        for (ix, transactions) in transactions_list.into_iter().enumerate() {
+            let info = state.get_last_block_info().clone();
+            let block = Block::new(info.offset, 1, info.hash.clone(), 
+                                   validator.clone(), U256::from(0), 
+                                   U256::from(ix as u64 + 1));
+            diffs.push(state.roll_up(info.bix + 1, &block, &transactions, 
+                                     &schema).unwrap());
+            blocks.push(BlockData { bix: info.bix + 1, block, transactions });
+        }
+
+        let mut state2 = State::new().with_diff_tracking(true);
+        let diff = state2.roll_up_many(&blocks, &schema).unwrap();
+        assert_eq!(state2.get_owner(&coin), Some(&public1));
+        assert_eq!(state2.get_coin_counter(&coin), 2);
+        assert_eq!(state2.get_commitment(), state.get_commitment());
+        assert_eq!(diff, StateDiff::between(&State::new(), &state2));
+
+        let mut diff2 = diffs[0].clone();
+        diff2.extend(diffs[1].clone());
+        assert_eq!(diff2, diff);
+    }
+
+    #[test]
+    fn test_diff_tracking() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let (key1, public1) = schema.gen_pair(&mut rng);
+        let public2: U256 = schema.gen_pair(&mut rng).1;
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let account = TestAccount::new(&key1, &public1, &schema);
+
+        let transactions = [account.random_transfer(&mut rng, &public2)];
+        let info = BlockInfo::genesis();
+        let block = Block::new(info.offset, 1, info.hash.clone(), 
+                               validator, U256::from(0), U256::from(1));
+
+        // Not tracked by default, the state is still updated
+        let mut state = State::new();
+        assert!(!state.is_diff_tracking());
+        assert!(state.roll_up(1, &block, &transactions, &schema).is_none());
+        assert_eq!(state.get_last_block_info().bix, 1);
+        assert!(state.roll_down(1, &block, &transactions, &schema).is_none());
+        assert_eq!(state.get_last_block_info().bix, 0);
+
+        // Tracked
+        state.set_diff_tracking(true);
+        let diff = state.roll_up(1, &block, &transactions, &schema).unwrap();
+        assert_eq!(diff, StateDiff::between(&State::new(), &state));
+        let diff_down = state.roll_down(1, &block, &transactions, &schema)
+                             .unwrap();
+        assert_eq!(diff_down.coins, diff.reverse().coins);
+
+        // Not tracked again, the flag is not serialized
+        let state = state.with_diff_tracking(false);
+        assert!(!state.is_diff_tracking());
+        let state2 = State::new().with_diff_tracking(true);
+        let content = serde_json::to_string(&state2).unwrap();
+        let state2: State = serde_json::from_str(&content).unwrap();
+        assert!(!state2.is_diff_tracking());
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingObserver {
+        counts: std::sync::Mutex<[usize; 3]>,
+    }
+
+    impl StateObserver for CountingObserver {
+        fn on_coin_created(&self, _coin: &U256, _coin_info: &CoinInfo) {
+            self.counts.lock().unwrap()[0] += 1;
+        }
+
+        fn on_coin_transferred(&self, _coin: &U256, _owner_prev: &U256, 
+                               _coin_info: &CoinInfo) {
+            self.counts.lock().unwrap()[1] += 1;
+        }
+
+        fn on_coin_unwound(&self, _change: &CoinChange) {
+            self.counts.lock().unwrap()[2] += 1;
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let (key1, public1) = schema.gen_pair(&mut rng);
+        let public2: U256 = schema.gen_pair(&mut rng).1;
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let coin = coin_random(&mut rng, &public1);
+
+        let observer = Arc::new(CountingObserver::default());
+        let mut state = State::new();
+        state.add_observer(observer.clone());
+
+        let transactions = [
+            Transaction::build(&mut rng, coin.clone(), public2.clone(), 
+                               &key1, 0, &schema),
+        ];
+        let info = BlockInfo::genesis();
+        let block = Block::new(info.offset, 1, info.hash.clone(), 
+                               validator, U256::from(0), U256::from(1));
+        assert!(state.roll_up(1, &block, &transactions, &schema).is_none());
+        assert_eq!(*observer.counts.lock().unwrap(), [1, 0, 0]);
+        state.roll_down(1, &block, &transactions, &schema);
+        assert_eq!(*observer.counts.lock().unwrap(), [1, 0, 1]);
+
+        let mut state2 = state.clone();
+        state2.roll_up(1, &block, &transactions, &schema);
+        assert_eq!(*observer.counts.lock().unwrap(), [1, 0, 1]);
+
+        state.clear_observers();
+        state.roll_up(1, &block, &transactions, &schema);
+        assert_eq!(*observer.counts.lock().unwrap(), [1, 0, 1]);
+    }
+
+    #[test]
+    fn test_balance() {
+        let mut rng = rand::rng();
+        let owner: U256 = Schema::new().gen_pair(&mut rng).1;
+
+        let mut coin_info_map = CoinInfoMap::new();
+        for (ix, order) in [10, 10, 12].into_iter().enumerate() {
+            coin_info_map.insert(U256::from(ix as u64), CoinInfo {
+                owner: owner.clone(), order, counter: 1,
+            });
+        }
+        let state = State::from_coin_infos(coin_info_map, 
+                                           BlockInfo::genesis());
+
+        assert_eq!(state.get_balance(&owner), U256::from(6144));
+        assert_eq!(state.get_order_counts(&owner), 
+                   BTreeMap::from([(10, 2), (12, 1)]));
+        assert_eq!(state.get_balance(&U256::from(1)), U256::from(0));
+        assert!(state.get_order_counts(&U256::from(1)).is_empty());
+    }
+
+    #[test]
+    fn test_binary() {
+        let mut coin_info_map = CoinInfoMap::new();
+        for ix in 0..3 {
+            coin_info_map.insert(U256::from(ix), CoinInfo {
+                owner: U256::from(ix % 2), order: ix + 5, counter: 1,
+            });
+        }
+        let state = State::from_coin_infos(coin_info_map, 
+                                           BlockInfo::genesis());
+
+        let bytes = state.to_binary();
+        assert_eq!(bytes.len(), 1 + 64 + 8 + 3 * 80 + 1 + 8 + 1 + 48 + 32);
+
+        let state2 = State::from_binary(&bytes).unwrap();
+        assert_eq!(StateDiff::between(&state, &state2).coins, vec![]);
+        assert_eq!(state2.get_coins(&U256::from(0)), 
+                   state.get_coins(&U256::from(0)));
+
+        // Broken checksum and version
+        let mut bytes2 = bytes.clone();
+        bytes2[100] ^= 1;
+        assert_eq!(State::from_binary(&bytes2).unwrap_err().kind(),
+                   ErrorKind::EncodingInvalid);
+        let mut bytes3 = bytes[..bytes.len() - 32].to_vec();
+        bytes3[0] = STATE_FORMAT_VERSION + 1;
+        bytes3.extend(hash_of_bytes(&bytes3).to_bytes());
+        assert_eq!(State::from_binary(&bytes3).unwrap_err().kind(),
+                   ErrorKind::EncodingInvalid);
+
+        // The first format version has no spec
+        let mut bytes4 = bytes[..bytes.len() - 32 - 57].to_vec();
+        bytes4[0] = STATE_FORMAT_VERSION_1;
+        bytes4.extend(hash_of_bytes(&bytes4).to_bytes());
+        let state4 = State::from_binary(&bytes4).unwrap();
+        assert_eq!(state4.get_chain_id(), CHAIN_ID_MAINNET);
+        assert_eq!(state4.get_params(), &ProtocolParams::default());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut coin_info_map = CoinInfoMap::new();
+        for (ix, (owner, order)) in [(1, 5), (2, 7), (1, 5)]
+                                        .into_iter().enumerate() {
+            coin_info_map.insert(U256::from(ix as u64), CoinInfo {
+                owner: U256::from(owner), order, counter: 1,
+            });
+        }
+        let state = State::from_coin_infos(coin_info_map, 
+                                           BlockInfo::genesis());
+
+        let stats = state.stats();
+        assert_eq!(stats.coin_count, 3);
+        assert_eq!(stats.untracked_count, 0);
+        assert_eq!(stats.owner_count, 2);
+        assert_eq!(stats.orders, BTreeMap::from([(5, 2), (7, 1)]));
+        assert!(stats.memory_bytes > 3 * size_of::<(U256, CoinInfo)>());
+        assert!(stats.memory_bytes < State::new().stats().memory_bytes + 
+                                     10000);
+    }
+
+    #[test]
+    fn test_verify_integrity() {
+        let mut coin_info_map = CoinInfoMap::new();
+        for ix in 0..3 {
+            coin_info_map.insert(U256::from(ix), CoinInfo {
+                owner: U256::from(ix % 2), order: 5, counter: 1,
+            });
+        }
+        let mut state = State::from_coin_infos(coin_info_map, 
+                                               BlockInfo::genesis());
+        assert!(state.verify_integrity().is_ok());
+
+        // Dangling coin and the coin with another order
+        state.owner_coins_map.get_mut(&U256::from(1)).unwrap()
+             .get_mut(&5).unwrap().insert(U256::from(7));
+        state.coin_info_map.get_mut(&U256::from(0)).unwrap().order = 6;
+        let errors = state.verify_integrity();
+        assert!(errors.iter().all(|err| err.kind() == 
+                                        ErrorKind::StateInconsistent));
+        let mut coins: Vec<U256> = errors.iter()
+            .filter_map(|err| err.context()?.coin.clone()).collect();
+        coins.sort();
+        assert_eq!(coins, vec![U256::from(0), U256::from(0), U256::from(0), 
+                               U256::from(7)]);
+    }
+
+    #[test]
+    fn test_order_index() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+
+        let (key1, public1) = schema.gen_pair(&mut rng);
+        let public2: U256 = schema.gen_pair(&mut rng).1;
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let account = TestAccount::new(&key1, &public1, &schema);
+
+        // The coins of the order by scanning the owners
+        let scan = |state: &State, order: u64| {
+            let mut coins: Vec<U256> = state.owner_coins_map.values()
+                .filter_map(|coins_map| coins_map.get(&order))
+                .flatten().cloned().collect();
+            coins.sort();
+            coins
+        };
+        let index = |state: &State, order: u64| {
+            let mut coins: Vec<U256> = state.get_coins_of_order(order)
+                                            .cloned().collect();
+            coins.sort();
+            coins
+        };
+
+        let coins: Vec<U256> = (0..3).map(|_| account.coin(&mut rng))
+                                     .collect();
+        let mint = coins.iter().map(
+            |coin| account.transfer(&mut rng, coin.clone(), &public1)
+        ).collect::<Vec<Transaction>>();
+        let transfer = vec![
+            Transaction::build(&mut rng, coins[0].clone(), public2.clone(), 
+                               &key1, 1, &schema),
+        ];
+
+        let mut state = State::new();
+        let mut watch = State::new();
+        watch.set_watched(HashSet::from([public1.clone()]));
+        let mut blocks = Vec::new();
+        for transactions in [&mint, &transfer] {
+            let info = state.get_last_block_info().clone();
+            let block = Block::new(info.offset, transactions.len() as u64,
+                                   info.hash.clone(), validator.clone(),
+                                   U256::from(0), U256::from(info.bix + 1));
+            state.roll_up(info.bix + 1, &block, transactions, &schema);
+            watch.roll_up(info.bix + 1, &block, transactions, &schema);
+            blocks.push(block);
+        }
+
+        let orders: HashSet<u64> = coins.iter()
+            .map(|coin| state.get_coin_info(coin).unwrap().order).collect();
+        for order in orders.iter() {
+            assert_eq!(index(&state, *order), scan(&state, *order));
+            assert_eq!(index(&watch, *order), scan(&watch, *order));
+        }
+        assert!(state.verify_integrity().is_ok());
+
+        // The untracked coin is not indexed in the watch mode
+        let order0 = state.get_coin_info(&coins[0]).unwrap().order;
+        assert!(state.get_coins_of_order(order0).any(|c| c == &coins[0]));
+        assert!(!watch.get_coins_of_order(order0).any(|c| c == &coins[0]));
+
+        // Roll down
+        state.roll_down(2, &blocks[1], &transfer, &schema);
+        state.roll_down(1, &blocks[0], &mint, &schema);
+        for order in orders.iter() {
+            assert_eq!(state.get_coins_of_order(*order).count(), 0);
+        }
+        assert!(state.order_coins_map.is_empty());
+
+        // Dangling coin of the index
+        state.order_coins_map.entry(5).or_default().insert(U256::from(7));
+        let errors = state.verify_integrity();
+        assert_eq!(errors.iter().next().unwrap().context().unwrap().coin, 
+                   Some(U256::from(7)));
+    }
+
+    #[test]
+    fn test_serde_compact() {
+        let mut coin_info_map = CoinInfoMap::new();
+        for ix in 0..20 {
+            coin_info_map.insert(U256::from(ix), CoinInfo {
+                owner: U256::from(ix % 2), order: ix % 5, counter: 1,
+            });
+        }
+        let state = State::from_coin_infos(coin_info_map, 
+                                           BlockInfo::genesis());
+
+        // Compact form
+        let content = serde_json::to_string(&state).unwrap();
+        let state2: State = serde_json::from_str(&content).unwrap();
+        assert_eq!(state2.get_coins(&U256::from(1)), 
+                   state.get_coins(&U256::from(1)));
+        assert!(state2.verify_integrity().is_ok());
+
+        // Nested form of older dumps
+        let mut value = serde_json::to_value(&state).unwrap();
+        value["owner_coins_map"] = 
+            serde_json::to_value(&state.owner_coins_map).unwrap();
+        let content_nested = serde_json::to_string(&value).unwrap();
+        assert!(content_nested.len() > content.len());
+        let state3: State = serde_json::from_str(&content_nested).unwrap();
+        assert_eq!(state3.get_coins(&U256::from(0)), 
+                   state.get_coins(&U256::from(0)));
+        assert!(state3.verify_integrity().is_ok());
+    }
+
+    #[test]
+    fn test_rich_list() {
+        let mut coin_info_map = CoinInfoMap::new();
+        for (ix, (owner, order)) in [(1, 5), (2, 7), (3, 5), (1, 6)]
+                                        .into_iter().enumerate() {
+            coin_info_map.insert(U256::from(ix as u64), CoinInfo {
+                owner: U256::from(owner), order, counter: 1,
+            });
+        }
+        let state = State::from_coin_infos(coin_info_map, 
+                                           BlockInfo::genesis());
+
+        assert_eq!(state.iter_balances().count(), 3);
+        let mut coins: Vec<&U256> = state.get_coins_of_order(5).collect();
+        coins.sort();
+        assert_eq!(coins, vec![&U256::from(0), &U256::from(2)]);
+        assert_eq!(state.get_coins_of_order(8).count(), 0);
+        assert_eq!(state.get_order_supply(), 
+                   BTreeMap::from([(5, 2), (6, 1), (7, 1)]));
+        assert_eq!(state.get_rich_list(0, 10), vec![
+            (U256::from(2), U256::from(128)),
+            (U256::from(1), U256::from(96)),
+            (U256::from(3), U256::from(32)),
+        ]);
+        assert_eq!(state.get_rich_list(1, 1), 
+                   vec![(U256::from(1), U256::from(96))]);
+    }
+
+    #[cfg(feature = "blockchain")]
+    #[tokio::test]
+    async fn test_rebuild() {
+        use crate::difficulty::Retarget;
+        use crate::chain::Checkpoint;
+        use crate::testing::TempDir;
+
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let complexity = 4;
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let account = TestAccount::new(&key, &public, &schema);
+
+        let tmp = TempDir::new("uqoin-state");
+        let blockchain = Blockchain::new(tmp.path()).await.unwrap();
+
+        // Store the blocks
+        let mut state = State::new();
+        for ix in 0..3 {
+            let transactions = vec![
+                account.random_transfer(&mut rng, &U256::from(100))
+            ];
+            let timestamp = 1700000000 + ix;
+            let info = state.get_last_block_info().clone();
+            let senders = Transaction::calc_senders(&transactions, &state,
+                                                    &schema);
+            let nonce = Block::mine(&mut rng, &info, &validator, timestamp, 
+                                    &transactions, complexity, None)
+                .unwrap();
+            let block = Block::build(&info, validator.clone(), timestamp,
+                                     &transactions, U256::from_bytes(&nonce),
+                                     complexity, &state, &senders).unwrap();
+            state.roll_up(info.bix + 1, &block, &transactions, &schema);
+            blockchain.push_new_block(&block, &transactions).await.unwrap();
+        }
+
+        // Rebuild
+        let mut chain_validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity);
+        let mut progress = Vec::new();
+        let state2 = State::rebuild(&blockchain, &schema, 
+                                    &mut chain_validator, 
+                                    |bix, total| progress.push((bix, total)))
+            .await.unwrap();
+        assert_eq!(progress.last(), Some(&(3, 3)));
+        assert_eq!(state2.get_last_block_info(), state.get_last_block_info());
+        assert_eq!(state2.get_commitment(), state.get_commitment());
+        assert_eq!(state2.get_coins(&U256::from(100)), 
+                   state.get_coins(&U256::from(100)));
+        assert!(state2.verify_integrity().is_ok());
+
+        // The blocks are verified on the rebuild
+        let hash = blockchain.get_block_info(2).await.unwrap().hash;
+        let mut chain_validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity)
+            .with_checkpoints(vec![Checkpoint::new(2, hash)]);
+        assert!(State::rebuild(&blockchain, &schema, &mut chain_validator, 
+                               |_, _| {}).await.is_ok());
+
+        let mut chain_validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity)
+            .with_checkpoints(vec![Checkpoint::new(2, U256::from(1))]);
+        assert!(State::rebuild(&blockchain, &schema, &mut chain_validator, 
+                               |_, _| {}).await.is_err());
+
+        let mut chain_validator = ChainValidator::new(Retarget::default())
+            .with_complexity(complexity + 8);
+        assert!(State::rebuild(&blockchain, &schema, &mut chain_validator, 
+                               |_, _| {}).await.is_err());
+    }
+
+    #[cfg(feature = "blockchain")]
+    #[tokio::test]
+    async fn test_chain_spec_round_trip() {
+        use rand::Rng;
+        use crate::testing::TempDir;
+
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let complexity = 4;
+        let (key, public) = schema.gen_pair(&mut rng);
+        let validator: U256 = schema.gen_pair(&mut rng).1;
+        let spec = ChainSpec::new(2, rng.random())
+            .with_complexity(complexity)
+            .with_replay_protection(1);
+
+        let tmp = TempDir::new("uqoin-state");
+        let path = tmp.path().to_string();
+        let blockchain = Blockchain::new(&path).await.unwrap()
+            .with_chain_spec(spec.clone()).await.unwrap();
+
+        // Blocks with the transactions signed for the chain
+        let mut state = State::new().with_chain_spec(&spec);
+        for ix in 0..2 {
+            let mut tr = Transaction::new(coin_random(&mut rng, &public), 
+                                          U256::from(100), U256::from(0), 
+                                          U256::from(0))
+                .with_replay_protection();
+            tr.sign_for_chain(&mut rng, &key, 0, spec.chain_id, &schema);
+            let transactions = vec![tr];
+            let timestamp = 1700000000 + ix;
+            let info = state.get_last_block_info().clone();
+            let senders = Transaction::calc_senders(&transactions, &state,
+                                                    &schema);
+            let nonce = Block::mine(&mut rng, &info, &validator, timestamp, 
+                                    &transactions, complexity, None)
+                .unwrap();
+            let block = Block::build(&info, validator.clone(), timestamp,
+                                     &transactions, U256::from_bytes(&nonce),
+                                     complexity, &state, &senders).unwrap();
+            state.roll_up(info.bix + 1, &block, &transactions, &schema);
+            blockchain.push_new_block(&block, &transactions).await.unwrap();
+        }
+        assert!(state.get_coins(&U256::from(100)).is_some());
+
+        // The dumps keep the spec
+        let path_json = format!("{}/state.json", path);
+        let path_binary = format!("{}/state.bin", path);
+        state.dump(&path_json).await.unwrap();
+        state.dump_binary(&path_binary).await.unwrap();
+        for state2 in [State::load(&path_json).await.unwrap(), 
+                       State::load_binary(&path_binary).await.unwrap()] {
+            assert_eq!(state2.get_chain_id(), spec.chain_id);
+            assert_eq!(state2.get_params(), &spec.params);
+            assert!(state2.requires_replay_protection());
+            assert_eq!(state2.get_commitment(), state.get_commitment());
+        }
+
+        // The replay recovers the senders for the chain
+        let mut chain_validator = ChainValidator::new(spec.get_retarget());
+        let state3 = State::rebuild(&blockchain, &schema, 
+                                    &mut chain_validator, |_, _| {})
+            .await.unwrap();
+        assert_eq!(state3.get_chain_id(), spec.chain_id);
+        assert_eq!(state3.get_commitment(), state.get_commitment());
+
+        let store = StateSnapshotStore::new(&format!("{}/snapshots", path))
+            .await.unwrap();
+        let mut chain_validator = ChainValidator::new(spec.get_retarget());
+        let state4 = State::restore_to(1, &blockchain, &store, &schema,
+                                       &mut chain_validator).await.unwrap();
+        assert_eq!(state4.get_chain_id(), spec.chain_id);
+        assert_eq!(state4.get_last_block_info().bix, 1);
     }
 }
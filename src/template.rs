@@ -0,0 +1,371 @@
+//! Block templates for validators and external miners.
+//!
+//! A `BlockTemplate` is the equivalent of `getblocktemplate`: it is prepared
+//! from the pool (see `Pool::prepare`) on top of the last block of the state
+//! and freezes the block message, so the miner only needs the message, the
+//! number of transactions and the complexity (see `BlockTemplate::mine` or
+//! `Block::mine_msg`). When new groups arrive to the pool, the template can be
+//! refreshed: the new groups are appended, the already included ones stay 
+//! unchanged. The found nonce is turned into a block with 
+//! `BlockTemplate::build`.
+//!
+//! The template remembers the revision of the pool (see `Pool::get_revision`)
+//! and its groups, so `BlockTemplate::get_status` cheaply tells whether the
+//! miner keeps hashing an outdated block, and `BlockTemplate::renew` refreshes
+//! or rebuilds it.
+
+use std::collections::HashSet;
+
+use rand::{Rng, CryptoRng};
+use serde::{Serialize, Deserialize};
+
+use crate::utils::*;
+use crate::schema::Schema;
+use crate::transaction::{Transaction, group_transactions};
+use crate::block::{Block, BlockInfo};
+use crate::state::State;
+use crate::pool::Pool;
+
+
+/// Status of the template against the pool and the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateStatus {
+    /// The pool has not changed since the template was refreshed.
+    Current,
+
+    /// The pool has changed, new groups may be appended by refresh.
+    Outdated,
+
+    /// Some groups of the template left the pool (e.g. they are replaced with
+    /// a higher fee), the template must be prepared again.
+    Invalidated,
+
+    /// The state moved to another block, a new template is needed.
+    Stale,
+}
+
+
+/// Template of the next block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockTemplate {
+    block_info_prev: BlockInfo,
+    validator: U256,
+    timestamp: u64,
+    complexity: usize,
+    transactions: Vec<Transaction>,
+    senders: Vec<U256>,
+    groups: usize,
+    msg: U256,
+    #[serde(default)]
+    group_hashes: Vec<U256>,
+    #[serde(default)]
+    pool_revision: u64,
+}
+
+
+impl BlockTemplate {
+    /// Create a template from the prepared transactions and their senders.
+    pub fn new(block_info_prev: BlockInfo, validator: U256, timestamp: u64, 
+               complexity: usize, transactions: Vec<Transaction>, 
+               senders: Vec<U256>) -> Self {
+        let mut instance = Self {
+            block_info_prev, validator, timestamp, complexity, transactions, 
+            senders, groups: 0, msg: U256::from(0), group_hashes: Vec::new(),
+            pool_revision: 0,
+        };
+        instance.update_msg();
+        instance
+    }
+
+    /// Prepare a template from the pool for the next block of the state. The
+    /// pool must be updated according to this state.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_pool<R: Rng + CryptoRng>(rng: &mut R, pool: &Pool, 
+                                         state: &State, schema: &Schema, 
+                                         validator_key: &U256, timestamp: u64, 
+                                         complexity: usize, 
+                                         groups_max: Option<usize>) -> Self {
+        let mut instance = Self::new(
+            state.get_last_block_info().clone(), 
+            schema.get_public(validator_key), timestamp, complexity, 
+            Vec::new(), Vec::new()
+        );
+        instance.refresh(rng, pool, state, schema, validator_key, groups_max);
+        instance
+    }
+
+    /// Append the groups of the pool that do not conflict with the template
+    /// (the included transactions stay unchanged). Returns the number of 
+    /// added groups. The message is recalculated if anything is added.
+    pub fn refresh<R: Rng + CryptoRng>(&mut self, rng: &mut R, pool: &Pool, 
+                                       state: &State, schema: &Schema, 
+                                       validator_key: &U256, 
+                                       groups_max: Option<usize>) -> usize {
+        // Remember the pool revision
+        self.pool_revision = pool.get_revision();
+
+        // Coins of the template
+        let mut coins_seen: HashSet<U256> = self.transactions.iter()
+            .map(|tr| tr.coin.clone()).collect();
+
+        // Prepare the pool
+        let (transactions, senders) = pool.prepare(rng, state, schema, 
+                                                   validator_key, None);
+
+        // Encoded size of the template transactions
+        let mut bytes = Block::get_encoded_size(&self.transactions);
+
+        // Append non conflicting groups with their extensions
+        let mut added = 0;
+        for (offset, group, ext) in group_transactions(&transactions, state, 
+                                                       &senders) {
+            // Leave if groups_max is reached
+            if groups_max.is_some_and(|max| self.groups >= max) {
+                break;
+            }
+
+            // Skip the group if any coin is already in the template
+            let size = group.len() + ext.len();
+            let trs = &transactions[offset .. offset + size];
+            if trs.iter().any(|tr| coins_seen.contains(&tr.coin)) {
+                continue;
+            }
+
+            // Skip the group if the block limits would be exceeded
+            let trs_bytes = Block::get_encoded_size(trs);
+            if !state.get_params()
+                     .is_size_allowed(self.transactions.len() + size, 
+                                      bytes + trs_bytes) {
+                continue;
+            }
+            bytes += trs_bytes;
+
+            // Append the group
+            coins_seen.extend(trs.iter().map(|tr| tr.coin.clone()));
+            self.transactions.extend(trs.iter().cloned());
+            self.senders.extend(senders[offset .. offset + size].iter()
+                                                                .cloned());
+            self.group_hashes.push(group.get_hash());
+            self.groups += 1;
+            added += 1;
+        }
+
+        // Update the message
+        if added > 0 {
+            self.update_msg();
+        }
+
+        added
+    }
+
+    /// Check whether the template is still the best for the pool and the 
+    /// state.
+    pub fn get_status(&self, pool: &Pool, state: &State) -> TemplateStatus {
+        if state.get_last_block_info().hash != self.block_info_prev.hash {
+            TemplateStatus::Stale
+        } else if pool.get_revision() == self.pool_revision {
+            TemplateStatus::Current
+        } else if !self.group_hashes.iter()
+                       .all(|hash| pool.contains_hash(hash)) {
+            TemplateStatus::Invalidated
+        } else {
+            TemplateStatus::Outdated
+        }
+    }
+
+    /// Refresh the outdated template or prepare the invalidated one again 
+    /// keeping the timestamp and the complexity. The stale template is left
+    /// as is. Returns the status before the renewal.
+    pub fn renew<R: Rng + CryptoRng>(&mut self, rng: &mut R, pool: &Pool, 
+                                     state: &State, schema: &Schema, 
+                                     validator_key: &U256, 
+                                     groups_max: Option<usize>) -> 
+                                     TemplateStatus {
+        let status = self.get_status(pool, state);
+        match status {
+            TemplateStatus::Outdated => {
+                self.refresh(rng, pool, state, schema, validator_key, 
+                             groups_max);
+            },
+            TemplateStatus::Invalidated => {
+                *self = Self::from_pool(rng, pool, state, schema, 
+                                        validator_key, self.timestamp, 
+                                        self.complexity, groups_max);
+            },
+            _ => {},
+        }
+        status
+    }
+
+    /// Frozen block message.
+    pub fn get_msg(&self) -> &U256 {
+        &self.msg
+    }
+
+    /// Number of transactions.
+    pub fn get_size(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Complexity of the block.
+    pub fn get_complexity(&self) -> usize {
+        self.complexity
+    }
+
+    /// Info of the previous block.
+    pub fn get_block_info_prev(&self) -> &BlockInfo {
+        &self.block_info_prev
+    }
+
+    /// Accessor to the transactions.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Accessor to the senders.
+    pub fn senders(&self) -> &[U256] {
+        &self.senders
+    }
+
+    /// Check if the nonce satisfies the complexity (e.g. submitted by an
+    /// external miner).
+    pub fn check_nonce(&self, nonce: &U256) -> bool {
+        let hash = Block::calc_hash(&self.msg, nonce);
+        Block::validate_hash_complexity(&hash, self.get_size(), 
+                                        self.complexity).is_ok()
+    }
+
+    /// Find the nonce for the template.
+    pub fn mine<R: Rng>(&self, rng: &mut R, 
+                        iterations: Option<usize>) -> Option<[u8; 32]> {
+        Block::mine_msg(rng, &self.msg, self.get_size(), self.complexity, 
+                        iterations)
+    }
+
+    /// Build the block with the found nonce. The state must be the one the
+    /// template was prepared for.
+    pub fn build(&self, nonce: U256, state: &State) -> UqoinResult<Block> {
+        Block::build(&self.block_info_prev, self.validator.clone(), 
+                     self.timestamp, &self.transactions, nonce, 
+                     self.complexity, state, &self.senders)
+    }
+
+    /// Recalculate the message.
+    fn update_msg(&mut self) {
+        self.msg = Block::calc_msg(
+            Block::get_version(self.timestamp), self.block_info_prev.offset, 
+            &self.block_info_prev.hash, &self.validator, self.timestamp, 
+            Block::get_declared_complexity(self.timestamp, self.complexity), 
+            &self.transactions
+        );
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestAccount;
+
+    #[test]
+    fn test_block_template() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let state = State::new();
+        let validator_key = schema.gen_key(&mut rng);
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        let account = TestAccount::new(&key, &public, &schema);
+        let addr = U256::from(100);
+
+        let mut pool = Pool::new();
+        pool.add(account.random_group(&mut rng, &addr, false, &state), 
+                 public.clone(), &state).unwrap();
+
+        let mut template = BlockTemplate::from_pool(
+            &mut rng, &pool, &state, &schema, &validator_key, 1700000000, 4, 
+            None
+        );
+        assert_eq!(template.get_size(), 1);
+        let msg = template.get_msg().clone();
+
+        // Refresh with the same pool adds nothing
+        assert_eq!(template.refresh(&mut rng, &pool, &state, &schema, 
+                                    &validator_key, None), 0);
+        assert_eq!(template.get_msg(), &msg);
+
+        // A new group is appended
+        pool.add(account.random_group(&mut rng, &addr, false, &state), 
+                 public.clone(), &state).unwrap();
+        assert_eq!(template.refresh(&mut rng, &pool, &state, &schema, 
+                                    &validator_key, None), 1);
+        assert_eq!(template.get_size(), 2);
+        assert_ne!(template.get_msg(), &msg);
+
+        // Mine and build
+        let nonce = U256::from_bytes(&template.mine(&mut rng, None).unwrap());
+        assert!(template.check_nonce(&nonce));
+        let block = template.build(nonce, &state).unwrap();
+        assert_eq!(block.size, 2);
+        assert!(block.validate(template.transactions(), 
+                               template.get_block_info_prev(), 4, &state, 
+                               template.senders()).is_ok());
+    }
+    #[test]
+    fn test_template_status() {
+        let mut rng = rand::rng();
+        let schema = Schema::new();
+        let mut state = State::new();
+        let validator_key = schema.gen_key(&mut rng);
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        let account = TestAccount::new(&key, &public, &schema);
+        let addr = U256::from(100);
+
+        let coin = account.coin(&mut rng);
+        let mut pool = Pool::new();
+        pool.add(account.group(&mut rng, coin.clone(), &addr, None, &state), 
+                 public.clone(), &state).unwrap();
+
+        let mut template = BlockTemplate::from_pool(
+            &mut rng, &pool, &state, &schema, &validator_key, 1700000000, 4, 
+            None
+        );
+        assert_eq!(template.get_status(&pool, &state), 
+                   TemplateStatus::Current);
+
+        // A new group arrived
+        pool.add(account.random_group(&mut rng, &addr, false, &state), 
+                 public.clone(), &state).unwrap();
+        assert_eq!(template.get_status(&pool, &state), 
+                   TemplateStatus::Outdated);
+        assert_eq!(template.renew(&mut rng, &pool, &state, &schema, 
+                                  &validator_key, None), 
+                   TemplateStatus::Outdated);
+        assert_eq!(template.get_size(), 2);
+        assert_eq!(template.get_status(&pool, &state), 
+                   TemplateStatus::Current);
+
+        // The first group is replaced with a higher fee
+        let fee_coin = account.coin(&mut rng);
+        let group = account.group(&mut rng, coin, &addr, Some(fee_coin), 
+                                  &state);
+        pool.add(group.clone(), public.clone(), &state).unwrap();
+        assert_eq!(template.renew(&mut rng, &pool, &state, &schema, 
+                                  &validator_key, None), 
+                   TemplateStatus::Invalidated);
+        assert_eq!(template.get_size(), 3);
+        assert!(template.transactions().iter()
+                        .any(|tr| tr.get_hash() == group.get_hash()));
+        assert_eq!(template.get_status(&pool, &state), 
+                   TemplateStatus::Current);
+
+        // The state moved to the next block
+        let nonce = U256::from_bytes(&template.mine(&mut rng, None).unwrap());
+        let block = template.build(nonce, &state).unwrap();
+        state.roll_up(1, &block, template.transactions(), &schema);
+        assert_eq!(template.renew(&mut rng, &pool, &state, &schema, 
+                                  &validator_key, None), 
+                   TemplateStatus::Stale);
+    }
+}
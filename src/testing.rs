@@ -0,0 +1,122 @@
+//! Shared helpers of the tests: the temporary directories and the builders
+//! of the transactions and the groups of an account.
+
+use rand::{Rng, CryptoRng};
+
+use crate::utils::*;
+use crate::schema::Schema;
+use crate::coin::{coin_random, coin_order};
+use crate::transaction::{Transaction, Group};
+use crate::state::State;
+
+
+/// Temporary directory that is removed on drop (also if the test panics).
+#[cfg(feature = "blockchain")]
+pub struct TempDir {
+    path: String,
+}
+
+
+#[cfg(feature = "blockchain")]
+impl TempDir {
+    /// Create the directory with the prefix and a random suffix in the
+    /// temporary directory of the system.
+    pub fn new(prefix: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "{}-{}", prefix, rand::rng().random::<u64>()
+        )).display().to_string();
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    /// Get the path of the directory.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+
+#[cfg(feature = "blockchain")]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+
+/// Key pair that builds the transactions of its coins.
+pub struct TestAccount<'a> {
+    pub key: &'a U256,
+    pub public: &'a U256,
+    pub schema: &'a Schema,
+}
+
+
+impl<'a> TestAccount<'a> {
+    /// Create the account of the key pair.
+    pub fn new(key: &'a U256, public: &'a U256, schema: &'a Schema) -> Self {
+        Self { key, public, schema }
+    }
+
+    /// Random coin of the account.
+    pub fn coin<R: Rng>(&self, rng: &mut R) -> U256 {
+        coin_random(rng, self.public)
+    }
+
+    /// Random coin of the account of the order.
+    pub fn coin_with_order<R: Rng>(&self, rng: &mut R, order: u64) -> U256 {
+        std::iter::repeat_with(|| self.coin(rng))
+            .find(|coin| coin_order(coin, self.public) == order).unwrap()
+    }
+
+    /// Random coin of the account of the order at least `min_order`.
+    pub fn coin_of_order<R: Rng>(&self, rng: &mut R,
+                                 min_order: u64) -> U256 {
+        std::iter::repeat_with(|| self.coin(rng))
+            .find(|coin| coin_order(coin, self.public) >= min_order).unwrap()
+    }
+
+    /// Transfer of the coin to the address.
+    pub fn transfer<R: Rng + CryptoRng>(&self, rng: &mut R, coin: U256,
+                                        addr: &U256) -> Transaction {
+        Transaction::build(rng, coin, addr.clone(), self.key, 0, self.schema)
+    }
+
+    /// Transfer of a random coin to the address.
+    pub fn random_transfer<R: Rng + CryptoRng>(&self, rng: &mut R,
+                                               addr: &U256) -> Transaction {
+        let coin = self.coin(rng);
+        self.transfer(rng, coin, addr)
+    }
+
+    /// Transfers of random coins to the address.
+    #[cfg(feature = "blockchain")]
+    pub fn random_transfers<R: Rng + CryptoRng>(
+        &self, rng: &mut R, count: usize, addr: &U256
+    ) -> Vec<Transaction> {
+        (0..count).map(|_| self.random_transfer(rng, addr)).collect()
+    }
+
+    /// Group of the transfer of the coin to the address, with the fee paid
+    /// by the fee coin if it is given.
+    pub fn group<R: Rng + CryptoRng>(&self, rng: &mut R, coin: U256,
+                                     addr: &U256, fee_coin: Option<U256>,
+                                     state: &State) -> Group {
+        let mut transactions = vec![self.transfer(rng, coin, addr)];
+        if let Some(fee_coin) = fee_coin {
+            transactions.push(self.transfer(rng, fee_coin, &U256::from(0)));
+        }
+        let senders = vec![self.public.clone(); transactions.len()];
+        Group::new(transactions, state, &senders).unwrap()
+    }
+
+    /// Group of the transfer of a random coin to the address, with the fee
+    /// paid by a random coin if `fee` is set.
+    pub fn random_group<R: Rng + CryptoRng>(&self, rng: &mut R, addr: &U256,
+                                            fee: bool,
+                                            state: &State) -> Group {
+        let coin = self.coin(rng);
+        let fee_coin = fee.then(|| self.coin(rng));
+        self.group(rng, coin, addr, fee_coin, state)
+    }
+}
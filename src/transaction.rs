@@ -14,21 +14,71 @@
 //! To optimize performance, it's advisable to cache sender addresses after
 //! extraction.
 //!
+//! Transactions are versioned: version 1 is the original layout, its message
+//! and hash are kept unchanged, so the existing chains remain valid. Later
+//! versions append the version number and their own fields to the hashed
+//! elements. Version 2 adds an optional validity window (a range of block
+//! numbers where the transaction can be included), version 3 adds an optional
+//! memo (up to `MEMO_MAX_SIZE` bytes, e.g. an invoice id) that is committed
+//! in the message by its hash. Version 4 uses the tagged hashing (see 
+//! `hash_tagged`) for the message and the hash, so they cannot be 
+//! reinterpreted as hashes of other contexts (e.g. block messages). Version
+//! 5 commits to the chain id in the message (see `ChainSpec::chain_id`), so
+//! a transaction signed on a testnet recovers a foreign sender on the mainnet
+//! and cannot be replayed there. The chain id is not stored in the
+//! transaction: it is taken from the state (see `State::get_chain_id`). The
+//! chains can require version 5 from a block on (see
+//! `ChainSpec::replay_protection_bix`), the earlier blocks stay valid.
+//!
 //! Transactions can be grouped, especially when combining operations like a
 //! main transaction with its associated fee.
 //! Such groupings are valid within a specific blockchain state.
 //! If the state changes, the validity of the group must be reassessed, ensuring
 //! consistency and preventing validation errors.
 
-use rand::Rng;
-use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+
+use rand::{Rng, CryptoRng};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error as DeError;
 
 use crate::validate;
 use crate::utils::*;
 use crate::schema::Schema;
-use crate::coin::{coin_validate, coin_order};
+use crate::coin::coin_validate;
 use crate::state::State;
-use crate::error::ErrorKind;
+use crate::spec::CHAIN_ID_MAINNET;
+use crate::error::{Error, ErrorKind, ValidationReport, ErrorCollector, 
+                   FailFast};
+use crate::codec::{Codec, Writer, Reader};
+
+
+/// Version of the original transaction layout.
+pub const TRANSACTION_VERSION_1: u8 = 1;
+
+/// Version that supports the validity window.
+pub const TRANSACTION_VERSION_2: u8 = 2;
+
+/// Version that supports the memo.
+pub const TRANSACTION_VERSION_3: u8 = 3;
+
+/// Version with the tagged message and hash.
+pub const TRANSACTION_VERSION_4: u8 = 4;
+
+/// Version with the chain id in the message.
+pub const TRANSACTION_VERSION_5: u8 = 5;
+
+/// Latest supported version of transactions.
+pub const TRANSACTION_VERSION: u8 = TRANSACTION_VERSION_5;
+
+/// Tag of the transaction message (since version 4).
+pub const TAG_TRANSACTION_MSG: &str = "uqoin/transaction/msg";
+
+/// Tag of the transaction hash (since version 4).
+pub const TAG_TRANSACTION_HASH: &str = "uqoin/transaction/hash";
+
+/// Maximum size of the memo in bytes.
+pub const MEMO_MAX_SIZE: usize = 64;
 
 
 /// Enumerates the different types of transactions in the Uqoin protocol.
@@ -41,36 +91,200 @@ pub enum Type {
 }
 
 
+/// Range of block numbers (`bix`, inclusive) where the transaction can be
+/// included.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Validity {
+    pub from: u64,
+    pub until: u64,
+}
+
+
+impl Validity {
+    /// Constructs a new validity window.
+    pub fn new(from: u64, until: u64) -> Self {
+        Self { from, until }
+    }
+
+    /// Check if the block number is inside the window.
+    pub fn contains(&self, bix: u64) -> bool {
+        (self.from <= bix) && (bix <= self.until)
+    }
+}
+
+
+/// Bounded payload attached to a transaction (e.g. an invoice id). It has
+/// a fixed size in memory so the transactions can be stored as plain records,
+/// and it is serialized as a hex string of the meaningful bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memo {
+    size: u8,
+    data: [u8; MEMO_MAX_SIZE],
+}
+
+
+impl Memo {
+    /// Create a memo from bytes. `TransactionMemoTooLong` is returned if there
+    /// are more than `MEMO_MAX_SIZE` bytes.
+    pub fn new(bytes: &[u8]) -> UqoinResult<Self> {
+        validate!(bytes.len() <= MEMO_MAX_SIZE, TransactionMemoTooLong)?;
+        let mut data = [0u8; MEMO_MAX_SIZE];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Ok(Self { size: bytes.len() as u8, data })
+    }
+
+    /// Accessor to the bytes of the memo.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len()]
+    }
+
+    /// Size of the memo in bytes.
+    pub fn len(&self) -> usize {
+        (self.size as usize).min(MEMO_MAX_SIZE)
+    }
+
+    /// Check if the memo is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Validate the size of the memo (it may be broken in raw records).
+    pub fn validate(&self) -> UqoinResult<()> {
+        validate!(self.size as usize <= MEMO_MAX_SIZE, TransactionMemoTooLong)
+    }
+
+    /// Hash of the memo that is committed in the transaction message.
+    pub fn get_hash(&self) -> U256 {
+        hash_of_bytes(self.as_bytes())
+    }
+}
+
+
+impl Serialize for Memo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> 
+                                Result<S::Ok, S::Error> {
+        serializer.serialize_str(&bytes_to_hex(self.as_bytes()))
+    }
+}
+
+
+impl<'de> Deserialize<'de> for Memo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> 
+                                         Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let bytes = hex_to_bytes(&hex)
+            .ok_or_else(|| D::Error::custom("invalid hex of memo"))?;
+        Self::new(&bytes).map_err(D::Error::custom)
+    }
+}
+
+
 /// Represents a transaction in the Uqoin protocol.
 ///
 /// Each transaction includes:
 /// - `coin`: The identifier of the coin involved.
 /// - `addr`: The recipient's address.
 /// - `sign_r` and `sign_s`: Components of the digital signature.
+/// - `version`: Format version (`1` if missing in the serialized data).
+/// - `validity`: Optional validity window (since version 2).
+/// - `memo`: Optional memo (since version 3).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
+    #[serde(with = "u256_hex")]
     pub coin: U256,
+    #[serde(with = "u256_hex")]
     pub addr: U256,
+    #[serde(with = "u256_hex")]
     pub sign_r: U256,
+    #[serde(with = "u256_hex")]
     pub sign_s: U256,
+    #[serde(default = "default_version")]
+    pub version: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validity: Option<Validity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<Memo>,
 }
 
 
 impl Transaction {
-    /// Constructs a new `Transaction` instance.
+    /// Constructs a new `Transaction` instance of version 1.
     pub fn new(coin: U256, addr: U256, sign_r: U256, sign_s: U256) -> Self {
-        Self { coin, addr, sign_r, sign_s }
+        Self { 
+            coin, addr, sign_r, sign_s, 
+            version: TRANSACTION_VERSION_1, 
+            validity: None,
+            memo: None,
+        }
+    }
+
+    /// Set the validity window. The version is raised to 2 if it is lower.
+    /// The transaction must be signed after that.
+    pub fn with_validity(mut self, validity: Validity) -> Self {
+        self.version = self.version.max(TRANSACTION_VERSION_2);
+        self.validity = Some(validity);
+        self
+    }
+
+    /// Set the memo. The version is raised to 3 if it is lower. The 
+    /// transaction must be signed after that.
+    pub fn with_memo(mut self, memo: Memo) -> Self {
+        self.version = self.version.max(TRANSACTION_VERSION_3);
+        self.memo = Some(memo);
+        self
+    }
+
+    /// Use the tagged message and hash. The version is raised to 4 if it is
+    /// lower. The transaction must be signed after that.
+    pub fn with_tagged_hash(mut self) -> Self {
+        self.version = self.version.max(TRANSACTION_VERSION_4);
+        self
+    }
+
+    /// Commit to the chain id in the message. The version is raised to 5 
+    /// if it is lower. The transaction must be signed after that for the 
+    /// chain (see `sign_for_chain`).
+    pub fn with_replay_protection(mut self) -> Self {
+        self.version = self.version.max(TRANSACTION_VERSION_5);
+        self
+    }
+
+    /// Sign the transaction with `key` for the given coin `counter`. The 
+    /// message includes all the fields of the version. Since version 5 it is
+    /// signed for the mainnet.
+    pub fn sign<R: Rng + CryptoRng>(&mut self, rng: &mut R, key: &U256, 
+                                    counter: u64, schema: &Schema) {
+        self.sign_for_chain(rng, key, counter, CHAIN_ID_MAINNET, schema);
+    }
+
+    /// Sign the transaction with `key` for the given coin `counter` on the
+    /// chain `chain_id` (it matters since version 5).
+    pub fn sign_for_chain<R: Rng + CryptoRng>(&mut self, rng: &mut R, 
+                                              key: &U256, counter: u64, 
+                                              chain_id: u64, 
+                                              schema: &Schema) {
+        let msg = self.get_msg_of_chain(counter, chain_id);
+        (self.sign_r, self.sign_s) = schema.build_signature(rng, &msg, key);
     }
 
     /// Build a transaction of the `coin` from `key` to `addr`. In case of
     /// fee, split and merge use 0, 1 and 2 for `addr` respectively.
-    pub fn build<R: Rng>(rng: &mut R, coin: U256, addr: U256, key: &U256, 
-                         counter: u64, schema: &Schema) -> Self {
+    pub fn build<R: Rng + CryptoRng>(rng: &mut R, coin: U256, addr: U256, 
+                                     key: &U256, counter: u64, 
+                                     schema: &Schema) -> Self {
         let hash = Self::calc_msg(&coin, &addr, counter);
         let (sign_r, sign_s) = schema.build_signature(rng, &hash, key);
         Self::new(coin, addr, sign_r, sign_s)
     }
 
+    /// Check if the transaction must not be sent to its sender (including
+    /// the fees the validator pays to itself, see `TransactionSelfTransfer`).
+    /// The rule is active since version 4, the earlier transactions are 
+    /// accepted as they were.
+    pub fn forbids_self_transfer(&self) -> bool {
+        self.version >= TRANSACTION_VERSION_4
+    }
+
     /// Determines the type of the transaction based on the recipient's address.
     pub fn get_type(&self) -> Type {
         if self.addr == U256::from(0) {
@@ -84,16 +298,98 @@ impl Transaction {
         }
     }
 
-    /// Computes the message hash used for signing the transaction.
+    /// Computes the message hash used for signing the transaction. It depends
+    /// on the version, since version 5 it is the message of the mainnet (see
+    /// `get_msg_of_chain`).
     pub fn get_msg(&self, counter: u64) -> U256 {
-        Self::calc_msg(&self.coin, &self.addr, counter)
+        self.get_msg_of_chain(counter, CHAIN_ID_MAINNET)
+    }
+
+    /// Computes the message hash used for signing the transaction on the 
+    /// chain `chain_id` (it is appended to the elements since version 5).
+    pub fn get_msg_of_chain(&self, counter: u64, chain_id: u64) -> U256 {
+        if self.version == TRANSACTION_VERSION_1 {
+            Self::calc_msg(&self.coin, &self.addr, counter)
+        } else {
+            let mut elems = vec![
+                self.coin.clone(), self.addr.clone(), U256::from(counter)
+            ];
+            elems.extend(self.get_ext_elems());
+            if self.version >= TRANSACTION_VERSION_5 {
+                elems.push(U256::from(chain_id));
+            }
+            if self.version >= TRANSACTION_VERSION_4 {
+                hash_tagged(TAG_TRANSACTION_MSG, elems.iter())
+            } else {
+                hash_of_u256(elems.iter())
+            }
+        }
     }
 
-    /// Get transaction hash.
+    /// Get transaction hash. It depends on the version.
     pub fn get_hash(&self) -> U256 {
-        hash_of_u256(
-            [&self.coin, &self.addr, &self.sign_r, &self.sign_s].into_iter()
-        )
+        let mut elems = vec![
+            self.coin.clone(), self.addr.clone(), 
+            self.sign_r.clone(), self.sign_s.clone()
+        ];
+        if self.version != TRANSACTION_VERSION_1 {
+            elems.extend(self.get_ext_elems());
+        }
+        if self.version >= TRANSACTION_VERSION_4 {
+            hash_tagged(TAG_TRANSACTION_HASH, elems.iter())
+        } else {
+            hash_of_u256(elems.iter())
+        }
+    }
+
+    /// Size of the canonical encoding of the transaction in bytes.
+    pub fn get_encoded_size(&self) -> usize {
+        let mut writer = Writer::new();
+        self.encode(&mut writer);
+        writer.len()
+    }
+
+    /// Validate that the version of the transaction is supported and the 
+    /// fields correspond the version.
+    pub fn validate_version(&self) -> UqoinResult<()> {
+        validate!((TRANSACTION_VERSION_1..=TRANSACTION_VERSION)
+                  .contains(&self.version), TransactionUnsupportedVersion)?;
+        if self.version < TRANSACTION_VERSION_2 {
+            validate!(self.validity.is_none(), TransactionUnsupportedVersion)?;
+        }
+        if self.version < TRANSACTION_VERSION_3 {
+            validate!(self.memo.is_none(), TransactionUnsupportedVersion)?;
+        }
+        if let Some(memo) = self.memo.as_ref() {
+            memo.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Check that the transaction commits to the chain id if the state 
+    /// requires it for the next block (see 
+    /// `State::requires_replay_protection`), `TransactionUnsupportedVersion`
+    /// otherwise.
+    pub fn validate_replay_protection(&self, 
+                                      state: &State) -> UqoinResult<()> {
+        if state.requires_replay_protection() {
+            validate!(self.version >= TRANSACTION_VERSION_5, 
+                      TransactionUnsupportedVersion)
+                .map_err(|err| err.with_values(TRANSACTION_VERSION_5, 
+                                               self.version))?;
+        }
+        Ok(())
+    }
+
+    /// Check if the transaction can be included into the block `bix`.
+    pub fn is_valid_at(&self, bix: u64) -> bool {
+        self.validity.as_ref().map(|v| v.contains(bix)).unwrap_or(true)
+    }
+
+    /// Check if the transaction cannot be included into any block starting 
+    /// from `bix`.
+    pub fn is_expired_at(&self, bix: u64) -> bool {
+        self.validity.as_ref().map(|v| v.until < bix).unwrap_or(false)
     }
 
     /// Get transaction sender.
@@ -101,53 +397,149 @@ impl Transaction {
     pub fn get_sender(&self, state: &State, schema: &Schema) -> U256 {
         let counter = state.get_coin_counter(&self.coin);
         schema.extract_public(
-            &self.get_msg(counter), 
+            &self.get_msg_of_chain(counter, state.get_chain_id()), 
             &(self.sign_r.clone(), self.sign_s.clone())
         )
     }
 
-    /// Get order of the coin.
+    /// Get order of the coin. For new coins the order is calculated through
+    /// the state, so the attached coin order cache is used.
     pub fn get_order(&self, state: &State, sender: &U256) -> u64 {
-        if let Some(coin_info) = state.get_coin_info(&self.coin) {
-            coin_info.order
+        if let Some(order) = state.get_coin_order(&self.coin) {
+            order
         } else {
-            coin_order(&self.coin, sender)
+            state.calc_coin_order(&self.coin, sender)
         }
     }
 
     /// Validate coin in the transaction. The checks:
-    /// 1. Sender is the owner of each coin, if it met before.
+    /// 1. Sender is the owner of each coin, if it met before (the owners of
+    ///    the untracked coins in the watch mode are unknown, so they are not 
+    ///    checked; the state marks such coins as of unverified origin, see
+    ///    `State::is_verified_coin`).
     /// 2. The coin number corresponds the previous block hash and the sender
-    /// if the coin is new (just mined).
+    ///    if the coin is new (just mined), and its order is not less than the 
+    ///    minimum one of the state (see `State::with_chain_spec`).
     pub fn validate_coin(&self, state: &State, 
                          sender: &U256) -> UqoinResult<()> {
         // Try to find the coin in coin-owner map
         if let Some(owner) = state.get_owner(&self.coin) {
             // Check ownership
             validate!(owner == sender, TransactionInvalidSender)?;
-        } else {
+        } else if !state.is_untracked_coin(&self.coin) {
             // Check mining
             coin_validate(&self.coin, sender)?;
+            if state.get_min_coin_order() > 0 {
+                let order = state.calc_coin_order(&self.coin, sender);
+                validate!(order >= state.get_min_coin_order(), CoinTooCheap)
+                    .map_err(|err| err.with_coin(&self.coin)
+                                      .with_values(state.get_min_coin_order(),
+                                                   order))?;
+            }
         }
 
         Ok(())
     }
 
-    /// Calculate transaction message as hash of the `coin` and `addr`.
+    /// Calculate transaction message of version 1 as hash of the `coin`, 
+    /// `addr` and `counter`.
     pub fn calc_msg(coin: &U256, addr: &U256, counter: u64) -> U256 {
         hash_of_u256([coin, addr, &U256::from(counter)].into_iter())
     }
 
+    /// Elements appended to the message and hash since version 2: the 
+    /// version, the validity window (`0..=u64::MAX` if not set) and, since
+    /// version 3, the memo hash (zero if not set).
+    fn get_ext_elems(&self) -> Vec<U256> {
+        let (from, until) = match &self.validity {
+            Some(validity) => (validity.from, validity.until),
+            None => (0, u64::MAX),
+        };
+        let mut elems = vec![
+            U256::from(self.version), U256::from(from), U256::from(until)
+        ];
+        if self.version >= TRANSACTION_VERSION_3 {
+            elems.push(self.memo.as_ref().map(|memo| memo.get_hash())
+                                         .unwrap_or(U256::from(0)));
+        }
+        elems
+    }
+
+    /// Calculate sender of the transaction extracting it from the signature 
+    /// for the coin counter and the chain id in the state.
+    pub fn calc_sender(&self, state: &State, schema: &Schema) -> U256 {
+        let counter = state.get_coin_counter(&self.coin);
+        let msg = self.get_msg_of_chain(counter, state.get_chain_id());
+        let signature = (self.sign_r.clone(), self.sign_s.clone());
+        schema.extract_public(&msg, &signature)
+    }
+
     /// Calculate senders of given transactions. Since the sender is extracted
-    /// from signature, it takes a while, so use it carefully.
+    /// from signature, it takes a while, so use it carefully. With the 
+    /// `parallel` feature the senders are calculated in the `rayon` thread
+    /// pool keeping the order of the transactions.
     pub fn calc_senders(transactions: &[Self], state: &State, 
                         schema: &Schema) -> Vec<U256> {
-        transactions.iter().map(|tr| {
-            let counter = state.get_coin_counter(&tr.coin);
-            let msg = Self::calc_msg(&tr.coin, &tr.addr, counter);
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            transactions.par_iter()
+                .map(|tr| tr.calc_sender(state, schema))
+                .collect::<Vec<U256>>()
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        {
+            transactions.iter()
+                .map(|tr| tr.calc_sender(state, schema))
+                .collect::<Vec<U256>>()
+        }
+    }
+
+    /// Calculate senders of the transactions of consecutive blocks that
+    /// follow the state. The counters of the coins are advanced with each
+    /// block, so the senders of all the blocks are calculated at once (in
+    /// parallel with the `parallel` feature).
+    pub fn calc_senders_of_blocks(blocks: &[&[Self]], state: &State, 
+                                  schema: &Schema) -> Vec<Vec<U256>> {
+        // Counters of the coins in each block
+        let mut counters: HashMap<&U256, u64> = HashMap::new();
+        let items: Vec<(&Self, u64)> = blocks.iter().flat_map(|trs| {
+            let items: Vec<(&Self, u64)> = trs.iter().map(|tr| {
+                let counter = counters.get(&tr.coin).copied()
+                    .unwrap_or_else(|| state.get_coin_counter(&tr.coin));
+                (tr, counter)
+            }).collect();
+            for tr in trs.iter() {
+                *counters.entry(&tr.coin)
+                         .or_insert_with(|| state.get_coin_counter(&tr.coin)) 
+                    += 1;
+            }
+            items
+        }).collect();
+
+        // Extract senders
+        let chain_id = state.get_chain_id();
+        let extract = |(tr, counter): &(&Self, u64)| {
             let signature = (tr.sign_r.clone(), tr.sign_s.clone());
-            schema.extract_public(&msg, &signature)
-        }).collect::<Vec<U256>>()
+            schema.extract_public(&tr.get_msg_of_chain(*counter, chain_id), 
+                                  &signature)
+        };
+
+        #[cfg(feature = "parallel")]
+        let mut senders = {
+            use rayon::prelude::*;
+            items.par_iter().map(extract).collect::<Vec<U256>>().into_iter()
+        };
+
+        #[cfg(not(feature = "parallel"))]
+        let mut senders = items.iter().map(extract).collect::<Vec<U256>>()
+                               .into_iter();
+
+        // Split by blocks
+        blocks.iter()
+            .map(|trs| senders.by_ref().take(trs.len()).collect())
+            .collect()
     }
 }
 
@@ -155,61 +547,30 @@ impl Transaction {
 /// Group of transactions. Due to the check on create, group cannot be invalid.
 /// The valid group must have: 1) unique coins, 2) the same sender, 3) correct 
 /// coins ownership, 4) consistent transaction order, types, values and count.  
-/// Empty group is not allowed.
+/// Empty group is not allowed. The group owns its transactions by default,
+/// `Group<&[Transaction]>` borrows them from a block (see 
+/// `try_group_transactions`) without copying.
 #[derive(Debug, Clone)]
-pub struct Group(Vec<Transaction>);
+pub struct Group<T = Vec<Transaction>>(T);
 
 
-impl Group {
+impl<T: AsRef<[Transaction]>> Group<T> {
     /// Create group from transactions. Validation is included, so if the
     /// vector is not valid, `None` will be returned.
-    pub fn new(transactions: Vec<Transaction>, state: &State, 
+    pub fn new(transactions: T, state: &State, 
                senders: &[U256]) -> UqoinResult<Self> {
-        Self::validate_transactions(&transactions, state, senders)?;
+        Group::validate_transactions(transactions.as_ref(), state, senders)?;
         Ok(Self(transactions))
     }
 
-    /// Try to create a group from the leading transactions in the given slice.
-    /// Fees are joined by the greedy approach.
-    pub fn from_vec(transactions: &mut Vec<Transaction>, state: &State, 
-                    senders: &[U256]) -> UqoinResult<Self> {
-        if transactions.is_empty() {
-            // `TransactionEmpty` if the slice is empty
-            Err(ErrorKind::TransactionEmpty.into())
-        } else {
-            // Size of the group without fee
-            let mut size = match transactions[0].get_type() {
-                Type::Split => 1,
-                Type::Merge => 3,
-                Type::Transfer => 1,
-                _ => 0,
-            };
-
-            if size == 0 {
-                // `TransactionBrokenGroup` if we start from a fee transaction
-                Err(ErrorKind::TransactionBrokenGroup.into())
-            } else {
-                // Increment size if the next transaction is fee
-                if (size < transactions.len()) && 
-                   (transactions[size].get_type() == Type::Fee) {
-                    size += 1;
-                }
-
-                // Try to create a group using validation in `Self::new`
-                let trs = vec_split_left(transactions, size);
-                Self::new(trs, state, &senders[..size])
-            }
-        }
-    }
-
     /// Accessor to the inner transactions.
     pub fn transactions(&self) -> &[Transaction] {
-        &self.0
+        self.0.as_ref()
     }
 
     /// Get type of the group.
     pub fn get_type(&self) -> Type {
-        self.0[0].get_type()
+        self.transactions()[0].get_type()
     }
 
     /// Get sender of the group.
@@ -219,31 +580,39 @@ impl Group {
 
     /// Get fee transaction.
     pub fn get_fee(&self) -> Option<&Transaction> {
-        let fee_ix = match self.0[0].get_type() {
+        let fee_ix = match self.get_type() {
             Type::Split => 1,
             Type::Merge => 3,
             Type::Transfer => 1,
             _ => panic!("Invalid group."),
         };
-        self.0.get(fee_ix)
+        self.transactions().get(fee_ix)
+    }
+
+    /// Get order of the fee coin, `None` if there is no fee.
+    pub fn get_fee_order(&self, state: &State, 
+                         senders: &[U256]) -> Option<u64> {
+        let sender = self.get_sender(senders);
+        self.get_fee().map(|fee| fee.get_order(state, &sender))
     }
 
     /// Get hash of the group as the hash of leading transaction.
     pub fn get_hash(&self) -> U256 {
-        self.0[0].get_hash()
+        self.transactions()[0].get_hash()
     }
 
     /// Get total number of transactions.
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.transactions().len()
     }
 
     /// Get order of the main coins.
     pub fn get_order(&self, state: &State, senders: &[U256]) -> u64 {
+        let order = self.transactions()[0].get_order(state, &senders[0]);
         match self.get_type() {
-            Type::Split => self.0[0].get_order(state, &senders[0]),
-            Type::Merge => self.0[0].get_order(state, &senders[0]) + 1,
-            Type::Transfer => self.0[0].get_order(state, &senders[0]),
+            Type::Split => order,
+            Type::Merge => order + 1,
+            Type::Transfer => order,
             _ => panic!("Invalid transactions in the group."),
         }
     }
@@ -257,36 +626,102 @@ impl Group {
             _ => panic!("Invalid transactions in the group."),
         }
     }
+}
 
-    /// Validate transactions for the group creation.
+
+impl<'a> Group<&'a [Transaction]> {
+    /// Try to create a group from the leading transactions in the given slice
+    /// borrowing them. Fees are joined by the greedy approach.
+    pub fn from_slice(transactions: &'a [Transaction], state: &State, 
+                      senders: &[U256]) -> UqoinResult<Self> {
+        let size = Group::leading_size(transactions)?;
+        Self::new(&transactions[..size], state, &senders[..size])
+    }
+
+    /// Copy the transactions into an owned group.
+    pub fn into_owned(self) -> Group {
+        Group(self.0.to_vec())
+    }
+}
+
+
+impl Group {
+    /// Try to create a group from the leading transactions in the given 
+    /// vector removing them. Fees are joined by the greedy approach.
+    pub fn from_vec(transactions: &mut Vec<Transaction>, state: &State, 
+                    senders: &[U256]) -> UqoinResult<Self> {
+        let size = Self::leading_size(transactions)?;
+        let trs = vec_split_left(transactions, size);
+        Self::new(trs, state, &senders[..size])
+    }
+
+    /// Validate transactions for the group creation. It stops at the first
+    /// error (the same one `validate_transactions_report` starts with).
     pub fn validate_transactions(transactions: &[Transaction], state: &State, 
                                  senders: &[U256]) -> UqoinResult<()> {
-        // Error if no transactions in the slice
-        validate!(!transactions.is_empty(), TransactionEmpty)?;
+        Self::validate_transactions_with(transactions, state, senders, 
+                                         &mut FailFast)
+    }
 
-        // Check unique coins
-        validate!(check_unique(transactions.iter().map(|tr| &tr.coin)), 
-                  CoinNotUnique)?;
+    /// Validate transactions as `validate_transactions` does, but report 
+    /// every transaction with a repeated coin, another sender, unsupported 
+    /// version or a foreign coin. The structure of the group is checked only
+    /// if there are no such errors, because the orders of invalid coins are
+    /// meaningless.
+    pub fn validate_transactions_report(transactions: &[Transaction], 
+                                        state: &State, 
+                                        senders: &[U256]) -> 
+                                        ValidationReport {
+        ValidationReport::run(|report| Self::validate_transactions_with(
+            transactions, state, senders, report
+        ))
+    }
+
+    /// Checks of `validate_transactions` and `validate_transactions_report`
+    /// passing the errors to the collector.
+    fn validate_transactions_with<C: ErrorCollector>(
+                transactions: &[Transaction], state: &State, 
+                senders: &[U256], errors: &mut C) -> UqoinResult<()> {
+        // Error if no transactions in the slice
+        errors.collect(validate!(!transactions.is_empty(), 
+                                 TransactionEmpty))?;
 
-        // Check same sender
-        validate!(check_same(senders.iter()), TransactionInvalidSender)?;
+        // Check unique coins and same sender
+        Self::validate_coins_and_senders(transactions, state, senders, 
+                                         errors)?;
 
-        // Check ownership
-        for transaction in transactions.iter() {
-            transaction.validate_coin(state, &senders[0])?;
+        // Check the structure
+        if errors.is_ok() {
+            errors.collect(Self::validate_structure(transactions, state, 
+                                                    senders))?;
         }
 
+        Ok(())
+    }
+
+    /// Check the types, the fees and the orders of the group transactions 
+    /// (their coins and senders must be valid).
+    fn validate_structure(transactions: &[Transaction], state: &State, 
+                          senders: &[U256]) -> UqoinResult<()> {
         // Check the first type
         match transactions[0].get_type() {
             // Error if the first transaction is fee
-            Type::Fee => validate!(false, TransactionBrokenGroup)?,
+            Type::Fee => {
+                return Err(Error::from(ErrorKind::TransactionBrokenGroup)
+                    .with_index(0).with_coin(&transactions[0].coin));
+            },
 
             // Check the rest fees if split
             Type::Split => {
                 if transactions.len() > 1 {
-                    validate!(transactions.len() == 2, TransactionBrokenGroup)?;
+                    validate!(transactions.len() == 2, TransactionBrokenGroup)
+                        .map_err(|err| err.with_values(
+                            "2 with fee", transactions.len()
+                        ))?;
                     validate!(transactions[1].get_type() == Type::Fee, 
-                              TransactionBrokenGroup)?;
+                              TransactionBrokenGroup)
+                        .map_err(|err| err.with_index(1)
+                                          .with_coin(&transactions[1].coin))?;
                 }
             },
 
@@ -297,36 +732,111 @@ impl Group {
                     (transactions[3].get_type() == Type::Fee)
                 );
 
-                validate!(fee_check, TransactionBrokenGroup)?;
-
-                let type_check = 
-                    (transactions[1].get_type() == Type::Merge) && 
-                    (transactions[2].get_type() == Type::Merge);
+                validate!(fee_check, TransactionBrokenGroup)
+                    .map_err(|err| err.with_values("3 or 4 with fee", 
+                                                   transactions.len()))?;
 
-                validate!(type_check, TransactionBrokenGroup)?;
+                for (ix, tr) in transactions.iter().enumerate().take(3) {
+                    validate!(tr.get_type() == Type::Merge, 
+                              TransactionBrokenGroup)
+                        .map_err(|err| err.with_index(ix).with_coin(&tr.coin))?;
+                }
 
                 let order0 = transactions[0].get_order(state, &senders[0]);
-                let order1 = transactions[1].get_order(state, &senders[1]);
-                let order2 = transactions[2].get_order(state, &senders[2]);
-
-                let order_check = (order1 + 1 == order0) && 
-                                  (order2 + 1 == order0);
 
-                validate!(order_check, TransactionBrokenGroup)?;
+                for ix in 1..3 {
+                    let tr = &transactions[ix];
+                    let order = tr.get_order(state, &senders[ix]);
+                    validate!(order + 1 == order0, TransactionBrokenGroup)
+                        .map_err(|err| err.with_index(ix)
+                                          .with_coin(&tr.coin)
+                                          .with_values(order0, order + 1))?;
+                }
             },
 
-            // Check the rest fees if transfer
+            // Check the receiver and the rest fees if transfer
             Type::Transfer => {
+                validate!(!transactions[0].forbids_self_transfer() || 
+                          (transactions[0].addr != senders[0]), 
+                          TransactionSelfTransfer)
+                    .map_err(|err| err.with_index(0)
+                                      .with_coin(&transactions[0].coin))?;
+
                 if transactions.len() > 1 {
-                    validate!(transactions.len() == 2, TransactionBrokenGroup)?;
+                    validate!(transactions.len() == 2, TransactionBrokenGroup)
+                        .map_err(|err| err.with_values(
+                            "2 with fee", transactions.len()
+                        ))?;
                     validate!(transactions[1].get_type() == Type::Fee, 
-                              TransactionBrokenGroup)?;
+                              TransactionBrokenGroup)
+                        .map_err(|err| err.with_index(1)
+                                          .with_coin(&transactions[1].coin))?;
                 }
             },
         }
 
         Ok(())
     }
+
+    /// Size of the group of the leading transactions: the main ones and the
+    /// following fee if any.
+    fn leading_size(transactions: &[Transaction]) -> UqoinResult<usize> {
+        // `TransactionEmpty` if the slice is empty
+        validate!(!transactions.is_empty(), TransactionEmpty)?;
+
+        // Size of the group without fee
+        let mut size = match transactions[0].get_type() {
+            Type::Split => 1,
+            Type::Merge => 3,
+            Type::Transfer => 1,
+            _ => 0,
+        };
+
+        // `TransactionBrokenGroup` if we start from a fee transaction
+        validate!(size > 0, TransactionBrokenGroup)
+            .map_err(|err| err.with_index(0))?;
+
+        // Increment size if the next transaction is fee
+        if (size < transactions.len()) && 
+           (transactions[size].get_type() == Type::Fee) {
+            size += 1;
+        }
+
+        // `TransactionBrokenGroup` if the group is incomplete
+        validate!(size <= transactions.len(), TransactionBrokenGroup)
+            .map_err(|err| err.with_index(transactions.len()))?;
+
+        Ok(size)
+    }
+
+    /// Check that the coins are unique, the senders are the same, the
+    /// versions are supported and the coins belong to the sender. It is common
+    /// for groups and extensions.
+    fn validate_coins_and_senders<C: ErrorCollector>(
+                transactions: &[Transaction], state: &State, 
+                senders: &[U256], errors: &mut C) -> UqoinResult<()> {
+        // Check unique coins
+        let mut coins_seen = HashSet::new();
+        for (ix, tr) in transactions.iter().enumerate() {
+            errors.collect(validate!(coins_seen.insert(&tr.coin), 
+                                     CoinNotUnique)
+                .map_err(|err| err.with_index(ix).with_coin(&tr.coin)))?;
+        }
+
+        // Check same sender, versions and ownership
+        for (ix, (tr, sender)) in transactions.iter().zip(senders.iter())
+                                              .enumerate() {
+            errors.collect(validate!(sender == &senders[0], 
+                                     TransactionInvalidSender)
+                .map_err(|err| err.with_index(ix).with_coin(&tr.coin)))?;
+            errors.collect(tr.validate_version()
+                .and_then(|_| tr.validate_replay_protection(state))
+                .and_then(|_| tr.validate_coin(state, &senders[0]))
+                .map_err(|err| err.with_index(ix).with_coin(&tr.coin)))?;
+        }
+
+        Ok(())
+    }
 }
 
 
@@ -335,27 +845,28 @@ impl Group {
 /// be invalid. The valid extension must have: 1) unique coins, 2) the same  
 /// sender (validator), 3) correct coins ownership, 4) consistent transaction  
 /// order, types, values and count depending on the group type. Extension can be 
-/// empty for `Transfer` type.
+/// empty for `Transfer` type. As the group, it owns the transactions by 
+/// default or borrows them (`Ext<&[Transaction]>`).
 #[derive(Debug, Clone)]
-pub struct Ext(Vec<Transaction>);
+pub struct Ext<T = Vec<Transaction>>(T);
 
 
-impl Ext {
+impl<T: AsRef<[Transaction]>> Ext<T> {
     /// Create a new extension from transactions.
-    pub fn new(transactions: Vec<Transaction>, state: &State, 
+    pub fn new(transactions: T, state: &State, 
                senders: &[U256]) -> UqoinResult<Self> {
-        Self::validate_transactions(&transactions, state, senders)?;
+        Ext::validate_transactions(transactions.as_ref(), state, senders)?;
         Ok(Self(transactions))
     }
 
     /// Accessor to the inner transactions.
     pub fn transactions(&self) -> &[Transaction] {
-        &self.0
+        self.0.as_ref()
     }
 
     /// Get type of the extension.
     pub fn get_type(&self) -> Type {
-        match self.0.len() {
+        match self.len() {
             0 => Type::Transfer,
             1 => Type::Merge,
             3 => Type::Split,
@@ -365,7 +876,7 @@ impl Ext {
 
     /// Get sender of the extension.
     pub fn get_sender(&self, senders: &[U256]) -> Option<U256> {
-        if self.0.is_empty() {
+        if self.len() == 0 {
             None
         } else {
             Some(senders[0].clone())
@@ -374,33 +885,38 @@ impl Ext {
 
     /// Get total number of transactions.
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.transactions().len()
     }
 
     /// Get order of the main coins in the extension.
     pub fn get_order(&self, state: &State, senders: &[U256]) -> u64 {
-        match self.0.len() {
+        let trs = self.transactions();
+        match trs.len() {
             0 => 0,
-            1 => self.0[0].get_order(state, &senders[0]),
-            3 => &self.0[0].get_order(state, &senders[0]) + 1,
+            1 => trs[0].get_order(state, &senders[0]),
+            3 => trs[0].get_order(state, &senders[0]) + 1,
             _ => panic!("Invalid transactions in the group."),
         }
     }
+}
+
+
+impl Ext<&[Transaction]> {
+    /// Copy the transactions into an owned extension.
+    pub fn into_owned(self) -> Ext {
+        Ext(self.0.to_vec())
+    }
+}
+
+
+impl Ext {
 
     /// Validate transactions for the extension creation.
     pub fn validate_transactions(transactions: &[Transaction], state: &State, 
                                  senders: &[U256]) -> UqoinResult<()> {
-        // Check unique coins
-        validate!(check_unique(transactions.iter().map(|tr| &tr.coin)), 
-                  CoinNotUnique)?;
-
-        // Check same sender
-        validate!(check_same(senders.iter()), TransactionInvalidSender)?;
-
-        // Check ownership
-        for transaction in transactions.iter() {
-            transaction.validate_coin(state, &senders[0])?;
-        }
+        // Check unique coins and same sender
+        Group::validate_coins_and_senders(transactions, state, senders, 
+                                          &mut FailFast)?;
 
         // Check the size
         match transactions.len() {
@@ -409,7 +925,9 @@ impl Ext {
 
             // Check the type for the merge type
             1 => validate!(transactions[0].get_type() == Type::Transfer, 
-                           TransactionBrokenExt)?,
+                           TransactionBrokenExt)
+                .map_err(|err| err.with_index(0)
+                                  .with_coin(&transactions[0].coin))?,
 
             // Complex check for the split check
             3 => {
@@ -417,27 +935,32 @@ impl Ext {
                 let addr = &transactions[0].addr;
 
                 // Check transfer type
-                let type_check = transactions.iter()
-                    .all(|tr| tr.get_type() == Type::Transfer);
-
-                validate!(type_check, TransactionBrokenExt)?;
+                for (ix, tr) in transactions.iter().enumerate() {
+                    validate!(tr.get_type() == Type::Transfer, 
+                              TransactionBrokenExt)
+                        .map_err(|err| err.with_index(ix).with_coin(&tr.coin))?;
+                }
 
                 // Check same addr
-                let addr_check = 
-                    (&transactions[1].addr == addr) && 
-                    (&transactions[2].addr == addr);
-
-                validate!(addr_check, TransactionBrokenExt)?;
+                for (ix, tr) in transactions.iter().enumerate().skip(1) {
+                    validate!(&tr.addr == addr, TransactionBrokenExt)
+                        .map_err(|err| err.with_index(ix)
+                                          .with_coin(&tr.coin)
+                                          .with_values(addr.to_hex(), 
+                                                       tr.addr.to_hex()))?;
+                }
 
                 // Check order
                 let order0 = transactions[0].get_order(state, &senders[0]);
-                let order1 = transactions[1].get_order(state, &senders[1]);
-                let order2 = transactions[2].get_order(state, &senders[2]);
 
-                let order_check = (order1 + 1 == order0) && 
-                                  (order2 + 1 == order0);
-
-                validate!(order_check, TransactionBrokenExt)?;
+                for ix in 1..3 {
+                    let tr = &transactions[ix];
+                    let order = tr.get_order(state, &senders[ix]);
+                    validate!(order + 1 == order0, TransactionBrokenExt)
+                        .map_err(|err| err.with_index(ix)
+                                          .with_coin(&tr.coin)
+                                          .with_values(order0, order + 1))?;
+                }
             },
 
             // Panic if the wrong size
@@ -449,32 +972,504 @@ impl Ext {
 }
 
 
+/// Canonical encoding of the transaction, its width depends on the version:
+/// - `version`: 1 byte.
+/// - `coin`, `addr`, `sign_r`, `sign_s`: 32 bytes each.
+/// - Since version 2, `validity`: flag, `from` and `until` (17 bytes).
+/// - Since version 3, `memo`: flag, size and data padded with zeros up to
+///   `MEMO_MAX_SIZE` (66 bytes).
+impl Codec for Transaction {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_u8(self.version);
+        writer.write_u256(&self.coin);
+        writer.write_u256(&self.addr);
+        writer.write_u256(&self.sign_r);
+        writer.write_u256(&self.sign_s);
+
+        if self.version >= TRANSACTION_VERSION_2 {
+            writer.write_flag(self.validity.is_some());
+            let (from, until) = self.validity.as_ref()
+                .map(|v| (v.from, v.until)).unwrap_or((0, 0));
+            writer.write_u64(from);
+            writer.write_u64(until);
+        }
+
+        if self.version >= TRANSACTION_VERSION_3 {
+            writer.write_flag(self.memo.is_some());
+            let (size, data) = self.memo.as_ref()
+                .map(|m| (m.size, m.data)).unwrap_or((0, [0; MEMO_MAX_SIZE]));
+            writer.write_u8(size);
+            writer.write_bytes(&data);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> UqoinResult<Self> {
+        let version = reader.read_u8()?;
+        let mut transaction = Self::new(
+            reader.read_u256()?, reader.read_u256()?, 
+            reader.read_u256()?, reader.read_u256()?
+        );
+        transaction.version = version;
+        validate!((TRANSACTION_VERSION_1..=TRANSACTION_VERSION)
+                  .contains(&version), TransactionUnsupportedVersion)?;
+
+        if version >= TRANSACTION_VERSION_2 {
+            if reader.read_flag()? {
+                let from = reader.read_u64()?;
+                let until = reader.read_u64()?;
+                transaction.validity = Some(Validity::new(from, until));
+            } else {
+                reader.read_padding(16)?;
+            }
+        }
+
+        if version >= TRANSACTION_VERSION_3 {
+            if reader.read_flag()? {
+                let size = reader.read_u8()? as usize;
+                validate!(size <= MEMO_MAX_SIZE, TransactionMemoTooLong)?;
+                let memo = Memo::new(reader.read_bytes(size)?)?;
+                reader.read_padding(MEMO_MAX_SIZE - size)?;
+                transaction.memo = Some(memo);
+            } else {
+                reader.read_padding(1 + MEMO_MAX_SIZE)?;
+            }
+        }
+
+        Ok(transaction)
+    }
+}
+
+
+fn default_version() -> u8 {
+    TRANSACTION_VERSION_1
+}
+
+
+/// Slices of the group and the extension borrowed from the transactions.
+pub type GroupSlices<'a> = (usize, Group<&'a [Transaction]>, 
+                            Ext<&'a [Transaction]>);
+
+
 /// Try to split transactions into groups and extensions. In case of not valid
 /// `transactions` the iterator stops until the first error, so for the
-/// validation purpose check the total size of yielded groups and extensions.
-pub fn group_transactions(mut transactions: Vec<Transaction>, state: &State, 
-                          senders: &[U256]) -> 
-                          impl Iterator<Item = (usize, Group, Ext)> {
+/// validation purpose check the total size of yielded groups and extensions
+/// or use `try_group_transactions`.
+pub fn group_transactions<'a>(transactions: &'a [Transaction], state: &State, 
+                              senders: &[U256]) -> 
+                              impl Iterator<Item = GroupSlices<'a>> {
+    try_group_transactions(transactions, state, senders).map_while(Result::ok)
+}
+
+
+/// Split transactions into groups and extensions yielding the first error
+/// if the transactions cannot be groupped. The index in the error context is
+/// the position in `transactions`. The iterator stops after the error. The
+/// groups and extensions borrow the transactions, nothing is copied.
+pub fn try_group_transactions<'a>(transactions: &'a [Transaction], 
+                                  state: &State, senders: &[U256]) -> 
+                                  impl Iterator<Item = UqoinResult<
+                                      GroupSlices<'a>
+                                  >> {
     let mut offset = 0;
+    let mut failed = false;
     std::iter::from_fn(move || {
-        if let Ok(group) = Group::from_vec(&mut transactions, state, 
-                                           &senders[offset..]) {
-            let group_size = group.len();
-            let ext_size = group.ext_size();
-            let ext_trs = vec_split_left(&mut transactions, ext_size);
-            let ext_senders = &senders[
-                offset + group_size .. offset + group_size + ext_size
-            ];
+        if failed || (offset == transactions.len()) {
+            return None;
+        }
 
-            if let Ok(ext) = Ext::new(ext_trs, state, ext_senders) {
-                let res = (offset, group, ext);
-                offset += group_size + ext_size;
-                Some(res)
-            } else {
-                None
-            }
-        } else {
-            None
+        let res = Group::from_slice(&transactions[offset..], state, 
+                                    &senders[offset..])
+            .map_err(|err| err.with_offset(offset))
+            .and_then(|group| {
+                let ext_size = group.ext_size();
+                let ext_offset = offset + group.len();
+
+                validate!(ext_offset + ext_size <= transactions.len(), 
+                          TransactionBrokenExt)
+                    .map_err(|err| err.with_index(ext_offset))?;
+
+                let range = ext_offset .. ext_offset + ext_size;
+                let ext = Ext::new(&transactions[range.clone()], state, 
+                                   &senders[range])
+                    .map_err(|err| err.with_offset(ext_offset))?;
+
+                Ok((offset, group, ext))
+            });
+
+        match res.as_ref() {
+            Ok((_, group, ext)) => offset += group.len() + ext.len(),
+            Err(_) => failed = true,
         }
+
+        Some(res)
     })
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::coin::coin_random;
+    use crate::spec::ChainSpec;
+    use crate::testing::TestAccount;
+
+    #[test]
+    fn test_version() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let coin: U256 = rng.random();
+        let addr: U256 = rng.random();
+
+        // Version 1 keeps the original message and hash
+        let mut tr = Transaction::build(&mut rng, coin.clone(), addr.clone(),
+                                        &key, 0, &schema);
+        assert_eq!(tr.version, TRANSACTION_VERSION_1);
+        assert_eq!(tr.get_msg(0), Transaction::calc_msg(&coin, &addr, 0));
+        assert_eq!(tr.get_hash(), hash_of_u256(
+            [&tr.coin, &tr.addr, &tr.sign_r, &tr.sign_s].into_iter()
+        ));
+        assert!(tr.validate_version().is_ok());
+        assert_eq!(Transaction::calc_senders(&[tr.clone()], &State::new(), 
+                                             &schema), vec![public.clone()]);
+
+        // Version 4 tags the message and hash
+        let mut tr4 = Transaction::new(coin.clone(), addr.clone(), 
+                                       U256::from(0), U256::from(0))
+            .with_tagged_hash();
+        tr4.sign(&mut rng, &key, 0, &schema);
+        assert_eq!(tr4.version, TRANSACTION_VERSION_4);
+        assert_ne!(tr4.get_msg(0), tr.get_msg(0));
+        assert_ne!(tr4.get_hash(), tr.get_hash());
+        assert!(tr4.validate_version().is_ok());
+        assert_eq!(Transaction::calc_senders(&[tr4], &State::new(), &schema), 
+                   vec![public]);
+
+        // Version 1 cannot have the validity window
+        tr.validity = Some(Validity::new(0, 10));
+        assert!(tr.validate_version().is_err());
+
+        // Unknown version changes the message and hash and it is rejected
+        tr.version = TRANSACTION_VERSION + 1;
+        assert_ne!(tr.get_msg(0), Transaction::calc_msg(&coin, &addr, 0));
+        assert_eq!(tr.validate_version().unwrap_err().kind(), 
+                   ErrorKind::TransactionUnsupportedVersion);
+    }
+
+    #[test]
+    fn test_validity() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        let mut tr = Transaction::new(rng.random(), rng.random(), 
+                                      U256::from(0), U256::from(0))
+            .with_validity(Validity::new(5, 7));
+        tr.sign(&mut rng, &key, 0, &schema);
+
+        assert_eq!(tr.version, TRANSACTION_VERSION_2);
+        assert!(tr.validate_version().is_ok());
+        assert_eq!(Transaction::calc_senders(&[tr.clone()], &State::new(), 
+                                             &schema), vec![public]);
+
+        assert!(!tr.is_valid_at(4));
+        assert!(tr.is_valid_at(5));
+        assert!(tr.is_valid_at(7));
+        assert!(!tr.is_valid_at(8));
+        assert!(!tr.is_expired_at(7));
+        assert!(tr.is_expired_at(8));
+
+        // The window is signed
+        let hash = tr.get_hash();
+        let msg = tr.get_msg(0);
+        tr.validity = Some(Validity::new(5, 8));
+        assert_ne!(tr.get_hash(), hash);
+        assert_ne!(tr.get_msg(0), msg);
+    }
+
+    #[test]
+    fn test_replay_protection() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let testnet = ChainSpec::new(2, rng.random()).with_replay_protection(1);
+        let state = State::new().with_chain_spec(&testnet);
+        assert_eq!(state.get_chain_id(), 2);
+        assert!(state.requires_replay_protection());
+
+        // Version 5 is signed for the chain
+        let mut tr = Transaction::new(coin_random(&mut rng, &public), 
+                                      rng.random(), U256::from(0), 
+                                      U256::from(0))
+            .with_replay_protection();
+        tr.sign_for_chain(&mut rng, &key, 0, 2, &schema);
+        assert_eq!(tr.version, TRANSACTION_VERSION_5);
+        assert_ne!(tr.get_msg_of_chain(0, 2), tr.get_msg(0));
+        assert_eq!(tr.calc_sender(&state, &schema), public);
+        assert!(tr.validate_replay_protection(&state).is_ok());
+        let senders = vec![public.clone()];
+        assert!(Group::new(vec![tr.clone()], &state, &senders).is_ok());
+
+        // It cannot be replayed on the mainnet
+        assert_ne!(tr.calc_sender(&State::new(), &schema), public);
+
+        // Older versions do not depend on the chain and they are rejected
+        let mut tr4 = tr.clone();
+        tr4.version = TRANSACTION_VERSION_4;
+        assert_eq!(tr4.get_msg_of_chain(0, 2), tr4.get_msg(0));
+        assert_eq!(tr4.validate_replay_protection(&state).unwrap_err().kind(),
+                   ErrorKind::TransactionUnsupportedVersion);
+        assert_eq!(Group::new(vec![tr4.clone()], &state, &senders)
+                        .unwrap_err().kind(),
+                   ErrorKind::TransactionUnsupportedVersion);
+
+        // Before the upgrade they are valid
+        let state = State::new()
+            .with_chain_spec(&testnet.clone().with_replay_protection(2));
+        assert!(!state.requires_replay_protection());
+        assert!(tr4.validate_replay_protection(&state).is_ok());
+    }
+
+    #[test]
+    fn test_calc_senders() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+
+        let pairs = (0..8).map(|_| schema.gen_pair(&mut rng))
+            .collect::<Vec<(U256, U256)>>();
+        let transactions = pairs.iter().map(|(key, _)| {
+            let coin: U256 = rng.random();
+            let addr: U256 = rng.random();
+            Transaction::build(&mut rng, coin, addr, key, 0, &schema)
+        }).collect::<Vec<Transaction>>();
+
+        // The order of the senders corresponds the transactions
+        let senders = Transaction::calc_senders(&transactions, &State::new(), 
+                                                &schema);
+        assert_eq!(senders, pairs.into_iter().map(|(_, public)| public)
+                                  .collect::<Vec<U256>>());
+    }
+
+    #[test]
+    fn test_try_group_transactions() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        let account = TestAccount::new(&key, &public, &schema);
+
+        // Transfer with fee and split without extension
+        let transactions = [100, 0, 1].map(
+            |addr| account.random_transfer(&mut rng, &U256::from(addr))
+        );
+        let senders = vec![public.clone(); transactions.len()];
+
+        let items = try_group_transactions(&transactions, &state, &senders)
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 2);
+        let (offset, group, ext) = items[0].as_ref().unwrap();
+        assert_eq!(*offset, 0);
+        assert_eq!(group.len(), 2);
+        assert!(std::ptr::eq(group.transactions(), &transactions[..2]));
+        assert_eq!(ext.len(), 0);
+        assert_eq!(group.clone().into_owned().get_hash(), 
+                   transactions[0].get_hash());
+
+        let err = items[1].as_ref().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TransactionBrokenExt);
+        assert_eq!(err.context().unwrap().index, Some(3));
+
+        assert_eq!(group_transactions(&transactions, &state, &senders)
+                       .count(), 1);
+    }
+
+    #[test]
+    fn test_group_report() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let (key2, public2) = schema.gen_pair(&mut rng);
+
+        let coin = crate::coin::coin_random(&mut rng, &public);
+        let coin2 = crate::coin::coin_random(&mut rng, &public2);
+        let tr = Transaction::build(&mut rng, coin, U256::from(100), &key, 0, 
+                                    &schema);
+        let tr2 = Transaction::build(&mut rng, coin2, U256::from(100), &key2,
+                                     0, &schema);
+
+        assert!(Group::validate_transactions_report(
+            std::slice::from_ref(&tr), &state, std::slice::from_ref(&public)
+        ).is_ok());
+
+        // Repeated coin, another sender and its coin are all reported
+        let report = Group::validate_transactions_report(
+            &[tr.clone(), tr, tr2], &state, &[public.clone(), public, public2]
+        );
+        let kinds = report.iter().map(|err| err.kind()).collect::<Vec<_>>();
+        assert_eq!(kinds, [ErrorKind::CoinNotUnique, 
+                           ErrorKind::TransactionInvalidSender,
+                           ErrorKind::CoinInvalid]);
+        assert_eq!(report[1].context().unwrap().index, Some(2));
+        assert_eq!(report[2].context().unwrap().index, Some(2));
+    }
+
+    #[test]
+    fn test_self_transfer() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        let coin = crate::coin::coin_random(&mut rng, &public);
+        let mut tr = Transaction::new(coin, public.clone(), U256::from(0), 
+                                      U256::from(0)).with_tagged_hash();
+        tr.sign(&mut rng, &key, 0, &schema);
+        assert_eq!(Group::new(vec![tr], &state, std::slice::from_ref(&public))
+                       .unwrap_err().kind(),
+                   ErrorKind::TransactionSelfTransfer);
+
+        // The earlier versions are not restricted
+        let coin = crate::coin::coin_random(&mut rng, &public);
+        let tr = Transaction::build(&mut rng, coin, public.clone(), &key, 0, 
+                                    &schema);
+        assert!(Group::new(vec![tr], &state, &[public]).is_ok());
+    }
+
+    #[test]
+    fn test_leading_order_zero() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        // Coins of the order 0 cannot lead a merge or a split extension
+        let coin0 = std::iter::repeat_with(
+            || crate::coin::coin_random(&mut rand::rng(), &public)
+        ).find(|coin| crate::coin::coin_order(coin, &public) == 0).unwrap();
+        let mut build = |coin: U256, addr: U256| {
+            Transaction::build(&mut rng, coin, addr, &key, 0, &schema)
+        };
+        let coins: Vec<U256> = (0..2).map(|_| {
+            crate::coin::coin_random(&mut rand::rng(), &public)
+        }).collect();
+        let senders = vec![public.clone(); 3];
+
+        let merge = vec![build(coin0.clone(), U256::from(2)),
+                         build(coins[0].clone(), U256::from(2)),
+                         build(coins[1].clone(), U256::from(2))];
+        let err = Group::validate_transactions(&merge, &state, &senders)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TransactionBrokenGroup);
+        assert_eq!(err.context().unwrap().index, Some(1));
+
+        let split = vec![build(coin0, U256::from(100)),
+                         build(coins[0].clone(), U256::from(100)),
+                         build(coins[1].clone(), U256::from(100))];
+        assert_eq!(Ext::validate_transactions(&split, &state, &senders)
+                       .unwrap_err().kind(),
+                   ErrorKind::TransactionBrokenExt);
+    }
+
+    #[test]
+    fn test_memo() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        assert_eq!(Memo::new(&[7; MEMO_MAX_SIZE + 1]).unwrap_err().kind(), 
+                   ErrorKind::TransactionMemoTooLong);
+
+        let memo = Memo::new(b"invoice-42").unwrap();
+        assert_eq!(memo.as_bytes(), b"invoice-42");
+
+        let mut tr = Transaction::new(rng.random(), rng.random(), 
+                                      U256::from(0), U256::from(0))
+            .with_memo(memo.clone());
+        tr.sign(&mut rng, &key, 0, &schema);
+
+        assert_eq!(tr.version, TRANSACTION_VERSION_3);
+        assert!(tr.validate_version().is_ok());
+        assert_eq!(Transaction::calc_senders(&[tr.clone()], &State::new(), 
+                                             &schema), vec![public]);
+
+        // Serialization keeps the memo and so the hash
+        let json = serde_json::to_string(&tr).unwrap();
+        assert!(json.contains(&bytes_to_hex(b"invoice-42")));
+        let tr2: Transaction = serde_json::from_str(&json).unwrap();
+        assert_eq!(tr2.memo, Some(memo));
+        assert_eq!(tr2.get_hash(), tr.get_hash());
+
+        // The memo is signed
+        let msg = tr.get_msg(0);
+        tr.memo = Some(Memo::new(b"invoice-43").unwrap());
+        assert_ne!(tr.get_msg(0), msg);
+
+        // Too long memo is not deserialized
+        let json = json.replace(&bytes_to_hex(b"invoice-42"), 
+                                &bytes_to_hex(&[7; MEMO_MAX_SIZE + 1]));
+        assert!(serde_json::from_str::<Transaction>(&json).is_err());
+    }
+
+    #[test]
+    fn test_codec() {
+        let mut rng = rand::rng();
+        let tr = Transaction::new(rng.random(), rng.random(), 
+                                  rng.random(), rng.random());
+
+        // Version 1 takes 129 bytes
+        let bytes = tr.to_bytes();
+        assert_eq!(bytes.len(), 129);
+        assert_eq!(bytes[0], TRANSACTION_VERSION_1);
+        assert_eq!(&bytes[1..33], tr.coin.to_bytes().as_slice());
+        let tr2 = Transaction::from_bytes(&bytes).unwrap();
+        assert_eq!(tr2.get_hash(), tr.get_hash());
+        assert_eq!(tr2.version, TRANSACTION_VERSION_1);
+        assert_eq!(tr.get_encoded_size(), 129);
+
+        // Version 3 takes fixed 212 bytes regardless the optional fields
+        let tr3 = tr.clone().with_memo(Memo::new(b"abc").unwrap());
+        let bytes = tr3.to_bytes();
+        assert_eq!(bytes.len(), 212);
+        let tr4 = Transaction::from_bytes(&bytes).unwrap();
+        assert_eq!(tr4.memo, tr3.memo);
+        assert_eq!(tr4.validity, None);
+        assert_eq!(tr4.get_hash(), tr3.get_hash());
+
+        let tr5 = tr3.with_validity(Validity::new(3, 9));
+        let tr6 = Transaction::from_bytes(&tr5.to_bytes()).unwrap();
+        assert_eq!(tr6.validity, Some(Validity::new(3, 9)));
+        assert_eq!(tr6.to_bytes(), tr5.to_bytes());
+        assert_eq!(tr5.get_encoded_size(), 212);
+
+        // Broken bytes
+        let mut bytes = tr5.to_bytes();
+        assert!(Transaction::from_bytes(&bytes[..200]).is_err());
+        bytes.push(0);
+        assert!(Transaction::from_bytes(&bytes).is_err());
+        bytes.pop();
+        bytes[0] = TRANSACTION_VERSION + 1;
+        assert!(Transaction::from_bytes(&bytes).is_err());
+        bytes[0] = TRANSACTION_VERSION_3;
+        *bytes.last_mut().unwrap() = 1;
+        assert!(Transaction::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_version_serde() {
+        let json = format!(
+            "{{\"coin\":\"{0}\",\"addr\":\"{0}\",\"sign_r\":\"{0}\",\
+             \"sign_s\":\"{0}\"}}", U256::from(1).to_hex()
+        );
+        let tr: Transaction = serde_json::from_str(&json).unwrap();
+        assert_eq!(tr.version, TRANSACTION_VERSION_1);
+
+        let tr2: Transaction = serde_json::from_str(
+            &serde_json::to_string(&tr).unwrap()
+        ).unwrap();
+        assert_eq!(tr2.version, TRANSACTION_VERSION_1);
+        assert_eq!(tr2.get_hash(), tr.get_hash());
+    }
+}
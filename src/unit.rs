@@ -0,0 +1,265 @@
+//! Provides a registry of human-readable units for displaying coin values.
+//!
+//! The value of a coin is `2^order`, so raw values quickly become unreadable.
+//! Similar to satoshi and BTC in Bitcoin, a `UnitRegistry` maps order ranges
+//! to named units: each unit has a name and a base order, so one unit equals
+//! `2^order` of the smallest value. A value is displayed in the largest unit
+//! that does not exceed it (e.g. `"12.5 Q"`).
+//!
+//! The registry is serializable, so each chain can configure its own units.
+//! `UnitRegistry::standard()` provides the recommended default.
+
+use serde::{Serialize, Deserialize};
+
+use crate::utils::*;
+use crate::coin::coin_value;
+use crate::state::OrderCoinsMap;
+
+
+/// Default number of decimal digits in formatted values.
+pub const UNIT_PRECISION: usize = 3;
+
+
+/// Named unit that equals `2^order` of the smallest value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unit {
+    /// Display name of the unit.
+    pub name: String,
+
+    /// Base order of the unit.
+    pub order: u64,
+}
+
+
+impl Unit {
+    /// Create a new unit.
+    pub fn new(name: &str, order: u64) -> Self {
+        Self { name: name.to_string(), order }
+    }
+}
+
+
+/// Registry of units sorted by their base orders.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnitRegistry {
+    units: Vec<Unit>,
+    precision: usize,
+    #[serde(default)]
+    separator: Option<char>,
+}
+
+
+impl UnitRegistry {
+    /// Create a registry from the given units.
+    pub fn new(units: Vec<Unit>) -> Self {
+        let mut instance = Self {
+            units: Vec::new(),
+            precision: UNIT_PRECISION,
+            separator: None,
+        };
+        for unit in units.into_iter() {
+            instance.add(unit);
+        }
+        instance
+    }
+
+    /// Standard registry: `U` for the smallest value (order 0) and `Q` for
+    /// `2^32` of them.
+    pub fn standard() -> Self {
+        Self::new(vec![Unit::new("U", 0), Unit::new("Q", 32)])
+    }
+
+    /// Add a unit. A unit with the same order is replaced.
+    pub fn add(&mut self, unit: Unit) {
+        match self.units.binary_search_by_key(&unit.order, |u| u.order) {
+            Ok(ix) => self.units[ix] = unit,
+            Err(ix) => self.units.insert(ix, unit),
+        }
+    }
+
+    /// Accessor to the units sorted by order.
+    pub fn units(&self) -> &[Unit] {
+        &self.units
+    }
+
+    /// Number of decimal digits in formatted values.
+    pub fn precision(&self) -> usize {
+        self.precision
+    }
+
+    /// Set number of decimal digits in formatted values.
+    pub fn set_precision(&mut self, precision: usize) {
+        self.precision = precision;
+    }
+
+    /// Separator of the thousands in formatted values (none by default).
+    pub fn separator(&self) -> Option<char> {
+        self.separator
+    }
+
+    /// Set separator of the thousands in formatted values.
+    pub fn set_separator(&mut self, separator: Option<char>) {
+        self.separator = separator;
+    }
+
+    /// Get the unit covering the order, that is the unit with the largest base
+    /// order not exceeding `order`.
+    pub fn get_unit(&self, order: u64) -> Option<&Unit> {
+        self.units.iter().rev().find(|unit| unit.order <= order)
+    }
+
+    /// Find unit by name.
+    pub fn get_unit_by_name(&self, name: &str) -> Option<&Unit> {
+        self.units.iter().find(|unit| unit.name == name)
+    }
+
+    /// Format the value in the unit covering its highest bit (e.g. `"12.5 Q"`).
+    pub fn format_amount(&self, value: &U256) -> String {
+        let order = if value.bit_len() > 0 {
+            value.bit_len() as u64 - 1
+        } else {
+            0
+        };
+        match self.get_unit(order) {
+            Some(unit) => self.format_amount_in(value, unit),
+            None => format_integer(value, self.separator),
+        }
+    }
+
+    /// Format the value in the given unit.
+    pub fn format_amount_in(&self, value: &U256, unit: &Unit) -> String {
+        let number = format_scaled(value, unit.order as usize, self.precision,
+                                   self.separator);
+        format!("{} {}", number, unit.name)
+    }
+
+    /// Format the total value of coins in the order map (see
+    /// `State::get_coins`).
+    pub fn format_value(&self, order_map: &OrderCoinsMap) -> String {
+        self.format_amount(&order_map_value(order_map))
+    }
+
+    /// Format the value of a single coin of the order.
+    pub fn format_order(&self, order: u64) -> String {
+        self.format_amount(&coin_value(order))
+    }
+}
+
+
+impl Default for UnitRegistry {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+
+/// Calculate total value of coins in the order map.
+pub fn order_map_value(order_map: &OrderCoinsMap) -> U256 {
+    order_map.iter()
+        .map(|(order, coins)| &coin_value(*order) * coins.len() as u64)
+        .sum()
+}
+
+
+/// Format `value / 2^shift` as a decimal number with at most `precision`
+/// digits after the point (trailing zeros are trimmed).
+fn format_scaled(value: &U256, shift: usize, precision: usize, 
+                 separator: Option<char>) -> String {
+    // Integer part
+    let integer = value >> shift;
+
+    // Fractional part as the lowest `shift` bits, truncated to 64 bits
+    let mut frac = value.clone();
+    frac.rem_2k(shift);
+    let (mut frac, bits) = if shift > 64 {
+        (u64::from(&(&frac >> (shift - 64))) as u128, 64)
+    } else {
+        (u64::from(&frac) as u128, shift)
+    };
+
+    // Decimal digits of the fraction
+    let mask = (1u128 << bits) - 1;
+    let mut digits = String::new();
+    for _ in 0..precision {
+        frac *= 10;
+        digits.push(char::from(b'0' + (frac >> bits) as u8));
+        frac &= mask;
+    }
+    let digits = digits.trim_end_matches('0');
+
+    if digits.is_empty() {
+        format_integer(&integer, separator)
+    } else {
+        format!("{}.{}", format_integer(&integer, separator), digits)
+    }
+}
+
+
+/// Format the integer in decimal with the optional thousands separator.
+fn format_integer(value: &U256, separator: Option<char>) -> String {
+    match separator {
+        Some(separator) => u256_to_decimal_grouped(value, separator),
+        None => value.to_decimal(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn test_get_unit() {
+        let registry = UnitRegistry::standard();
+        assert_eq!(registry.get_unit(0).unwrap().name, "U");
+        assert_eq!(registry.get_unit(31).unwrap().name, "U");
+        assert_eq!(registry.get_unit(32).unwrap().name, "Q");
+        assert_eq!(registry.get_unit(255).unwrap().name, "Q");
+        assert_eq!(registry.get_unit_by_name("Q").unwrap().order, 32);
+        assert!(UnitRegistry::new(vec![Unit::new("K", 10)])
+            .get_unit(9).is_none());
+    }
+
+    #[test]
+    fn test_format_amount() {
+        let registry = UnitRegistry::standard();
+        assert_eq!(registry.format_amount(&U256::from(0)), "0 U");
+        assert_eq!(registry.format_amount(&U256::from(1000)), "1000 U");
+        assert_eq!(registry.format_order(32), "1 Q");
+        assert_eq!(registry.format_order(35), "8 Q");
+
+        let value = &(&coin_value(35) * 12) + &coin_value(31);
+        assert_eq!(registry.format_amount(&value), "96.5 Q");
+
+        let value = &coin_value(32) + &U256::from(1);
+        assert_eq!(registry.format_amount(&value), "1 Q");
+
+        let value = &coin_value(32) + &coin_value(22);
+        assert_eq!(registry.format_amount(&value), "1 Q");
+        assert_eq!(UnitRegistry::standard().format_amount_in(
+            &coin_value(78), &Unit::new("T", 70)
+        ), "256 T");
+
+        // Thousands separator
+        let mut registry = UnitRegistry::standard();
+        registry.set_separator(Some(','));
+        assert_eq!(registry.format_amount(&U256::from(1234567)), 
+                   "1,234,567 U");
+        let value = &(&coin_value(32) * 4096) + &coin_value(31);
+        assert_eq!(registry.format_amount(&value), "4,096.5 Q");
+    }
+
+    #[test]
+    fn test_format_value() {
+        let registry = UnitRegistry::standard();
+
+        let mut order_map = HashMap::new();
+        order_map.insert(35, HashSet::from([U256::from(1), U256::from(2)]));
+        order_map.insert(31, HashSet::from([U256::from(3)]));
+
+        assert_eq!(order_map_value(&order_map),
+                   &(&coin_value(35) * 2) + &coin_value(31));
+        assert_eq!(registry.format_value(&order_map), "16.5 Q");
+    }
+}
@@ -0,0 +1,373 @@
+//! Unsigned transaction groups for offline signing.
+//!
+//! An `UnsignedGroup` carries everything needed to sign a group without access
+//! to the blockchain state: the transactions with empty signatures, the coin
+//! counters taken from the state and the public keys of the required signers.
+//! It is serializable, so it can be created on an online machine, passed to an
+//! air-gapped one that keeps the keys, signed there with `UnsignedGroup::sign`
+//! and brought back to be finalized into a `Group` with
+//! `UnsignedGroup::finalize`.
+//!
+//! The counters are fixed at creation, so the group must be finalized before
+//! any of its coins is spent in another block. The chain id is taken from the
+//! state as well, and the transactions commit to it (version 5) if the chain
+//! requires the replay protection (see `State::requires_replay_protection`).
+//!
+//! `GroupBuilder` assembles the common groups (transfer, split and merge) with
+//! an optional fee, so the order of merge coins and the place of the fee are
+//! always correct.
+
+use rand::{Rng, CryptoRng};
+use serde::{Serialize, Deserialize};
+
+use crate::validate;
+use crate::utils::*;
+use crate::schema::Schema;
+use crate::state::State;
+use crate::transaction::{Transaction, Group, Validity, Memo};
+use crate::coin::coin_validate;
+use crate::spec::CHAIN_ID_MAINNET;
+
+
+/// Transaction waiting for the signature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnsignedTransaction {
+    /// Transaction with empty signature until it is signed.
+    pub transaction: Transaction,
+
+    /// Counter of the coin the signature is built for.
+    pub counter: u64,
+
+    /// Public key of the required signer (the coin owner).
+    pub signer: U256,
+
+    /// Chain id the signature is built for.
+    #[serde(default = "default_chain_id")]
+    pub chain_id: u64,
+}
+
+
+impl UnsignedTransaction {
+    /// Create an unsigned transaction of the coin to `addr`. The counter and
+    /// the chain id are taken from the state.
+    pub fn new(coin: U256, addr: U256, signer: U256, state: &State) -> Self {
+        let counter = state.get_coin_counter(&coin);
+        let zero = U256::from(0);
+        let mut transaction = Transaction::new(coin, addr, zero.clone(), zero);
+        if state.requires_replay_protection() {
+            transaction = transaction.with_replay_protection();
+        }
+        Self { transaction, counter, signer, chain_id: state.get_chain_id() }
+    }
+
+    /// Check if the transaction is signed.
+    pub fn is_signed(&self) -> bool {
+        let zero = U256::from(0);
+        (self.transaction.sign_r != zero) || (self.transaction.sign_s != zero)
+    }
+
+    /// Get the message to sign.
+    pub fn get_msg(&self) -> U256 {
+        self.transaction.get_msg_of_chain(self.counter, self.chain_id)
+    }
+}
+
+
+fn default_chain_id() -> u64 {
+    CHAIN_ID_MAINNET
+}
+
+
+/// Group of transactions waiting for the signatures.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnsignedGroup {
+    transactions: Vec<UnsignedTransaction>,
+}
+
+
+impl UnsignedGroup {
+    /// Create an unsigned group that sends `coins` to `addr` (use 1 and 2 for
+    /// split and merge) with an optional `fee` coin. All the coins must belong
+    /// to `signer`.
+    pub fn new(coins: &[U256], addr: &U256, fee: Option<&U256>,
+               signer: &U256, state: &State) -> Self {
+        let mut transactions: Vec<UnsignedTransaction> = coins.iter()
+            .map(|coin| UnsignedTransaction::new(coin.clone(), addr.clone(),
+                                                 signer.clone(), state))
+            .collect();
+        if let Some(fee) = fee {
+            transactions.push(UnsignedTransaction::new(
+                fee.clone(), U256::from(0), signer.clone(), state
+            ));
+        }
+        Self { transactions }
+    }
+
+    /// Create an unsigned group from the given unsigned transactions.
+    pub fn from_transactions(transactions: Vec<UnsignedTransaction>) -> Self {
+        Self { transactions }
+    }
+
+    /// Accessor to the unsigned transactions.
+    pub fn transactions(&self) -> &[UnsignedTransaction] {
+        &self.transactions
+    }
+
+    /// Set the validity window for all transactions. It must be called before
+    /// signing.
+    pub fn with_validity(mut self, validity: Validity) -> Self {
+        for utr in self.transactions.iter_mut() {
+            utr.transaction = utr.transaction.clone()
+                                 .with_validity(validity.clone());
+        }
+        self
+    }
+
+    /// Set the memo for the leading transaction. It must be called before
+    /// signing.
+    pub fn with_memo(mut self, memo: Memo) -> Self {
+        if let Some(utr) = self.transactions.first_mut() {
+            utr.transaction = utr.transaction.clone().with_memo(memo);
+        }
+        self
+    }
+
+    /// Get unique public keys of the required signers.
+    pub fn get_signers(&self) -> Vec<U256> {
+        let mut signers: Vec<U256> = Vec::new();
+        for utr in self.transactions.iter() {
+            if !signers.contains(&utr.signer) {
+                signers.push(utr.signer.clone());
+            }
+        }
+        signers
+    }
+
+    /// Sign all transactions that require the signature of `key`. Returns the
+    /// number of signed transactions. The state is not needed, so it can be
+    /// done offline.
+    pub fn sign<R: Rng + CryptoRng>(&mut self, rng: &mut R, key: &U256,
+                                    schema: &Schema) -> usize {
+        let public = schema.get_public(key);
+        let mut count = 0;
+        for utr in self.transactions.iter_mut() {
+            if utr.signer == public {
+                let (counter, chain_id) = (utr.counter, utr.chain_id);
+                utr.transaction.sign_for_chain(rng, key, counter, chain_id,
+                                               schema);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Check if all the transactions are signed.
+    pub fn is_complete(&self) -> bool {
+        self.transactions.iter().all(|utr| utr.is_signed())
+    }
+
+    /// Finalize the group. The checks:
+    /// 1. All the transactions are signed.
+    /// 2. Each signature corresponds the required signer.
+    /// 3. The transactions form a valid group in the state.
+    pub fn finalize(self, state: &State, schema: &Schema) -> UqoinResult<Group> {
+        validate!(self.is_complete(), TransactionUnsigned)?;
+
+        let mut transactions = Vec::new();
+        let mut senders = Vec::new();
+
+        for utr in self.transactions.into_iter() {
+            let sender = schema.extract_public(
+                &utr.get_msg(),
+                &(utr.transaction.sign_r.clone(),
+                  utr.transaction.sign_s.clone())
+            );
+            validate!(sender == utr.signer, TransactionInvalidSender)?;
+            transactions.push(utr.transaction);
+            senders.push(sender);
+        }
+
+        Group::new(transactions, state, &senders)
+    }
+}
+
+
+/// Builder of signed groups. Example:
+/// ```ignore
+/// let group = GroupBuilder::transfer(coin, addr).with_fee(fee_coin)
+///                          .sign(&mut rng, &key, &state, &schema)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct GroupBuilder {
+    coins: Vec<U256>,
+    addr: U256,
+    fee: Option<U256>,
+    validity: Option<Validity>,
+    memo: Option<Memo>,
+}
+
+
+impl GroupBuilder {
+    /// Transfer the coin to the address.
+    pub fn transfer(coin: U256, to: U256) -> Self {
+        Self::new(vec![coin], to)
+    }
+
+    /// Split the coin into three coins of lower orders.
+    pub fn split(coin: U256) -> Self {
+        Self::new(vec![coin], U256::from(1))
+    }
+
+    /// Merge three coins into one of a higher order. The coins may go in any
+    /// order: the coin of the higher order is placed first on build.
+    pub fn merge(coins: [U256; 3]) -> Self {
+        Self::new(coins.to_vec(), U256::from(2))
+    }
+
+    /// Attach the fee coin.
+    pub fn with_fee(mut self, coin: U256) -> Self {
+        self.fee = Some(coin);
+        self
+    }
+
+    /// Set the validity window for all transactions.
+    pub fn with_validity(mut self, validity: Validity) -> Self {
+        self.validity = Some(validity);
+        self
+    }
+
+    /// Set the memo for the leading transaction.
+    pub fn with_memo(mut self, memo: Memo) -> Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    /// Build the unsigned group for the signer, it can be signed offline.
+    pub fn unsigned(&self, signer: &U256, state: &State) -> UnsignedGroup {
+        // Place the coin of the highest order first for merge
+        let mut coins = self.coins.clone();
+        coins.sort_by_key(|coin| {
+            std::cmp::Reverse(Self::get_coin_order(coin, signer, state))
+        });
+
+        let mut unsigned = UnsignedGroup::new(&coins, &self.addr, 
+                                              self.fee.as_ref(), signer, state);
+        if let Some(validity) = self.validity.as_ref() {
+            unsigned = unsigned.with_validity(validity.clone());
+        }
+        if let Some(memo) = self.memo.as_ref() {
+            unsigned = unsigned.with_memo(memo.clone());
+        }
+        unsigned
+    }
+
+    /// Build the group signing it with the key. The counters are taken from
+    /// the state, and the group is validated in it.
+    pub fn sign<R: Rng + CryptoRng>(&self, rng: &mut R, key: &U256, 
+                                    state: &State, 
+                                    schema: &Schema) -> UqoinResult<Group> {
+        let signer = schema.get_public(key);
+        let mut unsigned = self.unsigned(&signer, state);
+        unsigned.sign(rng, key, schema);
+        unsigned.finalize(state, schema)
+    }
+
+    fn new(coins: Vec<U256>, addr: U256) -> Self {
+        Self { coins, addr, fee: None, validity: None, memo: None }
+    }
+
+    /// Order of the coin for sorting: invalid new coins get the lowest order,
+    /// they are rejected on finalize anyway.
+    fn get_coin_order(coin: &U256, signer: &U256, state: &State) -> u64 {
+        if let Some(coin_info) = state.get_coin_info(coin) {
+            coin_info.order
+        } else if coin_validate(coin, signer).is_ok() {
+            state.calc_coin_order(coin, signer)
+        } else {
+            0
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin::coin_random;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_unsigned_group() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        let coin = coin_random(&mut rng, &public);
+        let fee = coin_random(&mut rng, &public);
+        let addr: U256 = rng.random();
+
+        let unsigned = UnsignedGroup::new(std::slice::from_ref(&coin), &addr,
+                                          Some(&fee), &public, &state);
+        assert_eq!(unsigned.get_signers(), vec![public.clone()]);
+        assert_eq!(unsigned.clone().finalize(&state, &schema).unwrap_err()
+                       .kind(), ErrorKind::TransactionUnsigned);
+
+        // Transfer to the offline machine and back
+        let json = serde_json::to_string(&unsigned).unwrap();
+        let mut offline: UnsignedGroup = serde_json::from_str(&json).unwrap();
+
+        // Wrong key signs nothing
+        let other_key = schema.gen_key(&mut rng);
+        assert_eq!(offline.sign(&mut rng, &other_key, &schema), 0);
+        assert_eq!(offline.sign(&mut rng, &key, &schema), 2);
+        assert!(offline.is_complete());
+
+        let json = serde_json::to_string(&offline).unwrap();
+        let signed: UnsignedGroup = serde_json::from_str(&json).unwrap();
+
+        let group = signed.finalize(&state, &schema).unwrap();
+        assert_eq!(group.len(), 2);
+        assert_eq!(group.transactions()[0].coin, coin);
+        assert_eq!(group.get_fee().unwrap().coin, fee);
+    }
+
+    #[test]
+    fn test_group_builder() {
+        let schema = Schema::new();
+        let mut rng = rand::rng();
+        let state = State::new();
+        let (key, public) = schema.gen_pair(&mut rng);
+
+        let mut mine = |order: u64| loop {
+            let coin = coin_random(&mut rng, &public);
+            if state.calc_coin_order(&coin, &public) == order {
+                break coin;
+            }
+        };
+
+        let high = mine(3);
+        let low1 = mine(2);
+        let low2 = mine(2);
+        let fee = mine(1);
+
+        let group = GroupBuilder::merge([low1.clone(), high.clone(), 
+                                         low2.clone()])
+            .with_fee(fee.clone())
+            .sign(&mut rng, &key, &state, &schema).unwrap();
+        assert_eq!(group.len(), 4);
+        assert_eq!(group.transactions()[0].coin, high);
+        assert_eq!(group.get_fee().unwrap().coin, fee);
+        assert_eq!(group.ext_size(), 1);
+
+        let group = GroupBuilder::transfer(high.clone(), U256::from(100))
+            .with_validity(Validity::new(1, 10))
+            .sign(&mut rng, &key, &state, &schema).unwrap();
+        assert_eq!(group.len(), 1);
+        assert!(group.transactions()[0].is_valid_at(10));
+
+        // Merge of wrong orders is rejected
+        assert!(GroupBuilder::merge([high, low1, fee])
+            .sign(&mut rng, &key, &state, &schema).is_err());
+    }
+}
@@ -6,10 +6,16 @@
 
 use std::mem;
 use std::hash::Hash;
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap, BTreeMap};
 
 use sha3::{Sha3_256, Digest};
 use finitelib::prelude::*;
+use serde::{Serializer, Deserializer};
+use serde::de::{self, Unexpected};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 
 
 /// A 256-bit unsigned integer type, fundamental for representing large 
@@ -27,14 +33,176 @@ pub type Signature = (U256, U256);
 pub type UqoinResult<T> = Result<T, crate::error::Error>;
 
 
+/// Creates a cryptographically secure random number generator seeded from 
+/// the OS entropy. Use it (or another `CryptoRng`) for keys, seeds and 
+/// signature nonces.
+pub fn secure_rng() -> StdRng {
+    StdRng::from_os_rng()
+}
+
+
+/// Incremental SHA3-256 hasher of `U256` values and bytes. It is cloned to 
+/// finalize a common prefix with different suffixes (e.g. the block message
+/// with nonces in mining).
+#[derive(Clone, Default)]
+pub struct Hasher256(Sha3_256);
+
+
+impl Hasher256 {
+    /// Create an empty hasher.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the bytes of the value (little-endian, as `U256::to_bytes`).
+    pub fn update_u256(&mut self, value: &U256) {
+        self.0.update(value.to_bytes());
+    }
+
+    /// Add the bytes.
+    pub fn update<B: AsRef<[u8]>>(&mut self, bytes: B) {
+        self.0.update(bytes);
+    }
+
+    /// Get the hash bytes.
+    pub fn finalize_bytes(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+
+    /// Get the hash as `U256`.
+    pub fn finalize(self) -> U256 {
+        U256::from_bytes(&self.finalize_bytes())
+    }
+
+    /// Get the hash bytes with the suffix added to the copy of the hasher, 
+    /// the hasher itself is not changed.
+    pub fn finalize_with(&self, suffix: &[u8]) -> [u8; 32] {
+        let mut hasher = self.clone();
+        hasher.update(suffix);
+        hasher.finalize_bytes()
+    }
+}
+
+
 /// Computes the SHA3-256 hash of an iterator over U256 elements.
 pub fn hash_of_u256<'a, I: Iterator<Item = &'a U256>>(elems: I) -> U256 {
-    let mut hasher = Sha3_256::new();
+    let mut hasher = Hasher256::new();
     for elem in elems {
-        hasher.update(elem.to_bytes());
+        hasher.update_u256(elem);
+    }
+    hasher.finalize()
+}
+
+
+/// Computes the tagged (domain-separated) SHA3-256 hash of U256 elements: 
+/// the elements are prefixed with the hash of the tag twice, so the hashes of
+/// different contexts (e.g. a transaction and a block message) never 
+/// coincide even for the same elements.
+pub fn hash_tagged<'a, I: Iterator<Item = &'a U256>>(tag: &str, 
+                                                     elems: I) -> U256 {
+    let tag_hash = hash_of_bytes(tag.as_bytes());
+    let mut hasher = Hasher256::new();
+    hasher.update_u256(&tag_hash);
+    hasher.update_u256(&tag_hash);
+    for elem in elems {
+        hasher.update_u256(elem);
+    }
+    hasher.finalize()
+}
+
+
+/// Computes the SHA3-256 hash of bytes.
+pub fn hash_of_bytes(bytes: &[u8]) -> U256 {
+    let mut hasher = Hasher256::new();
+    hasher.update(bytes);
+    hasher.finalize()
+}
+
+
+/// Encodes bytes as an uppercase hex string.
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+
+/// Decodes a hex string into bytes. Returns `None` if the string is not a 
+/// valid hex.
+pub fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
     }
-    let bytes = hasher.finalize();
-    U256::from_bytes(&bytes)
+    (0..hex.len()).step_by(2)
+        .map(|ix| u8::from_str_radix(hex.get(ix..ix + 2)?, 16).ok())
+        .collect()
+}
+
+
+/// Computes the merkle root of the leaves: each level hashes the pairs of 
+/// nodes (`hash_of_u256`), an odd node is moved to the next level as it is. 
+/// The root of no leaves is zero.
+pub fn merkle_root(leaves: &[U256]) -> U256 {
+    if leaves.is_empty() {
+        return U256::from(0);
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level.chunks(2).map(|pair| {
+            if pair.len() == 2 {
+                hash_of_u256(pair.iter())
+            } else {
+                pair[0].clone()
+            }
+        }).collect();
+    }
+    level.pop().unwrap()
+}
+
+
+/// Computes the merkle branch of the leaf `index`: the sibling nodes from 
+/// the leaves to the root (the levels where the node is odd have no 
+/// sibling).
+pub fn merkle_branch(leaves: &[U256], index: usize) -> Vec<U256> {
+    let mut branch = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut ix = index;
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(ix ^ 1) {
+            branch.push(sibling.clone());
+        }
+        level = level.chunks(2).map(|pair| {
+            if pair.len() == 2 {
+                hash_of_u256(pair.iter())
+            } else {
+                pair[0].clone()
+            }
+        }).collect();
+        ix /= 2;
+    }
+    branch
+}
+
+
+/// Computes the merkle root from the leaf `index` of `size` leaves and its 
+/// branch (see `merkle_branch`). It returns `None` if the branch does not 
+/// fit the size.
+pub fn merkle_root_of_branch(leaf: &U256, index: usize, size: usize, 
+                             branch: &[U256]) -> Option<U256> {
+    if index >= size {
+        return None;
+    }
+    let mut node = leaf.clone();
+    let mut siblings = branch.iter();
+    let (mut ix, mut len) = (index, size);
+    while len > 1 {
+        if ix % 2 == 1 {
+            node = hash_of_u256([siblings.next()?, &node].into_iter());
+        } else if ix + 1 < len {
+            node = hash_of_u256([&node, siblings.next()?].into_iter());
+        }
+        ix /= 2;
+        len = len.div_ceil(2);
+    }
+    siblings.next().is_none().then_some(node)
 }
 
 
@@ -60,6 +228,86 @@ pub fn check_unique<T: Eq + Hash, I: Iterator<Item = T>>(it: I) -> bool {
 }
 
 
+/// Finds the index of the first element that repeats a previous one.
+pub fn find_repeated<T: Eq + Hash, I: Iterator<Item = T>>(it: I) -> 
+                     Option<usize> {
+    let mut set = HashSet::<T>::new();
+    for (ix, elem) in it.enumerate() {
+        if !set.insert(elem) {
+            return Some(ix);
+        }
+    }
+    None
+}
+
+
+/// Checks whether all elements in the slice are unique. With the `parallel`
+/// feature the slice is checked in the `rayon` thread pool, so it is faster
+/// than `check_unique` for thousands of elements.
+pub fn par_check_unique<T: Eq + Hash + Sync>(items: &[T]) -> bool {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        items.par_iter()
+            .try_fold(HashSet::new, |mut set, elem| {
+                set.insert(elem).then_some(set)
+            })
+            .try_reduce(HashSet::new, |set1, set2| {
+                let (mut set, other) = if set1.len() >= set2.len() {
+                    (set1, set2)
+                } else {
+                    (set2, set1)
+                };
+                other.into_iter().all(|elem| set.insert(elem)).then_some(set)
+            })
+            .is_some()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    {
+        check_unique(items.iter())
+    }
+}
+
+
+/// Calculates the hashes of the items keeping their order (in parallel with
+/// the `parallel` feature).
+pub fn par_hashes<T, F>(items: &[T], hash: F) -> Vec<U256>
+        where T: Sync, F: Fn(&T) -> U256 + Sync + Send {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        items.par_iter().map(hash).collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    {
+        items.iter().map(hash).collect()
+    }
+}
+
+
+/// Calculates XOR of the hashes of the items (in parallel with the 
+/// `parallel` feature). The result does not depend on the order, but it is
+/// linear over the hashes, so it is not a secure commitment to sets (see
+/// `Commitment`).
+pub fn par_xor_hashes<T, F>(items: &[T], hash: F) -> U256
+        where T: Sync, F: Fn(&T) -> U256 + Sync + Send {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        items.par_iter().map(hash)
+            .reduce(|| U256::from(0), |acc, elem| &acc ^ &elem)
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    {
+        items.iter().map(hash)
+            .fold(U256::from(0), |acc, elem| &acc ^ &elem)
+    }
+}
+
+
 /// Determines if all elements in an iterator are equal.
 pub fn check_same<T: PartialEq, I: Iterator<Item = T>>(it: I) -> bool {
     let mut value: Option<T> = None;
@@ -74,11 +322,410 @@ pub fn check_same<T: PartialEq, I: Iterator<Item = T>>(it: I) -> bool {
 }
 
 
+/// Cache of at most `capacity` entries evicting the least recently used one.
+/// It is used for the caches of derived values (e.g. `CoinOrderCache`).
+#[derive(Debug, Clone)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    ticks: BTreeMap<u64, K>,
+}
+
+
+impl<K: Eq + Hash + Clone, V> LruCache<K, V> {
+    /// Create an empty cache keeping at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            ticks: BTreeMap::new(),
+        }
+    }
+
+    /// Maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Current number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Check if the key is cached without refreshing its usage.
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Get the value without refreshing its usage.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Get the value marking it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+        let (value, tick) = self.entries.get_mut(key)?;
+        let key = self.ticks.remove(tick).unwrap();
+        *tick = self.tick;
+        self.ticks.insert(self.tick, key);
+        Some(value)
+    }
+
+    /// Insert the value as the most recently used one. Returns the evicted
+    /// entry if the cache was full.
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        self.tick += 1;
+
+        // Replace the cached value
+        if let Some((value_old, tick)) = self.entries.get_mut(&key) {
+            *value_old = value;
+            self.ticks.remove(tick);
+            *tick = self.tick;
+            self.ticks.insert(self.tick, key);
+            return None;
+        }
+
+        // Evict the least recently used entry if the cache is full
+        let evicted = if self.entries.len() >= self.capacity {
+            self.ticks.pop_first().map(|(_, key_old)| {
+                let (value_old, _) = self.entries.remove(&key_old).unwrap();
+                (key_old, value_old)
+            })
+        } else {
+            None
+        };
+
+        self.ticks.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+        evicted
+    }
+
+    /// Remove the entry returning its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, tick) = self.entries.remove(key)?;
+        self.ticks.remove(&tick);
+        Some(value)
+    }
+
+    /// Remove all entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.ticks.clear();
+    }
+}
+
+
+/// Bloom filter of `U256` values: `contains` may give false positives (with
+/// the rate set on creation), but never false negatives. It is a fast path
+/// for the checks of coins or transaction hashes before the exact lookup.
+/// The bit positions are taken from the 64-bit limbs of the value, so the
+/// values must be uniformly distributed (hashes, coin numbers).
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: usize,
+    count: usize,
+}
+
+
+impl BloomFilter {
+    /// Create a filter for `capacity` values with the false positive rate
+    /// `fp_rate` (e.g. 0.01) at this capacity.
+    pub fn new(capacity: usize, fp_rate: f64) -> Self {
+        assert!(fp_rate > 0.0 && fp_rate < 1.0);
+        let ln2 = std::f64::consts::LN_2;
+        let size = (-(capacity.max(1) as f64) * fp_rate.ln() / ln2 / ln2)
+            .ceil() as usize;
+        let hashes = ((size as f64) / (capacity.max(1) as f64) * ln2)
+            .round() as usize;
+        Self::with_size(size, hashes.max(1))
+    }
+
+    /// Create a filter of `size` bits (rounded up to 64) and `hashes` bit
+    /// positions per value.
+    pub fn with_size(size: usize, hashes: usize) -> Self {
+        assert!(size > 0 && hashes > 0);
+        Self { bits: vec![0; size.div_ceil(64)], hashes, count: 0 }
+    }
+
+    /// Number of bits.
+    pub fn size(&self) -> usize {
+        self.bits.len() * 64
+    }
+
+    /// Number of bit positions per value.
+    pub fn hashes(&self) -> usize {
+        self.hashes
+    }
+
+    /// Number of inserted values (the repeated ones are counted too).
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Insert the value.
+    pub fn insert(&mut self, value: &U256) {
+        for ix in self.positions(value) {
+            self.bits[ix / 64] |= 1 << (ix % 64);
+        }
+        self.count += 1;
+    }
+
+    /// Check if the value may be inserted (`false` means it is not for sure).
+    pub fn contains(&self, value: &U256) -> bool {
+        self.positions(value)
+            .all(|ix| self.bits[ix / 64] & (1 << (ix % 64)) != 0)
+    }
+
+    /// Remove all values.
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+        self.count = 0;
+    }
+
+    /// Bit positions of the value by double hashing.
+    fn positions(&self, value: &U256) -> impl Iterator<Item = usize> + use<> {
+        let limbs = value.as_array();
+        let h1 = limbs[0] ^ limbs[2];
+        let h2 = (limbs[1] ^ limbs[3]) | 1;
+        let size = self.size() as u64;
+        (0..self.hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+}
+
+
+/// Value that is not carried into the clones of its owner: the clone gets
+/// the default value instead (e.g. the subscribers of a pool or the
+/// observers of a state).
+#[derive(Debug, Default)]
+pub struct Detached<T: Default>(pub T);
+
+
+impl<T: Default> Clone for Detached<T> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+
+impl<T: Default> std::ops::Deref for Detached<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+
+impl<T: Default> std::ops::DerefMut for Detached<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+
+/// Parses the fixed-width hex of `U256` (64 digits, the case and the `0x`
+/// prefix are ignored). Returns `None` if the string is not valid.
+pub fn u256_from_hex(hex: &str) -> Option<U256> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    if (hex.len() != 64) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(U256::from_hex(hex))
+}
+
+
+/// Converts `U256` to `u64`. Returns `None` if the value does not fit.
+pub fn u256_to_u64(value: &U256) -> Option<u64> {
+    (value.bit_len() <= 64).then(|| u64::from(value))
+}
+
+
+/// Converts `U256` to `u128`. Returns `None` if the value does not fit.
+pub fn u256_to_u128(value: &U256) -> Option<u128> {
+    (value.bit_len() <= 128).then(|| u128::from(value))
+}
+
+
+/// Parses `U256` from a decimal string (digits only, `_` and `,` separators
+/// are ignored). Returns `None` if the string is not valid or the value 
+/// overflows.
+pub fn u256_from_decimal(dec: &str) -> Option<U256> {
+    let mut res = U256::from(0);
+    let mut empty = true;
+    for ch in dec.chars().filter(|ch| (*ch != '_') && (*ch != ',')) {
+        let digit = ch.to_digit(10)?;
+        if (res.mul_unit(10) > 0) || res.add_unit(digit as u64) {
+            return None;
+        }
+        empty = false;
+    }
+    (!empty).then_some(res)
+}
+
+
+/// Formats `U256` as a decimal string with the thousands separated by 
+/// `separator` (e.g. `"1,234,567"`).
+pub fn u256_to_decimal_grouped(value: &U256, separator: char) -> String {
+    let dec = value.to_decimal();
+    let mut res = String::with_capacity(dec.len() * 4 / 3);
+    for (ix, ch) in dec.chars().enumerate() {
+        if (ix > 0) && (dec.len() - ix).is_multiple_of(3) {
+            res.push(separator);
+        }
+        res.push(ch);
+    }
+    res
+}
+
+
+/// Encodes `U256` as 32 big-endian bytes (the order of the hex digits). 
+/// `U256::to_bytes` gives little-endian ones.
+pub fn u256_to_be_bytes(value: &U256) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&value.to_bytes());
+    bytes.reverse();
+    bytes
+}
+
+
+/// Decodes `U256` from 32 big-endian bytes. Returns `None` if the length is
+/// not 32.
+pub fn u256_from_be_bytes(bytes: &[u8]) -> Option<U256> {
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut bytes = bytes.to_vec();
+    bytes.reverse();
+    Some(U256::from_bytes(&bytes))
+}
+
+
+/// Encodes `U256` as base64 of its big-endian bytes (the order of the hex 
+/// digits).
+pub fn u256_to_base64(value: &U256) -> String {
+    BASE64.encode(u256_to_be_bytes(value))
+}
+
+
+/// Decodes `U256` from base64 of its big-endian bytes. Returns `None` if the
+/// string is not valid.
+pub fn u256_from_base64(base64: &str) -> Option<U256> {
+    u256_from_be_bytes(&BASE64.decode(base64).ok()?)
+}
+
+
+/// Serde adapter for `U256` fields (`#[serde(with = "u256_hex")]`): the 
+/// fixed-width uppercase hex in human-readable formats (e.g. JSON), the 32 
+/// little-endian bytes (as in `Codec`) in binary ones. Unlike the default 
+/// implementation, the invalid hex is an error instead of a panic.
+pub mod u256_hex {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &U256, 
+                                    serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_u256(value, serializer, U256::to_hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> 
+                                                  Result<U256, D::Error> {
+        deserialize_u256(deserializer, u256_from_hex)
+    }
+}
+
+
+/// Serde adapter for `U256` fields (`#[serde(with = "u256_base64")]`): 
+/// base64 (44 characters) in human-readable formats, the 32 little-endian 
+/// bytes in binary ones.
+pub mod u256_base64 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &U256, 
+                                    serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_u256(value, serializer, u256_to_base64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> 
+                                                  Result<U256, D::Error> {
+        deserialize_u256(deserializer, u256_from_base64)
+    }
+}
+
+
+fn serialize_u256<S: Serializer>(value: &U256, serializer: S, 
+                                 encode: fn(&U256) -> String) -> 
+                                 Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&encode(value))
+    } else {
+        serializer.serialize_bytes(&value.to_bytes())
+    }
+}
+
+
+fn deserialize_u256<'de, D: Deserializer<'de>>(
+            deserializer: D, decode: fn(&str) -> Option<U256>
+        ) -> Result<U256, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(U256Visitor(decode))
+    } else {
+        deserializer.deserialize_bytes(U256Visitor(decode))
+    }
+}
+
+
+/// Visitor of `U256` as a string (decoded by the function) or 32 bytes.
+struct U256Visitor(fn(&str) -> Option<U256>);
+
+
+impl<'de> de::Visitor<'de> for U256Visitor {
+    type Value = U256;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> 
+                 std::fmt::Result {
+        formatter.write_str("U256 as an encoded string or 32 bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<U256, E> {
+        (self.0)(value).ok_or_else(
+            || E::invalid_value(Unexpected::Str(value), &self)
+        )
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<U256, E> {
+        if value.len() != 32 {
+            return Err(E::invalid_length(value.len(), &self));
+        }
+        Ok(U256::from_bytes(value))
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, 
+                                        mut seq: A) -> Result<U256, A::Error> {
+        let mut bytes = Vec::with_capacity(32);
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use test::Bencher;
     use rand::Rng;
+    use serde::{Serialize, Deserialize};
 
     #[test]
     fn test_hash_of_u256() {
@@ -89,6 +736,15 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_hex() {
+        assert_eq!(bytes_to_hex(&[0, 15, 171, 255]), "000FABFF");
+        assert_eq!(hex_to_bytes("000FabFF"), Some(vec![0, 15, 171, 255]));
+        assert_eq!(hex_to_bytes(""), Some(vec![]));
+        assert_eq!(hex_to_bytes("ABC"), None);
+        assert_eq!(hex_to_bytes("XY"), None);
+    }
+
     #[test]
     fn test_vec_split_left() {
         let mut vec = vec![1, 2, 3, 4, 5];
@@ -102,6 +758,40 @@ mod tests {
         assert!(check_unique([1, 2, 3, 4, 5].iter()));
         assert!(!check_unique([1, 2, 3, 2, 5].iter()));
         assert!(check_unique(std::iter::empty::<i32>()));
+        assert_eq!(find_repeated([1, 2, 3, 2, 5].iter()), Some(3));
+        assert_eq!(find_repeated([1, 2, 3].iter()), None);
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let leaves = (1..=3).map(U256::from).collect::<Vec<U256>>();
+        assert_eq!(merkle_root(&[]), U256::from(0));
+        assert_eq!(merkle_root(&leaves[..1]), leaves[0]);
+        let left = hash_of_u256(leaves[..2].iter());
+        assert_eq!(merkle_root(&leaves), 
+                   hash_of_u256([&left, &leaves[2]].into_iter()));
+    }
+
+    #[test]
+    fn test_merkle_branch() {
+        for size in 1..=7 {
+            let leaves = (1..=size).map(U256::from).collect::<Vec<U256>>();
+            let root = merkle_root(&leaves);
+            for ix in 0..leaves.len() {
+                let branch = merkle_branch(&leaves, ix);
+                assert_eq!(merkle_root_of_branch(&leaves[ix], ix, leaves.len(),
+                                                 &branch), 
+                           Some(root.clone()));
+                assert_ne!(merkle_root_of_branch(&U256::from(0), ix, 
+                                                 leaves.len(), &branch), 
+                           Some(root.clone()));
+            }
+        }
+        let leaves = (1..=4).map(U256::from).collect::<Vec<U256>>();
+        let branch = merkle_branch(&leaves, 1);
+        assert_eq!(merkle_root_of_branch(&leaves[1], 1, 4, &branch[..1]), 
+                   None);
+        assert_eq!(merkle_root_of_branch(&leaves[1], 4, 4, &branch), None);
     }
 
     #[test]
@@ -140,4 +830,182 @@ mod tests {
             let _hash = hash_of_u256(arr.iter());
         });
     }
+
+    #[test]
+    fn test_hasher() {
+        let elems: Vec<U256> = (0..3).map(|_| rand::rng().random()).collect();
+
+        let mut hasher = Hasher256::new();
+        hasher.update_u256(&elems[0]);
+        hasher.update(elems[1].to_bytes());
+        let prefix = hasher.clone();
+        hasher.update_u256(&elems[2]);
+        let hash = hasher.finalize();
+        assert_eq!(hash, hash_of_u256(elems.iter()));
+
+        // The prefix is not changed by the finalization with suffix
+        let bytes = prefix.finalize_with(&elems[2].to_bytes());
+        assert_eq!(U256::from_bytes(&bytes), hash);
+        assert_eq!(prefix.finalize(), hash_of_u256(elems[..2].iter()));
+    }
+
+    #[test]
+    fn test_hash_tagged() {
+        let elems: Vec<U256> = (0..3).map(|_| rand::rng().random()).collect();
+        let hash = hash_tagged("uqoin/test", elems.iter());
+        assert_eq!(hash, hash_tagged("uqoin/test", elems.iter()));
+        assert_ne!(hash, hash_tagged("uqoin/test2", elems.iter()));
+        assert_ne!(hash, hash_of_u256(elems.iter()));
+    }
+
+    #[test]
+    fn test_secure_rng() {
+        let schema = crate::schema::Schema::new();
+        let mut rng = secure_rng();
+        let (key, public) = schema.gen_pair(&mut rng);
+        let msg: U256 = rng.random();
+        let signature = schema.build_signature(&mut rng, &msg, &key);
+        assert!(schema.check_signature(&msg, &public, &signature));
+        assert_ne!(secure_rng().random::<U256>(), 
+                   secure_rng().random::<U256>());
+    }
+
+    #[test]
+    fn test_par_utils() {
+        let items: Vec<U256> = (0..1000).map(|_| rand::rng().random())
+                                        .collect();
+        assert!(par_check_unique(&items));
+        assert!(par_check_unique::<U256>(&[]));
+        let mut repeated = items.clone();
+        repeated.push(items[500].clone());
+        assert!(!par_check_unique(&repeated));
+
+        let hash = |item: &U256| hash_of_u256([item].into_iter());
+        let hashes = par_hashes(&items, hash);
+        assert_eq!(hashes.len(), items.len());
+        assert_eq!(hashes[7], hash(&items[7]));
+
+        let xor = hashes.iter().fold(U256::from(0), |acc, h| &acc ^ h);
+        assert_eq!(par_xor_hashes(&items, hash), xor);
+    }
+
+    #[test]
+    fn test_lru_cache() {
+        let mut cache = LruCache::new(2);
+        assert!(cache.is_empty());
+        assert_eq!(cache.insert(1, "a"), None);
+        assert_eq!(cache.insert(2, "b"), None);
+
+        // The first entry is refreshed, so the second one is evicted
+        assert_eq!(cache.get(&1), Some(&"a"));
+        assert_eq!(cache.insert(3, "c"), Some((2, "b")));
+        assert!(!cache.contains(&2));
+        assert_eq!(cache.len(), 2);
+
+        // Peek does not refresh
+        assert_eq!(cache.peek(&1), Some(&"a"));
+        assert_eq!(cache.insert(3, "d"), None);
+        assert_eq!(cache.insert(4, "e"), Some((1, "a")));
+
+        assert_eq!(cache.remove(&3), Some("d"));
+        assert_eq!(cache.remove(&3), None);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut rng = rand::rng();
+        let mut filter = BloomFilter::new(1000, 0.01);
+        assert_eq!(filter.hashes(), 7);
+
+        let values: Vec<U256> = (0..1000).map(|_| rng.random()).collect();
+        for value in values.iter() {
+            filter.insert(value);
+        }
+        assert_eq!(filter.count(), 1000);
+        assert!(values.iter().all(|value| filter.contains(value)));
+
+        // False positives are rare
+        let positives = (0..1000).filter(|_| filter.contains(&rng.random()))
+                                 .count();
+        assert!(positives < 50);
+
+        filter.clear();
+        assert!(!filter.contains(&values[0]));
+    }
+
+    #[test]
+    fn test_detached() {
+        let value = Detached(vec![1, 2, 3]);
+        assert_eq!(value.len(), 3);
+        assert!(value.clone().is_empty());
+    }
+
+    #[test]
+    fn test_u256_conversions() {
+        // Integers
+        let value = U256::from(u128::MAX);
+        assert_eq!(u256_to_u128(&value), Some(u128::MAX));
+        assert_eq!(u256_to_u64(&value), None);
+        assert_eq!(u256_to_u64(&U256::from(7)), Some(7));
+        assert_eq!(u256_to_u128(&(&value + &U256::from(1))), None);
+
+        // Decimal
+        assert_eq!(u256_from_decimal("1,234_567"), Some(U256::from(1234567)));
+        assert_eq!(u256_from_decimal(&U256::max().to_decimal()), 
+                   Some(U256::max()));
+        assert_eq!(u256_from_decimal(&format!("{}0", 
+                                              U256::max().to_decimal())), 
+                   None);
+        assert_eq!(u256_from_decimal(""), None);
+        assert_eq!(u256_from_decimal("12a"), None);
+        assert_eq!(u256_to_decimal_grouped(&U256::from(1234567), ','), 
+                   "1,234,567");
+        assert_eq!(u256_to_decimal_grouped(&U256::from(123456), ' '), 
+                   "123 456");
+        assert_eq!(u256_to_decimal_grouped(&U256::from(0), ','), "0");
+
+        // Big-endian bytes
+        let value: U256 = rand::rng().random();
+        let bytes = u256_to_be_bytes(&value);
+        assert_eq!(bytes_to_hex(&bytes), value.to_hex());
+        assert_eq!(u256_from_be_bytes(&bytes), Some(value));
+        assert_eq!(u256_from_be_bytes(&bytes[1..]), None);
+    }
+
+    #[test]
+    fn test_u256_serde() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Item {
+            #[serde(with = "u256_hex")]
+            hex: U256,
+            #[serde(with = "u256_base64")]
+            base64: U256,
+        }
+
+        let value: U256 = rand::rng().random();
+        let item = Item { hex: value.clone(), base64: value.clone() };
+        let json = serde_json::to_string(&item).unwrap();
+        assert_eq!(json, format!("{{\"hex\":\"{}\",\"base64\":\"{}\"}}", 
+                                 value.to_hex(), u256_to_base64(&value)));
+        assert_eq!(serde_json::from_str::<Item>(&json).unwrap(), item);
+
+        // The hex is the same as the default one
+        assert_eq!(serde_json::to_string(&value).unwrap(), 
+                   format!("\"{}\"", value.to_hex()));
+
+        // Lowercase and prefixed hex is accepted, the invalid one is an error
+        let lower = format!("0x{}", value.to_hex().to_lowercase());
+        assert_eq!(u256_from_hex(&lower), Some(value.clone()));
+        for hex in ["", "0x12", &"G".repeat(64), &"0".repeat(65)] {
+            assert!(u256_from_hex(hex).is_none());
+            let json = format!("{{\"hex\":\"{}\",\"base64\":\"\"}}", hex);
+            assert!(serde_json::from_str::<Item>(&json).is_err());
+        }
+
+        assert_eq!(u256_from_base64(&u256_to_base64(&value)), Some(value));
+        assert_eq!(u256_to_base64(&U256::from(1)).len(), 44);
+        assert!(u256_from_base64("AQ==").is_none());
+    }
 }